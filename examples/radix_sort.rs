@@ -77,7 +77,7 @@ impl RunTime {
             .0
             .iter()
             .map(|d| {
-                let diff = if *d > avg { *d - avg } else { avg - *d };
+                let diff = d.abs_diff(avg);
                 diff.as_nanos().pow(2)
             })
             .sum::<u128>()
@@ -92,9 +92,11 @@ impl RunTime {
     }
 }
 
+type SortFn = Box<dyn FnMut(&mut Vec<u32>)>;
+
 struct Benchmarker {
     runs: usize,
-    times: HashMap<String, (Box<dyn FnMut(&mut Vec<u32>)>, RunTime)>,
+    times: HashMap<String, (SortFn, RunTime)>,
 }
 
 impl Benchmarker {
@@ -145,7 +147,7 @@ impl Benchmarker {
 
 fn main() {
     let mut benchmarker = Benchmarker::new(10);
-    benchmarker.register("radix sort", |data| radix_sort(data));
+    benchmarker.register("radix sort", radix_sort);
     benchmarker.register("unstable sort", |data| data.sort_unstable());
     // benchmarker.register("stable sort", |data| data.sort());
 
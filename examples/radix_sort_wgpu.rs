@@ -1,3 +1,6 @@
+//! Single-workgroup GPU radix sort benchmarked against the CPU, kept for teaching.
+//! The simulation uses the multi-workgroup key/value sort of `src/gpu/sort.rs`.
+
 use std::{collections::HashMap, num::NonZeroU64, time::Duration};
 
 use wgpu::{MemoryHints, PollType, util::DeviceExt};
@@ -85,7 +88,7 @@ impl RunTime {
             .0
             .iter()
             .map(|d| {
-                let diff = if *d > avg { *d - avg } else { avg - *d };
+                let diff = d.abs_diff(avg);
                 diff.as_nanos().pow(2)
             })
             .sum::<u128>()
@@ -100,9 +103,11 @@ impl RunTime {
     }
 }
 
+type SortFn = Box<dyn FnMut(&mut Vec<u32>)>;
+
 struct Benchmarker {
     runs: usize,
-    times: HashMap<String, (Box<dyn FnMut(&mut Vec<u32>)>, RunTime)>,
+    times: HashMap<String, (SortFn, RunTime)>,
 }
 
 impl Benchmarker {
@@ -250,7 +255,7 @@ impl GpuRadixSorter {
         }
     }
 
-    fn sort(&mut self, values: &mut Vec<u32>) -> Result<(), String> {
+    fn sort(&mut self, values: &mut [u32]) -> Result<(), String> {
         if values.is_empty() {
             return Ok(());
         }
@@ -274,7 +279,7 @@ impl GpuRadixSorter {
 
         let data_buf_b = self.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("radix::data_buf_b"),
-            size: (values.len() * std::mem::size_of::<u32>()) as u64,
+            size: std::mem::size_of_val(values) as u64,
             usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
             mapped_at_creation: false,
         });
@@ -292,7 +297,7 @@ impl GpuRadixSorter {
                 usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             });
 
-        let readback_size = (values.len() * std::mem::size_of::<u32>()) as u64;
+        let readback_size = std::mem::size_of_val(values) as u64;
         let readback_buf = self.device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("radix::readback_buf"),
             size: readback_size,
@@ -355,7 +360,7 @@ impl GpuRadixSorter {
                 .map_err(|_| "Failed to wait for GPU radix pass".to_string())?;
         }
 
-        let final_src = if passes.len() % 2 == 0 {
+        let final_src = if passes.len().is_multiple_of(2) {
             &data_buf_a
         } else {
             &data_buf_b
@@ -453,7 +458,7 @@ fn main() {
 
     // Benchmark CPU vs GPU using the shared `Benchmarker` harness.
     let mut benchmarker = Benchmarker::new(5);
-    benchmarker.register("CPU radix sort", |data| radix_sort(data));
+    benchmarker.register("CPU radix sort", radix_sort);
 
     benchmarker.register("GPU radix sort", {
         let mut sorter = gpu_sorter;
@@ -0,0 +1,203 @@
+// Barnes-Hut solver
+//
+// Appended to nbody.wgsl, `update_barnes_hut` replaces the direct-sum `update` entry
+// point and passes the tree walk acceleration to the same `integrate`.
+// The quadtree has a fixed depth: particles are sorted by the Morton code of their cell
+// in a uniform leaf grid (radix_sort.wgsl), each leaf sums its particles in f32, then
+// each coarser level is reduced from the 4 cells below it. Node (level l, x, y) lives
+// at `level_offset(l) + y * 2^l + x` in `nodes`.
+
+const TREE_DEPTH : u32 = __TREE_DEPTH__; // Set at compile time
+const LEAF_SIDE : u32 = 1u << TREE_DEPTH;
+const STACK_SIZE : u32 = 3u * TREE_DEPTH + 1u; // each pop pushes at most 4 children
+
+@group(1) @binding(0) var<storage, read_write> bh_ranges : array<u32>; // (start, end) in `bh_particles` per leaf Morton code
+@group(1) @binding(1) var<storage, read_write> nodes : array<vec4<f32>>; // (com.x, com.y, mass, 0)
+@group(1) @binding(2) var<uniform> reduce_level : vec4<u32>; // (level being reduced, 0, 0, 0)
+@group(1) @binding(24) var<storage, read_write> bh_keys : array<u32>;      // Morton code of the leaf of each particle, then sorted
+@group(1) @binding(25) var<storage, read_write> bh_particles : array<u32>; // particle indices, then sorted by leaf
+
+fn level_offset(level: u32) -> u32 {
+  return ((1u << (2u * level)) - 1u) / 3u;
+}
+
+fn leaf_cell(p: Position, world_min: vec2<f32>, world_size: vec2<f32>) -> vec2<u32> {
+  let t = (p - world_min) / world_size * f32(LEAF_SIDE);
+  let c = clamp(vec2<i32>(floor(t)), vec2<i32>(0), vec2<i32>(i32(LEAF_SIDE) - 1));
+  return vec2<u32>(c);
+}
+
+fn pack_node(level: u32, cell: vec2<u32>) -> u32 {
+  return (level << 28u) | (cell.x << 14u) | cell.y;
+}
+
+// Key every particle with the Morton code of its leaf cell, see `grid_morton`
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn bh_assign(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (id >= n) { return; }
+
+  let world_min = S.world.xy;
+  let world_size = S.world.zw - world_min;
+  bh_keys[id] = grid_morton(leaf_cell(position_read[id], world_min, world_size));
+  bh_particles[id] = id;
+}
+
+// Every leaf starts at the first of its sorted codes and ends after the last one,
+// `bh_ranges` is cleared beforehand so empty leaves stay empty
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn bh_bounds(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let i = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (i >= n) { return; }
+
+  let code = bh_keys[i];
+  if (i == 0u || bh_keys[i - 1u] != code) {
+    bh_ranges[2u * code] = i;
+  }
+  if (i + 1u == n || bh_keys[i + 1u] != code) {
+    bh_ranges[2u * code + 1u] = i + 1u;
+  }
+}
+
+// Sum the sorted particles of each leaf into a (center of mass, mass) node
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn bh_leaves(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let idx = gid.x;
+  if (idx >= LEAF_SIDE * LEAF_SIDE) { return; }
+
+  let world_min = S.world.xy;
+  let world_size = S.world.zw - world_min;
+  let cell = vec2<u32>(idx % LEAF_SIDE, idx / LEAF_SIDE);
+  let code = grid_morton(cell);
+
+  // Offsets inside the cell keep the sum precise far from the origin
+  var m = 0.0;
+  var sum = vec2<f32>(0.0);
+  for (var k = bh_ranges[2u * code]; k < bh_ranges[2u * code + 1u]; k = k + 1u) {
+    let id = bh_particles[k];
+    let local = clamp((position_read[id] - world_min) / world_size * f32(LEAF_SIDE) - vec2<f32>(cell), vec2<f32>(0.0), vec2<f32>(1.0));
+    m += mass[id];
+    sum += mass[id] * local;
+  }
+
+  var node = vec4<f32>(0.0);
+  if (m > 0.0) {
+    let com = world_min + (vec2<f32>(cell) + sum / m) / f32(LEAF_SIDE) * world_size;
+    node = vec4<f32>(com, m, 0.0);
+  }
+  nodes[level_offset(TREE_DEPTH) + idx] = node;
+}
+
+// Build the nodes of `reduce_level` from their 4 children
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn bh_reduce(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let level = reduce_level.x;
+  let side = 1u << level;
+  let idx = gid.x;
+  if (idx >= side * side) { return; }
+
+  let x = idx % side;
+  let y = idx / side;
+  let child_side = side * 2u;
+  let child_base = level_offset(level + 1u) + (2u * y) * child_side + 2u * x;
+
+  var mass = 0.0;
+  var weighted = vec2<f32>(0.0);
+  for (var k: u32 = 0u; k < 4u; k = k + 1u) {
+    let child = nodes[child_base + (k / 2u) * child_side + (k % 2u)];
+    mass += child.z;
+    weighted += child.xy * child.z;
+  }
+
+  var node = vec4<f32>(0.0);
+  if (mass > 0.0) {
+    node = vec4<f32>(weighted / mass, mass, 0.0);
+  }
+  nodes[level_offset(level) + idx] = node;
+}
+
+fn bh_acceleration(inP: Position, inM: f32, theta: f32, g: f32, soft2: f32, world_min: vec2<f32>, world_size: vec2<f32>, wrap: u32) -> Acceleration {
+  let own_leaf = leaf_cell(inP, world_min, world_size);
+  let world_extent = max(world_size.x, world_size.y);
+  let theta2 = theta * theta;
+
+  var acc : Acceleration = Acceleration(0.0, 0.0);
+  var stack : array<u32, STACK_SIZE>;
+  var top : u32 = 1u;
+  stack[0] = pack_node(0u, vec2<u32>(0u, 0u)); // root
+
+  loop {
+    if (top == 0u) { break; }
+    top -= 1u;
+
+    let packed = stack[top];
+    let level = packed >> 28u;
+    let cell = vec2<u32>((packed >> 14u) & 0x3FFFu, packed & 0x3FFFu);
+    let side = 1u << level;
+
+    let node = nodes[level_offset(level) + cell.y * side + cell.x];
+    var mass = node.z;
+    if (mass <= 0.0) { continue; }
+    var com = node.xy;
+
+    let own = all(own_leaf >> vec2<u32>(TREE_DEPTH - level) == cell);
+    var open = own && level < TREE_DEPTH; // always open the nodes containing the particle
+
+    if (own && level == TREE_DEPTH) {
+      // Own leaf: remove the particle itself from the aggregate
+      mass -= inM;
+      if (mass <= 1e-6 * node.z) { continue; }
+      com = (node.xy * node.z - inP * inM) / mass;
+    }
+
+    var delta = com - inP;
+    if (wrap == 1u) {
+      delta = wrapped_delta(delta, world_size);
+    }
+
+    // Opening criterion: node size / distance > theta
+    let size = world_extent / f32(side);
+    if (!open && level < TREE_DEPTH && size * size > theta2 * dot(delta, delta)) {
+      open = true;
+    }
+
+    if (open) {
+      let child = cell * 2u;
+      stack[top] = pack_node(level + 1u, child);
+      stack[top + 1u] = pack_node(level + 1u, child + vec2<u32>(1u, 0u));
+      stack[top + 2u] = pack_node(level + 1u, child + vec2<u32>(0u, 1u));
+      stack[top + 3u] = pack_node(level + 1u, child + vec2<u32>(1u, 1u));
+      top += 4u;
+      continue;
+    }
+
+    let dist2 = dot(delta, delta) + soft2; // add softening term to avoid singularity
+    let invd  = inverseSqrt(dist2);
+    let invd3 = invd * invd * invd;
+    acc += g * mass * delta * invd3;
+  }
+
+  return acc;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn update_barnes_hut(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]); // Number of particles
+  if (id >= n) { return; }
+
+  let inP : Position = position_read[id];
+
+  let g = S.dt_g_soft_n[1];
+  let soft = S.dt_g_soft_n[2];
+  let world_min = S.world.xy;
+  let world_size = S.world.zw - world_min;
+  let wrap = u32(S.damp_wrap_color[1]);
+  let theta = S.solver[1];
+
+  let acc = bh_acceleration(inP, mass[id], theta, g, soft * soft, world_min, world_size, wrap);
+
+  integrate(id, inP, acc);
+}
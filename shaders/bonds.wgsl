@@ -0,0 +1,69 @@
+// Spring networks
+//
+// Appended to nbody.wgsl, whose `scratch` slots carry the bond accelerations.
+// `bond_forces` runs before every force evaluation of a step: each particle gathers the
+// Hooke forces of its bonds from its adjacency list and leaves the acceleration in its
+// last `scratch` slot, where `integrate` adds it to the solver acceleration. Gathering
+// rather than scattering needs no atomics and keeps the summation order fixed.
+
+struct Bond {
+  i: u32,
+  j: u32,
+  rest: f32,      // length without tension
+  stiffness: f32, // force per unit of stretch
+  damping: f32,   // force per unit of stretching speed
+};
+
+@group(1) @binding(13) var<storage, read> bonds : array<Bond>;
+@group(1) @binding(14) var<storage, read> bond_offsets : array<u32>; // n + 1, first entry of each particle in `bond_refs`
+@group(1) @binding(15) var<storage, read> bond_refs : array<u32>;    // bond indices grouped by particle
+
+// The particle at the other end of `bond` from particle `id`
+fn bond_other(id: u32, bond: Bond) -> u32 {
+  return select(bond.i, bond.j, bond.i == id);
+}
+
+// Vector from `p` to particle `other`
+fn bond_delta(p: Position, other: u32) -> vec2<f32> {
+  var delta = position_read[other] - p;
+  if (u32(S.damp_wrap_color[1]) == 1u) {
+    delta = wrapped_delta(delta, S.world.zw - S.world.xy);
+  }
+  return delta;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn bond_forces(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (id >= n) {
+    return;
+  }
+
+  let p = position_read[id];
+  let v = velocity_read[id];
+  var force = vec2<f32>(0.0, 0.0);
+  for (var r = bond_offsets[id]; r < bond_offsets[id + 1u]; r = r + 1u) {
+    let bond = bonds[bond_refs[r]];
+    let other = bond_other(id, bond);
+    let delta = bond_delta(p, other);
+    let dist = sqrt(dot(delta, delta));
+    if (dist > 0.0) {
+      let direction = delta / dist;
+      let stretching = dot(velocity_read[other] - v, direction);
+      force += (bond.stiffness * (dist - bond.rest) + bond.damping * stretching) * direction;
+    }
+  }
+  scratch[SCRATCH_STRIDE * id + 2u] = vec4<f32>(force / mass[id], 0.0, 0.0);
+}
+
+// Elastic energy of the bonds of particle `id` at `p`, each bond counts half at both ends
+fn bond_potential(id: u32, p: Position) -> f32 {
+  var energy = 0.0;
+  for (var r = bond_offsets[id]; r < bond_offsets[id + 1u]; r = r + 1u) {
+    let bond = bonds[bond_refs[r]];
+    let stretch = length(bond_delta(p, bond_other(id, bond))) - bond.rest;
+    energy += 0.25 * bond.stiffness * stretch * stretch;
+  }
+  return energy;
+}
@@ -0,0 +1,67 @@
+// Inelastic collisions, nearest neighbors
+//
+// Appended to nbody.wgsl, it only reads the positions, through `position_read`, and
+// the neighbors come from the cell lists of grid.wgsl built for the merge radius.
+// Each particle looks for its nearest neighbor within the merge radius, and mutual
+// nearest neighbors merge into the lower index. `merge_find` stores the neighbor of each
+// particle in `merge_links`, merging writes every particle buffer, which these bindings
+// only read, so it is done by the stand-alone merge.wgsl. `merge_pairs` counts the
+// pairs to merge, so merging only runs when there are some.
+// src/sim/collisions.rs is the CPU reference.
+
+const MERGE_NONE : u32 = 0xffffffffu; // no neighbor within the merge radius, see merge.wgsl
+
+@group(2) @binding(22) var<storage, read_write> merge_links : array<u32>; // nearest neighbor per particle
+@group(2) @binding(23) var<storage, read_write> merge_count : atomic<u32>;  // mutual nearest neighbors
+
+// Nearest neighbor within the merge radius, the lowest index among equally close ones
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn merge_find(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (id >= n) {
+    return;
+  }
+
+  let p = position_read[id];
+  let home = grid_cell(p);
+  let world_size = S.world.zw - S.world.xy;
+  let radius2 = G.radius * G.radius;
+  var best = MERGE_NONE;
+  var best_dist2 = radius2;
+  for (var dy = -1; dy <= 1; dy = dy + 1) {
+    for (var dx = -1; dx <= 1; dx = dx + 1) {
+      let offset = vec2<i32>(dx, dy);
+      if (!grid_visit(home, offset)) {
+        continue;
+      }
+      let range = grid_range(home, offset);
+      for (var k = range.x; k < range.y; k = k + 1u) {
+        let other = grid_particles[k];
+        var delta = position_read[other] - p;
+        if (grid_wrap()) {
+          delta = wrapped_delta(delta, world_size);
+        }
+        let dist2 = dot(delta, delta);
+        if (other != id && dist2 < radius2 && (dist2 < best_dist2 || (dist2 == best_dist2 && other < best))) {
+          best = other;
+          best_dist2 = dist2;
+        }
+      }
+    }
+  }
+  merge_links[id] = best;
+}
+
+// Count the mutual nearest neighbors `merge_find` left in `merge_links`, once per pair
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn merge_pairs(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  if (id >= u32(S.dt_g_soft_n[3])) {
+    return;
+  }
+  let other = merge_links[id];
+  if (other != MERGE_NONE && other > id && merge_links[other] == id) {
+    atomicAdd(&merge_count, 1u);
+  }
+}
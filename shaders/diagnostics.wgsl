@@ -0,0 +1,144 @@
+// Conserved quantities
+//
+// Appended to nbody.wgsl, the potential reuses the `pos_tile` and `mass_tile` tiles
+// of the direct-sum kernel.
+// Each workgroup sums the kinetic energy, potential energy, momentum, angular momentum
+// and mass moments of its particles into `diag_partials`, then a single workgroup adds
+// the partial sums up into `diag_result`. No atomics are needed, so the result does not
+// depend on the scheduling of the workgroups. The O(N^2) potential is only summed with
+// the direct-sum solver, the others leave it at zero.
+
+// Bindings 0..8 and 24..27 of group 1 belong to the Barnes-Hut and Particle-Mesh
+// solvers, the charges of binding 12 are declared in nbody.wgsl, the bonds of bindings
+// 13..15 in bonds.wgsl and the cell lists of bindings 16..20 in grid.wgsl
+@group(1) @binding(9) var<storage, read_write> diag_partials : array<vec4<f32>>; // 2 per workgroup
+@group(1) @binding(10) var<storage, read_write> diag_result : array<vec4<f32>, 2>;
+
+// (kinetic, potential, angular momentum, mass) and (momentum.xy, mass weighted position.xy)
+var<workgroup> diag_scalars : array<vec4<f32>, WORKGROUP_SIZE>;
+var<workgroup> diag_vectors : array<vec4<f32>, WORKGROUP_SIZE>;
+
+// Tree reduction of the workgroup arrays into their first entry
+fn diag_reduce_workgroup(lane: u32) {
+  for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride = stride / 2u) {
+    workgroupBarrier();
+    if (lane < stride) {
+      diag_scalars[lane] += diag_scalars[lane + stride];
+      diag_vectors[lane] += diag_vectors[lane + stride];
+    }
+  }
+  workgroupBarrier();
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn diag_partial(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(local_invocation_id)  lid: vec3<u32>,
+  @builtin(workgroup_id)         wid: vec3<u32>
+) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  let in_range = id < n; // every lane must reach the barriers, no early return
+
+  let g = S.coulomb[0];
+  let soft2 = S.dt_g_soft_n[2] * S.dt_g_soft_n[2];
+  let k = S.coulomb[1];
+  let coulomb_soft2 = S.coulomb[2] * S.coulomb[2];
+  let world_min = S.world.xy;
+  let world_max = S.world.zw;
+  let world_size = world_max - world_min;
+  let center = 0.5 * (world_min + world_max);
+  let wrap = u32(S.damp_wrap_color[1]);
+
+  var p = Position(0.0, 0.0);
+  var q = 0.0;
+  if (in_range) {
+    p = position_read[id];
+    q = charge[id];
+  }
+
+  // Softened potential of particle `id`, with the same pair sum as the direct kernel,
+  // `phi` per unit mass and `phi_q` per unit charge
+  let pairs = select(0u, n, u32(S.solver[0]) == 0u);
+  var phi = 0.0;
+  var phi_q = 0.0;
+  var base : u32 = 0u;
+  loop {
+    if (base >= pairs) { break; }
+
+    let j = base + lid.x;
+    if (j < n) {
+      pos_tile[lid.x] = position_read[j];
+      mass_tile[lid.x] = mass[j];
+      charge_tile[lid.x] = charge[j];
+    }
+    workgroupBarrier();
+
+    let count = min(TILE, n - base);
+    for (var t: u32 = 0u; t < count; t = t + 1u) {
+      if (base + t == id) {
+        continue;
+      }
+
+      var delta = pos_tile[t] - p;
+      if (wrap == 1u) {
+        delta = wrapped_delta(delta, world_size);
+      }
+      let dist2 = dot(delta, delta);
+      phi -= g * mass_tile[t] * inverseSqrt(dist2 + soft2);
+      if (k != 0.0) {
+        phi_q += k * charge_tile[t] * inverseSqrt(dist2 + coulomb_soft2);
+      }
+    }
+    workgroupBarrier();
+
+    base += TILE;
+  }
+
+  var scalars = vec4<f32>(0.0);
+  var vectors = vec4<f32>(0.0);
+  if (in_range) {
+    let m = mass[id];
+    let v = velocity_read[id];
+    let r = p - center;
+    // Each pair appears twice in the sum over particles
+    var potential = 0.0;
+    if (pairs > 0u) {
+      potential = 0.5 * (m * phi + q * phi_q) + bond_potential(id, p);
+    }
+    scalars = vec4<f32>(0.5 * m * dot(v, v), potential, m * (r.x * v.y - r.y * v.x), m);
+    vectors = vec4<f32>(m * v, m * p);
+  }
+  diag_scalars[lid.x] = scalars;
+  diag_vectors[lid.x] = vectors;
+
+  diag_reduce_workgroup(lid.x);
+
+  if (lid.x == 0u) {
+    diag_partials[2u * wid.x] = diag_scalars[0];
+    diag_partials[2u * wid.x + 1u] = diag_vectors[0];
+  }
+}
+
+// Single workgroup, adds up the partial sums of `diag_partial`
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn diag_total(@builtin(local_invocation_id) lid: vec3<u32>) {
+  let n = u32(S.dt_g_soft_n[3]);
+  let groups = (n + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE;
+
+  var scalars = vec4<f32>(0.0);
+  var vectors = vec4<f32>(0.0);
+  for (var i = lid.x; i < groups; i += WORKGROUP_SIZE) {
+    scalars += diag_partials[2u * i];
+    vectors += diag_partials[2u * i + 1u];
+  }
+  diag_scalars[lid.x] = scalars;
+  diag_vectors[lid.x] = vectors;
+
+  diag_reduce_workgroup(lid.x);
+
+  if (lid.x == 0u) {
+    diag_result[0] = diag_scalars[0];
+    diag_result[1] = diag_vectors[0];
+  }
+}
@@ -0,0 +1,118 @@
+// Particle removal
+//
+// Stand-alone module, it does not share the bindings of nbody.wgsl, the workgroup scan
+// comes from scan.wgsl. The particles outside the erased disc, or those the collision
+// pass did not absorb, are compacted to the front of the buffers, keeping their order:
+// `edit_mark` scans the keep flags of each workgroup, `edit_blocks` scans the workgroup
+// counts in a single workgroup and stores the number of kept particles after them, and
+// `edit_scatter` packs the kept particles into `moved`. Once the count is known on the
+// CPU, `edit_unpack` copies them back over the particle buffers.
+
+const WORKGROUP_SIZE : u32 = __WORKGROUP_SIZE__; // Set at compile time
+
+struct Edit {
+  region: vec4<f32>, // (center x, center y, radius, wrap(0/1))
+  world: vec4<f32>,  // (min x, min y, max x, max y)
+  n: u32,
+  groups: u32,       // workgroups covering the n particles
+  mode: u32,         // 0 for the disc of `region`, ABSORBED
+}
+
+@group(0) @binding(0) var<uniform> E : Edit;
+@group(0) @binding(1) var<storage, read_write> positions : array<vec2<f32>>;
+@group(0) @binding(2) var<storage, read_write> velocities : array<vec2<f32>>;
+@group(0) @binding(3) var<storage, read_write> colors : array<vec4<f32>>;
+@group(0) @binding(4) var<storage, read_write> masses : array<f32>;
+@group(0) @binding(5) var<storage, read_write> offsets : array<u32>; // rank among the kept particles of the workgroup, REMOVED for the others
+@group(0) @binding(6) var<storage, read_write> blocks : array<u32>;  // per workgroup, then the total
+@group(0) @binding(7) var<storage, read_write> moved : array<vec4<f32>>; // 3 per kept particle
+@group(0) @binding(8) var<storage, read_write> charges : array<f32>;
+
+const REMOVED : u32 = 0xffffffffu; // see src/gpu/edit.rs
+const ABSORBED : u32 = 1u;         // merged into another particle, left with a zero mass
+
+
+// Whether particle `id` lies outside the erased disc, or was not absorbed
+fn edit_keep(id: u32) -> bool {
+  if (E.mode == ABSORBED) {
+    return masses[id] > 0.0;
+  }
+  var delta = positions[id] - E.region.xy;
+  if (E.region.w > 0.5) {
+    let size = E.world.zw - E.world.xy;
+    delta = delta - size * round(delta / size);
+  }
+  return dot(delta, delta) >= E.region.z * E.region.z;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn edit_mark(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(local_invocation_id)  lid: vec3<u32>,
+  @builtin(workgroup_id)         wid: vec3<u32>
+) {
+  let id = gid.x;
+  var kept = 0u;
+  if (id < E.n && edit_keep(id)) { // every lane must reach the barriers, no early return
+    kept = 1u;
+  }
+  let inclusive = scan_workgroup(lid.x, kept);
+  if (id < E.n) {
+    offsets[id] = select(REMOVED, inclusive - kept, kept == 1u);
+  }
+  if (lid.x == WORKGROUP_SIZE - 1u) {
+    blocks[wid.x] = inclusive;
+  }
+}
+
+// Single workgroup, turns the workgroup counts into their exclusive prefix sum
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn edit_blocks(@builtin(local_invocation_id) lid: vec3<u32>) {
+  var carry = 0u;
+  for (var base = 0u; base < E.groups; base = base + WORKGROUP_SIZE) {
+    let i = base + lid.x;
+    var count = 0u;
+    if (i < E.groups) {
+      count = blocks[i];
+    }
+    let inclusive = scan_workgroup(lid.x, count);
+    if (i < E.groups) {
+      blocks[i] = carry + inclusive - count;
+    }
+    carry = carry + scan_total();
+    workgroupBarrier(); // the next chunk overwrites `scan_values`
+  }
+  if (lid.x == 0u) {
+    blocks[E.groups] = carry;
+  }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn edit_scatter(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(workgroup_id)         wid: vec3<u32>
+) {
+  let id = gid.x;
+  if (id >= E.n || offsets[id] == REMOVED) {
+    return;
+  }
+  let slot = 3u * (blocks[wid.x] + offsets[id]);
+  moved[slot] = vec4<f32>(positions[id], velocities[id]);
+  moved[slot + 1u] = colors[id];
+  moved[slot + 2u] = vec4<f32>(masses[id], charges[id], 0.0, 0.0);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn edit_unpack(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  if (id >= blocks[E.groups]) {
+    return;
+  }
+  let state = moved[3u * id];
+  positions[id] = state.xy;
+  velocities[id] = state.zw;
+  colors[id] = moved[3u * id + 1u];
+  let extra = moved[3u * id + 2u];
+  masses[id] = extra.x;
+  charges[id] = extra.y;
+}
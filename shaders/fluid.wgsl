@@ -0,0 +1,169 @@
+// Smoothed particle hydrodynamics (SPH)
+//
+// Appended to nbody.wgsl, whose `S.fluid` uniform holds the fluid model, and
+// the neighbors come from the cell lists of grid.wgsl built for the smoothing length.
+// `fluid_density` sums the kernel-weighted masses around each particle and turns the
+// density into a pressure, `fluid_forces` then leaves the pressure, viscous and gravity
+// accelerations in the fluid slot of `scratch`, where `integrate` adds them. Without
+// wrapping, the neighbors near a wall are mirrored across it (ghost particles).
+// src/sim/fluid.rs is the CPU reference.
+
+const CUBIC_SPLINE : u32 = 0u;
+const WENDLAND_C2 : u32 = 1u;
+
+const TAIT : u32 = 0u;
+const POLYTROPIC : u32 = 1u;
+
+// Images of every neighbor, itself and its mirrors across up to two walls
+const FLUID_IMAGES : u32 = 4u;
+
+@group(2) @binding(21) var<storage, read_write> fluid_state : array<vec2<f32>>; // (density, pressure) per particle
+
+// Kernel value and radial derivative at distance `r`, both vanish at the smoothing length
+fn fluid_kernel(r: f32) -> vec2<f32> {
+  let h = S.fluid[0];
+  let q = r / h;
+  if (q >= 1.0) {
+    return vec2<f32>(0.0, 0.0);
+  }
+  if (u32(S.fluid_model[0]) == WENDLAND_C2) {
+    let sigma = 7.0 / (PI * h * h);
+    let t = 1.0 - q;
+    return sigma * vec2<f32>(t * t * t * t * (1.0 + 4.0 * q), -20.0 * q * t * t * t / h);
+  }
+  let sigma = 40.0 / (7.0 * PI * h * h);
+  if (q <= 0.5) {
+    return sigma * vec2<f32>(1.0 - 6.0 * q * q + 6.0 * q * q * q, (-12.0 * q + 18.0 * q * q) / h);
+  }
+  let t = 1.0 - q;
+  return sigma * vec2<f32>(2.0 * t * t * t, -6.0 * t * t / h);
+}
+
+fn fluid_pressure(density: f32) -> f32 {
+  let rest = S.fluid[1];
+  let c = S.fluid[2];
+  let gamma = S.fluid[3];
+  let stiffness = rest * c * c / gamma;
+  let ratio = pow(density / rest, gamma);
+  if (u32(S.fluid_model[1]) == POLYTROPIC) {
+    return stiffness * ratio;
+  }
+  return max(stiffness * (ratio - 1.0), 0.0);
+}
+
+// Nearest wall of `p` on each axis and whether it lies within the smoothing length,
+// never near when the world wraps
+fn fluid_walls(p: Position) -> vec4<f32> {
+  if (grid_wrap()) {
+    return vec4<f32>(0.0);
+  }
+  let lower = p - S.world.xy;
+  let upper = S.world.zw - p;
+  let wall = select(S.world.zw, S.world.xy, lower < upper);
+  let near = select(vec2<f32>(0.0), vec2<f32>(1.0), min(lower, upper) < vec2<f32>(S.fluid[0]));
+  return vec4<f32>(wall, near);
+}
+
+// Mirror axes of image `k` (1 for x, 2 for y)
+fn fluid_mirror(k: u32) -> vec2<f32> {
+  return vec2<f32>(f32(k & 1u), f32(k >> 1u));
+}
+
+// Vector from `p` to `q`, across the edges when the world wraps
+fn fluid_delta(p: Position, q: Position) -> vec2<f32> {
+  if (grid_wrap()) {
+    return wrapped_delta(q - p, S.world.zw - S.world.xy);
+  }
+  return q - p;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn fluid_density(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (id >= n) {
+    return;
+  }
+
+  let p = position_read[id];
+  let home = grid_cell(p);
+  let walls = fluid_walls(p);
+  var density = 0.0;
+  for (var dy = -1; dy <= 1; dy = dy + 1) {
+    for (var dx = -1; dx <= 1; dx = dx + 1) {
+      let offset = vec2<i32>(dx, dy);
+      if (!grid_visit(home, offset)) {
+        continue;
+      }
+      let range = grid_range(home, offset);
+      for (var k = range.x; k < range.y; k = k + 1u) {
+        let other = grid_particles[k];
+        let q = position_read[other];
+        for (var image = 0u; image < FLUID_IMAGES; image = image + 1u) {
+          let mirror = fluid_mirror(image);
+          if (any(mirror > walls.zw)) {
+            continue;
+          }
+          let r = length(fluid_delta(p, q + mirror * 2.0 * (walls.xy - q)));
+          density += mass[other] * fluid_kernel(r).x;
+        }
+      }
+    }
+  }
+  fluid_state[id] = vec2<f32>(density, fluid_pressure(density));
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn fluid_forces(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (id >= n) {
+    return;
+  }
+
+  let h = S.fluid[0];
+  let c = S.fluid[2];
+  let viscosity = S.fluid_model[2];
+  let p = position_read[id];
+  let v = velocity_read[id];
+  let state = fluid_state[id];
+  let home = grid_cell(p);
+  let walls = fluid_walls(p);
+  var acc = vec2<f32>(0.0, -S.fluid_model[3]);
+  for (var dy = -1; dy <= 1; dy = dy + 1) {
+    for (var dx = -1; dx <= 1; dx = dx + 1) {
+      let offset = vec2<i32>(dx, dy);
+      if (!grid_visit(home, offset)) {
+        continue;
+      }
+      let range = grid_range(home, offset);
+      for (var k = range.x; k < range.y; k = k + 1u) {
+        let other = grid_particles[k];
+        let other_state = fluid_state[other];
+        let pressures = state.y / (state.x * state.x) + other_state.y / (other_state.x * other_state.x);
+        let q = position_read[other];
+        for (var image = 0u; image < FLUID_IMAGES; image = image + 1u) {
+          let mirror = fluid_mirror(image);
+          if (any(mirror > walls.zw)) {
+            continue;
+          }
+          let d = fluid_delta(p, q + mirror * 2.0 * (walls.xy - q));
+          let r = length(d);
+          if (r == 0.0 || r >= h) {
+            continue;
+          }
+          var term = pressures;
+          // Monaghan viscosity, only between approaching particles
+          let w = velocity_read[other] * (1.0 - 2.0 * mirror);
+          let vr = dot(v - w, -d);
+          if (vr < 0.0) {
+            let mu = h * vr / (r * r + 0.01 * h * h);
+            term += -viscosity * c * mu / (0.5 * (state.x + other_state.x));
+          }
+          acc += mass[other] * term * fluid_kernel(r).y / r * d;
+        }
+      }
+    }
+  }
+  scratch[SCRATCH_STRIDE * id + 3u] = vec4<f32>(acc, 0.0, 0.0);
+}
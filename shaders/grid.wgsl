@@ -0,0 +1,142 @@
+// Cell lists for short-range interactions
+//
+// Appended to nbody.wgsl, the cells are built from `position_read` and `S.world`.
+// The world is split into cells at least the interaction radius wide, so the particles
+// within the radius of a particle lie in its cell or the 8 around it. `grid_assign`
+// gives each particle the Morton code of its cell, the radix sort (radix_sort.wgsl)
+// orders the particles by code, and `grid_bounds` records where the particles of each
+// cell start and end in `grid_particles`. A kernel then visits the neighbors with
+// `grid_visit` and `grid_range`, see `grid_count`.
+
+struct Grid {
+  cell: vec2<f32>,  // cell size
+  cells: vec2<u32>, // cells per side
+  radius: f32,      // interaction radius, at most the cell size
+  table: u32,       // entries of `grid_ranges`, past the Morton code of the last cell
+}
+
+@group(1) @binding(16) var<uniform> G : Grid;
+@group(1) @binding(17) var<storage, read_write> grid_keys : array<u32>;      // Morton code of the cell of each particle, then sorted
+@group(1) @binding(18) var<storage, read_write> grid_particles : array<u32>; // particle indices, then sorted by cell
+@group(1) @binding(19) var<storage, read_write> grid_ranges : array<u32>;    // (start, end) in `grid_particles` per Morton code
+@group(1) @binding(20) var<storage, read_write> grid_neighbors : array<u32>; // particles within the radius, per particle
+
+fn grid_wrap() -> bool {
+  return u32(S.damp_wrap_color[1]) == 1u;
+}
+
+// Spread the low 16 bits of `x` over the even bits, see src/sim/sort.rs
+fn grid_spread(x: u32) -> u32 {
+  var v = x & 0xffffu;
+  v = (v | (v << 8u)) & 0x00ff00ffu;
+  v = (v | (v << 4u)) & 0x0f0f0f0fu;
+  v = (v | (v << 2u)) & 0x33333333u;
+  return (v | (v << 1u)) & 0x55555555u;
+}
+
+fn grid_morton(cell: vec2<u32>) -> u32 {
+  return grid_spread(cell.x) | (grid_spread(cell.y) << 1u);
+}
+
+// Cell of `p`, taken back into the world first when it wraps
+fn grid_cell(p: Position) -> vec2<i32> {
+  let world_min = S.world.xy;
+  let world_size = S.world.zw - world_min;
+  var q = p;
+  if (grid_wrap()) {
+    q = q - world_size * floor((q - world_min) / world_size);
+  }
+  let cell = vec2<i32>(floor((q - world_min) / G.cell));
+  return clamp(cell, vec2<i32>(0), vec2<i32>(G.cells) - 1);
+}
+
+// Whether the neighbor cell at `offset` from `home` is visited: outside cells only
+// exist when the world wraps, and with fewer than 3 cells per side the offsets that
+// wrap to the same cell are visited once
+fn grid_visit(home: vec2<i32>, offset: vec2<i32>) -> bool {
+  let cells = vec2<i32>(G.cells);
+  if (grid_wrap()) {
+    return all(offset + 1 < cells);
+  }
+  let cell = home + offset;
+  return all(cell >= vec2<i32>(0)) && all(cell < cells);
+}
+
+// Start and end in `grid_particles` of the cell at `offset` from `home`
+fn grid_range(home: vec2<i32>, offset: vec2<i32>) -> vec2<u32> {
+  // `home + offset` is at least -1, shifted by one period to avoid a negative remainder
+  let cell = vec2<u32>(home + offset + vec2<i32>(G.cells)) % G.cells;
+  let code = grid_morton(cell);
+  return vec2<u32>(grid_ranges[2u * code], grid_ranges[2u * code + 1u]);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn grid_assign(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (id >= n) {
+    return;
+  }
+  grid_keys[id] = grid_morton(vec2<u32>(grid_cell(position_read[id])));
+  grid_particles[id] = id;
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn grid_clear(@builtin(global_invocation_id) gid: vec3<u32>) {
+  if (gid.x < 2u * G.table) {
+    grid_ranges[gid.x] = 0u;
+  }
+}
+
+// Every cell starts at the first of its sorted codes and ends after the last one
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn grid_bounds(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let i = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (i >= n) {
+    return;
+  }
+  let code = grid_keys[i];
+  if (i == 0u || grid_keys[i - 1u] != code) {
+    grid_ranges[2u * code] = i;
+  }
+  if (i + 1u == n || grid_keys[i + 1u] != code) {
+    grid_ranges[2u * code + 1u] = i + 1u;
+  }
+}
+
+// Number of other particles within the radius of each particle
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn grid_count(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (id >= n) {
+    return;
+  }
+
+  let p = position_read[id];
+  let home = grid_cell(p);
+  let world_size = S.world.zw - S.world.xy;
+  let radius2 = G.radius * G.radius;
+  var count = 0u;
+  for (var dy = -1; dy <= 1; dy = dy + 1) {
+    for (var dx = -1; dx <= 1; dx = dx + 1) {
+      let offset = vec2<i32>(dx, dy);
+      if (!grid_visit(home, offset)) {
+        continue;
+      }
+      let range = grid_range(home, offset);
+      for (var k = range.x; k < range.y; k = k + 1u) {
+        let other = grid_particles[k];
+        var delta = position_read[other] - p;
+        if (grid_wrap()) {
+          delta = wrapped_delta(delta, world_size);
+        }
+        if (other != id && dot(delta, delta) < radius2) {
+          count = count + 1u;
+        }
+      }
+    }
+  }
+  grid_neighbors[id] = count;
+}
@@ -0,0 +1,62 @@
+// Inelastic collisions, merging
+//
+// Stand-alone module like edit.wgsl, it does not share the bindings of nbody.wgsl.
+// `merge_find` (collisions.wgsl) left the nearest neighbor of each particle in `links`,
+// mutual nearest neighbors merge into the lower index, which takes the mass, momentum
+// and charge of both. The absorbed particle is left with a zero mass for the edit pass
+// to compact away, closer groups keep merging over the next frames.
+
+const WORKGROUP_SIZE : u32 = __WORKGROUP_SIZE__; // Set at compile time
+
+struct Merge {
+  world: vec4<f32>, // (min x, min y, max x, max y)
+  n: u32,
+  wrap: u32,        // 0/1
+}
+
+@group(0) @binding(0) var<uniform> M : Merge;
+@group(0) @binding(1) var<storage, read_write> positions : array<vec2<f32>>;
+@group(0) @binding(2) var<storage, read_write> velocities : array<vec2<f32>>;
+@group(0) @binding(3) var<storage, read_write> colors : array<vec4<f32>>;
+@group(0) @binding(4) var<storage, read_write> masses : array<f32>;
+@group(0) @binding(5) var<storage, read_write> charges : array<f32>;
+@group(0) @binding(6) var<storage, read_write> links : array<u32>; // nearest neighbor per particle
+
+const NONE : u32 = 0xffffffffu; // see MERGE_NONE in collisions.wgsl
+
+// Vector from `p` to `q`, across the world edges when they wrap
+fn merge_delta(p: vec2<f32>, q: vec2<f32>) -> vec2<f32> {
+  var delta = q - p;
+  if (M.wrap == 1u) {
+    let size = M.world.zw - M.world.xy;
+    delta = delta - size * round(delta / size);
+  }
+  return delta;
+}
+
+// Merge mutual nearest neighbors into the lower index, see `collisions::merge`
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn merge_apply(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  if (id >= M.n) {
+    return;
+  }
+  let other = links[id];
+  if (other == NONE || other < id || links[other] != id) {
+    return;
+  }
+
+  let mass = masses[id] + masses[other];
+  let weight = masses[other] / mass;
+  var p = positions[id] + weight * merge_delta(positions[id], positions[other]);
+  if (M.wrap == 1u) {
+    let size = M.world.zw - M.world.xy;
+    p = p - size * floor((p - M.world.xy) / size);
+  }
+  positions[id] = p;
+  velocities[id] = (masses[id] * velocities[id] + masses[other] * velocities[other]) / mass;
+  colors[id] = mix(colors[id], colors[other], weight);
+  charges[id] = charges[id] + charges[other];
+  masses[id] = mass;
+  masses[other] = 0.0;
+}
@@ -1,13 +1,39 @@
 struct Sim {
   dt_g_soft_n: vec4<f32>,         // (dt, g, softening, n)
-  damp_wrap_color_bootstrap: vec4<f32>,   // (damping, wrap(0/1), color(0/1), bootstrap(0/1))
+  damp_wrap_color: vec4<f32>,     // (damping, wrap(0/1), color by speed(0/1), color by charge(0/1))
   world: vec4<f32>,               // (world.min.x, world.min.y, world.max.x, world.max.y)
+  solver: vec4<f32>,              // (solver (0 = direct, 1 = barnes-hut), theta, 0, 0)
+  timestep: vec4<f32>,            // (mode (0 = fixed, 1 = acceleration, 2 = velocity), accuracy, dt min, integrator)
+  brush: vec4<f32>,               // (pointer x, pointer y, signed strength, radius), zero when released
+  coulomb: vec4<f32>,             // (g of the direct sum, coulomb constant, coulomb softening, 0)
+  fluid: vec4<f32>,               // (smoothing length, rest density, sound speed, gamma), zero without the fluid
+  fluid_model: vec4<f32>,         // (kernel, equation of state, viscosity, gravity)
 };
 
+// Written by `ts_total` after every step, see timestep.wgsl
+struct Step {
+  prev_dt: f32,     // length of the previous step, 0 before the first one
+  dt: f32,          // length of the next step when adaptive
+  time: f32,        // simulated time
+  time_error: f32,  // Kahan compensation of `time`
+  stage: u32,       // integrator stage within the step, advanced by `ts_stage`
+};
+
+// Integrators, see src/sim/integrator.rs
+const LEAPFROG : u32 = 0u;
+const VELOCITY_VERLET : u32 = 1u;
+const RK4 : u32 = 2u;
+const YOSHIDA4 : u32 = 3u;
+
+const YOSHIDA_W1 : f32 = 1.3512072;  // 1 / (2 - 2^(1/3))
+const YOSHIDA_W0 : f32 = -1.7024144; // -2^(1/3) / (2 - 2^(1/3))
+
 const WORKGROUP_SIZE : u32 = __WORKGROUP_SIZE__; // Set at compile time
 const TILE : u32 = WORKGROUP_SIZE;
 
 var<workgroup> pos_tile : array<Position, TILE>;
+var<workgroup> mass_tile : array<f32, TILE>;
+var<workgroup> charge_tile : array<f32, TILE>;
 
 alias Position = vec2<f32>;
 alias Velocity = vec2<f32>;
@@ -20,6 +46,15 @@ alias Color = vec4<f32>;
 @group(0) @binding(3) var<storage, read> velocity_read : array<Velocity>;
 @group(0) @binding(4) var<storage, read_write> color : array<Color>;
 @group(0) @binding(5) var<uniform> S : Sim;
+@group(0) @binding(6) var<storage, read> mass : array<f32>;
+@group(0) @binding(7) var<storage, read_write> timestep : Step;
+@group(0) @binding(8) var<storage, read_write> scratch : array<vec4<f32>>; // per particle: (position, velocity) at the start of the step, RK4 sum, bond acceleration, fluid acceleration
+
+const SCRATCH_STRIDE : u32 = 4u;
+
+// Group 0 is full, the charges of the direct sum and the diagnostics live in group 1
+// next to the bindings of the other passes
+@group(1) @binding(12) var<storage, read> charge : array<f32>;
 
 fn compute_color(v: Velocity) -> Color {
   let speed = length(v);
@@ -98,6 +133,151 @@ fn update_position(p: Position, v: Velocity, dt: f32, world_min: vec2<f32>, worl
   return np;
 }
 
+// Length of the current step, the uniform dt unless adaptive
+fn step_dt() -> f32 {
+  if (u32(S.timestep[0]) == 0u) {
+    return S.dt_g_soft_n[0];
+  }
+  return timestep.dt;
+}
+
+// Leapfrog kick of the current step, half a step after a reset
+fn step_kick() -> f32 {
+  let dt = step_dt();
+  if (timestep.prev_dt == 0.0) {
+    return 0.5 * dt;                    // one-time half-kick: v_{+1/2} from v0
+  }
+  return 0.5 * (timestep.prev_dt + dt); // v_{n+1/2} from v_{n-1/2}, exact for a constant dt
+}
+
+// Move `p` by `v * dt` and keep it inside the world
+fn move_particle(p: Position, v: Velocity, dt: f32) -> Position {
+  return update_position(p, v, dt, S.world.xy, S.world.zw, u32(S.damp_wrap_color[1]));
+}
+
+// Damping and color of the velocity that ends a step
+fn end_step(id: u32, v: Velocity) -> Velocity {
+  // Treat damping as velocity retention per simulated second.
+  let v_out = v * pow(S.damp_wrap_color[0], step_dt());
+
+  // Update color based on speed
+  let cspd = S.damp_wrap_color[2]; // 0 or 1
+  color[id] = mix(color[id], compute_color(v_out), cspd);
+  return v_out;
+}
+
+fn yoshida_drift(stage: u32) -> f32 {
+  if (stage == 0u || stage == 3u) {
+    return 0.5 * YOSHIDA_W1;
+  }
+  return 0.5 * (YOSHIDA_W0 + YOSHIDA_W1);
+}
+
+fn yoshida_kick(stage: u32) -> f32 {
+  return select(YOSHIDA_W1, YOSHIDA_W0, stage == 2u);
+}
+
+// Pointer force field, falls linearly from its strength at the pointer to 0 at its radius
+fn brush_acceleration(p: Position) -> Acceleration {
+  let strength = S.brush[2];
+  let radius = S.brush[3];
+  if (strength == 0.0) {
+    return Acceleration(0.0, 0.0);
+  }
+
+  var delta = S.brush.xy - p;
+  if (u32(S.damp_wrap_color[1]) == 1u) {
+    delta = wrapped_delta(delta, S.world.zw - S.world.xy);
+  }
+  let dist2 = dot(delta, delta);
+  if (dist2 >= radius * radius) {
+    return Acceleration(0.0, 0.0);
+  }
+  // Softened direction, so particles under the pointer do not get kicked around
+  let soft = S.dt_g_soft_n[2];
+  return strength * (1.0 - sqrt(dist2) / radius) * delta * inverseSqrt(dist2 + soft * soft);
+}
+
+// One stage of the selected integrator, shared by all solvers, writes the new state
+// of particle `id` from the solver acceleration `solver_acc` at its current position `inP`
+// and the accelerations left in `scratch` by `bond_forces` (bonds.wgsl) and
+// `fluid_forces` (fluid.wgsl)
+fn integrate(id: u32, inP: Position, solver_acc: Acceleration) {
+  var acc = solver_acc + brush_acceleration(inP) + scratch[SCRATCH_STRIDE * id + 2u].xy;
+  if (S.fluid[0] > 0.0) {
+    acc += scratch[SCRATCH_STRIDE * id + 3u].xy;
+  }
+  let dt = step_dt();
+  let stage = timestep.stage;
+  let v = velocity_read[id];
+
+  // The step start is kept for RK4 and for the adaptive time step
+  var start = vec4<f32>(inP, v);
+  if (stage == 0u) {
+    scratch[SCRATCH_STRIDE * id] = start;
+  } else {
+    start = scratch[SCRATCH_STRIDE * id];
+  }
+
+  var p_new = inP;
+  var v_new = v;
+  switch (u32(S.timestep[3])) {
+    case VELOCITY_VERLET: {
+      // Stage 0: half kick and drift, stage 1: half kick at the new position
+      v_new = v + acc * (0.5 * dt);
+      if (stage == 0u) {
+        p_new = move_particle(inP, v_new, dt);
+      } else {
+        v_new = end_step(id, v_new);
+      }
+    }
+    case RK4: {
+      // Stage i evaluates k_i = (v_i, a_i) and sets up the state of stage i + 1
+      var sum = vec4<f32>(v, acc) * select(2.0, 1.0, stage == 0u || stage == 3u);
+      if (stage > 0u) {
+        sum += scratch[SCRATCH_STRIDE * id + 1u];
+      }
+      if (stage < 3u) {
+        scratch[SCRATCH_STRIDE * id + 1u] = sum;
+        let h = select(0.5 * dt, dt, stage == 2u);
+        p_new = move_particle(start.xy, v, h);
+        v_new = start.zw + acc * h;
+      } else {
+        p_new = move_particle(start.xy, sum.xy, dt / 6.0);
+        v_new = end_step(id, start.zw + sum.zw * (dt / 6.0));
+      }
+    }
+    case YOSHIDA4: {
+      // Drift, then three kick-drift stages
+      if (stage > 0u) {
+        v_new = v + acc * (yoshida_kick(stage) * dt);
+      }
+      if (stage == 3u) {
+        v_new = end_step(id, v_new);
+      }
+      p_new = move_particle(inP, v_new, yoshida_drift(stage) * dt);
+    }
+    case LEAPFROG, default: {
+      // after a reset: v0; else: v_{n-1/2}
+      v_new = end_step(id, v + acc * step_kick());
+      p_new = move_particle(inP, v_new, dt);
+    }
+  }
+
+  // Store results
+  position_write[id] = p_new;
+  velocity_write[id] = v_new;
+}
+
+// Stages that evaluate no force, the first drift of Yoshida, and the stages of the
+// fluid without self-gravity
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn drift(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  if (id >= u32(S.dt_g_soft_n[3])) { return; }
+  integrate(id, position_read[id], Acceleration(0.0, 0.0));
+}
+
 @compute @workgroup_size(WORKGROUP_SIZE)
 fn update(
   @builtin(global_invocation_id) gid: vec3<u32>,
@@ -114,16 +294,17 @@ fn update(
   let inP : Position = position_read[id];
 
   // Load parameters
-  let dt = S.dt_g_soft_n[0];
-  let g = S.dt_g_soft_n[1];
+  let g = S.coulomb[0];
   let soft = S.dt_g_soft_n[2];
   let world_min = S.world.xy;
   let world_max = S.world.zw;
   let world_size = world_max - world_min;
-  let damp = S.damp_wrap_color_bootstrap[0];
-  let wrap = u32(S.damp_wrap_color_bootstrap[1]);
-  let cspd = S.damp_wrap_color_bootstrap[2]; // 0 or 1
-  let bootstrap = u32(S.damp_wrap_color_bootstrap[3]); // 0 or 1
+  let wrap = u32(S.damp_wrap_color[1]);
+
+  // Coulomb force per unit mass of particle `id`, 0 without the Coulomb interaction
+  let k = S.coulomb[1];
+  let coulomb = select(0.0, k * charge[id] / mass[id], k != 0.0);
+  let coulomb_soft2 = S.coulomb[2] * S.coulomb[2];
 
   var acc : Acceleration = Acceleration(0.0, 0.0);
   var base : u32 = 0u;
@@ -136,6 +317,8 @@ fn update(
     let j = base + lid.x;
     if (j < n) {
       pos_tile[lid.x] = position_read[j];   // one coalesced load per lane
+      mass_tile[lid.x] = mass[j];
+      charge_tile[lid.x] = charge[j];
     }
     workgroupBarrier();
 
@@ -153,35 +336,18 @@ fn update(
       let dist2 = dot(delta, delta) + soft2; // add softening term to avoid singularity
       let invd  = inverseSqrt(dist2);
       let invd3 = invd * invd * invd;
-      acc += g * delta * invd3;
+      acc += g * mass_tile[k] * delta * invd3;
+
+      // Like charges repel, opposite charges attract
+      if (coulomb != 0.0) {
+        let cinvd = inverseSqrt(dot(delta, delta) + coulomb_soft2);
+        acc -= coulomb * charge_tile[k] * delta * (cinvd * cinvd * cinvd);
+      }
     }
     workgroupBarrier();
 
     base += TILE;
   }
 
-
-  var v_half = velocity_read[id];   // if bootstrap: this is v0; else: v_{n-1/2}
-
-  if (bootstrap == 1u) {
-    v_half = v_half + acc * (0.5 * dt);   // one-time half-kick: v_{+1/2} from v0
-  } else {
-    v_half = v_half + acc * dt;           // normal leapfrog kick
-  }
-
-  // Treat damping as velocity retention per simulated second.
-  let damp_step = pow(damp, dt);
-  v_half *= damp_step;
-
-  let p_new = update_position(inP, v_half, dt, world_min, world_max, wrap);
-
-  // Update color based on speed
-  let c = compute_color(v_half);
-  color[id] = mix(color[id], c, cspd); // 0 or 1
-
-  // Store results
-  position_write[id] = p_new;
-  velocity_write[id] = v_half;
-
-  return;
+  integrate(id, inP, acc);
 }
@@ -0,0 +1,206 @@
+// Particle-Mesh solver
+//
+// Appended to nbody.wgsl, `update_particle_mesh` takes the place of the direct-sum
+// `update` and passes the mesh acceleration to its `integrate`.
+// Mass is deposited on a periodic grid with cloud-in-cell weights, gathered by each cell
+// from the particles sorted by the lower corner of their stencil (radix_sort.wgsl) so the
+// sums stay in f32, convolved with the softened Green's function in frequency space (radix-2 Stockham FFT, one dispatch per
+// stage), differentiated, and interpolated back to the particles with the same weights.
+// The grid size comes from the uniform and must be a power of two.
+
+const PI : f32 = 3.14159265358979;
+
+// Bindings 0..2 and 24..25 of group 1 belong to the Barnes-Hut solver
+@group(1) @binding(3) var<storage, read_write> pm_ranges : array<u32>; // (start, end) in `pm_particles` per cell
+@group(1) @binding(4) var<storage, read> pm_src : array<vec2<f32>>;
+@group(1) @binding(5) var<storage, read_write> pm_dst : array<vec2<f32>>;
+@group(1) @binding(6) var<storage, read> pm_green : array<vec2<f32>>;
+@group(1) @binding(7) var<storage, read_write> pm_acc : array<vec2<f32>>;
+@group(1) @binding(8) var<uniform> fft_stage : vec4<u32>; // (half butterfly span, axis, inverse, 0)
+@group(1) @binding(26) var<storage, read_write> pm_keys : array<u32>;      // lower stencil cell of each particle, then sorted
+@group(1) @binding(27) var<storage, read_write> pm_particles : array<u32>; // particle indices, then sorted by lower stencil cell
+
+struct Cic {
+  cells: vec4<u32>,   // flat indices of the 4 surrounding cells
+  weights: vec4<f32>,
+};
+
+fn pm_grid_size() -> u32 {
+  return u32(S.solver[2]);
+}
+
+fn cmul(a: vec2<f32>, b: vec2<f32>) -> vec2<f32> {
+  return vec2<f32>(a.x * b.x - a.y * b.y, a.x * b.y + a.y * b.x);
+}
+
+// Lower cell of the cloud-in-cell stencil of `p`, and the weights of the upper cells
+struct CicBase {
+  cell: vec2<u32>,
+  upper: vec2<f32>,
+};
+
+fn pm_cic_base(p: Position, world_min: vec2<f32>, cell_size: vec2<f32>, grid: u32) -> CicBase {
+  let u = (p - world_min) / cell_size - vec2<f32>(0.5);
+  let base = floor(u);
+
+  let g = i32(grid);
+  var out: CicBase;
+  out.cell = vec2<u32>(((vec2<i32>(base) % g) + g) % g);
+  out.upper = u - base;
+  return out;
+}
+
+fn pm_cic(p: Position, world_min: vec2<f32>, cell_size: vec2<f32>, grid: u32) -> Cic {
+  let base = pm_cic_base(p, world_min, cell_size, grid);
+  let f = base.upper;
+  let i0 = base.cell;
+  let i1 = (i0 + vec2<u32>(1u)) % grid;
+
+  var out: Cic;
+  out.cells = vec4<u32>(i0.y * grid + i0.x, i0.y * grid + i1.x, i1.y * grid + i0.x, i1.y * grid + i1.x);
+  out.weights = vec4<f32>((1.0 - f.x) * (1.0 - f.y), f.x * (1.0 - f.y), (1.0 - f.x) * f.y, f.x * f.y);
+  return out;
+}
+
+// Key every particle with the lower cell of its stencil
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn pm_assign(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (id >= n) { return; }
+
+  let grid = pm_grid_size();
+  let world_min = S.world.xy;
+  let cell_size = (S.world.zw - world_min) / f32(grid);
+
+  let cell = pm_cic_base(position_read[id], world_min, cell_size, grid).cell;
+  pm_keys[id] = cell.y * grid + cell.x;
+  pm_particles[id] = id;
+}
+
+// Every cell starts at the first of its sorted keys and ends after the last one,
+// `pm_ranges` is cleared beforehand so empty cells stay empty
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn pm_bounds(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let i = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+  if (i >= n) { return; }
+
+  let key = pm_keys[i];
+  if (i == 0u || pm_keys[i - 1u] != key) {
+    pm_ranges[2u * key] = i;
+  }
+  if (i + 1u == n || pm_keys[i + 1u] != key) {
+    pm_ranges[2u * key + 1u] = i + 1u;
+  }
+}
+
+// Gather the mass of the particles whose stencil covers each cell into a complex field,
+// they have their lower cell at most one cell below and to the left
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn pm_load(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let grid = pm_grid_size();
+  let idx = gid.x;
+  if (idx >= grid * grid) { return; }
+
+  let world_min = S.world.xy;
+  let cell_size = (S.world.zw - world_min) / f32(grid);
+  let cell = vec2<u32>(idx % grid, idx / grid);
+
+  var density = 0.0;
+  for (var k: u32 = 0u; k < 4u; k = k + 1u) {
+    // Offset of the lower cell, this cell is its upper neighbor along the set axes
+    let offset = vec2<u32>(k % 2u, k / 2u);
+    let lower = (cell + vec2<u32>(grid) - offset) % grid;
+    let key = lower.y * grid + lower.x;
+    for (var i = pm_ranges[2u * key]; i < pm_ranges[2u * key + 1u]; i = i + 1u) {
+      let id = pm_particles[i];
+      let upper = pm_cic_base(position_read[id], world_min, cell_size, grid).upper;
+      let w = select(vec2<f32>(1.0) - upper, upper, offset == vec2<u32>(1u));
+      density += mass[id] * w.x * w.y;
+    }
+  }
+  pm_dst[idx] = vec2<f32>(density, 0.0);
+}
+
+fn pm_index(line: u32, i: u32, axis: u32, grid: u32) -> u32 {
+  if (axis == 0u) {
+    return line * grid + i;
+  }
+  return i * grid + line;
+}
+
+// One radix-2 Stockham stage along rows (axis 0) or columns (axis 1)
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn pm_fft(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let grid = pm_grid_size();
+  let half = grid / 2u;
+  if (gid.x >= grid * half) { return; }
+
+  let line = gid.x / half;
+  let j = gid.x % half;
+  let span = fft_stage.x;
+  let axis = fft_stage.y;
+  let sign = select(-1.0, 1.0, fft_stage.z == 1u);
+
+  let k = j % span;
+  let angle = sign * PI * f32(k) / f32(span);
+  let v0 = pm_src[pm_index(line, j, axis, grid)];
+  let v1 = cmul(pm_src[pm_index(line, j + half, axis, grid)], vec2<f32>(cos(angle), sin(angle)));
+
+  let out = (j / span) * span * 2u + k;
+  pm_dst[pm_index(line, out, axis, grid)] = v0 + v1;
+  pm_dst[pm_index(line, out + span, axis, grid)] = v0 - v1;
+}
+
+// Multiply by the Green's function spectrum, folding in g and the inverse FFT scale
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn pm_convolve(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let grid = pm_grid_size();
+  let idx = gid.x;
+  if (idx >= grid * grid) { return; }
+
+  let g = S.dt_g_soft_n[1];
+  pm_dst[idx] = cmul(pm_src[idx], pm_green[idx]) * (g / f32(grid * grid));
+}
+
+// Acceleration field as minus the central difference of the potential
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn pm_gradient(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let grid = pm_grid_size();
+  let idx = gid.x;
+  if (idx >= grid * grid) { return; }
+
+  let cell_size = (S.world.zw - S.world.xy) / f32(grid);
+  let x = idx % grid;
+  let y = idx / grid;
+  let xp = (x + 1u) % grid;
+  let xm = (x + grid - 1u) % grid;
+  let yp = (y + 1u) % grid;
+  let ym = (y + grid - 1u) % grid;
+
+  let ax = -(pm_src[y * grid + xp].x - pm_src[y * grid + xm].x) / (2.0 * cell_size.x);
+  let ay = -(pm_src[yp * grid + x].x - pm_src[ym * grid + x].x) / (2.0 * cell_size.y);
+  pm_acc[idx] = vec2<f32>(ax, ay);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn update_particle_mesh(@builtin(global_invocation_id) gid: vec3<u32>) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]); // Number of particles
+  if (id >= n) { return; }
+
+  let grid = pm_grid_size();
+  let world_min = S.world.xy;
+  let cell_size = (S.world.zw - world_min) / f32(grid);
+
+  let inP : Position = position_read[id];
+  let stencil = pm_cic(inP, world_min, cell_size, grid);
+
+  var acc : Acceleration = Acceleration(0.0, 0.0);
+  for (var k: u32 = 0u; k < 4u; k = k + 1u) {
+    acc += pm_acc[stencil.cells[k]] * stencil.weights[k];
+  }
+
+  integrate(id, inP, acc);
+}
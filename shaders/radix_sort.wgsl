@@ -0,0 +1,128 @@
+// Radix sort of key/value pairs
+//
+// Stand-alone module like edit.wgsl, it does not share the bindings of nbody.wgsl.
+// The workgroup scan comes from scan.wgsl.
+// Each pass orders the pairs by one RADIX_BITS digit of the keys, from the lowest, and
+// keeps the order of equal digits, so four passes sort 32-bit keys stably. The pairs are
+// split into tiles of one workgroup: `sort_count` counts the digits of each tile,
+// `sort_scan` and `sort_blocks` turn the counts, stored digit by digit, into the first
+// output entry of each digit of each tile, and `sort_scatter` moves the pairs there,
+// ranked by their index among the equal digits of the tile.
+
+const WORKGROUP_SIZE : u32 = __WORKGROUP_SIZE__; // Set at compile time
+
+const RADIX_BITS : u32 = 8u; // see src/gpu/sort.rs
+const RADIX : u32 = 1u << RADIX_BITS;
+const NO_DIGIT : u32 = RADIX; // lanes past the last pair
+
+struct Sort {
+  n: u32,
+  shift: u32,  // lowest bit of the digit of this pass
+  tiles: u32,  // workgroups covering the n pairs
+  chunks: u32, // workgroups covering the RADIX * tiles counts
+}
+
+@group(0) @binding(0) var<uniform> P : Sort;
+@group(0) @binding(1) var<storage, read> keys_in : array<u32>;
+@group(0) @binding(2) var<storage, read> values_in : array<u32>;
+@group(0) @binding(3) var<storage, read_write> keys_out : array<u32>;
+@group(0) @binding(4) var<storage, read_write> values_out : array<u32>;
+@group(0) @binding(5) var<storage, read_write> counts : array<u32>; // per digit, then per tile
+@group(0) @binding(6) var<storage, read_write> blocks : array<u32>; // per workgroup of counts, then the total
+
+var<workgroup> sort_histogram : array<atomic<u32>, RADIX>;
+var<workgroup> sort_digits : array<u32, WORKGROUP_SIZE>;
+
+fn sort_digit(key: u32) -> u32 {
+  return (key >> P.shift) & (RADIX - 1u);
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn sort_count(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(local_invocation_id)  lid: vec3<u32>,
+  @builtin(workgroup_id)         wid: vec3<u32>
+) {
+  for (var d = lid.x; d < RADIX; d = d + WORKGROUP_SIZE) {
+    atomicStore(&sort_histogram[d], 0u);
+  }
+  workgroupBarrier();
+  if (gid.x < P.n) {
+    atomicAdd(&sort_histogram[sort_digit(keys_in[gid.x])], 1u);
+  }
+  workgroupBarrier();
+  for (var d = lid.x; d < RADIX; d = d + WORKGROUP_SIZE) {
+    counts[d * P.tiles + wid.x] = atomicLoad(&sort_histogram[d]);
+  }
+}
+
+// Exclusive prefix sum of the counts within each workgroup of them
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn sort_scan(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(local_invocation_id)  lid: vec3<u32>,
+  @builtin(workgroup_id)         wid: vec3<u32>
+) {
+  let total = RADIX * P.tiles;
+  var count = 0u;
+  if (gid.x < total) {
+    count = counts[gid.x];
+  }
+  let inclusive = scan_workgroup(lid.x, count);
+  if (gid.x < total) {
+    counts[gid.x] = inclusive - count;
+  }
+  if (lid.x == WORKGROUP_SIZE - 1u) {
+    blocks[wid.x] = inclusive;
+  }
+}
+
+// Single workgroup, turns the workgroup totals into their exclusive prefix sum
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn sort_blocks(@builtin(local_invocation_id) lid: vec3<u32>) {
+  var carry = 0u;
+  for (var base = 0u; base < P.chunks; base = base + WORKGROUP_SIZE) {
+    let i = base + lid.x;
+    var count = 0u;
+    if (i < P.chunks) {
+      count = blocks[i];
+    }
+    let inclusive = scan_workgroup(lid.x, count);
+    if (i < P.chunks) {
+      blocks[i] = carry + inclusive - count;
+    }
+    carry = carry + scan_total();
+    workgroupBarrier(); // the next chunk overwrites `scan_values`
+  }
+  if (lid.x == 0u) {
+    blocks[P.chunks] = carry;
+  }
+}
+
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn sort_scatter(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(local_invocation_id)  lid: vec3<u32>,
+  @builtin(workgroup_id)         wid: vec3<u32>
+) {
+  let id = gid.x;
+  var digit = NO_DIGIT;
+  if (id < P.n) { // every lane must reach the barrier, no early return
+    digit = sort_digit(keys_in[id]);
+  }
+  sort_digits[lid.x] = digit;
+  workgroupBarrier();
+  if (id >= P.n) {
+    return;
+  }
+
+  // Equal digits earlier in the tile go first, which keeps the sort stable
+  var rank = 0u;
+  for (var j = 0u; j < lid.x; j = j + 1u) {
+    rank = rank + u32(sort_digits[j] == digit);
+  }
+  let slot = digit * P.tiles + wid.x;
+  let dest = blocks[slot / WORKGROUP_SIZE] + counts[slot] + rank;
+  keys_out[dest] = keys_in[id];
+  values_out[dest] = values_in[id];
+}
@@ -4,6 +4,52 @@ alias Color = vec4<f32>;
 
 @group(0) @binding(0) var<storage, read> position : array<Particle>;
 @group(0) @binding(1) var<storage, read> color : array<Color>;
+@group(0) @binding(2) var<storage, read> mass : array<f32>;
+
+struct Camera {
+    center_scale: vec4<f32>, // (center.x, center.y, scale.x, scale.y)
+};
+
+@group(0) @binding(3) var<uniform> camera : Camera;
+
+// Leading fields of `Sim` in nbody.wgsl
+struct Sim {
+    dt_g_soft_n: vec4<f32>,
+    damp_wrap_color: vec4<f32>, // (damping, wrap, color by speed, color by charge)
+    world: vec4<f32>,           // (min x, min y, max x, max y)
+};
+
+// Same layout as in bonds.wgsl
+struct Bond {
+    i: u32,
+    j: u32,
+    rest: f32,
+    stiffness: f32,
+    damping: f32,
+};
+
+@group(0) @binding(4) var<storage, read> charge : array<f32>;
+@group(0) @binding(5) var<uniform> S : Sim;
+@group(0) @binding(6) var<storage, read> bonds : array<Bond>;
+
+// Red for positive charges, blue for negative ones, gray when neutral
+fn charge_color(q: f32) -> Color {
+    let t = clamp(abs(q), 0.0, 1.0);
+    let tint = select(vec3<f32>(0.2, 0.4, 1.0), vec3<f32>(1.0, 0.3, 0.2), q > 0.0);
+    return Color(mix(vec3<f32>(0.5), tint, t), 1.0);
+}
+
+// Color of particle `idx`, or of its charge when coloring by charge
+fn particle_color(idx: u32) -> Color {
+    if (S.damp_wrap_color[3] > 0.5) {
+        return charge_color(charge[idx]);
+    }
+    return color[idx];
+}
+
+fn to_clip(p: Particle) -> ParticleExt {
+    return ParticleExt((p - camera.center_scale.xy) * camera.center_scale.zw, 0.0, 1.0);
+}
 
 struct VertexShaderOutput {
     @builtin(position) pos: ParticleExt,
@@ -12,12 +58,34 @@ struct VertexShaderOutput {
 
 @vertex
 fn vs_main(@builtin(vertex_index) idx: u32) -> VertexShaderOutput {    
-    let p = position[idx];
-    let c = color[idx];
+    let c = particle_color(idx);
+
+    // Heavier particles are drawn brighter (unit mass keeps its color)
+    let brightness = clamp(sqrt(mass[idx]), 0.5, 3.0);
+
+    var out: VertexShaderOutput;
+    out.pos = to_clip(position[idx]);
+    out.color = Color(c.rgb * brightness, c.a);
+
+    return out;
+}
+
+// Two vertices per bond, drawn faintly in the colors of its particles
+@vertex
+fn vs_bond(@builtin(vertex_index) idx: u32) -> VertexShaderOutput {
+    let bond = bonds[idx / 2u];
+    let end = select(bond.i, bond.j, idx % 2u == 1u);
+
+    // Bonds across a wrapped edge would cross the whole world, they shrink to a point
+    var p = position[end];
+    let span = abs(position[bond.j] - position[bond.i]);
+    if (S.damp_wrap_color[1] > 0.5 && any(span > 0.5 * (S.world.zw - S.world.xy))) {
+        p = position[bond.i];
+    }
 
     var out: VertexShaderOutput;
-    out.pos = ParticleExt(p, 0.0, 1.0);
-    out.color = c;
+    out.pos = to_clip(p);
+    out.color = Color(particle_color(end).rgb, 0.35);
 
     return out;
 }
@@ -0,0 +1,27 @@
+// Workgroup prefix sum
+//
+// Appended to the stand-alone modules (edit.wgsl, radix_sort.wgsl, merge.wgsl) at shader
+// creation, each of them defines WORKGROUP_SIZE.
+
+var<workgroup> scan_values : array<u32, WORKGROUP_SIZE>;
+
+// Inclusive prefix sum of `value` over the workgroup (Hillis-Steele)
+fn scan_workgroup(lane: u32, value: u32) -> u32 {
+  scan_values[lane] = value;
+  for (var stride = 1u; stride < WORKGROUP_SIZE; stride = stride * 2u) {
+    workgroupBarrier();
+    var add = 0u;
+    if (lane >= stride) {
+      add = scan_values[lane - stride];
+    }
+    workgroupBarrier();
+    scan_values[lane] = scan_values[lane] + add;
+  }
+  workgroupBarrier();
+  return scan_values[lane];
+}
+
+// Sum over the workgroup of the last `scan_workgroup`, before any lane writes again
+fn scan_total() -> u32 {
+  return scan_values[WORKGROUP_SIZE - 1u];
+}
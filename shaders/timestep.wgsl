@@ -0,0 +1,110 @@
+// Time step selection
+//
+// Appended to nbody.wgsl, it updates the `timestep` state declared there.
+// After every step `ts_partial` finds the largest acceleration and speed of each
+// workgroup, the acceleration being recovered from the velocity change over the step so
+// the solvers need no extra output. `ts_total` then reduces the workgroup maxima, picks the
+// length of the next step and advances the simulated time, all without a CPU round trip.
+// With a fixed time step only `ts_total` runs, to keep the step bookkeeping.
+// Between the stages of multi-stage integrators `ts_stage` advances the stage index.
+
+// Bindings 0..10 of group 1 belong to the solvers and the diagnostics
+@group(1) @binding(11) var<storage, read_write> ts_partials : array<vec2<f32>>; // (max |a|, max |v|) per workgroup
+
+var<workgroup> ts_max : array<vec2<f32>, WORKGROUP_SIZE>;
+
+// Tree reduction of `ts_max` into its first entry
+fn ts_reduce_workgroup(lane: u32) {
+  for (var stride = WORKGROUP_SIZE / 2u; stride > 0u; stride = stride / 2u) {
+    workgroupBarrier();
+    if (lane < stride) {
+      ts_max[lane] = max(ts_max[lane], ts_max[lane + stride]);
+    }
+  }
+  workgroupBarrier();
+}
+
+// Runs with the bind group of the step that was just encoded, before `ts_total`
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn ts_partial(
+  @builtin(global_invocation_id) gid: vec3<u32>,
+  @builtin(local_invocation_id)  lid: vec3<u32>,
+  @builtin(workgroup_id)         wid: vec3<u32>
+) {
+  let id = gid.x;
+  let n = u32(S.dt_g_soft_n[3]);
+
+  var m = vec2<f32>(0.0);
+  if (id < n) { // every lane must reach the barriers, no early return
+    let v_new = velocity_write[id];
+    // Undo the damping of `end_step` to get the velocity change back, `scratch` holds
+    // the velocity at the start of the step
+    let damp_step = pow(S.damp_wrap_color[0], step_dt());
+    var kick = step_dt();
+    if (u32(S.timestep[3]) == LEAPFROG) {
+      kick = step_kick();
+    }
+    let acc = (v_new / damp_step - scratch[SCRATCH_STRIDE * id].zw) / kick;
+    m = vec2<f32>(length(acc), length(v_new));
+  }
+  ts_max[lid.x] = m;
+
+  ts_reduce_workgroup(lid.x);
+
+  if (lid.x == 0u) {
+    ts_partials[wid.x] = ts_max[0];
+  }
+}
+
+// Single invocation, between two stages of a step
+@compute @workgroup_size(1)
+fn ts_stage() {
+  timestep.stage += 1u;
+}
+
+// Single workgroup, picks the next step from the maxima of `ts_partial`
+@compute @workgroup_size(WORKGROUP_SIZE)
+fn ts_total(@builtin(local_invocation_id) lid: vec3<u32>) {
+  let mode = u32(S.timestep[0]);
+  let n = u32(S.dt_g_soft_n[3]);
+  let groups = select(0u, (n + WORKGROUP_SIZE - 1u) / WORKGROUP_SIZE, mode != 0u);
+
+  var m = vec2<f32>(0.0);
+  for (var i = lid.x; i < groups; i += WORKGROUP_SIZE) {
+    m = max(m, ts_partials[i]);
+  }
+  ts_max[lid.x] = m;
+
+  ts_reduce_workgroup(lid.x);
+
+  if (lid.x == 0u) {
+    let dt_max = S.dt_g_soft_n[0];
+    let dt_min = S.timestep[2];
+    let accuracy = S.timestep[1];
+    let soft = S.dt_g_soft_n[2];
+    let max_acc = ts_max[0].x;
+    let max_speed = ts_max[0].y;
+
+    // Without any motion the largest step is taken
+    var next = dt_max;
+    if (mode == 1u && max_acc > 0.0) {
+      next = accuracy * sqrt(soft / max_acc);
+    } else if (mode == 2u && max_speed > 0.0) {
+      next = accuracy * soft / max_speed;
+    }
+    if (!(next >= dt_min)) { // also catches NaN
+      next = dt_min;
+    }
+    next = min(next, max(dt_max, dt_min));
+
+    // Kahan summation keeps the time accurate over long runs
+    let dt = step_dt();
+    let y = dt - timestep.time_error;
+    let t = timestep.time + y;
+    timestep.time_error = (t - timestep.time) - y;
+    timestep.time = t;
+    timestep.prev_dt = dt;
+    timestep.dt = next;
+    timestep.stage = 0u;
+  }
+}
@@ -0,0 +1,59 @@
+// Tone mapping of the accumulated particle density
+//
+// The particles are first drawn additively into an Rgba16Float target, so overlapping
+// particles sum instead of saturating. This pass maps the summed color to the output
+// (the sRGB surface or the recorded frame) with one fullscreen triangle.
+
+const LINEAR : u32 = 0u;
+const LOG : u32 = 1u;
+const ACES : u32 = 2u;
+const ASINH : u32 = 3u;
+
+// Exposed value mapped to white by the log and asinh curves
+const WHITE : f32 = 100.0;
+
+struct ToneMap {
+    exposure: f32,
+    curve: u32,
+    _pad: vec2<u32>,
+};
+
+@group(0) @binding(0) var density : texture_2d<f32>;
+@group(0) @binding(1) var<uniform> T : ToneMap;
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) idx: u32) -> @builtin(position) vec4<f32> {
+    // Covers the viewport with (-1, -1), (3, -1) and (-1, 3)
+    let uv = vec2<f32>(f32((idx << 1u) & 2u), f32(idx & 2u));
+    return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+}
+
+// Narkowicz's fit of the ACES filmic curve
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    return (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14);
+}
+
+fn tone_map(x: vec3<f32>) -> vec3<f32> {
+    let y = max(x * T.exposure, vec3<f32>(0.0));
+    switch T.curve {
+        case LOG: {
+            return log(1.0 + y) / log(1.0 + WHITE);
+        }
+        case ACES: {
+            return aces(y);
+        }
+        case ASINH: {
+            // asinh(y) = ln(y + sqrt(y² + 1)), linear near 0 and logarithmic above 1
+            return log(y + sqrt(y * y + 1.0)) / log(WHITE + sqrt(WHITE * WHITE + 1.0));
+        }
+        default: {
+            return y;
+        }
+    }
+}
+
+@fragment
+fn fs_tonemap(@builtin(position) pos: vec4<f32>) -> @location(0) vec4<f32> {
+    let color = textureLoad(density, vec2<i32>(pos.xy), 0).rgb;
+    return vec4<f32>(clamp(tone_map(color), vec3<f32>(0.0), vec3<f32>(1.0)), 1.0);
+}
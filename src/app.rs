@@ -1,25 +1,224 @@
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
-use winit::{application::ApplicationHandler, event::WindowEvent, window::Window};
+use glam::Vec2;
+use winit::{
+    application::ApplicationHandler,
+    dpi::PhysicalSize,
+    event::{ElementState, MouseButton, MouseScrollDelta, WindowEvent},
+    keyboard::{Key, NamedKey},
+    window::Window,
+};
 
-use crate::{constants, gpu};
+use crate::{
+    constants,
+    gpu::{self, RecorderSettings, RenderSettings},
+    sim::{SimParams, scenario_file::ScenarioFile},
+};
+
+/// Mouse state needed to drive the camera between events
+#[derive(Default)]
+struct CameraInput {
+    /// Last cursor position in window pixels
+    cursor: Vec2,
+    /// Whether a pan drag is in progress
+    panning: bool,
+}
+
+impl CameraInput {
+    /// Apply a window event to the camera, returns `true` if the view changed
+    fn handle(&mut self, state: &mut gpu::State, event: &WindowEvent, consumed: bool) -> bool {
+        match event {
+            WindowEvent::CursorMoved { position, .. } => {
+                let cursor = Vec2::new(position.x as f32, position.y as f32);
+                let delta = cursor - self.cursor;
+                self.cursor = cursor;
+                if self.panning {
+                    state.camera_mut().pan_pixels(delta);
+                    return true;
+                }
+            }
+
+            WindowEvent::MouseInput {
+                state: button_state,
+                button: MouseButton::Right | MouseButton::Middle,
+                ..
+            } => {
+                // A release always ends the drag, even over egui, so it never gets stuck
+                self.panning = *button_state == ElementState::Pressed && !consumed;
+            }
+
+            WindowEvent::MouseWheel { delta, .. } if !consumed => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => {
+                        position.y as f32 / constants::camera::PIXELS_PER_WHEEL_LINE
+                    }
+                };
+                let factor = constants::camera::WHEEL_ZOOM_FACTOR.powf(lines);
+                state.camera_mut().zoom_at(self.cursor, factor);
+                return true;
+            }
+
+            WindowEvent::KeyboardInput { event, .. }
+                if !consumed && event.state == ElementState::Pressed =>
+            {
+                let pan = constants::camera::KEY_PAN_FRACTION;
+                let zoom = constants::camera::KEY_ZOOM_FACTOR;
+                let camera = state.camera_mut();
+                match event.logical_key.as_ref() {
+                    Key::Named(NamedKey::ArrowLeft) => camera.pan_fraction(Vec2::new(-pan, 0.0)),
+                    Key::Named(NamedKey::ArrowRight) => camera.pan_fraction(Vec2::new(pan, 0.0)),
+                    Key::Named(NamedKey::ArrowUp) => camera.pan_fraction(Vec2::new(0.0, pan)),
+                    Key::Named(NamedKey::ArrowDown) => camera.pan_fraction(Vec2::new(0.0, -pan)),
+                    Key::Character("+" | "=") => camera.zoom_by(zoom),
+                    Key::Character("-") => camera.zoom_by(zoom.recip()),
+                    Key::Named(NamedKey::Home) => state.reset_camera(),
+                    _ => return false,
+                }
+                return true;
+            }
+
+            _ => {}
+        }
+        false
+    }
+}
+
+/// Pointer state driving the force brush
+#[derive(Default)]
+struct BrushInput {
+    /// Whether the left button was pressed outside of egui and is still held
+    held: bool,
+    /// Whether Shift is held, reversing the force
+    shift: bool,
+}
+
+impl BrushInput {
+    /// Apply a window event to the brush, `cursor` is the latest pointer position
+    fn handle(
+        &mut self,
+        state: &mut gpu::State,
+        event: &WindowEvent,
+        consumed: bool,
+        cursor: Vec2,
+    ) {
+        match event {
+            WindowEvent::MouseInput {
+                state: button_state,
+                button: MouseButton::Left,
+                ..
+            } => {
+                // Like panning, a release always ends the stroke
+                self.held = *button_state == ElementState::Pressed && !consumed;
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.shift = modifiers.state().shift_key();
+            }
+            WindowEvent::CursorLeft { .. } => self.held = false,
+            WindowEvent::CursorMoved { .. } => {}
+            _ => return,
+        }
+        state.set_brush(self.held.then_some(cursor), self.shift);
+    }
+}
+
+/// Window and surface settings from the command line, `None` picks the default
+#[derive(Debug, Default, Clone)]
+pub struct WindowOptions {
+    /// Inner size in physical pixels
+    pub size: Option<[u32; 2]>,
+    pub present_mode: Option<wgpu::PresentMode>,
+    /// Part of the adapter name, also used by the headless modes
+    pub adapter: Option<String>,
+    /// Render mode and tone mapping, also used by headless recordings
+    pub render: RenderSettings,
+}
 
 #[derive(Default)]
 pub struct App {
     window: Option<Arc<Window>>,
     state: Option<gpu::State>,
+    /// Setup of the first simulation, consumed when the GPU state is created
+    setup: Option<ScenarioFile>,
+    /// Scenario file to watch once the GPU state exists
+    watch: Option<PathBuf>,
+    /// Snapshot to resume from once the GPU state exists
+    snapshot: Option<PathBuf>,
+    /// Recording to start once the GPU state exists
+    record: Option<RecorderSettings>,
+    window_options: WindowOptions,
+    camera_input: CameraInput,
+    brush_input: BrushInput,
+}
+
+impl App {
+    pub fn new(
+        setup: ScenarioFile,
+        watch: Option<PathBuf>,
+        snapshot: Option<PathBuf>,
+        record: Option<RecorderSettings>,
+        window_options: WindowOptions,
+    ) -> Self {
+        Self {
+            setup: Some(setup),
+            watch,
+            snapshot,
+            record,
+            window_options,
+            ..Default::default()
+        }
+    }
 }
 
 impl ApplicationHandler for App {
     fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        let mut attributes = Window::default_attributes().with_title(constants::window::TITLE);
+        if let Some([width, height]) = self.window_options.size {
+            attributes = attributes.with_inner_size(PhysicalSize::new(width, height));
+        }
         let window = Arc::new(
             event_loop
-                .create_window(Window::default_attributes().with_title(constants::window::TITLE))
+                .create_window(attributes)
                 .expect("Failed to create window"),
         );
 
-        let state = pollster::block_on(gpu::State::new(window.clone(), true))
-            .expect("Failed to create GPU state");
+        let setup = self.setup.take().unwrap_or_default();
+        let mut params = SimParams::default();
+        setup.sim.apply(&mut params);
+        let mut state = match pollster::block_on(gpu::State::new(
+            window.clone(),
+            true,
+            params,
+            self.window_options.present_mode,
+            self.window_options.adapter.as_deref(),
+        )) {
+            Ok(state) => state,
+            Err(err) => {
+                log::error!("Failed to create GPU state: {err:#}");
+                event_loop.exit();
+                return;
+            }
+        };
+
+        state.set_render_settings(self.window_options.render.clone());
+        if let Err(err) = state.apply_scenario_file(&setup, setup.customizes_particles()) {
+            log::error!("{err:#}");
+        }
+        if let Some(path) = self.watch.take() {
+            state.start_watching(&path, setup);
+        }
+
+        if let Some(path) = self.snapshot.take()
+            && let Err(err) = state.load_snapshot(&path)
+        {
+            log::error!("{err:#}");
+        }
+
+        if let Some(settings) = self.record.take()
+            && let Err(err) = state.start_recording(settings)
+        {
+            log::error!("Failed to start recording: {err:#}");
+        }
 
         self.window = Some(window);
         self.state = Some(state);
@@ -52,9 +251,19 @@ impl ApplicationHandler for App {
             window.request_redraw();
         }
 
+        // Pan, zoom and brush with whatever egui did not use
+        if self.camera_input.handle(state, &event, response.consumed) {
+            window.request_redraw();
+        }
+        self.brush_input
+            .handle(state, &event, response.consumed, self.camera_input.cursor);
+
         match event {
             WindowEvent::CloseRequested => {
                 log::info!("Window close requested, terminating application");
+                if let Err(err) = state.stop_recording() {
+                    log::error!("{err:#}");
+                }
                 event_loop.exit();
             }
 
@@ -0,0 +1,453 @@
+//! Command-line arguments
+//!
+//! Every simulation parameter can be set here, values are checked against the same
+//! ranges as the UI sliders (`constants::sim`) so a run never starts outside of them.
+
+use std::{fmt::Display, ops::RangeInclusive, path::PathBuf, str::FromStr};
+
+use crate::{
+    app::WindowOptions,
+    constants,
+    gpu::{self, RecorderSettings, RenderMode, RenderSettings, ToneMap},
+    headless,
+    sim::{
+        Interaction, ParamsOverrides, Solver, check_range,
+        integrator::Integrator,
+        scenario::{ChargeScheme, Scenarios},
+        scenario_file::ScenarioFile,
+        timestep::TimestepMode,
+    },
+};
+
+/// How the program was asked to run
+pub enum Mode {
+    /// Interactive window (default)
+    Windowed {
+        /// Scenario file and command-line values, the latter taking precedence
+        setup: ScenarioFile,
+        /// Scenario file to watch for changes
+        watch: Option<PathBuf>,
+        load: Option<PathBuf>,
+        record: Option<RecorderSettings>,
+        window: WindowOptions,
+    },
+    /// Step the simulation without a window
+    Headless {
+        steps: u32,
+        setup: ScenarioFile,
+        load: Option<PathBuf>,
+        save: Option<PathBuf>,
+        record: Option<RecorderSettings>,
+        adapter: Option<String>,
+        render: RenderSettings,
+    },
+    /// Compare the compute kernel against the CPU reference
+    Validate {
+        steps: u32,
+        solver: Solver,
+        seed: u64,
+        adapter: Option<String>,
+    },
+    /// Print the usage and exit
+    Help,
+}
+
+pub const USAGE: &str = "\
+Usage: particle_playground [OPTIONS]
+
+Modes:
+  --headless                 Step the simulation without a window
+  --validate                 Compare the GPU kernels against the CPU references, only
+                             --steps, --solver, --seed and --adapter apply
+  --steps N                  Steps run by --headless or --validate
+  -h, --help                 Print this help
+
+Simulation:
+  -n, --particles N          Particle count
+  --dt SECONDS               Time step, the largest one when adaptive
+  --substeps N               Compute steps per rendered frame
+  --timestep MODE            fixed, acceleration or velocity (adaptive modes)
+  --accuracy VALUE           Safety factor of the adaptive time step
+  --integrator NAME          leapfrog, velocity-verlet, rk4 or yoshida4
+  --g VALUE                  Gravitational constant
+  --softening VALUE          Softening length
+  --interaction NAME         gravity, coulomb or gravity-and-coulomb (direct solver only)
+  --coulomb VALUE            Coulomb constant
+  --coulomb-softening VALUE  Softening length of the Coulomb force
+  --damping VALUE            Velocity retention per simulated second
+  --merge-radius VALUE       Merge particles that come closer than VALUE
+  --wrap, --no-wrap          Wrap the world around at the edges
+  --solver NAME              direct, barnes-hut or particle-mesh
+  --theta VALUE              Barnes-Hut opening angle
+  --pm-grid CELLS            Particle-Mesh cells per side
+  --seed N                   Seed of the initial conditions
+  --scenario ID              Initial conditions generator
+  --scenario-file PATH       TOML scenario file, reapplied when it changes in the window
+  --charges SCHEME           neutral, alternating or random particle charges
+  --charge VALUE             Magnitude of the particle charges
+  --color-by-speed           Color the particles by speed
+  --color-by-charge          Color the particles by charge
+  --hide-bonds               Do not draw the bonds of the cloth and rope scenarios
+  --paused, --running        Start the window paused or running
+
+Window:
+  --window-size WIDTHxHEIGHT Initial inner size of the window
+  --present-mode MODE        auto-vsync, auto-no-vsync, fifo, fifo-relaxed, mailbox or immediate
+  --adapter NAME             Use the first adapter whose name contains NAME (also headless)
+
+Rendering:
+  --render-mode MODE         blended or density (additive, tone mapped), also recorded
+  --tone-map NAME            linear, log, aces or asinh, for the density mode
+  --exposure VALUE           Scale of the density before tone mapping
+
+Files:
+  --load PATH                Resume from a snapshot
+  --save PATH                Write the final state of a headless run
+  --record PATH              Record PNG frames into a directory, or a .y4m video
+  --record-interval SECONDS  Simulated time between two recorded frames
+";
+
+/// Remaining arguments, with helpers to read and check option values
+struct Args(std::iter::Skip<std::env::Args>);
+
+impl Args {
+    fn value(&mut self, flag: &str) -> anyhow::Result<String> {
+        self.0
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("{flag} expects a value"))
+    }
+
+    fn parse<T>(&mut self, flag: &str) -> anyhow::Result<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = self.value(flag)?;
+        value
+            .parse()
+            .map_err(|err| anyhow::anyhow!("Invalid {flag} value '{value}': {err}"))
+    }
+
+    /// Parse a value that must lie within `range`
+    fn parse_in<T>(&mut self, flag: &str, range: RangeInclusive<T>) -> anyhow::Result<T>
+    where
+        T: FromStr + PartialOrd + Display,
+        T::Err: Display,
+    {
+        check_range(flag, self.parse(flag)?, &range)
+    }
+}
+
+fn parse_solver(value: &str) -> anyhow::Result<Solver> {
+    match value {
+        "direct" => Ok(Solver::Direct),
+        "barnes-hut" => Ok(Solver::BarnesHut),
+        "particle-mesh" => Ok(Solver::ParticleMesh),
+        _ => anyhow::bail!(
+            "Invalid --solver value '{value}', expected 'direct', 'barnes-hut' or 'particle-mesh'"
+        ),
+    }
+}
+
+fn parse_interaction(value: &str) -> anyhow::Result<Interaction> {
+    match value {
+        "gravity" => Ok(Interaction::Gravity),
+        "coulomb" => Ok(Interaction::Coulomb),
+        "gravity-and-coulomb" => Ok(Interaction::GravityAndCoulomb),
+        _ => anyhow::bail!(
+            "Invalid --interaction value '{value}', expected 'gravity', 'coulomb' or 'gravity-and-coulomb'"
+        ),
+    }
+}
+
+fn parse_charges(value: &str) -> anyhow::Result<ChargeScheme> {
+    match value {
+        "neutral" => Ok(ChargeScheme::Neutral),
+        "alternating" => Ok(ChargeScheme::Alternating),
+        "random" => Ok(ChargeScheme::Random),
+        _ => anyhow::bail!(
+            "Invalid --charges value '{value}', expected 'neutral', 'alternating' or 'random'"
+        ),
+    }
+}
+
+fn parse_timestep(value: &str) -> anyhow::Result<TimestepMode> {
+    match value {
+        "fixed" => Ok(TimestepMode::Fixed),
+        "acceleration" => Ok(TimestepMode::Acceleration),
+        "velocity" => Ok(TimestepMode::Velocity),
+        _ => anyhow::bail!(
+            "Invalid --timestep value '{value}', expected 'fixed', 'acceleration' or 'velocity'"
+        ),
+    }
+}
+
+fn parse_integrator(value: &str) -> anyhow::Result<Integrator> {
+    match value {
+        "leapfrog" => Ok(Integrator::Leapfrog),
+        "velocity-verlet" => Ok(Integrator::VelocityVerlet),
+        "rk4" => Ok(Integrator::Rk4),
+        "yoshida4" => Ok(Integrator::Yoshida4),
+        _ => anyhow::bail!(
+            "Invalid --integrator value '{value}', expected 'leapfrog', 'velocity-verlet', 'rk4' or 'yoshida4'"
+        ),
+    }
+}
+
+fn parse_render_mode(value: &str) -> anyhow::Result<RenderMode> {
+    match value {
+        "blended" => Ok(RenderMode::Blended),
+        "density" => Ok(RenderMode::Density),
+        _ => {
+            anyhow::bail!("Invalid --render-mode value '{value}', expected 'blended' or 'density'")
+        }
+    }
+}
+
+fn parse_tone_map(value: &str) -> anyhow::Result<ToneMap> {
+    match value {
+        "linear" => Ok(ToneMap::Linear),
+        "log" => Ok(ToneMap::Log),
+        "aces" => Ok(ToneMap::Aces),
+        "asinh" => Ok(ToneMap::Asinh),
+        _ => anyhow::bail!(
+            "Invalid --tone-map value '{value}', expected 'linear', 'log', 'aces' or 'asinh'"
+        ),
+    }
+}
+
+fn parse_present_mode(value: &str) -> anyhow::Result<wgpu::PresentMode> {
+    Ok(match value {
+        "auto-vsync" => wgpu::PresentMode::AutoVsync,
+        "auto-no-vsync" => wgpu::PresentMode::AutoNoVsync,
+        "fifo" => wgpu::PresentMode::Fifo,
+        "fifo-relaxed" => wgpu::PresentMode::FifoRelaxed,
+        "mailbox" => wgpu::PresentMode::Mailbox,
+        "immediate" => wgpu::PresentMode::Immediate,
+        _ => anyhow::bail!(
+            "Invalid --present-mode value '{value}', expected 'auto-vsync', 'auto-no-vsync', \
+'fifo', 'fifo-relaxed', 'mailbox' or 'immediate'"
+        ),
+    })
+}
+
+fn parse_window_size(value: &str) -> anyhow::Result<[u32; 2]> {
+    let range = constants::window::SIZE_RANGE;
+    let size = value
+        .split_once('x')
+        .and_then(|(w, h)| Some([w.parse().ok()?, h.parse().ok()?]))
+        .ok_or_else(|| {
+            anyhow::anyhow!("Invalid --window-size value '{value}', expected WIDTHxHEIGHT")
+        })?;
+    if !size.iter().all(|side| range.contains(side)) {
+        anyhow::bail!(
+            "--window-size sides must be between {} and {} pixels, got {value}",
+            range.start(),
+            range.end()
+        );
+    }
+    Ok(size)
+}
+
+pub fn parse_args() -> anyhow::Result<Mode> {
+    let mut headless = false;
+    let mut validate = false;
+    let mut steps = None;
+    let mut overrides = ParamsOverrides::default();
+    let mut scenario = None;
+    let mut scenario_file = None;
+    let mut charges = None;
+    let mut charge = None;
+    let mut load = None;
+    let mut save = None;
+    let mut record = None;
+    let mut record_interval = None;
+    let mut window = WindowOptions::default();
+
+    let mut args = Args(std::env::args().skip(1));
+    while let Some(arg) = args.0.next() {
+        let flag = arg.as_str();
+        match flag {
+            "-h" | "--help" => return Ok(Mode::Help),
+            "--headless" => headless = true,
+            "--validate" => validate = true,
+            "--steps" => steps = Some(args.parse(flag)?),
+
+            "-n" | "--particles" => {
+                overrides.n = Some(args.parse_in(flag, constants::sim::INITIAL_PARTICLES_RANGE)?)
+            }
+            "--dt" => overrides.dt = Some(args.parse_in(flag, constants::sim::DT_RANGE)?),
+            "--substeps" => {
+                overrides.substeps = Some(args.parse_in(flag, constants::sim::SUBSTEPS_RANGE)?)
+            }
+            "--timestep" => overrides.timestep = Some(parse_timestep(&args.value(flag)?)?),
+            "--accuracy" => {
+                overrides.accuracy =
+                    Some(args.parse_in(flag, constants::sim::TIMESTEP_ACCURACY_RANGE)?)
+            }
+            "--integrator" => overrides.integrator = Some(parse_integrator(&args.value(flag)?)?),
+            "--g" => overrides.g = Some(args.parse_in(flag, constants::sim::G_RANGE)?),
+            "--softening" => {
+                overrides.softening = Some(args.parse_in(flag, constants::sim::SOFTENING_RANGE)?)
+            }
+            "--interaction" => overrides.interaction = Some(parse_interaction(&args.value(flag)?)?),
+            "--coulomb" => {
+                overrides.coulomb_k = Some(args.parse_in(flag, constants::sim::COULOMB_K_RANGE)?)
+            }
+            "--coulomb-softening" => {
+                overrides.coulomb_softening =
+                    Some(args.parse_in(flag, constants::sim::SOFTENING_RANGE)?)
+            }
+            "--damping" => {
+                overrides.damping = Some(args.parse_in(flag, constants::sim::DAMPING_RANGE)?)
+            }
+            "--merge-radius" => {
+                overrides.merge_radius =
+                    Some(args.parse_in(flag, constants::sim::MERGE_RADIUS_RANGE)?);
+                overrides.collisions = Some(true);
+            }
+            "--wrap" => overrides.wrap = Some(true),
+            "--no-wrap" => overrides.wrap = Some(false),
+            "--solver" => overrides.solver = Some(parse_solver(&args.value(flag)?)?),
+            "--theta" => overrides.theta = Some(args.parse_in(flag, constants::sim::THETA_RANGE)?),
+            "--pm-grid" => {
+                let cells: u32 = args.parse(flag)?;
+                if !constants::sim::PM_GRID_OPTIONS.contains(&cells) {
+                    anyhow::bail!(
+                        "--pm-grid must be one of {:?}, got {cells}",
+                        constants::sim::PM_GRID_OPTIONS
+                    );
+                }
+                overrides.pm_grid = Some(cells);
+            }
+            "--seed" => overrides.seed = Some(args.parse(flag)?),
+            "--scenario" => {
+                let id = args.value(flag)?;
+                Scenarios::default().select(&id)?;
+                scenario = Some(id);
+            }
+            "--scenario-file" => scenario_file = Some(PathBuf::from(args.value(flag)?)),
+            "--charges" => charges = Some(parse_charges(&args.value(flag)?)?),
+            "--charge" => charge = Some(args.parse_in(flag, constants::scenario::CHARGE_RANGE)?),
+            "--color-by-speed" => overrides.color_by_speed = Some(true),
+            "--color-by-charge" => overrides.color_by_charge = Some(true),
+            "--hide-bonds" => overrides.show_bonds = Some(false),
+            "--paused" => overrides.paused = Some(true),
+            "--running" => overrides.paused = Some(false),
+
+            "--window-size" => window.size = Some(parse_window_size(&args.value(flag)?)?),
+            "--present-mode" => window.present_mode = Some(parse_present_mode(&args.value(flag)?)?),
+            "--adapter" => window.adapter = Some(args.value(flag)?),
+
+            "--render-mode" => window.render.mode = parse_render_mode(&args.value(flag)?)?,
+            "--tone-map" => window.render.tone_map = parse_tone_map(&args.value(flag)?)?,
+            "--exposure" => {
+                window.render.exposure = args.parse_in(flag, constants::render::EXPOSURE_RANGE)?
+            }
+
+            "--load" => load = Some(PathBuf::from(args.value(flag)?)),
+            "--save" => save = Some(PathBuf::from(args.value(flag)?)),
+            "--record" => {
+                record = Some(RecorderSettings::for_path(&PathBuf::from(
+                    args.value(flag)?,
+                )))
+            }
+            "--record-interval" => {
+                let interval: f32 = args.parse(flag)?;
+                if interval.is_nan() || interval <= 0.0 {
+                    anyhow::bail!("--record-interval must be positive, got {interval}");
+                }
+                record_interval = Some(interval);
+            }
+            other => anyhow::bail!("Unknown argument '{other}'"),
+        }
+    }
+
+    if save.is_some() && !headless {
+        anyhow::bail!("--save is only supported with --headless, use the UI in windowed mode");
+    }
+
+    let charged = charges.is_some() || charge.is_some();
+    if load.is_some()
+        && (overrides.n.is_some() || scenario.is_some() || scenario_file.is_some() || charged)
+    {
+        anyhow::bail!(
+            "--particles, --scenario, --scenario-file, --charges and --charge generate new \
+particles and cannot be combined with --load"
+        );
+    }
+
+    if headless || validate {
+        if window.size.is_some() || window.present_mode.is_some() {
+            anyhow::bail!("--window-size and --present-mode only apply to the window");
+        }
+        if overrides.paused.is_some() {
+            anyhow::bail!("--paused and --running only apply to the window, use --steps instead");
+        }
+    }
+
+    // The checks set up their own particles and parameters
+    let validated = ParamsOverrides {
+        solver: overrides.solver,
+        seed: overrides.seed,
+        ..ParamsOverrides::default()
+    };
+    if validate
+        && (overrides != validated
+            || scenario.is_some()
+            || scenario_file.is_some()
+            || charged
+            || load.is_some()
+            || save.is_some()
+            || record.is_some()
+            || window.render != RenderSettings::default())
+    {
+        anyhow::bail!("--validate only takes --steps, --solver, --seed and --adapter");
+    }
+
+    if let Some(interval) = record_interval {
+        let Some(settings) = &mut record else {
+            anyhow::bail!("--record-interval requires --record");
+        };
+        settings.frame_time = interval;
+    }
+
+    let mut setup = match &scenario_file {
+        Some(path) => ScenarioFile::load(path)?,
+        None => ScenarioFile::default(),
+    };
+    setup.override_with(&overrides, scenario.as_deref());
+    setup.sim.validate()?;
+    if charged {
+        let section = setup.charges.get_or_insert_default();
+        section.scheme = charges.unwrap_or(section.scheme);
+        section.magnitude = charge.unwrap_or(section.magnitude);
+    }
+
+    Ok(if validate {
+        Mode::Validate {
+            steps: steps.unwrap_or(gpu::VALIDATION_STEPS),
+            solver: overrides.solver.unwrap_or_default(),
+            seed: overrides.seed.unwrap_or(gpu::VALIDATION_SEED),
+            adapter: window.adapter,
+        }
+    } else if headless {
+        Mode::Headless {
+            steps: steps.unwrap_or(headless::DEFAULT_STEPS),
+            setup,
+            load,
+            save,
+            record,
+            adapter: window.adapter,
+            render: window.render,
+        }
+    } else {
+        Mode::Windowed {
+            setup,
+            watch: scenario_file,
+            load,
+            record,
+            window,
+        }
+    })
+}
@@ -1,5 +1,8 @@
 pub mod window {
+    use std::ops::RangeInclusive;
+
     pub const TITLE: &str = "Particle Playground";
+    pub const SIZE_RANGE: RangeInclusive<u32> = 200..=8192; // Per side, in physical pixels
 }
 
 pub mod gpu {
@@ -21,13 +24,25 @@ pub mod gpu {
     ];
 }
 
+pub mod camera {
+    use std::ops::RangeInclusive;
+
+    pub const ZOOM_RANGE: RangeInclusive<f32> = 0.1..=200.0;
+    pub const WHEEL_ZOOM_FACTOR: f32 = 1.15; // Zoom change per wheel notch
+    pub const PIXELS_PER_WHEEL_LINE: f32 = 40.0; // Touchpads report pixels instead of notches
+    pub const KEY_ZOOM_FACTOR: f32 = 1.25;
+    pub const KEY_PAN_FRACTION: f32 = 0.05; // Fraction of the visible size per key press
+}
+
 pub mod egui {
     pub const BORDER_RADIUS: egui::CornerRadius = egui::CornerRadius::same(2);
     pub const SHADOW: egui::epaint::Shadow = egui::epaint::Shadow::NONE;
+    /// Wide enough for the 20 digits of the largest seed
+    pub const SEED_WIDTH: f32 = 150.0;
 }
 
 pub mod sim {
-    use std::ops::RangeInclusive;
+    use std::ops::{Range, RangeInclusive};
 
     use glam::Vec2;
 
@@ -35,6 +50,14 @@ pub mod sim {
     pub const DT_RANGE: RangeInclusive<f32> = 0.001..=0.03;
     pub const DT_STEP: f64 = 0.0005;
 
+    pub const SUBSTEPS: u32 = 1; // Compute steps per rendered frame
+    pub const SUBSTEPS_RANGE: RangeInclusive<u32> = 1..=32;
+
+    /// Adaptive steps pick a dt between the start of `DT_RANGE` and `dt`
+    pub const TIMESTEP_ACCURACY: f32 = 0.25;
+    pub const TIMESTEP_ACCURACY_RANGE: RangeInclusive<f32> = 0.01..=2.0;
+    pub const TIMESTEP_ACCURACY_STEP: f64 = 0.01;
+
     pub const G: f32 = 1.5e-5; // Tuned for visible clustering without immediate collapse
     pub const G_RANGE: RangeInclusive<f32> = 1e-7..=5e-5;
     pub const G_STEP: f64 = 1e-7;
@@ -43,9 +66,15 @@ pub mod sim {
     pub const SOFTENING_RANGE: RangeInclusive<f32> = 0.002..=0.08;
     pub const SOFTENING_STEP: f64 = 0.001;
 
+    pub const COULOMB_K: f32 = 1.5e-5; // Unit charges of unit mass feel forces as strong as gravity
+    pub const COULOMB_K_RANGE: RangeInclusive<f32> = 1e-7..=5e-4;
+    pub const COULOMB_K_STEP: f64 = 1e-7;
+    pub const COULOMB_SOFTENING: f32 = 0.02;
+
     pub const INITIAL_PARTICLES: u32 = 100_000;
     pub const INITIAL_PARTICLES_RANGE: RangeInclusive<u32> = 10_000..=1_000_000;
     pub const INITIAL_PARTICLES_STEP: f64 = 10_000.0;
+    pub const MAX_PARTICLES: u32 = 1_000_000; // Upper bound when spawning with the brush
 
     pub const WORLD_SIZE: [Vec2; 2] = [Vec2::splat(-1.0), Vec2::splat(1.0)];
 
@@ -55,11 +84,187 @@ pub mod sim {
 
     pub const WRAP: bool = true;
     pub const COLOR_BY_SPEED: bool = false;
+    pub const COLOR_BY_CHARGE: bool = false;
+    pub const SHOW_BONDS: bool = true;
 
     pub const PAUSED: bool = true;
+
+    pub const COLLISIONS: bool = false;
+    pub const MERGE_RADIUS: f32 = 0.005; // Well inside the default softening
+    pub const MERGE_RADIUS_RANGE: RangeInclusive<f32> = 0.0005..=0.05;
+    pub const MERGE_RADIUS_STEP: f64 = 0.0005;
+
+    pub const MASS_RANGE: Range<f32> = 0.5..1.5; // Mean of 1 keeps the tuned g meaningful
+    pub const CENTRAL_MASS_FRACTION: f32 = 0.01; // Central body mass relative to its disc
+
+    pub const THETA: f32 = 0.5; // Barnes-Hut opening angle, classic accuracy/speed trade-off
+    pub const THETA_RANGE: RangeInclusive<f32> = 0.1..=1.5;
+    pub const THETA_STEP: f64 = 0.05;
+
+    pub const PM_GRID: u32 = 256; // Cell size close to the default softening
+    pub const PM_GRID_OPTIONS: &[u32] = &[64, 128, 256, 512, 1024];
+}
+
+/// Initial condition generators, lengths are fractions of the half world size
+pub mod scenario {
+    use std::ops::RangeInclusive;
+
+    pub const RADIUS_RANGE: RangeInclusive<f32> = 0.05..=0.95;
+    pub const PARTICLE_MASS: f32 = 1.0; // Equal masses for the non-galaxy scenarios
+    pub const SOLID_COLOR: [f32; 3] = [1.0, 0.85, 0.6]; // Warm white
+    pub const CHARGE: f32 = 1.0; // Magnitude of the assigned charges
+    pub const CHARGE_RANGE: RangeInclusive<f32> = 0.0..=10.0;
+
+    pub const COLLISION_SEPARATION: f32 = 0.7;
+    pub const COLLISION_SEPARATION_RANGE: RangeInclusive<f32> = 0.0..=1.5;
+    pub const COLLISION_RADIUS: f32 = 0.45;
+
+    pub const SPIRAL_ARMS: u32 = 2;
+    pub const SPIRAL_ARMS_RANGE: RangeInclusive<u32> = 1..=8;
+    pub const SPIRAL_RADIUS: f32 = 0.7;
+    pub const SPIRAL_WINDING: f32 = 6.0; // Radians turned per unit of radius
+    pub const SPIRAL_WINDING_RANGE: RangeInclusive<f32> = 0.0..=20.0;
+    pub const SPIRAL_SPREAD: f32 = 0.35; // Angular scatter around an arm, in radians
+    pub const SPIRAL_SPREAD_RANGE: RangeInclusive<f32> = 0.0..=1.5;
+
+    pub const PLUMMER_SCALE: f32 = 0.15;
+    pub const PLUMMER_SCALE_RANGE: RangeInclusive<f32> = 0.02..=0.5;
+    pub const PLUMMER_TRUNCATION: f32 = 0.95; // Outermost radius
+    pub const VIRIAL: f32 = 1.0; // Kinetic energy relative to the circular speed
+    pub const VIRIAL_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+
+    pub const COLLAPSE_RADIUS: f32 = 0.6;
+    pub const SPIN: f32 = 0.0; // Fraction of the circular speed
+    pub const SPIN_RANGE: RangeInclusive<f32> = 0.0..=1.5;
+
+    pub const BOX_HALF_SIZE: f32 = 1.0;
+    pub const BOX_DISPERSION: f32 = 0.0;
+    pub const BOX_DISPERSION_RANGE: RangeInclusive<f32> = 0.0..=0.5;
+
+    pub const LATTICE_EXTENT: f32 = 0.8;
+    pub const LATTICE_JITTER: f32 = 0.0; // Fraction of the lattice spacing
+    pub const LATTICE_JITTER_RANGE: RangeInclusive<f32> = 0.0..=0.5;
+
+    pub const RING_RADIUS: f32 = 0.5;
+    pub const RING_WIDTH: f32 = 0.02;
+    pub const RING_WIDTH_RANGE: RangeInclusive<f32> = 0.0..=0.2;
+    pub const RING_CENTRAL_MASS: f32 = 0.05; // Central body mass relative to the ring
+    pub const RING_CENTRAL_MASS_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+    pub const RING_SPIN: f32 = 1.0;
+
+    pub const BOND_STIFFNESS: f32 = 1000.0; // Unit masses oscillate in ~0.2 s, far above dt
+    pub const BOND_STIFFNESS_RANGE: RangeInclusive<f32> = 1.0..=10_000.0;
+    pub const BOND_DAMPING: f32 = 1.0;
+    pub const BOND_DAMPING_RANGE: RangeInclusive<f32> = 0.0..=20.0;
+
+    pub const CLOTH_SIZE: f32 = 0.6;
+    pub const CLOTH_SHEAR: bool = true;
+
+    pub const ROPES: u32 = 8;
+    pub const ROPES_RANGE: RangeInclusive<u32> = 1..=64;
+    pub const ROPE_LENGTH: f32 = 0.8;
+    pub const ROPE_WAVE: f32 = 0.2; // Peak transverse speed of the initial wave
+    pub const ROPE_WAVE_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+
+    pub const DAM_WIDTH: f32 = 0.4; // Fractions of the world size
+    pub const DAM_HEIGHT: f32 = 0.6;
+    pub const DAM_RANGE: RangeInclusive<f32> = 0.05..=1.0;
+}
+
+/// Smoothed particle hydrodynamics, lengths in world units
+pub mod fluid {
+    use std::ops::RangeInclusive;
+
+    pub const ENABLED: bool = false;
+    pub const SMOOTHING: f32 = 0.025; // About 3 particle spacings of the dam break
+    pub const SMOOTHING_RANGE: RangeInclusive<f32> = 0.005..=0.2;
+    pub const SMOOTHING_STEP: f64 = 0.001;
+    pub const REST_DENSITY: f32 = 1000.0;
+    pub const REST_DENSITY_RANGE: RangeInclusive<f32> = 1.0..=10_000.0;
+    pub const SOUND_SPEED: f32 = 4.0; // Ten times the dam break flow, dt must stay below h / c
+    pub const SOUND_SPEED_RANGE: RangeInclusive<f32> = 0.05..=20.0;
+    pub const GAMMA: f32 = 7.0; // Tait exponent of water
+    pub const GAMMA_RANGE: RangeInclusive<f32> = 1.0..=7.0;
+    pub const GAMMA_STEP: f64 = 0.1;
+    pub const VISCOSITY: f32 = 0.1; // Artificial viscosity coefficient (alpha)
+    pub const VISCOSITY_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+    pub const VISCOSITY_STEP: f64 = 0.01;
+    pub const GRAVITY: f32 = 0.1; // Downward acceleration, world units / s²
+    pub const GRAVITY_RANGE: RangeInclusive<f32> = 0.0..=2.0;
+    pub const GRAVITY_STEP: f64 = 0.01;
+    pub const SELF_GRAVITY: bool = false;
+}
+
+/// Pointer force brush, lengths in world units
+pub mod brush {
+    use std::ops::RangeInclusive;
+
+    pub const STRENGTH: f32 = 5.0; // Acceleration at the center, world units / s²
+    pub const STRENGTH_RANGE: RangeInclusive<f32> = 0.1..=100.0;
+    pub const STRENGTH_STEP: f64 = 0.1;
+    pub const RADIUS: f32 = 0.25;
+    pub const RADIUS_RANGE: RangeInclusive<f32> = 0.02..=1.0;
+    pub const RADIUS_STEP: f64 = 0.01;
+
+    pub const SPAWN_COUNT: u32 = 500; // Particles per click
+    pub const SPAWN_COUNT_RANGE: RangeInclusive<u32> = 1..=20_000;
+    pub const SPAWN_DISPERSION: f32 = 0.05; // Velocity standard deviation per axis
+    pub const SPAWN_DISPERSION_RANGE: RangeInclusive<f32> = 0.0..=1.0;
+    pub const SPAWN_DISPERSION_STEP: f64 = 0.005;
+}
+
+pub mod diagnostics {
+    use std::ops::RangeInclusive;
+
+    pub const ENABLED: bool = false;
+    pub const INTERVAL: u32 = 50; // Each sample costs about one direct-sum step
+    pub const INTERVAL_RANGE: RangeInclusive<u32> = 1..=10_000;
+    pub const HISTORY: usize = 1_000; // Samples kept for the plots
+    pub const PLOT_HEIGHT: f32 = 80.0;
+}
+
+pub mod scenario_file {
+    use std::time::Duration;
+
+    pub const DIRECTORY: &str = "scenarios"; // Listed by the file picker
+    pub const DEFAULT_PATH: &str = "scenarios/galaxy-collision.toml";
+    pub const WATCH: bool = true;
+    pub const POLL_INTERVAL: Duration = Duration::from_millis(500);
+}
+
+pub mod snapshot {
+    pub const DEFAULT_PATH: &str = "snapshot.ppsnap";
+}
+
+/// Density accumulation and tone mapping
+pub mod render {
+    use std::ops::RangeInclusive;
+
+    pub const EXPOSURE: f32 = 1.0; // Scale of the accumulated density before tone mapping
+    pub const EXPOSURE_RANGE: RangeInclusive<f32> = 0.001..=100.0;
+}
+
+pub mod recorder {
+    use std::ops::RangeInclusive;
+
+    pub const DEFAULT_PATH: &str = "frames";
+    pub const WIDTH: u32 = 1280;
+    pub const HEIGHT: u32 = 720;
+    pub const SIZE_RANGE: RangeInclusive<u32> = 16..=8192;
+    pub const FPS: u32 = 30;
+    pub const FPS_RANGE: RangeInclusive<u32> = 1..=120;
+    pub const FRAME_TIME: f32 = 0.016; // Two default steps per frame
+    pub const FRAME_TIME_RANGE: RangeInclusive<f32> = 0.001..=1.0;
 }
 
 pub mod shader {
     pub const WORKGROUP_SIZE: u32 = 256;
     pub const WORKGROUP_SIZE_PAYLOAD: &str = "__WORKGROUP_SIZE__";
+
+    /// Depth of the Barnes-Hut quadtree, the leaf grid is 2^depth cells per side
+    pub const TREE_DEPTH: u32 = 8;
+    pub const TREE_DEPTH_PAYLOAD: &str = "__TREE_DEPTH__";
+
+    /// Most cells per side of the cell lists, larger radii get larger cells
+    pub const GRID_MAX_CELLS: u32 = 1024;
 }
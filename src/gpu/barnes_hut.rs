@@ -0,0 +1,334 @@
+use crate::{
+    constants,
+    gpu::{
+        compute,
+        sort::RadixSort,
+        validation::{FirstKick, VALIDATION_MASS_SCALES, Validation},
+    },
+    sim::Solver,
+};
+
+/// Byte stride between the per-level entries of the reduce uniform (dynamic offset alignment)
+const LEVEL_STRIDE: u64 = 256;
+
+/// GPU resources of the Barnes-Hut solver (`shaders/barnes_hut.wgsl`)
+///
+/// The quadtree is rebuilt from scratch every step: particles are sorted by leaf cell
+/// and summed per leaf, then every level is reduced bottom-up before the force
+/// traversal.
+pub struct BarnesHut {
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// Start and end in `particles` of each leaf, by Morton code
+    ranges: wgpu::Buffer,
+    /// (center of mass, mass) for every node of every level
+    nodes: wgpu::Buffer,
+    /// Level index for each reduce dispatch, addressed with dynamic offsets
+    levels: wgpu::Buffer,
+    /// Morton code of the leaf of each particle, then sorted
+    keys: wgpu::Buffer,
+    /// Particle indices sorted by leaf
+    particles: wgpu::Buffer,
+    /// Number of particles `keys` and `particles` can hold
+    capacity: u32,
+
+    bind_group: wgpu::BindGroup,
+
+    sort: RadixSort,
+
+    assign_pipeline: wgpu::ComputePipeline,
+    bounds_pipeline: wgpu::ComputePipeline,
+    leaves_pipeline: wgpu::ComputePipeline,
+    reduce_pipeline: wgpu::ComputePipeline,
+    update_pipeline: wgpu::ComputePipeline,
+}
+
+fn leaf_count() -> u32 {
+    1 << (2 * constants::shader::TREE_DEPTH)
+}
+
+fn node_count() -> u32 {
+    // Sum of 4^l for l in 0..=depth
+    ((1 << (2 * (constants::shader::TREE_DEPTH + 1))) - 1) / 3
+}
+
+pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("barnes_hut_bgl"),
+        entries: &[
+            // leaf ranges
+            storage(0),
+            // nodes
+            storage(1),
+            wgpu::BindGroupLayoutEntry {
+                // reduce level
+                binding: 2,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(16),
+                },
+                count: None,
+            },
+            // leaf Morton codes
+            storage(24),
+            // particles sorted by leaf
+            storage(25),
+        ],
+    })
+}
+
+fn make_particle_buffers(device: &wgpu::Device, capacity: u32) -> [wgpu::Buffer; 2] {
+    let mk = |label: &str| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: std::mem::size_of::<u32>() as u64 * capacity as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    };
+    [mk("barnes_hut_keys"), mk("barnes_hut_particles")]
+}
+
+fn make_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    [ranges, nodes, levels, keys, particles]: [&wgpu::Buffer; 5],
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("barnes_hut_bg"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                // leaf ranges
+                binding: 0,
+                resource: ranges.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                // nodes
+                binding: 1,
+                resource: nodes.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                // reduce level (one 16 byte entry per dynamic offset)
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: levels,
+                    offset: 0,
+                    size: wgpu::BufferSize::new(16),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                // leaf Morton codes
+                binding: 24,
+                resource: keys.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                // particles sorted by leaf
+                binding: 25,
+                resource: particles.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+impl BarnesHut {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        shader: &wgpu::ShaderModule,
+        compute_bind_group_layout: &wgpu::BindGroupLayout,
+        n: u32,
+    ) -> Self {
+        let depth = constants::shader::TREE_DEPTH;
+
+        // The Morton codes of the leaf grid, a power of two per side, cover exactly
+        // `leaf_count` entries
+        let ranges = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("barnes_hut_ranges"),
+            size: std::mem::size_of::<[u32; 2]>() as u64 * leaf_count() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let nodes = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("barnes_hut_nodes"),
+            size: std::mem::size_of::<[f32; 4]>() as u64 * node_count() as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+
+        let levels = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("barnes_hut_levels"),
+            size: LEVEL_STRIDE * depth as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        for level in 0..depth {
+            queue.write_buffer(
+                &levels,
+                LEVEL_STRIDE * level as u64,
+                bytemuck::cast_slice(&[level, 0, 0, 0]),
+            );
+        }
+
+        let capacity = n.max(1);
+        let [keys, particles] = make_particle_buffers(device, capacity);
+
+        let bind_group_layout = make_bind_group_layout(device);
+        let bind_group = make_bind_group(
+            device,
+            &bind_group_layout,
+            [&ranges, &nodes, &levels, &keys, &particles],
+        );
+
+        let pipeline_layout =
+            compute::make_pipeline_layout(device, &[compute_bind_group_layout, &bind_group_layout]);
+        let mk = |entry_point| {
+            compute::make_entry_pipeline(device, &pipeline_layout, shader, entry_point)
+        };
+
+        Self {
+            bind_group_layout,
+            ranges,
+            nodes,
+            levels,
+            keys,
+            particles,
+            capacity,
+
+            bind_group,
+
+            sort: RadixSort::new(device, capacity),
+
+            assign_pipeline: mk("bh_assign"),
+            bounds_pipeline: mk("bh_bounds"),
+            leaves_pipeline: mk("bh_leaves"),
+            reduce_pipeline: mk("bh_reduce"),
+            update_pipeline: mk("update_barnes_hut"),
+        }
+    }
+
+    /// Record the tree build and the force/integration pass for `n` particles
+    ///
+    /// `compute_bind_group` is the regular compute bind group (group 0) for this step. The
+    /// sort parameters are written through `queue`, see [`RadixSort::encode`].
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        compute_bind_group: &wgpu::BindGroup,
+        n: u32,
+    ) {
+        let workgroup_size = constants::shader::WORKGROUP_SIZE;
+        let depth = constants::shader::TREE_DEPTH;
+
+        if n > self.capacity {
+            self.capacity = n;
+            [self.keys, self.particles] = make_particle_buffers(device, n);
+            self.bind_group = make_bind_group(
+                device,
+                &self.bind_group_layout,
+                [
+                    &self.ranges,
+                    &self.nodes,
+                    &self.levels,
+                    &self.keys,
+                    &self.particles,
+                ],
+            );
+        }
+
+        // Sort the particles by leaf
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Barnes-Hut Assign Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, compute_bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.bind_group, &[0]);
+            compute_pass.set_pipeline(&self.assign_pipeline);
+            compute_pass.dispatch_workgroups(n.div_ceil(workgroup_size), 1, 1);
+        }
+        self.sort
+            .encode(device, queue, encoder, &self.keys, &self.particles, n);
+        encoder.clear_buffer(&self.ranges, 0, None);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Barnes-Hut Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.bind_group, &[0]);
+
+        // Sum the particles of each leaf
+        compute_pass.set_pipeline(&self.bounds_pipeline);
+        compute_pass.dispatch_workgroups(n.div_ceil(workgroup_size), 1, 1);
+
+        compute_pass.set_pipeline(&self.leaves_pipeline);
+        compute_pass.dispatch_workgroups(leaf_count().div_ceil(workgroup_size), 1, 1);
+
+        // Reduce the tree bottom-up
+        compute_pass.set_pipeline(&self.reduce_pipeline);
+        for level in (0..depth).rev() {
+            let offset = (LEVEL_STRIDE * level as u64) as u32;
+            compute_pass.set_bind_group(1, &self.bind_group, &[offset]);
+            let cells: u32 = 1 << (2 * level);
+            compute_pass.dispatch_workgroups(cells.div_ceil(workgroup_size), 1, 1);
+        }
+
+        // Traverse the tree and integrate
+        compute_pass.set_pipeline(&self.update_pipeline);
+        compute_pass.dispatch_workgroups(n.div_ceil(workgroup_size), 1, 1);
+    }
+}
+
+/// Largest relative RMS force error accepted against the direct sum
+pub const VALIDATION_FORCE_TOLERANCE: f32 = 5e-2;
+
+/// Compare the first kick of Barnes-Hut with the direct sum, with the masses scaled by
+/// each of [`VALIDATION_MASS_SCALES`]
+pub fn validate(v: &Validation) -> anyhow::Result<()> {
+    let v = Validation {
+        solver: Solver::BarnesHut,
+        ..*v
+    };
+    for mass_scale in VALIDATION_MASS_SCALES {
+        let kick = FirstKick::run(&v, mass_scale)?;
+        let err = kick.error(&kick.direct);
+        log::info!(
+            "Barnes-Hut relative force error with the masses scaled by {mass_scale:e}: {err:e}"
+        );
+        if err.is_nan() || err > VALIDATION_FORCE_TOLERANCE {
+            anyhow::bail!(
+                "Barnes-Hut forces deviate from the direct sum by {err:e} with the masses \
+scaled by {mass_scale:e}"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn forces_match_the_direct_sum() -> anyhow::Result<()> {
+        validate(&Validation::quick())
+    }
+}
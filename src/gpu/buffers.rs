@@ -1,6 +1,13 @@
 use bytemuck::cast_slice;
 
-use crate::sim::{SimParams, SimUniform};
+use crate::{
+    gpu::BufferInUse,
+    sim::{
+        ParticleData, SimParams, SimUniform,
+        bonds::{self, Bond},
+        timestep::StepState,
+    },
+};
 
 pub struct GpuBuffers {
     /// Buffer containing particle positions (primary)
@@ -13,13 +20,40 @@ pub struct GpuBuffers {
     pub velocities_secondary: wgpu::Buffer,
     /// Buffer containing particle colors
     pub colors: wgpu::Buffer,
+    /// Buffer containing particle masses
+    pub masses: wgpu::Buffer,
+    /// Buffer containing particle charges
+    pub charges: wgpu::Buffer,
     /// Buffer containing simulation parameters
     pub uniform: wgpu::Buffer,
+    /// Buffer containing the step state, written by the GPU after every step
+    pub timestep: wgpu::Buffer,
+    /// Per-particle state kept between the stages of a step, three `vec4` per particle
+    pub scratch: wgpu::Buffer,
+    /// Springs between the particles
+    pub bonds: wgpu::Buffer,
+    /// First entry of each particle in `bond_refs`, `n + 1` entries
+    pub bond_offsets: wgpu::Buffer,
+    /// Bond indices grouped by particle, see [`bonds::adjacency`]
+    pub bond_refs: wgpu::Buffer,
     /// Number of particles the buffers can hold
     pub capacity: u32,
+    /// Number of bonds the bond buffers can hold
+    pub bond_capacity: u32,
 }
 
 impl GpuBuffers {
+    /// Positions and velocities holding the current state
+    ///
+    /// The last step wrote the buffers the renderer reads, `buffer_in_use` has ticked
+    /// since, so they are those of [`BufferInUse::id_render`].
+    pub fn current(&self, buffer_in_use: BufferInUse) -> (&wgpu::Buffer, &wgpu::Buffer) {
+        match buffer_in_use.id_render() {
+            0 => (&self.positions_primary, &self.velocities_primary),
+            _ => (&self.positions_secondary, &self.velocities_secondary),
+        }
+    }
+
     pub fn resize(&mut self, device: &wgpu::Device, new_capacity: u32) {
         if new_capacity <= self.capacity {
             return;
@@ -27,26 +61,128 @@ impl GpuBuffers {
         *self = Self::create(device, new_capacity);
     }
 
+    /// Reallocate for `new_capacity` particles, keeping the first `n` particles and the
+    /// step state, returns `true` if the buffers were reallocated
+    ///
+    /// The uniform and the bonds are not copied, the caller uploads them again.
+    pub fn grow(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        new_capacity: u32,
+        n: u32,
+    ) -> bool {
+        if new_capacity <= self.capacity {
+            return false;
+        }
+        let grown = Self::create(device, new_capacity);
+
+        let f2_size = std::mem::size_of::<[f32; 2]>() as u64;
+        let f4_size = std::mem::size_of::<[f32; 4]>() as u64;
+        let f1_size = std::mem::size_of::<f32>() as u64;
+        let n = n as u64;
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("grow_encoder"),
+        });
+        for (old, new, size) in [
+            (
+                &self.positions_primary,
+                &grown.positions_primary,
+                f2_size * n,
+            ),
+            (
+                &self.positions_secondary,
+                &grown.positions_secondary,
+                f2_size * n,
+            ),
+            (
+                &self.velocities_primary,
+                &grown.velocities_primary,
+                f2_size * n,
+            ),
+            (
+                &self.velocities_secondary,
+                &grown.velocities_secondary,
+                f2_size * n,
+            ),
+            (&self.colors, &grown.colors, f4_size * n),
+            (&self.masses, &grown.masses, f1_size * n),
+            (&self.charges, &grown.charges, f1_size * n),
+            (&self.timestep, &grown.timestep, self.timestep.size()),
+        ] {
+            if size > 0 {
+                encoder.copy_buffer_to_buffer(old, 0, new, 0, size);
+            }
+        }
+        queue.submit(Some(encoder.finish()));
+
+        *self = grown;
+        true
+    }
+
+    /// Write `data` after the first `offset` particles, into both ping-pong buffers
+    pub fn write_particles(&self, queue: &wgpu::Queue, offset: u32, data: &ParticleData) {
+        let offset = offset as u64;
+        let f2_offset = std::mem::size_of::<[f32; 2]>() as u64 * offset;
+        let positions = cast_slice(&data.positions);
+        let velocities = cast_slice(&data.velocities);
+        queue.write_buffer(&self.positions_primary, f2_offset, positions);
+        queue.write_buffer(&self.positions_secondary, f2_offset, positions);
+        queue.write_buffer(&self.velocities_primary, f2_offset, velocities);
+        queue.write_buffer(&self.velocities_secondary, f2_offset, velocities);
+        queue.write_buffer(
+            &self.colors,
+            std::mem::size_of::<[f32; 4]>() as u64 * offset,
+            cast_slice(&data.colors),
+        );
+        let f1_offset = std::mem::size_of::<f32>() as u64 * offset;
+        queue.write_buffer(&self.masses, f1_offset, cast_slice(&data.masses));
+        queue.write_buffer(&self.charges, f1_offset, cast_slice(&data.charges));
+    }
+
+    /// Upload `bonds` between the first `n` particles with their adjacency lists, returns
+    /// `true` if the bond buffers were reallocated
+    pub fn write_bonds(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bonds: &[Bond],
+        n: u32,
+    ) -> bool {
+        let count = bonds.len() as u32;
+        let reallocated = count > self.bond_capacity;
+        if reallocated {
+            self.bond_capacity = count.next_power_of_two();
+            [self.bonds, self.bond_refs] = make_bond_buffers(device, self.bond_capacity);
+        }
+
+        let (offsets, refs) = bonds::adjacency(bonds, n);
+        queue.write_buffer(&self.bonds, 0, cast_slice(bonds));
+        queue.write_buffer(&self.bond_offsets, 0, cast_slice(&offsets));
+        queue.write_buffer(&self.bond_refs, 0, cast_slice(&refs));
+        reallocated
+    }
+
+    /// Zero the bond accelerations kept in `scratch`, which no pass writes without bonds
+    pub fn clear_bond_forces(&self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("clear_bonds_encoder"),
+        });
+        encoder.clear_buffer(&self.scratch, 0, None);
+        queue.submit(Some(encoder.finish()));
+    }
+
     pub fn upload_data(
         &self,
         queue: &wgpu::Queue,
-        positions: Option<&[[f32; 2]]>,
-        velocities: Option<&[[f32; 2]]>,
-        colors: Option<&[[f32; 4]]>,
+        particles: Option<&ParticleData>,
         uniform: Option<&SimParams>,
     ) {
-        if let Some(positions) = positions {
-            queue.write_buffer(&self.positions_primary, 0, cast_slice(positions));
-            // Push here to avoid display issues on first frame
-            queue.write_buffer(&self.positions_secondary, 0, cast_slice(positions));
-        }
-        if let Some(velocities) = velocities {
-            // Keep both velocity buffers in sync so leapfrog bootstrap reads a valid state.
-            queue.write_buffer(&self.velocities_primary, 0, cast_slice(velocities));
-            queue.write_buffer(&self.velocities_secondary, 0, cast_slice(velocities));
-        }
-        if let Some(colors) = colors {
-            queue.write_buffer(&self.colors, 0, cast_slice(colors));
+        if let Some(particles) = particles {
+            // Both ping-pong sets receive the state, which avoids display issues on the
+            // first frame and gives the leapfrog bootstrap a valid state
+            self.write_particles(queue, 0, particles);
         }
         if let Some(params) = uniform {
             let uniform = params.to_uniform();
@@ -54,16 +190,70 @@ impl GpuBuffers {
         }
     }
 
+    pub fn upload_step_state(&self, queue: &wgpu::Queue, state: &StepState) {
+        queue.write_buffer(&self.timestep, 0, cast_slice(std::slice::from_ref(state)));
+    }
+
+    /// Forget the previous step length, so the next leapfrog step half-kicks again
+    pub fn restart_leapfrog(&self, queue: &wgpu::Queue) {
+        queue.write_buffer(&self.timestep, 0, bytemuck::bytes_of(&0.0f32));
+    }
+
+    /// Copy the first `count` elements of a storage buffer back to the CPU
+    ///
+    /// This blocks until the GPU has finished all submitted work, so it is meant for
+    /// headless runs and tooling rather than the per-frame render path.
+    pub fn read_back<T: bytemuck::Pod>(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffer: &wgpu::Buffer,
+        count: u32,
+    ) -> anyhow::Result<Vec<T>> {
+        let size = (std::mem::size_of::<T>() as u64) * count as u64;
+        if size == 0 {
+            return Ok(Vec::new());
+        }
+
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback_staging"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("readback_encoder"),
+        });
+        encoder.copy_buffer_to_buffer(buffer, 0, &staging, 0, size);
+        queue.submit(Some(encoder.finish()));
+
+        let slice = staging.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::PollType::Wait)?;
+        rx.recv()??;
+
+        let data = cast_slice::<u8, T>(&slice.get_mapped_range()).to_vec();
+        staging.unmap();
+
+        Ok(data)
+    }
+
     pub fn create(device: &wgpu::Device, mut capacity: u32) -> Self {
         // Align capacity to the closest power of two for better memory alignment
         capacity = capacity.next_power_of_two();
 
+        let f1_size = std::mem::size_of::<f32>() as u64;
         let f2_size = std::mem::size_of::<[f32; 2]>() as u64;
         let f4_size = std::mem::size_of::<[f32; 4]>() as u64;
 
         let pos_size = f2_size * capacity as u64;
         let vel_size = f2_size * capacity as u64;
         let col_size = f4_size * capacity as u64;
+        let mass_size = f1_size * capacity as u64;
+        let scratch_size = 4 * f4_size * capacity as u64;
 
         let mk = |label: &str, size: u64, usage: wgpu::BufferUsages| {
             device.create_buffer(&wgpu::BufferDescriptor {
@@ -77,31 +267,57 @@ impl GpuBuffers {
         let positions_primary = mk(
             "positions_primary",
             pos_size,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
         );
 
         let positions_secondary = mk(
             "positions_secondary",
             pos_size,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
         );
 
         let velocities_primary = mk(
             "velocities",
             vel_size,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
         );
 
         let velocities_secondary = mk(
             "velocities_secondary",
             vel_size,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
         );
 
         let colors = mk(
             "colors_primary",
             col_size,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        );
+
+        let masses = mk(
+            "masses",
+            mass_size,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        );
+
+        let charges = mk(
+            "charges",
+            mass_size,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
         );
 
         let uniform = mk(
@@ -110,14 +326,67 @@ impl GpuBuffers {
             wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         );
 
+        let timestep = mk(
+            "step_state",
+            std::mem::size_of::<StepState>() as u64,
+            wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+        );
+
+        let scratch = mk(
+            "integrator_scratch",
+            scratch_size,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+
+        let bond_offsets = mk(
+            "bond_offsets",
+            std::mem::size_of::<u32>() as u64 * (capacity as u64 + 1),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+        // Bonds are uploaded separately, see `write_bonds`
+        let bond_capacity = 1;
+        let [bonds, bond_refs] = make_bond_buffers(device, bond_capacity);
+
         Self {
             positions_primary,
             positions_secondary,
             velocities_primary,
             velocities_secondary,
             colors,
+            masses,
+            charges,
             uniform,
+            timestep,
+            scratch,
+            bonds,
+            bond_offsets,
+            bond_refs,
             capacity,
+            bond_capacity,
         }
     }
 }
+
+/// Bond list and adjacency buffers for `capacity` bonds
+fn make_bond_buffers(device: &wgpu::Device, capacity: u32) -> [wgpu::Buffer; 2] {
+    let mk = |label: &str, size: u64| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    };
+    [
+        mk(
+            "bonds",
+            std::mem::size_of::<Bond>() as u64 * capacity as u64,
+        ),
+        mk(
+            "bond_refs",
+            2 * std::mem::size_of::<u32>() as u64 * capacity as u64,
+        ),
+    ]
+}
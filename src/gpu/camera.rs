@@ -0,0 +1,132 @@
+use glam::Vec2;
+
+use crate::{constants, sim::scenario_file::CameraView};
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    /// (center.x, center.y, scale.x, scale.y), clip = (world - center) * scale
+    pub center_scale: [f32; 4],
+}
+
+/// 2D view over the world, with pan, zoom and aspect-ratio correction
+///
+/// At zoom 1 the whole world fits the shortest side of the window, and world units
+/// keep the same length on both axes whatever the window shape.
+#[derive(Clone)]
+pub struct Camera {
+    /// World position at the center of the window
+    pub center: Vec2,
+    /// Magnification relative to the fitted view
+    pub zoom: f32,
+    /// World half extent shown along the shortest window side at zoom 1
+    half_extent: f32,
+    /// Window size in pixels
+    viewport: Vec2,
+}
+
+impl Camera {
+    pub fn new(world: &[Vec2; 2], width: u32, height: u32) -> Self {
+        let mut camera = Self {
+            center: Vec2::ZERO,
+            zoom: 1.0,
+            half_extent: 1.0,
+            viewport: Vec2::ONE,
+        };
+        camera.fit(world);
+        camera.resize(width, height);
+        camera
+    }
+
+    /// Show the whole world, centered
+    pub fn fit(&mut self, world: &[Vec2; 2]) {
+        self.center = 0.5 * (world[0] + world[1]);
+        self.half_extent = 0.5 * (world[1] - world[0]).max_element();
+        self.zoom = 1.0;
+    }
+
+    pub fn resize(&mut self, width: u32, height: u32) {
+        if width > 0 && height > 0 {
+            self.viewport = Vec2::new(width as f32, height as f32);
+        }
+    }
+
+    /// World to clip space scale on each axis
+    fn scale(&self) -> Vec2 {
+        let shortest = self.viewport.min_element();
+        self.zoom / self.half_extent * shortest / self.viewport
+    }
+
+    pub fn to_uniform(&self) -> CameraUniform {
+        let scale = self.scale();
+        CameraUniform {
+            center_scale: [self.center.x, self.center.y, scale.x, scale.y],
+        }
+    }
+
+    /// Convert a window position in pixels (y down) to world coordinates
+    pub fn screen_to_world(&self, cursor: Vec2) -> Vec2 {
+        let ndc = Vec2::new(
+            2.0 * cursor.x / self.viewport.x - 1.0,
+            1.0 - 2.0 * cursor.y / self.viewport.y,
+        );
+        self.center + ndc / self.scale()
+    }
+
+    /// Move the view by a window displacement in pixels, the world follows the cursor
+    pub fn pan_pixels(&mut self, delta: Vec2) {
+        let ndc = Vec2::new(2.0 * delta.x, -2.0 * delta.y) / self.viewport;
+        self.center -= ndc / self.scale();
+    }
+
+    /// Move the view by a fraction of its visible size
+    pub fn pan_fraction(&mut self, direction: Vec2) {
+        self.center += direction * 2.0 / self.scale();
+    }
+
+    /// Multiply the zoom by `factor`, keeping the world point under `cursor` fixed
+    pub fn zoom_at(&mut self, cursor: Vec2, factor: f32) {
+        let anchor = self.screen_to_world(cursor);
+        let range = constants::camera::ZOOM_RANGE;
+        self.zoom = (self.zoom * factor).clamp(*range.start(), *range.end());
+        self.center += anchor - self.screen_to_world(cursor);
+    }
+
+    /// Multiply the zoom by `factor`, keeping the center of the window fixed
+    pub fn zoom_by(&mut self, factor: f32) {
+        self.zoom_at(0.5 * self.viewport, factor);
+    }
+
+    pub fn view(&self) -> CameraView {
+        CameraView {
+            center: self.center.to_array(),
+            zoom: self.zoom,
+        }
+    }
+
+    pub fn set_view(&mut self, view: &CameraView) {
+        let range = constants::camera::ZOOM_RANGE;
+        self.center = Vec2::from(view.center);
+        self.zoom = view.zoom.clamp(*range.start(), *range.end());
+    }
+
+    /// Zoom level and a button to go back to the fitted view
+    pub fn render_ui(&mut self, ui: &mut egui::Ui, world: &[Vec2; 2]) {
+        ui.heading("View");
+        ui.horizontal(|ui| {
+            ui.label(format!(
+                "Zoom: {:.2}x, Center: ({:.3}, {:.3})",
+                self.zoom, self.center.x, self.center.y
+            ));
+            if ui
+                .button("Reset View")
+                .on_hover_text("Show the whole world again (Home)")
+                .clicked()
+            {
+                self.fit(world);
+            }
+        });
+        ui.label("Drag with the right or middle mouse button to pan, scroll to zoom")
+            .on_hover_text("Arrow keys pan and +/- zoom as well");
+    }
+}
@@ -0,0 +1,537 @@
+use crate::{
+    constants,
+    gpu::{
+        Simulation,
+        buffers::GpuBuffers,
+        compute,
+        grid::CellGrid,
+        readback::AsyncReadBack,
+        validation::{
+            VALIDATION_POSITION_TOLERANCE, VALIDATION_RUN_STEPS, VALIDATION_SPAWN_CENTER,
+            VALIDATION_VELOCITY_TOLERANCE, Validation, max_abs_diff,
+        },
+    },
+    sim::{ParticleData, SimParams, collisions, scenario::ChargeScheme},
+};
+
+/// Uniform of `shaders/merge.wgsl`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct MergeUniform {
+    world: [f32; 4],
+    n: u32,
+    wrap: u32,
+    _pad: [u32; 2],
+}
+
+/// GPU resources of the particle merging (`shaders/collisions.wgsl`, `shaders/merge.wgsl`)
+///
+/// The neighbors come from the [`CellGrid`] built for the merge radius.
+pub struct CollisionPass {
+    /// Nearest neighbor of each particle, bound in group 2 of `merge_find`
+    links_bind_group_layout: wgpu::BindGroupLayout,
+    links_bind_group: wgpu::BindGroup,
+    links: wgpu::Buffer,
+    /// Number of particles `links` can hold
+    capacity: u32,
+    /// Pairs of mutual nearest neighbors, read back without blocking
+    pairs: wgpu::Buffer,
+    pairs_read_back: AsyncReadBack<u32>,
+
+    merge_bind_group_layout: wgpu::BindGroupLayout,
+    uniform: wgpu::Buffer,
+
+    find_pipeline: wgpu::ComputePipeline,
+    pairs_pipeline: wgpu::ComputePipeline,
+    apply_pipeline: wgpu::ComputePipeline,
+}
+
+pub fn make_links_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("collision_bgl"),
+        entries: &[
+            // nearest neighbors
+            storage(22),
+            // pairs to merge
+            storage(23),
+        ],
+    })
+}
+
+fn make_merge_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("merge_bgl"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                // merge parameters
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // positions
+            storage(1),
+            // velocities
+            storage(2),
+            // colors
+            storage(3),
+            // masses
+            storage(4),
+            // charges
+            storage(5),
+            // nearest neighbors
+            storage(6),
+        ],
+    })
+}
+
+fn workgroups(n: u32) -> u32 {
+    n.div_ceil(constants::shader::WORKGROUP_SIZE).max(1)
+}
+
+fn make_links(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("collision_links"),
+        size: std::mem::size_of::<u32>() as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+fn make_links_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    links: &wgpu::Buffer,
+    pairs: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("collision_bg"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                // nearest neighbors
+                binding: 22,
+                resource: links.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                // pairs to merge
+                binding: 23,
+                resource: pairs.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+impl CollisionPass {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        compute_bind_group_layout: &wgpu::BindGroupLayout,
+        grid_bind_group_layout: &wgpu::BindGroupLayout,
+        n: u32,
+    ) -> Self {
+        let capacity = n.max(1);
+        let links = make_links(device, capacity);
+        let links_bind_group_layout = make_links_bind_group_layout(device);
+        let pairs = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("collision_pairs"),
+            size: std::mem::size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let links_bind_group =
+            make_links_bind_group(device, &links_bind_group_layout, &links, &pairs);
+        let find_layout = compute::make_pipeline_layout(
+            device,
+            &[
+                compute_bind_group_layout,
+                grid_bind_group_layout,
+                &links_bind_group_layout,
+            ],
+        );
+
+        let merge_shader = compute::make_standalone_shader(
+            device,
+            "merge_shader",
+            include_str!("../../shaders/merge.wgsl"),
+        );
+        let merge_bind_group_layout = make_merge_bind_group_layout(device);
+        let merge_layout = compute::make_pipeline_layout(device, &[&merge_bind_group_layout]);
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("merge_params"),
+            size: std::mem::size_of::<MergeUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            links_bind_group_layout,
+            links_bind_group,
+            links,
+            capacity,
+            pairs_read_back: AsyncReadBack::new(device, "collision pairs"),
+            pairs,
+
+            merge_bind_group_layout,
+            uniform,
+
+            find_pipeline: compute::make_entry_pipeline(device, &find_layout, shader, "merge_find"),
+            pairs_pipeline: compute::make_entry_pipeline(
+                device,
+                &find_layout,
+                shader,
+                "merge_pairs",
+            ),
+            apply_pipeline: compute::make_entry_pipeline(
+                device,
+                &merge_layout,
+                &merge_shader,
+                "merge_apply",
+            ),
+        }
+    }
+
+    /// Rebuild `grid` for the merge radius and find the nearest neighbor of the `n`
+    /// particles `compute_bind_group` reads
+    fn encode_find(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        grid: &mut CellGrid,
+        compute_bind_group: &wgpu::BindGroup,
+        params: &SimParams,
+    ) {
+        let n = params.n;
+        if n > self.capacity {
+            self.capacity = n;
+            self.links = make_links(device, n);
+            self.links_bind_group = make_links_bind_group(
+                device,
+                &self.links_bind_group_layout,
+                &self.links,
+                &self.pairs,
+            );
+        }
+
+        grid.encode(
+            device,
+            queue,
+            encoder,
+            compute_bind_group,
+            params,
+            params.merge_radius,
+        );
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Collision Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, grid.bind_group(), &[]);
+        compute_pass.set_bind_group(2, &self.links_bind_group, &[]);
+        compute_pass.set_pipeline(&self.find_pipeline);
+        compute_pass.dispatch_workgroups(workgroups(n), 1, 1);
+    }
+
+    /// Start counting the pairs [`CollisionPass::merge`] would merge, unless a count is
+    /// still in flight
+    ///
+    /// The count arrives through [`CollisionPass::poll_pairs`] a frame or two later, so
+    /// the frame never waits for it.
+    pub fn request_pairs(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        grid: &mut CellGrid,
+        compute_bind_group: &wgpu::BindGroup,
+        params: &SimParams,
+    ) {
+        if self.pairs_read_back.is_pending() {
+            return;
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("collision_pairs_encoder"),
+        });
+        encoder.clear_buffer(&self.pairs, 0, None);
+        self.encode_find(
+            device,
+            queue,
+            &mut encoder,
+            grid,
+            compute_bind_group,
+            params,
+        );
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Collision Pairs Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, compute_bind_group, &[]);
+            compute_pass.set_bind_group(1, grid.bind_group(), &[]);
+            compute_pass.set_bind_group(2, &self.links_bind_group, &[]);
+            compute_pass.set_pipeline(&self.pairs_pipeline);
+            compute_pass.dispatch_workgroups(workgroups(params.n), 1, 1);
+        }
+        queue.submit(Some(encoder.finish()));
+
+        self.pairs_read_back.request(device, queue, &self.pairs);
+    }
+
+    /// The count started by [`CollisionPass::request_pairs`], once it has arrived
+    ///
+    /// This never blocks.
+    pub fn poll_pairs(&mut self, device: &wgpu::Device) -> Option<u32> {
+        self.pairs_read_back.poll(device)
+    }
+
+    /// Merge the mutual nearest neighbors within `params.merge_radius` of the current
+    /// `positions` and `velocities`, which `compute_bind_group` reads, see
+    /// [`crate::sim::collisions::merge`]
+    ///
+    /// `grid` is rebuilt for the merge radius and submitted on its own, so it can be
+    /// shared with the other short-range interactions. Absorbed particles are left in
+    /// place with a zero mass, for
+    /// [`EditPass::remove_absorbed`](super::edit::EditPass::remove_absorbed) to compact.
+    #[allow(clippy::too_many_arguments)]
+    pub fn merge(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        grid: &mut CellGrid,
+        compute_bind_group: &wgpu::BindGroup,
+        buffers: &GpuBuffers,
+        positions: &wgpu::Buffer,
+        velocities: &wgpu::Buffer,
+        params: &SimParams,
+    ) {
+        let n = params.n;
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("collision_encoder"),
+        });
+        self.encode_find(
+            device,
+            queue,
+            &mut encoder,
+            grid,
+            compute_bind_group,
+            params,
+        );
+
+        let uniform = MergeUniform {
+            world: [
+                params.world[0].x,
+                params.world[0].y,
+                params.world[1].x,
+                params.world[1].y,
+            ],
+            n,
+            wrap: params.wrap as u32,
+            _pad: [0; 2],
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+
+        let merge_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("merge_bg"),
+            layout: &self.merge_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: positions.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: velocities.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffers.colors.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffers.masses.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: buffers.charges.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.links.as_entire_binding(),
+                },
+            ],
+        });
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Merge Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, &merge_bind_group, &[]);
+            compute_pass.set_pipeline(&self.apply_pipeline);
+            compute_pass.dispatch_workgroups(workgroups(n), 1, 1);
+        }
+        queue.submit(Some(encoder.finish()));
+    }
+}
+
+/// Merge radius of the collision check, large enough for many merges
+pub const VALIDATION_MERGE_RADIUS: f32 = 0.05;
+/// Merge passes compared with the CPU, the later ones merge the particles merged before
+pub const VALIDATION_MERGE_ROUNDS: u32 = 3;
+/// Largest relative change of the total mass and momentum accepted through merging
+pub const VALIDATION_MERGE_TOLERANCE: f64 = 1e-5;
+
+/// Total mass and momentum of `particles`
+fn mass_and_momentum(particles: &ParticleData) -> (f64, glam::DVec2) {
+    particles.masses.iter().zip(&particles.velocities).fold(
+        (0.0, glam::DVec2::ZERO),
+        |(mass, momentum), (&m, v)| {
+            let m = m as f64;
+            (mass + m, momentum + m * glam::Vec2::from(*v).as_dvec2())
+        },
+    )
+}
+
+/// Merge colliding particles on the GPU and compare with the CPU reference
+///
+/// The cell lists must find the same mutual nearest neighbors as the brute-force
+/// search of the reference, across the wrapping edges too, and merging must keep the
+/// total mass and momentum. Merging a burst of `large_burst` particles covers crowded
+/// cells, and a run with collisions enabled must merge as it steps.
+pub fn validate(v: &Validation, large_burst: u32) -> anyhow::Result<()> {
+    let mut sim = v.simulation(SimParams {
+        merge_radius: VALIDATION_MERGE_RADIUS,
+        ..v.params()
+    })?;
+    sim.scenarios.charges = ChargeScheme::Random;
+    sim.reset_particles();
+
+    log::info!(
+        "Validating {VALIDATION_MERGE_ROUNDS} merges within {VALIDATION_MERGE_RADIUS} ({})",
+        v.solver.label()
+    );
+
+    sim.step(VALIDATION_RUN_STEPS);
+    let conserved = |sim: &Simulation| -> anyhow::Result<(f64, glam::DVec2)> {
+        Ok(mass_and_momentum(&sim.snapshot()?.particles))
+    };
+    let check_conserved = |before: (f64, glam::DVec2),
+                           after: (f64, glam::DVec2)|
+     -> anyhow::Result<()> {
+        let mass_err = (after.0 - before.0).abs() / before.0;
+        let momentum_err = (after.1 - before.1).length() / before.1.length().max(before.0);
+        if mass_err > VALIDATION_MERGE_TOLERANCE || momentum_err > VALIDATION_MERGE_TOLERANCE {
+            anyhow::bail!(
+                "Merging changed the total mass by {mass_err:e} and the momentum by {momentum_err:e}"
+            );
+        }
+        Ok(())
+    };
+
+    let mut merged_total = 0;
+    for round in 1..=VALIDATION_MERGE_ROUNDS {
+        let before = sim.snapshot()?;
+        let initial = mass_and_momentum(&before.particles);
+        let merged = sim.merge_collisions()?;
+        let mut expected = before.particles;
+        let expected_merged = collisions::merge(&before.params, &mut expected);
+        if merged == 0 || merged != expected_merged {
+            anyhow::bail!(
+                "Merge {round} absorbed {merged} of {} particles, the CPU finds {expected_merged}",
+                before.params.n
+            );
+        }
+
+        let after = sim.snapshot()?.particles;
+        let (pi, pos_err) = max_abs_diff(&after.positions, &expected.positions);
+        let (vi, vel_err) = max_abs_diff(&after.velocities, &expected.velocities);
+        if pos_err > VALIDATION_POSITION_TOLERANCE
+            || vel_err > VALIDATION_VELOCITY_TOLERANCE
+            || after.masses != expected.masses
+            || after.charges != expected.charges
+        {
+            anyhow::bail!(
+                "Merge {round} deviates from the CPU reference: position error {pos_err:e} \
+(particle {pi}), velocity error {vel_err:e} (particle {vi})"
+            );
+        }
+        check_conserved(initial, mass_and_momentum(&after))?;
+        merged_total += merged;
+    }
+
+    // Many particles per cell
+    let mut rng = rand::SeedableRng::seed_from_u64(sim.params.seed);
+    let burst = sim
+        .params
+        .brush
+        .burst(VALIDATION_SPAWN_CENTER, large_burst, [1.0; 4], &mut rng);
+    sim.spawn(&burst);
+    let before = conserved(&sim)?;
+    let merged_large = sim.merge_collisions()?;
+    if merged_large == 0 {
+        anyhow::bail!("No particle of the burst merged");
+    }
+    check_conserved(before, conserved(&sim)?)?;
+
+    sim.params.collisions = true;
+    let (n, merges) = (sim.params.n, sim.diagnostics.merges);
+    sim.step(VALIDATION_RUN_STEPS);
+    let merged_running = sim.diagnostics.merges - merges;
+    if merged_running == 0 || u64::from(n - sim.params.n) != merged_running {
+        anyhow::bail!(
+            "Stepping with collisions went from {n} to {} particles with {merged_running} merges",
+            sim.params.n
+        );
+    }
+
+    log::info!(
+        "Merged {merged_total} particles like the CPU reference, then {merged_large} and \
+{merged_running}"
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn merging_matches_the_cpu_reference() -> anyhow::Result<()> {
+        validate(&Validation::quick(), 2_000)
+    }
+}
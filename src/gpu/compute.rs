@@ -1,9 +1,33 @@
-use crate::{constants, gpu::buffers::GpuBuffers};
+use crate::{
+    constants,
+    gpu::{
+        buffers::GpuBuffers,
+        validation::{VALIDATION_BONDED_SCENARIO, Validation},
+    },
+    sim::{Interaction, SimParams, brush::Brush, integrator::Integrator, scenario::ChargeScheme},
+};
 
 pub fn make_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
-    let shader_str = include_str!("../../shaders/nbody.wgsl").replace(
+    // The solvers share the bindings and helpers of the direct-sum kernel
+    let shader_str = [
+        include_str!("../../shaders/nbody.wgsl"),
+        include_str!("../../shaders/barnes_hut.wgsl"),
+        include_str!("../../shaders/particle_mesh.wgsl"),
+        include_str!("../../shaders/bonds.wgsl"),
+        include_str!("../../shaders/grid.wgsl"),
+        include_str!("../../shaders/fluid.wgsl"),
+        include_str!("../../shaders/collisions.wgsl"),
+        include_str!("../../shaders/diagnostics.wgsl"),
+        include_str!("../../shaders/timestep.wgsl"),
+    ]
+    .join("\n")
+    .replace(
         constants::shader::WORKGROUP_SIZE_PAYLOAD,
         &constants::shader::WORKGROUP_SIZE.to_string(),
+    )
+    .replace(
+        constants::shader::TREE_DEPTH_PAYLOAD,
+        &constants::shader::TREE_DEPTH.to_string(),
     );
 
     device.create_shader_module(wgpu::ShaderModuleDescriptor {
@@ -12,6 +36,26 @@ pub fn make_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
     })
 }
 
+/// Make a stand-alone module, which does not share the bindings of the compute shader,
+/// with the workgroup scan of `shaders/scan.wgsl`
+pub fn make_standalone_shader(
+    device: &wgpu::Device,
+    label: &str,
+    source: &str,
+) -> wgpu::ShaderModule {
+    let shader_str = [source, include_str!("../../shaders/scan.wgsl")]
+        .join("\n")
+        .replace(
+            constants::shader::WORKGROUP_SIZE_PAYLOAD,
+            &constants::shader::WORKGROUP_SIZE.to_string(),
+        );
+
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(label),
+        source: wgpu::ShaderSource::Wgsl(shader_str.into()),
+    })
+}
+
 pub fn make_pipeline_layout(
     device: &wgpu::Device,
     bind_group_layouts: &[&wgpu::BindGroupLayout],
@@ -27,12 +71,22 @@ pub fn make_pipeline(
     device: &wgpu::Device,
     pipeline_layout: &wgpu::PipelineLayout,
     shader: &wgpu::ShaderModule,
+) -> wgpu::ComputePipeline {
+    make_entry_pipeline(device, pipeline_layout, shader, "update")
+}
+
+/// Make a compute pipeline for any entry point of the compute shader
+pub fn make_entry_pipeline(
+    device: &wgpu::Device,
+    pipeline_layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
 ) -> wgpu::ComputePipeline {
     device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-        label: Some("compute_pipeline"),
+        label: Some(entry_point),
         layout: Some(pipeline_layout),
         module: shader,
-        entry_point: Some("update"),
+        entry_point: Some(entry_point),
         compilation_options: wgpu::PipelineCompilationOptions::default(),
         cache: None, // No pipeline cache
     })
@@ -108,6 +162,39 @@ pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
                 },
                 count: None,
             },
+            wgpu::BindGroupLayoutEntry {
+                // masses (read-only)
+                binding: 6,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                // step state (read-write)
+                binding: 7,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                // integrator scratch (read-write)
+                binding: 8,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
         ],
     })
 }
@@ -156,6 +243,21 @@ pub fn make_bind_group(
                     binding: 5,
                     resource: buffers.uniform.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    // masses
+                    binding: 6,
+                    resource: buffers.masses.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // step state
+                    binding: 7,
+                    resource: buffers.timestep.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // integrator scratch
+                    binding: 8,
+                    resource: buffers.scratch.as_entire_binding(),
+                },
             ],
         }),
         device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -192,7 +294,181 @@ pub fn make_bind_group(
                     binding: 5,
                     resource: buffers.uniform.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    // masses
+                    binding: 6,
+                    resource: buffers.masses.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // step state
+                    binding: 7,
+                    resource: buffers.timestep.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // integrator scratch
+                    binding: 8,
+                    resource: buffers.scratch.as_entire_binding(),
+                },
             ],
         }),
     ]
 }
+
+/// Layout of the charges read by the direct sum, in group 1 as group 0 is full
+pub fn make_charge_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("charge_bgl"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            // charges (read-only)
+            binding: 12,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+pub fn make_charge_bind_group(
+    device: &wgpu::Device,
+    bgl: &wgpu::BindGroupLayout,
+    buffers: &GpuBuffers,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("charge_bg"),
+        layout: bgl,
+        entries: &[wgpu::BindGroupEntry {
+            // charges
+            binding: 12,
+            resource: buffers.charges.as_entire_binding(),
+        }],
+    })
+}
+
+/// Layout of the bonds and their adjacency lists, read by `bond_forces`
+pub fn make_bond_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("bond_bgl"),
+        entries: &[
+            // bonds
+            storage(13),
+            // adjacency offsets
+            storage(14),
+            // adjacency bond indices
+            storage(15),
+        ],
+    })
+}
+
+pub fn make_bond_bind_group(
+    device: &wgpu::Device,
+    bgl: &wgpu::BindGroupLayout,
+    buffers: &GpuBuffers,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("bond_bg"),
+        layout: bgl,
+        entries: &bond_entries(buffers),
+    })
+}
+
+/// Bindings 13..15 of the bonds, shared with the diagnostics bind group
+pub fn bond_entries(buffers: &GpuBuffers) -> [wgpu::BindGroupEntry<'_>; 3] {
+    [
+        wgpu::BindGroupEntry {
+            // bonds
+            binding: 13,
+            resource: buffers.bonds.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            // adjacency offsets
+            binding: 14,
+            resource: buffers.bond_offsets.as_entire_binding(),
+        },
+        wgpu::BindGroupEntry {
+            // adjacency bond indices
+            binding: 15,
+            resource: buffers.bond_refs.as_entire_binding(),
+        },
+    ]
+}
+
+/// Coulomb constant of one of the kernel checks, stronger than gravity
+pub const VALIDATION_COULOMB_K: f32 = 5e-5;
+/// Brush held during one of the kernel checks, strong enough to dominate gravity
+pub const VALIDATION_BRUSH_STRENGTH: f32 = 20.0;
+pub const VALIDATION_BRUSH_TARGET: glam::Vec2 = glam::Vec2::new(0.1, -0.2);
+
+/// Step the direct-sum kernel and the CPU reference side by side and fail on divergence
+///
+/// With every integrator, with charges, with the brush held and with bonds.
+pub fn validate(v: &Validation) -> anyhow::Result<()> {
+    let params = v.params();
+    for integrator in Integrator::ALL {
+        let params = SimParams {
+            integrator,
+            ..params.clone()
+        };
+        v.compare_steps(params, None, ChargeScheme::Neutral, integrator.label())?;
+    }
+    let coulomb = SimParams {
+        interaction: Interaction::GravityAndCoulomb,
+        coulomb_k: VALIDATION_COULOMB_K,
+        ..params.clone()
+    };
+    v.compare_steps(
+        coulomb,
+        None,
+        ChargeScheme::Alternating,
+        "the Coulomb force",
+    )?;
+    let brush = Brush {
+        strength: VALIDATION_BRUSH_STRENGTH,
+        target: Some(VALIDATION_BRUSH_TARGET),
+        ..Brush::default()
+    };
+    v.compare_steps(
+        SimParams {
+            brush,
+            ..params.clone()
+        },
+        None,
+        ChargeScheme::Neutral,
+        "the brush",
+    )?;
+    v.compare_steps(
+        params,
+        Some(VALIDATION_BONDED_SCENARIO),
+        ChargeScheme::Neutral,
+        "the bonds",
+    )?;
+
+    log::info!("GPU kernel matches the CPU reference");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn kernel_matches_the_cpu_reference() -> anyhow::Result<()> {
+        validate(&Validation::quick())
+    }
+}
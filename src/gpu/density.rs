@@ -0,0 +1,427 @@
+use wgpu::PipelineCompilationOptions;
+
+use crate::{
+    constants,
+    gpu::{
+        Camera, Recorder, RecorderSettings, renderer,
+        validation::{VALIDATION_PARTICLES, Validation},
+    },
+    sim::SimParams,
+};
+
+/// Format of the accumulated density, far above 1 in dense regions
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// How the particles reach the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    /// Points alpha-blended over each other, dense regions saturate
+    #[default]
+    Blended,
+    /// Points summed into an HDR target, then tone mapped
+    Density,
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 2] = [RenderMode::Blended, RenderMode::Density];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            RenderMode::Blended => "Blended",
+            RenderMode::Density => "Density (HDR)",
+        }
+    }
+}
+
+/// Curve mapping the accumulated density to the displayed color, see `shaders/tonemap.wgsl`
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ToneMap {
+    /// Clipped at 1, shows the sparse regions only
+    Linear = 0,
+    /// Compresses several decades of density
+    #[default]
+    Log = 1,
+    /// Filmic curve, soft shoulder towards white
+    Aces = 2,
+    /// Linear for sparse regions and logarithmic for dense ones
+    Asinh = 3,
+}
+
+impl ToneMap {
+    pub const ALL: [ToneMap; 4] = [ToneMap::Linear, ToneMap::Log, ToneMap::Aces, ToneMap::Asinh];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            ToneMap::Linear => "Linear",
+            ToneMap::Log => "Log",
+            ToneMap::Aces => "ACES",
+            ToneMap::Asinh => "Asinh",
+        }
+    }
+}
+
+/// Render mode and tone mapping, shared by the window and the recordings
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderSettings {
+    pub mode: RenderMode,
+    pub tone_map: ToneMap,
+    /// Scale of the density before tone mapping
+    pub exposure: f32,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            mode: RenderMode::default(),
+            tone_map: ToneMap::default(),
+            exposure: constants::render::EXPOSURE,
+        }
+    }
+}
+
+impl RenderSettings {
+    pub fn render_ui(&mut self, ui: &mut egui::Ui) {
+        ui.heading("Rendering");
+        egui::ComboBox::from_label("Mode")
+            .selected_text(self.mode.label())
+            .show_ui(ui, |ui| {
+                for option in RenderMode::ALL {
+                    ui.selectable_value(&mut self.mode, option, option.label());
+                }
+            })
+            .response
+            .on_hover_text("Density mode sums the particles, so dense cores keep their structure");
+        ui.add_enabled_ui(self.mode == RenderMode::Density, |ui| {
+            egui::ComboBox::from_label("Tone Mapping")
+                .selected_text(self.tone_map.label())
+                .show_ui(ui, |ui| {
+                    for option in ToneMap::ALL {
+                        ui.selectable_value(&mut self.tone_map, option, option.label());
+                    }
+                });
+            ui.add(
+                egui::Slider::new(&mut self.exposure, constants::render::EXPOSURE_RANGE)
+                    .text("Exposure")
+                    .logarithmic(true),
+            )
+            .on_hover_text("Scale of the summed colors before tone mapping");
+        });
+    }
+}
+
+/// Uniform of `shaders/tonemap.wgsl`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMapUniform {
+    exposure: f32,
+    curve: u32,
+    _pad: [u32; 2],
+}
+
+/// HDR target the particles are summed into, and the tone mapping pass that resolves it
+///
+/// The target follows the size of the output, see [`DensityTarget::resize`].
+pub struct DensityTarget {
+    view: wgpu::TextureView,
+    uniform: wgpu::Buffer,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+
+    /// Additive points and bonds, drawn into the target
+    pipeline: wgpu::RenderPipeline,
+    bond_pipeline: wgpu::RenderPipeline,
+    tonemap_pipeline: wgpu::RenderPipeline,
+}
+
+fn make_view(device: &wgpu::Device, width: u32, height: u32) -> wgpu::TextureView {
+    device
+        .create_texture(&wgpu::TextureDescriptor {
+            label: Some("density_texture"),
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        })
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("tonemap_bgl"),
+        entries: &[
+            // accumulated density
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            // tone mapping parameters
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    })
+}
+
+fn make_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    view: &wgpu::TextureView,
+    uniform: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("tonemap_bg"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                // accumulated density
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(view),
+            },
+            wgpu::BindGroupEntry {
+                // tone mapping parameters
+                binding: 1,
+                resource: uniform.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn make_tonemap_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    output_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("tonemap_shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("../../shaders/tonemap.wgsl").into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("tonemap_pipeline_layout"),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("tonemap"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: Some("vs_fullscreen"),
+            buffers: &[],
+            compilation_options: PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: Some("fs_tonemap"),
+            compilation_options: PipelineCompilationOptions::default(),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: output_format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
+
+impl DensityTarget {
+    /// `render_pipeline_layout` and `render_shader` are those of the particle pipelines,
+    /// the tone mapped image is written in `output_format`
+    pub fn new(
+        device: &wgpu::Device,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        render_shader: &wgpu::ShaderModule,
+        output_format: wgpu::TextureFormat,
+        width: u32,
+        height: u32,
+    ) -> Self {
+        let view = make_view(device, width, height);
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("tonemap_params"),
+            size: std::mem::size_of::<ToneMapUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = make_bind_group_layout(device);
+        let bind_group = make_bind_group(device, &bind_group_layout, &view, &uniform);
+        let [pipeline, bond_pipeline] =
+            renderer::make_density_pipelines(device, render_pipeline_layout, render_shader, FORMAT);
+        let tonemap_pipeline = make_tonemap_pipeline(device, &bind_group_layout, output_format);
+
+        Self {
+            view,
+            uniform,
+            bind_group_layout,
+            bind_group,
+
+            pipeline,
+            bond_pipeline,
+            tonemap_pipeline,
+        }
+    }
+
+    /// Reallocate the target for an output of `width` x `height` pixels
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.view = make_view(device, width, height);
+        self.bind_group =
+            make_bind_group(device, &self.bind_group_layout, &self.view, &self.uniform);
+    }
+
+    /// Sum the `n` particles of `bind_group` and their first `bonds` bonds, then tone
+    /// map them into `view` with `settings`, see [`renderer::draw`]
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+        bind_group: &wgpu::BindGroup,
+        n: u32,
+        bonds: u32,
+        settings: &RenderSettings,
+    ) {
+        let uniform = ToneMapUniform {
+            exposure: settings.exposure,
+            curve: settings.tone_map as u32,
+            _pad: [0; 2],
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+
+        renderer::draw(
+            encoder,
+            &self.view,
+            &self.pipeline,
+            &self.bond_pipeline,
+            bind_group,
+            n,
+            bonds,
+        );
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tone Mapping Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(constants::gpu::BACKGROUND_COLOR),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+        render_pass.set_pipeline(&self.tonemap_pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Frame size of the rendering check
+pub const VALIDATION_RENDER_SIZE: [u32; 2] = [256, 256];
+/// Exposure gain between the two frames compared for each tone map
+pub const VALIDATION_EXPOSURE_GAIN: f32 = 4.0;
+
+/// Compare the density render mode with the blended one, and check the tone maps
+///
+/// Summing opaque particles can only brighten a pixel over drawing the last one alone,
+/// so the linear density frame is nowhere darker than the blended frame. Every tone
+/// map brightens with the exposure. Pixels are compared with one level of rounding.
+pub fn validate(v: &Validation) -> anyhow::Result<()> {
+    let mut sim = v.simulation(SimParams {
+        n: VALIDATION_PARTICLES,
+        seed: v.seed,
+        ..SimParams::default()
+    })?;
+    sim.scenarios.select("plummer")?;
+    sim.reset_particles();
+
+    let [width, height] = VALIDATION_RENDER_SIZE;
+    let camera = Camera::new(&sim.params.world, width, height);
+    let path = std::env::temp_dir().join(format!(
+        "particle_playground_validate_{}.y4m",
+        std::process::id()
+    ));
+    let settings = RecorderSettings {
+        width,
+        height,
+        ..RecorderSettings::for_path(&path)
+    };
+    let blended = RenderSettings::default();
+    let frames = Recorder::start(&sim, &camera, &blended, &settings).and_then(|recorder| {
+        let blended = recorder.capture(&sim, &camera, &blended)?;
+        let mut density = Vec::new();
+        for tone_map in ToneMap::ALL {
+            for exposure in [1.0, VALIDATION_EXPOSURE_GAIN] {
+                let render = RenderSettings {
+                    mode: RenderMode::Density,
+                    tone_map,
+                    exposure,
+                };
+                density.push(recorder.capture(&sim, &camera, &render)?);
+            }
+        }
+        recorder.finish()?;
+        Ok((blended, density))
+    });
+    let _ = std::fs::remove_file(&path);
+    let (blended, density) = frames?;
+
+    let darker = |a: &[u8], b: &[u8]| {
+        a.chunks_exact(4)
+            .zip(b.chunks_exact(4))
+            .position(|(a, b)| (0..3).any(|c| a[c] as u32 + 1 < b[c] as u32))
+    };
+    if !blended.chunks_exact(4).any(|pixel| pixel[..3] != [0; 3]) {
+        anyhow::bail!("Blended frame is empty");
+    }
+    // The linear tone map at unit exposure comes first
+    if let Some(pixel) = darker(&density[0], &blended) {
+        anyhow::bail!("Density frame is darker than the blended one at pixel {pixel}");
+    }
+    for (tone_map, pair) in ToneMap::ALL.iter().zip(density.chunks_exact(2)) {
+        if let Some(pixel) = darker(&pair[1], &pair[0]) {
+            anyhow::bail!(
+                "{} tone map darkens pixel {pixel} at a higher exposure",
+                tone_map.label()
+            );
+        }
+    }
+
+    log::info!("Density rendering and tone maps match the blended frame");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn density_rendering_matches_the_blended_frame() -> anyhow::Result<()> {
+        validate(&Validation::quick())
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{
+    constants,
+    gpu::{
+        buffers::GpuBuffers,
+        compute,
+        readback::AsyncReadBack,
+        validation::{VALIDATION_BONDED_SCENARIO, VALIDATION_PARTICLES, Validation},
+    },
+    sim::{
+        Interaction, SimParams,
+        diagnostics::{self, Backend, Sample},
+        scenario::ChargeScheme,
+    },
+};
+
+/// GPU resources of the diagnostics reduction (`shaders/diagnostics.wgsl`)
+pub struct DiagnosticsPass {
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// Two `vec4` of partial sums per workgroup
+    partials: wgpu::Buffer,
+    /// Two `vec4` with the totals, copied back to the CPU
+    result: wgpu::Buffer,
+    /// Number of workgroups `partials` can hold
+    capacity: u32,
+    /// Copy of `result`, read without blocking the frame
+    read_back: AsyncReadBack<[[f32; 4]; 2]>,
+    /// Epoch and whether the potential is measured, of the read back in flight
+    requested: (u128, bool),
+
+    partial_pipeline: wgpu::ComputePipeline,
+    total_pipeline: wgpu::ComputePipeline,
+}
+
+pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("diagnostics_bgl"),
+        entries: &[
+            // partial sums
+            storage(9, false),
+            // totals
+            storage(10, false),
+            // charges
+            storage(12, true),
+            // bonds and their adjacency lists
+            storage(13, true),
+            storage(14, true),
+            storage(15, true),
+        ],
+    })
+}
+
+fn workgroups(n: u32) -> u32 {
+    n.div_ceil(constants::shader::WORKGROUP_SIZE).max(1)
+}
+
+fn make_partials(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("diagnostics_partials"),
+        size: 2 * std::mem::size_of::<[f32; 4]>() as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+fn make_bind_group(
+    device: &wgpu::Device,
+    bgl: &wgpu::BindGroupLayout,
+    partials: &wgpu::Buffer,
+    result: &wgpu::Buffer,
+    buffers: &GpuBuffers,
+) -> wgpu::BindGroup {
+    let [bonds, bond_offsets, bond_refs] = compute::bond_entries(buffers);
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("diagnostics_bg"),
+        layout: bgl,
+        entries: &[
+            wgpu::BindGroupEntry {
+                // partial sums
+                binding: 9,
+                resource: partials.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                // totals
+                binding: 10,
+                resource: result.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                // charges
+                binding: 12,
+                resource: buffers.charges.as_entire_binding(),
+            },
+            bonds,
+            bond_offsets,
+            bond_refs,
+        ],
+    })
+}
+
+impl DiagnosticsPass {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        compute_bind_group_layout: &wgpu::BindGroupLayout,
+        n: u32,
+    ) -> Self {
+        let capacity = workgroups(n);
+        let partials = make_partials(device, capacity);
+        let result = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("diagnostics_result"),
+            size: 2 * std::mem::size_of::<[f32; 4]>() as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        let bind_group_layout = make_bind_group_layout(device);
+
+        let pipeline_layout =
+            compute::make_pipeline_layout(device, &[compute_bind_group_layout, &bind_group_layout]);
+        let mk = |entry_point| {
+            compute::make_entry_pipeline(device, &pipeline_layout, shader, entry_point)
+        };
+
+        Self {
+            bind_group_layout,
+            partials,
+            result,
+            capacity,
+            read_back: AsyncReadBack::new(device, "diagnostics"),
+            requested: (0, false),
+
+            partial_pipeline: mk("diag_partial"),
+            total_pipeline: mk("diag_total"),
+        }
+    }
+
+    /// Reduce the `n` particles of `compute_bind_group` into `result`
+    fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        compute_bind_group: &wgpu::BindGroup,
+        buffers: &GpuBuffers,
+        n: u32,
+    ) {
+        let groups = workgroups(n);
+        if groups > self.capacity {
+            self.capacity = groups;
+            self.partials = make_partials(device, groups);
+        }
+        // The charges and bonds buffers are reallocated with the particles, measuring is
+        // rare enough to build the bind group every time
+        let bind_group = make_bind_group(
+            device,
+            &self.bind_group_layout,
+            &self.partials,
+            &self.result,
+            buffers,
+        );
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Diagnostics Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, &bind_group, &[]);
+
+        compute_pass.set_pipeline(&self.partial_pipeline);
+        compute_pass.dispatch_workgroups(groups, 1, 1);
+
+        compute_pass.set_pipeline(&self.total_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// Measure the particles of `compute_bind_group`, blocking until the GPU is done
+    /// like [`GpuBuffers::read_back`]
+    pub fn measure(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        compute_bind_group: &wgpu::BindGroup,
+        buffers: &GpuBuffers,
+        params: &SimParams,
+    ) -> anyhow::Result<Sample> {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("diagnostics_encoder"),
+        });
+        self.encode(device, &mut encoder, compute_bind_group, buffers, params.n);
+        queue.submit(Some(encoder.finish()));
+
+        let result = GpuBuffers::read_back(device, queue, &self.result, 2)?;
+        Ok(Sample::from_gpu(
+            params.epoch,
+            diagnostics::measures_potential(params),
+            &result,
+        ))
+    }
+
+    /// Start measuring the particles of `compute_bind_group`, returns false when a
+    /// measurement is still in flight
+    ///
+    /// The sample arrives through [`DiagnosticsPass::poll`], so the frame never waits
+    /// for it.
+    pub fn request(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        compute_bind_group: &wgpu::BindGroup,
+        buffers: &GpuBuffers,
+        params: &SimParams,
+    ) -> bool {
+        if self.read_back.is_pending() {
+            return false;
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("diagnostics_encoder"),
+        });
+        self.encode(device, &mut encoder, compute_bind_group, buffers, params.n);
+        queue.submit(Some(encoder.finish()));
+
+        self.read_back.request(device, queue, &self.result);
+        self.requested = (params.epoch, diagnostics::measures_potential(params));
+        true
+    }
+
+    /// The sample started by [`DiagnosticsPass::request`], once it has arrived
+    ///
+    /// This never blocks, unless `wait` is set.
+    pub fn poll(&mut self, device: &wgpu::Device, wait: bool) -> Option<Sample> {
+        let result = if wait {
+            self.read_back.wait(device)
+        } else {
+            self.read_back.poll(device)
+        }?;
+        let (epoch, potential) = self.requested;
+        Some(Sample::from_gpu(epoch, potential, &result))
+    }
+}
+
+/// Epochs between two diagnostics samples of the check
+pub const VALIDATION_INTERVAL: u32 = 5;
+/// Largest relative difference accepted between the GPU and CPU diagnostics
+pub const VALIDATION_TOLERANCE: f64 = 1e-4;
+
+/// Compare the GPU reduction of the conserved quantities with the CPU reference
+///
+/// The particle count is not a multiple of the workgroup size, so the last workgroup
+/// is only partially filled.
+pub fn validate(v: &Validation) -> anyhow::Result<()> {
+    let mut sim = v.simulation(SimParams {
+        n: VALIDATION_PARTICLES - 1,
+        interaction: Interaction::GravityAndCoulomb, // only the direct sum adds the Coulomb energy
+        ..v.params()
+    })?;
+    sim.scenarios.charges = ChargeScheme::Alternating;
+    sim.diagnostics.enabled = true;
+    sim.diagnostics.interval = VALIDATION_INTERVAL;
+    sim.diagnostics.backend = Backend::Gpu;
+    sim.reset_particles();
+
+    log::info!(
+        "Validating the GPU diagnostics reduction ({})",
+        v.solver.label()
+    );
+
+    let steps = 2 * VALIDATION_INTERVAL;
+    sim.step(steps);
+    let epochs: Vec<u128> = sim.diagnostics.samples().iter().map(|s| s.epoch).collect();
+    let expected: Vec<u128> = (0..=steps)
+        .step_by(VALIDATION_INTERVAL as usize)
+        .map(u128::from)
+        .collect();
+    if epochs != expected {
+        anyhow::bail!("Diagnostics sampled at epochs {epochs:?}, expected {expected:?}");
+    }
+
+    let gpu = sim.measure_diagnostics(Backend::Gpu)?;
+    let cpu = sim.measure_diagnostics(Backend::Cpu)?;
+
+    // Momenta are close to zero by symmetry, compare them to their largest possible value
+    let mass: f64 = sim.read_masses()?.iter().map(|&m| m as f64).sum();
+    let energy_scale = cpu.kinetic.abs() + cpu.potential.unwrap_or(0.0).abs();
+    let momentum_scale = (2.0 * mass * cpu.kinetic).sqrt();
+    let world_scale = 0.5 * (sim.params.world[1] - sim.params.world[0]).max_element() as f64;
+
+    let errors = [
+        (
+            "kinetic energy",
+            (gpu.kinetic - cpu.kinetic).abs() / energy_scale,
+        ),
+        (
+            "potential energy",
+            match (gpu.potential, cpu.potential) {
+                (Some(gpu), Some(cpu)) => (gpu - cpu).abs() / energy_scale,
+                (None, None) => 0.0,
+                _ => f64::NAN, // measured on one side only
+            },
+        ),
+        (
+            "momentum",
+            (gpu.momentum - cpu.momentum).length() / momentum_scale,
+        ),
+        (
+            "angular momentum",
+            (gpu.angular_momentum - cpu.angular_momentum).abs() / (momentum_scale * world_scale),
+        ),
+        (
+            "center of mass",
+            (gpu.center_of_mass - cpu.center_of_mass).length() / world_scale,
+        ),
+    ];
+    for (name, err) in errors {
+        log::debug!("Diagnostics {name} relative error: {err:e}");
+        if err.is_nan() || err > VALIDATION_TOLERANCE {
+            anyhow::bail!("GPU {name} deviates from the CPU reference by {err:e}");
+        }
+    }
+
+    // Stretched bonds add their elastic energy
+    sim.scenarios.select(VALIDATION_BONDED_SCENARIO)?;
+    sim.reset_particles();
+    sim.step(VALIDATION_INTERVAL);
+    let gpu = sim.measure_diagnostics(Backend::Gpu)?;
+    let cpu = sim.measure_diagnostics(Backend::Cpu)?;
+    // Only the direct sum measures the potential
+    if let (Some(gpu_potential), Some(cpu_potential)) = (gpu.potential, cpu.potential) {
+        let err = (gpu_potential - cpu_potential).abs() / (cpu.kinetic.abs() + cpu_potential.abs());
+        log::debug!("Diagnostics bonded potential energy relative error: {err:e}");
+        if err.is_nan() || err > VALIDATION_TOLERANCE {
+            anyhow::bail!(
+                "GPU potential energy of the bonds deviates from the CPU reference by {err:e}"
+            );
+        }
+    } else if gpu.potential.is_some() || cpu.potential.is_some() {
+        anyhow::bail!("The potential energy of the bonds is measured on one side only");
+    }
+
+    log::info!("GPU diagnostics match the CPU reference");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn diagnostics_match_the_cpu_reference() -> anyhow::Result<()> {
+        validate(&Validation::quick())
+    }
+}
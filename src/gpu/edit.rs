@@ -0,0 +1,559 @@
+use glam::Vec2;
+
+use crate::{
+    constants,
+    gpu::{
+        Simulation,
+        buffers::GpuBuffers,
+        compute,
+        validation::{
+            VALIDATION_BONDED_SCENARIO, VALIDATION_EDGE, VALIDATION_RUN_STEPS,
+            VALIDATION_SPAWN_CENTER, Validation, same_bits,
+        },
+    },
+    sim::{ParticleData, SimParams, bonds, cpu, scenario::ChargeScheme},
+};
+
+/// Uniform of `shaders/edit.wgsl`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct EditUniform {
+    region: [f32; 4],
+    world: [f32; 4],
+    n: u32,
+    groups: u32,
+    /// `ERASE_DISC` or `ABSORBED`
+    mode: u32,
+    _pad: u32,
+}
+
+/// GPU resources of the particle removal (`shaders/edit.wgsl`)
+pub struct EditPass {
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform: wgpu::Buffer,
+    /// Rank of each kept particle within its workgroup, `REMOVED` for the others
+    offsets: wgpu::Buffer,
+    /// Kept particles before each workgroup, followed by the total
+    blocks: wgpu::Buffer,
+    /// Kept particles packed as three `vec4`
+    moved: wgpu::Buffer,
+    /// Number of particles the scratch buffers can hold
+    capacity: u32,
+
+    mark_pipeline: wgpu::ComputePipeline,
+    blocks_pipeline: wgpu::ComputePipeline,
+    scatter_pipeline: wgpu::ComputePipeline,
+    unpack_pipeline: wgpu::ComputePipeline,
+}
+
+/// Rank of the removed particles in `offsets`, see `edit_mark`
+const REMOVED: u32 = u32::MAX;
+
+/// Removal modes of `edit_keep`: the particles within the region, or those left with
+/// a zero mass by the collision pass
+const ERASE_DISC: u32 = 0;
+const ABSORBED: u32 = 1;
+
+fn make_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
+    compute::make_standalone_shader(
+        device,
+        "edit_shader",
+        include_str!("../../shaders/edit.wgsl"),
+    )
+}
+
+pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("edit_bgl"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                // edit parameters
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // positions
+            storage(1),
+            // velocities
+            storage(2),
+            // colors
+            storage(3),
+            // masses
+            storage(4),
+            // workgroup ranks
+            storage(5),
+            // workgroup offsets and total
+            storage(6),
+            // packed kept particles
+            storage(7),
+            // charges
+            storage(8),
+        ],
+    })
+}
+
+fn workgroups(n: u32) -> u32 {
+    n.div_ceil(constants::shader::WORKGROUP_SIZE).max(1)
+}
+
+fn make_scratch(device: &wgpu::Device, capacity: u32) -> [wgpu::Buffer; 3] {
+    let mk = |label: &str, size: u64, usage: wgpu::BufferUsages| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage,
+            mapped_at_creation: false,
+        })
+    };
+    let u32_size = std::mem::size_of::<u32>() as u64;
+    [
+        mk(
+            "edit_offsets",
+            u32_size * capacity as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        ),
+        mk(
+            "edit_blocks",
+            u32_size * (workgroups(capacity) + 1) as u64,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        ),
+        mk(
+            "edit_moved",
+            3 * std::mem::size_of::<[f32; 4]>() as u64 * capacity as u64,
+            wgpu::BufferUsages::STORAGE,
+        ),
+    ]
+}
+
+impl EditPass {
+    pub fn new(device: &wgpu::Device, n: u32) -> Self {
+        let shader = make_shader(device);
+        let bind_group_layout = make_bind_group_layout(device);
+        let pipeline_layout = compute::make_pipeline_layout(device, &[&bind_group_layout]);
+        let mk = |entry_point| {
+            compute::make_entry_pipeline(device, &pipeline_layout, &shader, entry_point)
+        };
+
+        let capacity = n.max(1);
+        let [offsets, blocks, moved] = make_scratch(device, capacity);
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("edit_params"),
+            size: std::mem::size_of::<EditUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            bind_group_layout,
+            uniform,
+            offsets,
+            blocks,
+            moved,
+            capacity,
+
+            mark_pipeline: mk("edit_mark"),
+            blocks_pipeline: mk("edit_blocks"),
+            scatter_pipeline: mk("edit_scatter"),
+            unpack_pipeline: mk("edit_unpack"),
+        }
+    }
+
+    /// Remove the particles within `radius` of `center` from the current `positions`
+    /// and `velocities`, the colors, the masses and the charges, returns how many are left
+    ///
+    /// The remaining particles keep their order at the front of the buffers. Nothing
+    /// is written when no particle or every particle would be removed. This blocks
+    /// until the GPU is done, like [`GpuBuffers::read_back`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn erase(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffers: &GpuBuffers,
+        positions: &wgpu::Buffer,
+        velocities: &wgpu::Buffer,
+        params: &SimParams,
+        center: Vec2,
+        radius: f32,
+    ) -> anyhow::Result<u32> {
+        let region = [center.x, center.y, radius, params.wrap as u32 as f32];
+        self.compact(
+            device, queue, buffers, positions, velocities, params, region, ERASE_DISC,
+        )
+    }
+
+    /// Remove the particles absorbed by
+    /// [`CollisionPass::merge`](super::collisions::CollisionPass::merge), like
+    /// [`EditPass::erase`]
+    pub fn remove_absorbed(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffers: &GpuBuffers,
+        positions: &wgpu::Buffer,
+        velocities: &wgpu::Buffer,
+        params: &SimParams,
+    ) -> anyhow::Result<u32> {
+        self.compact(
+            device, queue, buffers, positions, velocities, params, [0.0; 4], ABSORBED,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn compact(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffers: &GpuBuffers,
+        positions: &wgpu::Buffer,
+        velocities: &wgpu::Buffer,
+        params: &SimParams,
+        region: [f32; 4],
+        mode: u32,
+    ) -> anyhow::Result<u32> {
+        let n = params.n;
+        if n > self.capacity {
+            self.capacity = n;
+            [self.offsets, self.blocks, self.moved] = make_scratch(device, n);
+        }
+
+        let groups = workgroups(n);
+        let uniform = EditUniform {
+            region,
+            world: [
+                params.world[0].x,
+                params.world[0].y,
+                params.world[1].x,
+                params.world[1].y,
+            ],
+            n,
+            groups,
+            mode,
+            _pad: 0,
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("edit_bg"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: positions.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: velocities.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: buffers.colors.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: buffers.masses.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: self.offsets.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: self.blocks.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: self.moved.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: buffers.charges.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("edit_encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Erase Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+
+            compute_pass.set_pipeline(&self.mark_pipeline);
+            compute_pass.dispatch_workgroups(groups, 1, 1);
+
+            compute_pass.set_pipeline(&self.blocks_pipeline);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+
+            compute_pass.set_pipeline(&self.scatter_pipeline);
+            compute_pass.dispatch_workgroups(groups, 1, 1);
+        }
+        queue.submit(Some(encoder.finish()));
+
+        let blocks = GpuBuffers::read_back::<u32>(device, queue, &self.blocks, groups + 1)?;
+        let kept = blocks[groups as usize];
+        if kept == 0 || kept == n {
+            return Ok(kept);
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("edit_encoder"),
+        });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Compaction Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.set_pipeline(&self.unpack_pipeline);
+            compute_pass.dispatch_workgroups(workgroups(kept), 1, 1);
+        }
+        queue.submit(Some(encoder.finish()));
+
+        Ok(kept)
+    }
+
+    /// Index of each of the `n` particles of the last removal among the kept
+    /// ones, `None` for the removed ones
+    ///
+    /// This blocks until the GPU is done, like [`GpuBuffers::read_back`].
+    pub fn new_indices(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        n: u32,
+    ) -> anyhow::Result<Vec<Option<u32>>> {
+        let offsets = GpuBuffers::read_back::<u32>(device, queue, &self.offsets, n)?;
+        let blocks = GpuBuffers::read_back::<u32>(device, queue, &self.blocks, workgroups(n))?;
+        let group_size = constants::shader::WORKGROUP_SIZE as usize;
+        Ok(offsets
+            .iter()
+            .enumerate()
+            .map(|(id, &rank)| (rank != REMOVED).then(|| blocks[id / group_size] + rank))
+            .collect())
+    }
+}
+
+/// Particles spawned by the editing check, the buffers must grow for them
+pub const VALIDATION_SPAWN_COUNT: u32 = 300;
+/// Particles added then removed by the particle count check
+pub const VALIDATION_COUNT_CHANGE: u32 = 200;
+/// Radius erased by the editing check, across the edge of the wrapping world
+pub const VALIDATION_ERASE_RADIUS: f32 = 0.3;
+
+/// Spawn, erase, add and drop particles mid-run, the other particles must keep their state
+///
+/// The spawned burst grows the buffers, which must preserve the particles and the
+/// step state. The erased particles must be exactly those the CPU finds within the
+/// radius, and the compaction must keep the order of the others, also through a burst
+/// of `large_burst` particles. Changing the particle count must neither reset the
+/// simulation nor touch the particles kept. Bonds follow their particles and are
+/// dropped with them.
+pub fn validate(v: &Validation, large_burst: u32) -> anyhow::Result<()> {
+    let mut sim = v.simulation(v.params())?;
+    sim.scenarios.charges = ChargeScheme::Random;
+    sim.reset_particles();
+
+    log::info!(
+        "Validating spawning {VALIDATION_SPAWN_COUNT} particles and erasing ({})",
+        v.solver.label()
+    );
+
+    sim.step(VALIDATION_RUN_STEPS);
+    let before = sim.snapshot()?;
+    let capacity = sim.buffers.capacity;
+
+    let mut rng = rand::SeedableRng::seed_from_u64(sim.params.seed);
+    let mut burst = sim.params.brush.burst(
+        VALIDATION_SPAWN_CENTER,
+        VALIDATION_SPAWN_COUNT,
+        [1.0; 4],
+        &mut rng,
+    );
+    sim.scenarios
+        .assign_charges(&mut burst, before.params.n, &mut rng);
+    if !sim.spawn(&burst) || sim.buffers.capacity <= capacity {
+        anyhow::bail!("Spawning past the capacity did not grow the buffers");
+    }
+    let spawned = sim.snapshot()?;
+
+    let mut expected = before.particles;
+    append(&mut expected, &burst);
+    if spawned.params.n != before.params.n + VALIDATION_SPAWN_COUNT
+        || !same_bits(&spawned.particles, &expected)
+    {
+        anyhow::bail!("Spawning changed the existing particles or lost the new ones");
+    }
+    if spawned.clock != before.clock {
+        anyhow::bail!(
+            "Growing the buffers changed the step state from {:?} to {:?}",
+            before.clock,
+            spawned.clock
+        );
+    }
+
+    sim.params.brush.radius = VALIDATION_ERASE_RADIUS;
+    let removed = check_erase(&mut sim, VALIDATION_EDGE)?;
+
+    // Both edits leave a state the solvers keep stepping
+    sim.step(VALIDATION_RUN_STEPS);
+    let positions = sim.read_positions()?;
+    if positions.iter().flatten().any(|x| !x.is_finite()) {
+        anyhow::bail!("Stepping after the edits produced non-finite positions");
+    }
+
+    let n = sim.params.n;
+    check_particle_count(&mut sim, n + VALIDATION_COUNT_CHANGE)?;
+    check_particle_count(&mut sim, n - VALIDATION_COUNT_CHANGE)?;
+
+    // The diagnostics would cost more than the edits
+    sim.diagnostics.enabled = false;
+    let burst = sim
+        .params
+        .brush
+        .burst(VALIDATION_SPAWN_CENTER, large_burst, [1.0; 4], &mut rng);
+    sim.spawn(&burst);
+    let removed_large = check_erase(&mut sim, VALIDATION_SPAWN_CENTER)?;
+
+    // Editing a cloth cuts and renumbers its bonds
+    let n = before.params.n;
+    sim.scenarios.select(VALIDATION_BONDED_SCENARIO)?;
+    sim.params.n = n;
+    sim.reset_particles();
+    sim.step(VALIDATION_RUN_STEPS);
+    check_particle_count(&mut sim, n + VALIDATION_COUNT_CHANGE)?;
+    check_particle_count(&mut sim, n - VALIDATION_COUNT_CHANGE)?;
+    let removed_bonded = check_erase(&mut sim, Vec2::ZERO)?;
+    if sim.bonds().is_empty() {
+        anyhow::bail!("Erasing the middle of the cloth removed all of its bonds");
+    }
+
+    log::info!(
+        "Spawned {} and erased {} particles, the others are unchanged",
+        VALIDATION_SPAWN_COUNT + large_burst,
+        removed + removed_large + removed_bonded
+    );
+
+    Ok(())
+}
+
+/// Append the particles and bonds of `data` to `expected`, as spawning does
+fn append(expected: &mut ParticleData, data: &ParticleData) {
+    let n = expected.positions.len() as u32;
+    expected.positions.extend(&data.positions);
+    expected.velocities.extend(&data.velocities);
+    expected.colors.extend(&data.colors);
+    expected.masses.extend(&data.masses);
+    expected.charges.extend(&data.charges);
+    expected
+        .bonds
+        .extend(bonds::remap(&data.bonds, |id| Some(n + id)));
+}
+
+/// Change the particle count to `n` and check that the particles kept are unchanged and
+/// that the new ones are those the scenario generates for `n` particles
+fn check_particle_count(sim: &mut Simulation, n: u32) -> anyhow::Result<()> {
+    let before = sim.snapshot()?;
+    sim.set_particle_count(n);
+    let after = sim.snapshot()?;
+
+    let mut expected = before.particles;
+    if n > before.params.n {
+        let params = SimParams {
+            n,
+            ..before.params.clone()
+        };
+        let mut generated = sim.scenarios.generate(&params);
+        let added = generated.split_off(before.params.n as usize);
+        append(&mut expected, &added);
+    } else {
+        expected.split_off(n as usize);
+    }
+
+    if after.params.n != n || !same_bits(&after.particles, &expected) {
+        anyhow::bail!(
+            "Changing the particle count from {} to {n} changed the particles kept",
+            before.params.n
+        );
+    }
+    if after.params.epoch != before.params.epoch || after.clock != before.clock {
+        anyhow::bail!("Changing the particle count reset the simulation");
+    }
+    Ok(())
+}
+
+/// Erase around `center` and compare the particles left with a CPU filter of the
+/// particles before, returns how many were removed
+fn check_erase(sim: &mut Simulation, center: Vec2) -> anyhow::Result<u32> {
+    let before = sim.snapshot()?;
+    let removed = sim.erase(center)?;
+
+    let radius = sim.params.brush.radius;
+    let world_size = sim.params.world[1] - sim.params.world[0];
+    let mut expected = ParticleData::with_capacity(before.params.n);
+    let mut new_indices = Vec::with_capacity(before.params.n as usize);
+    let particles = &before.particles;
+    for i in 0..particles.positions.len() {
+        let mut delta = Vec2::from(particles.positions[i]) - center;
+        if sim.params.wrap {
+            delta = cpu::wrapped_delta(delta, world_size);
+        }
+        let kept = delta.length_squared() >= radius * radius;
+        new_indices.push(kept.then_some(expected.positions.len() as u32));
+        if kept {
+            expected.positions.push(particles.positions[i]);
+            expected.velocities.push(particles.velocities[i]);
+            expected.colors.push(particles.colors[i]);
+            expected.masses.push(particles.masses[i]);
+            expected.charges.push(particles.charges[i]);
+        }
+    }
+
+    expected.bonds = bonds::remap(&particles.bonds, |id| new_indices[id as usize]);
+
+    let expected_removed = before.params.n - expected.positions.len() as u32;
+    if removed == 0 || removed != expected_removed {
+        anyhow::bail!(
+            "Erased {removed} of {} particles, the CPU finds {expected_removed}",
+            before.params.n
+        );
+    }
+    if !same_bits(&sim.snapshot()?.particles, &expected) {
+        anyhow::bail!("Erasing changed the remaining particles, their order or their bonds");
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn editing_matches_the_cpu_reference() -> anyhow::Result<()> {
+        validate(&Validation::quick(), 2_000)
+    }
+}
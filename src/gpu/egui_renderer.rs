@@ -58,6 +58,7 @@ impl EguiRenderer {
         self.state.on_window_event(window, event)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn draw(
         &mut self,
         device: &wgpu::Device,
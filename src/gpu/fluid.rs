@@ -0,0 +1,206 @@
+use crate::{
+    constants,
+    gpu::{compute, validation::Validation},
+    sim::{
+        SimParams,
+        fluid::{EquationOfState, Fluid, Kernel},
+        scenario::ChargeScheme,
+    },
+};
+
+/// GPU resources of the SPH fluid (`shaders/fluid.wgsl`)
+///
+/// The neighbors come from the [`super::grid::CellGrid`] built for the smoothing length,
+/// bound in group 1, the densities and pressures live in group 2.
+pub struct FluidPass {
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// (density, pressure) per particle
+    state: wgpu::Buffer,
+    /// Number of particles `state` can hold
+    capacity: u32,
+
+    bind_group: wgpu::BindGroup,
+
+    density_pipeline: wgpu::ComputePipeline,
+    forces_pipeline: wgpu::ComputePipeline,
+}
+
+pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("fluid_bgl"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            // densities and pressures
+            binding: 21,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+fn workgroups(n: u32) -> u32 {
+    n.div_ceil(constants::shader::WORKGROUP_SIZE).max(1)
+}
+
+fn make_state(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("fluid_state"),
+        size: std::mem::size_of::<[f32; 2]>() as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+fn make_bind_group(
+    device: &wgpu::Device,
+    bgl: &wgpu::BindGroupLayout,
+    state: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("fluid_bg"),
+        layout: bgl,
+        entries: &[wgpu::BindGroupEntry {
+            // densities and pressures
+            binding: 21,
+            resource: state.as_entire_binding(),
+        }],
+    })
+}
+
+impl FluidPass {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        compute_bind_group_layout: &wgpu::BindGroupLayout,
+        grid_bind_group_layout: &wgpu::BindGroupLayout,
+        n: u32,
+    ) -> Self {
+        let capacity = n.max(1);
+        let state = make_state(device, capacity);
+
+        let bind_group_layout = make_bind_group_layout(device);
+        let bind_group = make_bind_group(device, &bind_group_layout, &state);
+
+        let pipeline_layout = compute::make_pipeline_layout(
+            device,
+            &[
+                compute_bind_group_layout,
+                grid_bind_group_layout,
+                &bind_group_layout,
+            ],
+        );
+        let mk = |entry_point| {
+            compute::make_entry_pipeline(device, &pipeline_layout, shader, entry_point)
+        };
+
+        Self {
+            bind_group_layout,
+            state,
+            capacity,
+
+            bind_group,
+
+            density_pipeline: mk("fluid_density"),
+            forces_pipeline: mk("fluid_forces"),
+        }
+    }
+
+    /// Record the fluid accelerations of the `n` particles read by `compute_bind_group`,
+    /// after the cell lists in `grid_bind_group` were built for the smoothing length
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        compute_bind_group: &wgpu::BindGroup,
+        grid_bind_group: &wgpu::BindGroup,
+        n: u32,
+    ) {
+        if n > self.capacity {
+            self.capacity = n;
+            self.state = make_state(device, self.capacity);
+            self.bind_group = make_bind_group(device, &self.bind_group_layout, &self.state);
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Fluid Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, grid_bind_group, &[]);
+        compute_pass.set_bind_group(2, &self.bind_group, &[]);
+        compute_pass.set_pipeline(&self.density_pipeline);
+        compute_pass.dispatch_workgroups(workgroups(n), 1, 1);
+        compute_pass.set_pipeline(&self.forces_pipeline);
+        compute_pass.dispatch_workgroups(workgroups(n), 1, 1);
+    }
+}
+
+/// Smoothing length of the fluid checks, a few lattice spacings of the validation
+/// particles
+pub const VALIDATION_SMOOTHING: f32 = 0.12;
+/// Polytropic gas of the fluid checks, held together by its self-gravity: its rest
+/// density is the central density M / (pi a^2) of the validation Plummer sphere, so
+/// that its sound speed is close to the virial speed sqrt(G M / a) ~ 0.23
+pub const VALIDATION_GAS_SOUND_SPEED: f32 = 0.3;
+pub const VALIDATION_GAS_REST_DENSITY: f32 = 7_000.0;
+pub const VALIDATION_GAS_GAMMA: f32 = 1.4;
+
+/// Step the SPH fluid and the CPU reference side by side, a liquid between walls and
+/// a self-gravitating gas
+pub fn validate(v: &Validation) -> anyhow::Result<()> {
+    let params = v.params();
+    let liquid = Fluid {
+        enabled: true,
+        smoothing: VALIDATION_SMOOTHING,
+        ..Fluid::default()
+    };
+    v.compare_steps(
+        SimParams {
+            wrap: false,
+            fluid: liquid.clone(),
+            ..params.clone()
+        },
+        Some("dam-break"),
+        ChargeScheme::Neutral,
+        "the fluid between walls",
+    )?;
+    let gas = Fluid {
+        kernel: Kernel::WendlandC2,
+        rest_density: VALIDATION_GAS_REST_DENSITY,
+        sound_speed: VALIDATION_GAS_SOUND_SPEED,
+        equation_of_state: EquationOfState::Polytropic,
+        gamma: VALIDATION_GAS_GAMMA,
+        gravity: 0.0,
+        self_gravity: true,
+        ..liquid
+    };
+    v.compare_steps(
+        SimParams {
+            wrap: true,
+            fluid: gas,
+            ..params
+        },
+        Some("plummer"),
+        ChargeScheme::Neutral,
+        "the self-gravitating gas",
+    )?;
+
+    log::info!("GPU fluid matches the CPU reference");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn fluid_matches_the_cpu_reference() -> anyhow::Result<()> {
+        validate(&Validation::quick())
+    }
+}
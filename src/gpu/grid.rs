@@ -0,0 +1,400 @@
+use crate::{
+    constants,
+    gpu::{
+        buffers::GpuBuffers,
+        compute,
+        sort::RadixSort,
+        validation::{VALIDATION_EDGE, Validation},
+    },
+    sim::{
+        SimParams,
+        grid::{self, CellList},
+    },
+};
+
+/// Uniform of `shaders/grid.wgsl`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GridUniform {
+    cell: [f32; 2],
+    cells: [u32; 2],
+    radius: f32,
+    table: u32,
+    _pad: [u32; 2],
+}
+
+/// GPU cell lists of the particles (`shaders/grid.wgsl`), for short-range interactions
+///
+/// [`CellGrid::encode`] sorts the particles by cell and records where each cell starts
+/// and ends, the kernels appended to the compute shader then bind the grid in group 1 to
+/// visit the neighbors of a particle.
+pub struct CellGrid {
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: wgpu::BindGroup,
+    uniform: wgpu::Buffer,
+    /// Morton code of the cell of each particle, then sorted
+    keys: wgpu::Buffer,
+    /// Particle indices sorted by cell
+    particles: wgpu::Buffer,
+    /// Start and end in `particles` of each Morton code
+    ranges: wgpu::Buffer,
+    /// Particles within the radius of each particle, see `grid_count`
+    neighbors: wgpu::Buffer,
+    /// Number of particles and range table entries the buffers can hold
+    capacity: u32,
+    table_capacity: u32,
+    /// Range table entries of the last build
+    table: u32,
+
+    sort: RadixSort,
+
+    assign_pipeline: wgpu::ComputePipeline,
+    clear_pipeline: wgpu::ComputePipeline,
+    bounds_pipeline: wgpu::ComputePipeline,
+    count_pipeline: wgpu::ComputePipeline,
+}
+
+pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: false },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("grid_bgl"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                // grid parameters
+                binding: 16,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // cell Morton codes
+            storage(17),
+            // particles sorted by cell
+            storage(18),
+            // cell ranges
+            storage(19),
+            // neighbor counts
+            storage(20),
+        ],
+    })
+}
+
+fn workgroups(n: u32) -> u32 {
+    n.div_ceil(constants::shader::WORKGROUP_SIZE).max(1)
+}
+
+fn make_particle_buffers(device: &wgpu::Device, capacity: u32) -> [wgpu::Buffer; 3] {
+    let mk = |label: &str| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: std::mem::size_of::<u32>() as u64 * capacity as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    };
+    [mk("grid_keys"), mk("grid_particles"), mk("grid_neighbors")]
+}
+
+fn make_ranges(device: &wgpu::Device, table: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("grid_ranges"),
+        size: std::mem::size_of::<[u32; 2]>() as u64 * table as u64,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+impl CellGrid {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        compute_bind_group_layout: &wgpu::BindGroupLayout,
+        n: u32,
+    ) -> Self {
+        let bind_group_layout = make_bind_group_layout(device);
+        let pipeline_layout =
+            compute::make_pipeline_layout(device, &[compute_bind_group_layout, &bind_group_layout]);
+        let mk = |entry_point| {
+            compute::make_entry_pipeline(device, &pipeline_layout, shader, entry_point)
+        };
+
+        let capacity = n.max(1);
+        let [keys, particles, neighbors] = make_particle_buffers(device, capacity);
+        let ranges = make_ranges(device, 1);
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("grid_params"),
+            size: std::mem::size_of::<GridUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = make_bind_group(
+            device,
+            &bind_group_layout,
+            [&uniform, &keys, &particles, &ranges, &neighbors],
+        );
+
+        Self {
+            bind_group_layout,
+            bind_group,
+            uniform,
+            keys,
+            particles,
+            ranges,
+            neighbors,
+            capacity,
+            table_capacity: 1,
+            table: 1,
+
+            sort: RadixSort::new(device, capacity),
+
+            assign_pipeline: mk("grid_assign"),
+            clear_pipeline: mk("grid_clear"),
+            bounds_pipeline: mk("grid_bounds"),
+            count_pipeline: mk("grid_count"),
+        }
+    }
+
+    /// Layout of the group the kernels bind the cell lists in
+    pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.bind_group_layout
+    }
+
+    /// Cell lists of the last build, see [`CellGrid::encode`]
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    /// Record the cell lists of the `params.n` particles read by `compute_bind_group`,
+    /// for interactions within `radius`
+    ///
+    /// The grid parameters are written through `queue`, every build recorded before the
+    /// next submission must use the same `params` and `radius`.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        compute_bind_group: &wgpu::BindGroup,
+        params: &SimParams,
+        radius: f32,
+    ) {
+        let n = params.n;
+        let (cells, cell_size) = grid::dims(params, radius);
+        self.table = grid::table_size(cells);
+        if n > self.capacity || self.table > self.table_capacity {
+            if n > self.capacity {
+                self.capacity = n;
+                [self.keys, self.particles, self.neighbors] =
+                    make_particle_buffers(device, self.capacity);
+            }
+            if self.table > self.table_capacity {
+                self.table_capacity = self.table;
+                self.ranges = make_ranges(device, self.table_capacity);
+            }
+            self.bind_group = make_bind_group(
+                device,
+                &self.bind_group_layout,
+                [
+                    &self.uniform,
+                    &self.keys,
+                    &self.particles,
+                    &self.ranges,
+                    &self.neighbors,
+                ],
+            );
+        }
+
+        let uniform = GridUniform {
+            cell: cell_size.to_array(),
+            cells: cells.to_array(),
+            radius,
+            table: self.table,
+            _pad: [0; 2],
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Grid Assign Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, compute_bind_group, &[]);
+            compute_pass.set_bind_group(1, &self.bind_group, &[]);
+            compute_pass.set_pipeline(&self.assign_pipeline);
+            compute_pass.dispatch_workgroups(workgroups(n), 1, 1);
+        }
+
+        self.sort
+            .encode(device, queue, encoder, &self.keys, &self.particles, n);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Grid Bounds Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.bind_group, &[]);
+        compute_pass.set_pipeline(&self.clear_pipeline);
+        compute_pass.dispatch_workgroups(workgroups(2 * self.table), 1, 1);
+        compute_pass.set_pipeline(&self.bounds_pipeline);
+        compute_pass.dispatch_workgroups(workgroups(n), 1, 1);
+    }
+
+    /// Record the count of the particles within the radius of each particle, after
+    /// [`CellGrid::encode`]
+    pub fn encode_count(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        compute_bind_group: &wgpu::BindGroup,
+        n: u32,
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Grid Count Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.bind_group, &[]);
+        compute_pass.set_pipeline(&self.count_pipeline);
+        compute_pass.dispatch_workgroups(workgroups(n), 1, 1);
+    }
+
+    /// Read the cell lists of the last build of `n` particles back, see [`grid::build`]
+    ///
+    /// This blocks until the GPU is done, like [`GpuBuffers::read_back`].
+    pub fn read_cells(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        n: u32,
+    ) -> anyhow::Result<CellList> {
+        Ok(CellList {
+            particles: GpuBuffers::read_back(device, queue, &self.particles, n)?,
+            ranges: GpuBuffers::read_back(device, queue, &self.ranges, self.table)?,
+        })
+    }
+
+    /// Read the neighbor counts of the last [`CellGrid::encode_count`] back
+    pub fn read_neighbor_counts(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        n: u32,
+    ) -> anyhow::Result<Vec<u32>> {
+        GpuBuffers::read_back(device, queue, &self.neighbors, n)
+    }
+}
+
+fn make_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    [uniform, keys, particles, ranges, neighbors]: [&wgpu::Buffer; 5],
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("grid_bg"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 16,
+                resource: uniform.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 17,
+                resource: keys.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 18,
+                resource: particles.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 19,
+                resource: ranges.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 20,
+                resource: neighbors.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+/// Particles added by `--validate` across the wrapping edge
+pub const VALIDATION_BURST: u32 = 4_000;
+/// Interaction radii of the cell list checks, down to a single cell per side
+pub const VALIDATION_RADII: [f32; 3] = [0.05, 0.7, 1.5];
+
+/// Build the cell lists on the GPU with `burst` particles added across the edge, and
+/// count the neighbors through them, against the CPU lists and brute force, with and
+/// without wrapping
+pub fn validate(v: &Validation, burst: u32) -> anyhow::Result<()> {
+    let mut sim = v.simulation(v.params())?;
+    let mut rng = rand::SeedableRng::seed_from_u64(sim.params.seed);
+
+    log::info!(
+        "Validating the cell lists within {:?} of {} particles",
+        VALIDATION_RADII,
+        sim.params.n + 2 * burst
+    );
+
+    let mut neighbors = 0;
+    for wrap in [true, false] {
+        sim.params.wrap = wrap;
+        sim.sync_uniform();
+        // Stepped back into the world when it wraps, partly outside of it otherwise
+        let burst = sim
+            .params
+            .brush
+            .burst(VALIDATION_EDGE, burst, [1.0; 4], &mut rng);
+        sim.spawn(&burst);
+        if wrap {
+            sim.step(1);
+        }
+
+        let positions = sim.read_positions()?;
+        for radius in VALIDATION_RADII {
+            let (cells, counts) = sim.cell_lists(radius)?;
+            let expected = grid::build(&sim.params, radius, &positions);
+            if cells.particles != expected.particles || cells.ranges != expected.ranges {
+                anyhow::bail!(
+                    "The cell lists within {radius} (wrap {wrap}) differ from the CPU ones"
+                );
+            }
+            let expected_counts = grid::neighbor_counts(&sim.params, radius, &positions);
+            if let Some(i) = (0..counts.len()).find(|&i| counts[i] != expected_counts[i]) {
+                anyhow::bail!(
+                    "Particle {i} has {} neighbors within {radius} (wrap {wrap}) through the \
+cell lists, {} by brute force",
+                    counts[i],
+                    expected_counts[i]
+                );
+            }
+            neighbors += counts.iter().map(|&count| u64::from(count)).sum::<u64>();
+        }
+    }
+
+    log::info!("Found {neighbors} neighbors through the cell lists like brute force");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn cell_lists_match_the_cpu() -> anyhow::Result<()> {
+        validate(&Validation::quick(), 1_000)
+    }
+}
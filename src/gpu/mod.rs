@@ -1,18 +1,46 @@
+mod barnes_hut;
 mod buffers;
+mod camera;
+mod collisions;
 mod compute;
+mod density;
+mod diagnostics;
+mod edit;
 mod egui_renderer;
+mod fluid;
+mod grid;
+mod particle_mesh;
+mod readback;
+mod recorder;
 mod renderer;
+mod simulation;
+mod sort;
+mod timestep;
+mod validation;
 
+pub use camera::Camera;
+pub use density::{DensityTarget, RenderMode, RenderSettings, ToneMap};
 pub use egui_renderer::EguiRenderer;
+pub use recorder::{Recorder, RecorderSettings};
+pub use simulation::Simulation;
+pub use validation::{VALIDATION_SEED, VALIDATION_STEPS, validate};
 
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use glam::Vec2;
 use winit::window::Window;
 
 use crate::{
     constants,
-    gpu::buffers::GpuBuffers,
-    sim::{self, ParamsEguiAction, ParticleUpdated, SimParams, reset_galaxy},
+    sim::{
+        ParamsEguiAction, ParticleUpdated, SimParams,
+        brush::BrushMode,
+        scenario_file::{self, FileWatcher, ScenarioFile},
+        snapshot::{self, Snapshot},
+    },
 };
 
 #[repr(u32)]
@@ -46,11 +74,117 @@ impl BufferInUse {
     }
 }
 
+/// UI action that reads or replaces the particles
+///
+/// The step of the frame is already encoded when the UI runs, so these are applied
+/// once it is submitted and the buffers are ticked, see [`State::render`].
+enum DeferredAction {
+    Reset,
+    SaveSnapshot,
+    LoadSnapshot,
+    /// Grow or shrink to this many particles
+    ParticleCount(u32),
+    LoadScenarioFile,
+}
+
+/// Pick the best adapter, optionally restricted to those able to present to `surface`
+///
+/// Without a surface (headless mode) every adapter qualifies, software ones included.
+/// With `name`, only adapters whose name contains it (ignoring case) are considered.
+async fn select_adapter(
+    instance: &wgpu::Instance,
+    surface: Option<&wgpu::Surface<'_>>,
+    name: Option<&str>,
+) -> anyhow::Result<wgpu::Adapter> {
+    let mut adapters: Vec<wgpu::Adapter> = instance
+        .enumerate_adapters(wgpu::Backends::all())
+        .into_iter()
+        .filter(|a| surface.is_none_or(|surface| a.is_surface_supported(surface)))
+        .collect();
+
+    if let Some(name) = name {
+        let available: Vec<String> = adapters
+            .iter()
+            .map(|a| {
+                let info = a.get_info();
+                format!("'{}' ({:?})", info.name, info.backend)
+            })
+            .collect();
+        let pattern = name.to_lowercase();
+        adapters.retain(|a| a.get_info().name.to_lowercase().contains(&pattern));
+        if adapters.is_empty() {
+            anyhow::bail!(
+                "No WGPU adapter name contains '{name}', available: {}",
+                if available.is_empty() {
+                    "none".to_string()
+                } else {
+                    available.join(", ")
+                }
+            );
+        }
+    }
+
+    if adapters.is_empty() {
+        if surface.is_some() {
+            anyhow::bail!(
+                "No WGPU adapter supports the window surface. \
+This is common in WSL when GPU/WSLg support is missing or outdated."
+            );
+        }
+        anyhow::bail!(
+            "No WGPU adapter found. \
+Install a GPU driver or a software Vulkan implementation such as lavapipe."
+        );
+    }
+
+    let power_preference = wgpu::PowerPreference::default();
+    let rank = |info: &wgpu::AdapterInfo| -> u8 {
+        use wgpu::DeviceType::*;
+        match (power_preference, info.device_type) {
+            (wgpu::PowerPreference::None, DiscreteGpu) => 0,
+            (wgpu::PowerPreference::None, IntegratedGpu) => 1,
+            (wgpu::PowerPreference::HighPerformance, DiscreteGpu) => 0,
+            (wgpu::PowerPreference::HighPerformance, IntegratedGpu) => 1,
+            (wgpu::PowerPreference::LowPower, IntegratedGpu) => 0,
+            (wgpu::PowerPreference::LowPower, DiscreteGpu) => 1,
+            (_, VirtualGpu) => 2,
+            (_, Cpu) => 3,
+            (_, Other) => 4,
+        }
+    };
+
+    adapters.sort_by_key(|a| rank(&a.get_info()));
+    let adapter = adapters.remove(0);
+
+    let info = adapter.get_info();
+    log::info!(
+        "Selected adapter: {} (type: {:?}, backend: {:?}, driver: {})",
+        info.name,
+        info.device_type,
+        info.backend,
+        info.driver
+    );
+
+    Ok(adapter)
+}
+
+async fn request_device(adapter: &wgpu::Adapter) -> anyhow::Result<(wgpu::Device, wgpu::Queue)> {
+    let device_queue = adapter
+        .request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            required_limits: wgpu::Limits::default(),
+            memory_hints: Default::default(),
+            trace: wgpu::Trace::Off,
+        })
+        .await?;
+
+    Ok(device_queue)
+}
+
 pub struct State {
     // WGPU core components
     surface: wgpu::Surface<'static>,
-    device: wgpu::Device,
-    queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
 
     // Winit winwow
@@ -65,74 +199,56 @@ pub struct State {
     // Render pipeline
     render_bind_group_layout: wgpu::BindGroupLayout,
     render_pipeline: wgpu::RenderPipeline,
+    render_bond_pipeline: wgpu::RenderPipeline,
     render_bind_groups: [wgpu::BindGroup; 2],
+    /// Render mode and tone mapping, and the HDR target of the density mode
+    render_settings: RenderSettings,
+    density: DensityTarget,
 
-    /// Compute pipeline
-    compute_bind_group_layout: wgpu::BindGroupLayout,
-    compute_pipeline: wgpu::ComputePipeline,
-    compute_bind_groups: [wgpu::BindGroup; 2],
+    /// Pan/zoom view and the uniform buffer it is written to every frame
+    camera: Camera,
+    camera_buffer: wgpu::Buffer,
 
-    /// Buffers
-    buffers: buffers::GpuBuffers,
+    /// Device, buffers, compute pipeline and simulation parameters
+    sim: Simulation,
 
-    // Simulation state
-    params: sim::SimParams,
+    /// Path used by the snapshot buttons
+    snapshot_path: String,
 
-    // State information
-    last_frame: std::time::Instant,
-    buffer_in_use: BufferInUse,
-}
+    /// UI action waiting for the end of the frame
+    deferred_action: Option<DeferredAction>,
 
-impl State {
-    async fn select_adapter(
-        instance: &wgpu::Instance,
-        surface: &wgpu::Surface<'_>,
-    ) -> anyhow::Result<wgpu::Adapter> {
-        let mut adapters: Vec<wgpu::Adapter> = instance
-            .enumerate_adapters(wgpu::Backends::all())
-            .into_iter()
-            .filter(|a| a.is_surface_supported(surface))
-            .collect();
-
-        if adapters.is_empty() {
-            anyhow::bail!(
-                "No WGPU adapter supports the window surface. \
-This is common in WSL when GPU/WSLg support is missing or outdated."
-            );
-        }
-
-        let power_preference = wgpu::PowerPreference::default();
-        let rank = |info: &wgpu::AdapterInfo| -> u8 {
-            use wgpu::DeviceType::*;
-            match (power_preference, info.device_type) {
-                (wgpu::PowerPreference::None, DiscreteGpu) => 0,
-                (wgpu::PowerPreference::None, IntegratedGpu) => 1,
-                (wgpu::PowerPreference::HighPerformance, DiscreteGpu) => 0,
-                (wgpu::PowerPreference::HighPerformance, IntegratedGpu) => 1,
-                (wgpu::PowerPreference::LowPower, IntegratedGpu) => 0,
-                (wgpu::PowerPreference::LowPower, DiscreteGpu) => 1,
-                (_, VirtualGpu) => 2,
-                (_, Cpu) => 3,
-                (_, Other) => 4,
-            }
-        };
+    /// Path used by the scenario file buttons, and the watcher of the loaded file
+    scenario_file_path: String,
+    watch_scenario_file: bool,
+    scenario_watcher: Option<FileWatcher>,
 
-        adapters.sort_by_key(|a| rank(&a.get_info()));
-        let adapter = adapters.remove(0);
+    /// Settings of the next recording, and the recording in progress
+    recorder_settings: RecorderSettings,
+    recorder: Option<Recorder>,
 
-        let info = adapter.get_info();
-        log::info!(
-            "Selected adapter: {} (type: {:?}, backend: {:?}, driver: {})",
-            info.name,
-            info.device_type,
-            info.backend,
-            info.driver
-        );
+    /// Pointer holding the brush, in window pixels, and whether Shift reverses it
+    brush_cursor: Option<Vec2>,
+    brush_inverted: bool,
+    /// The current click already spawned its burst
+    brush_spawned: bool,
 
-        Ok(adapter)
-    }
+    // State information
+    last_frame: std::time::Instant,
+}
 
-    pub async fn new(window: Arc<Window>, enable_egui: bool) -> anyhow::Result<Self> {
+impl State {
+    /// Create the surface and the simulation for `window`
+    ///
+    /// `present_mode` and `adapter` come from the command line, the best available
+    /// choice is made when they are `None`.
+    pub async fn new(
+        window: Arc<Window>,
+        enable_egui: bool,
+        params: SimParams,
+        present_mode: Option<wgpu::PresentMode>,
+        adapter: Option<&str>,
+    ) -> anyhow::Result<Self> {
         let size = window.inner_size();
 
         let instance_desc = wgpu::InstanceDescriptor {
@@ -146,19 +262,9 @@ This is common in WSL when GPU/WSLg support is missing or outdated."
             .create_surface(window.clone())
             .expect("Failed to create surface");
 
-        let adapter = Self::select_adapter(&instance, &surface).await?;
-
-        let (device, queue) = adapter
-            .request_device(&wgpu::DeviceDescriptor {
-                label: None,
-                required_features: wgpu::Features::empty(),
-                required_limits: wgpu::Limits::default(),
-                memory_hints: Default::default(),
-                trace: wgpu::Trace::Off,
-                ..Default::default()
-            })
-            .await?;
+        let adapter = select_adapter(&instance, Some(&surface), adapter).await?;
 
+        let (device, queue) = request_device(&adapter).await?;
         let surface_caps = surface.get_capabilities(&adapter);
 
         // Try to find a srgb format, if not fallback to the first available
@@ -169,15 +275,23 @@ This is common in WSL when GPU/WSLg support is missing or outdated."
             .copied()
             .unwrap_or(wgpu::TextureFormat::Bgra8UnormSrgb);
 
-        // Try to find a present mode that supports low latency and vsync
-        // Fallback to the first available mode if not found
-        let mut present_mode = surface_caps.present_modes[0];
-        for &preferred in constants::gpu::PRESENT_MODE_PREFERENCES {
-            if surface_caps.present_modes.contains(&preferred) {
-                present_mode = preferred;
-                break;
-            }
-        }
+        // Use the requested present mode, otherwise try to find one that supports low
+        // latency and vsync, and fallback to the first available mode if not found
+        let present_mode = match present_mode {
+            // The automatic modes are always supported, wgpu picks among the others
+            Some(mode @ (wgpu::PresentMode::AutoVsync | wgpu::PresentMode::AutoNoVsync)) => mode,
+            Some(mode) if surface_caps.present_modes.contains(&mode) => mode,
+            Some(mode) => anyhow::bail!(
+                "Present mode {:?} is not supported by this surface, available: {:?}",
+                mode,
+                surface_caps.present_modes
+            ),
+            None => constants::gpu::PRESENT_MODE_PREFERENCES
+                .iter()
+                .copied()
+                .find(|mode| surface_caps.present_modes.contains(mode))
+                .unwrap_or(surface_caps.present_modes[0]),
+        };
         log::trace!("Chosen present mode: {:?}", present_mode);
 
         let config = wgpu::SurfaceConfiguration {
@@ -207,35 +321,40 @@ This is common in WSL when GPU/WSLg support is missing or outdated."
             None
         };
 
-        let params = SimParams::default();
-        let buffers = GpuBuffers::create(&device, params.n);
+        let sim = Simulation::new(device, queue, params);
+        let device = &sim.device;
 
-        let render_shader = renderer::make_shader(&device);
-        let render_bind_group_layout = renderer::make_bind_group_layout(&device);
+        let render_shader = renderer::make_shader(device);
+        let render_bind_group_layout = renderer::make_bind_group_layout(device);
         let render_pipeline_layout =
-            renderer::make_pipeline_layout(&device, &[&render_bind_group_layout]);
-        let render_pipeline = renderer::make_pipeline(
-            &device,
+            renderer::make_pipeline_layout(device, &[&render_bind_group_layout]);
+        let render_pipeline =
+            renderer::make_pipeline(device, &render_pipeline_layout, &render_shader, srgb_format);
+        let render_bond_pipeline = renderer::make_bond_pipeline(
+            device,
             &render_pipeline_layout,
             &render_shader,
             srgb_format,
         );
-        let render_bind_group =
-            renderer::make_bind_group(&device, &render_bind_group_layout, &buffers);
-
-        let compute_shader = compute::make_shader(&device);
-        let compute_bind_group_layout = compute::make_bind_group_layout(&device);
-        let compute_pipeline_layout =
-            compute::make_pipeline_layout(&device, &[&compute_bind_group_layout]);
-        let compute_pipeline =
-            compute::make_pipeline(&device, &compute_pipeline_layout, &compute_shader);
-        let compute_bind_group =
-            compute::make_bind_group(&device, &compute_bind_group_layout, &buffers);
-
-        let mut _self = Self {
-            surface,
+        let density = DensityTarget::new(
             device,
-            queue,
+            &render_pipeline_layout,
+            &render_shader,
+            srgb_format,
+            size.width,
+            size.height,
+        );
+        let camera = Camera::new(&sim.params.world, size.width, size.height);
+        let camera_buffer = renderer::make_camera_buffer(device);
+        let render_bind_group = renderer::make_bind_group(
+            device,
+            &render_bind_group_layout,
+            &sim.buffers,
+            &camera_buffer,
+        );
+
+        Ok(Self {
+            surface,
             config,
 
             window,
@@ -246,61 +365,209 @@ This is common in WSL when GPU/WSLg support is missing or outdated."
 
             render_bind_group_layout,
             render_pipeline,
+            render_bond_pipeline,
             render_bind_groups: render_bind_group,
+            render_settings: RenderSettings::default(),
+            density,
+
+            camera,
+            camera_buffer,
+
+            sim,
 
-            compute_bind_group_layout,
-            compute_pipeline,
-            compute_bind_groups: compute_bind_group,
+            snapshot_path: constants::snapshot::DEFAULT_PATH.to_string(),
 
-            buffers,
+            deferred_action: None,
 
-            params,
+            scenario_file_path: constants::scenario_file::DEFAULT_PATH.to_string(),
+            watch_scenario_file: constants::scenario_file::WATCH,
+            scenario_watcher: None,
 
+            recorder_settings: RecorderSettings::default(),
+            recorder: None,
+
+            brush_cursor: None,
+            brush_inverted: false,
+            brush_spawned: false,
             last_frame: std::time::Instant::now(),
-            buffer_in_use: BufferInUse::Primary,
-        };
+        })
+    }
 
-        _self.resize_particles();
-        _self.sync_uniform();
+    /// Replace the render mode and tone mapping, as the UI would
+    pub fn set_render_settings(&mut self, settings: RenderSettings) {
+        self.render_settings = settings;
+    }
 
-        Ok(_self)
+    fn rebuild_render_bind_groups(&mut self) {
+        self.render_bind_groups = renderer::make_bind_group(
+            &self.sim.device,
+            &self.render_bind_group_layout,
+            &self.sim.buffers,
+            &self.camera_buffer,
+        );
     }
 
-    pub fn resize_particles(&mut self) {
-        // Resize buffers if needed
-        if self.params.n > self.buffers.capacity {
-            self.buffers.resize(&self.device, self.params.n);
-            self.render_bind_groups = renderer::make_bind_group(
-                &self.device,
-                &self.render_bind_group_layout,
-                &self.buffers,
-            );
-            self.compute_bind_groups = compute::make_bind_group(
-                &self.device,
-                &self.compute_bind_group_layout,
-                &self.buffers,
-            );
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// Show the whole world again
+    pub fn reset_camera(&mut self) {
+        self.camera.fit(&self.sim.params.world);
+    }
+
+    /// Hold the brush at `cursor` (window pixels), or release it with `None`
+    pub fn set_brush(&mut self, cursor: Option<Vec2>, inverted: bool) {
+        self.brush_cursor = cursor;
+        self.brush_inverted = inverted;
+    }
+
+    /// Spawn a burst once per click, or erase under the brush while it is held
+    fn apply_brush_edit(&mut self, target: Option<Vec2>) {
+        match self.sim.params.brush.mode {
+            BrushMode::Spawn => {
+                if let Some(target) = target
+                    && !self.brush_spawned
+                    && self.sim.spawn_burst(target)
+                {
+                    self.rebuild_render_bind_groups();
+                }
+                self.brush_spawned = target.is_some();
+            }
+            BrushMode::Erase => {
+                if let Some(target) = target
+                    && let Err(err) = self.sim.erase(target)
+                {
+                    log::error!("Failed to erase particles: {err:#}");
+                }
+            }
+            BrushMode::Attract | BrushMode::Repel => {}
+        }
+    }
+
+    pub fn reset_particles(&mut self) {
+        if self.sim.reset_particles() {
+            self.rebuild_render_bind_groups();
         }
+    }
 
-        self.params.reset_epoch();
+    /// Add or drop particles without resetting the others, see [`Simulation::set_particle_count`]
+    pub fn set_particle_count(&mut self, n: u32) {
+        if self.sim.set_particle_count(n) {
+            self.rebuild_render_bind_groups();
+        }
+    }
 
-        // Compute new initial positions and velocities
-        let (pos, vel, col) = reset_galaxy(self.params.n);
-        self.buffer_in_use = BufferInUse::Primary; // reset to primary on upload
+    pub fn save_snapshot(&self, path: &Path) -> anyhow::Result<()> {
+        self.sim.snapshot()?.save(path)?;
+        log::info!(
+            "Saved snapshot of epoch {} to '{}'",
+            self.sim.params.epoch,
+            path.display()
+        );
+        Ok(())
+    }
 
-        // Upload to GPU
-        self.buffers.upload_data(
-            &self.queue,
-            Some(&pos),
-            Some(&vel),
-            Some(&col),
-            Some(&self.params),
+    pub fn load_snapshot(&mut self, path: &Path) -> anyhow::Result<()> {
+        let snapshot = Snapshot::load(path)?;
+        if self.sim.restore(snapshot) {
+            self.rebuild_render_bind_groups();
+        }
+        log::info!(
+            "Loaded snapshot of epoch {} with {} particles from '{}'",
+            self.sim.params.epoch,
+            self.sim.params.n,
+            path.display()
         );
+        Ok(())
     }
 
-    pub fn sync_uniform(&mut self) {
-        self.buffers
-            .upload_data(&self.queue, None, None, None, Some(&self.params));
+    /// Apply a scenario file, see [`Simulation::configure`]
+    pub fn apply_scenario_file(
+        &mut self,
+        file: &ScenarioFile,
+        regenerate: bool,
+    ) -> anyhow::Result<()> {
+        if self.sim.configure(file, regenerate)? {
+            self.rebuild_render_bind_groups();
+        }
+        if let Some(view) = &file.camera {
+            self.camera.set_view(view);
+        }
+        Ok(())
+    }
+
+    /// Load and apply the scenario file at `path`, then watch it if enabled
+    pub fn load_scenario_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        let file = ScenarioFile::load(path)?;
+        self.apply_scenario_file(&file, true)?;
+        log::info!("Loaded scenario file '{}'", path.display());
+        self.start_watching(path, file);
+        Ok(())
+    }
+
+    /// Watch `path` if enabled, `file` is the content that was applied from it
+    pub fn start_watching(&mut self, path: &Path, file: ScenarioFile) {
+        self.scenario_file_path = path.display().to_string();
+        self.scenario_watcher = self
+            .watch_scenario_file
+            .then(|| FileWatcher::new(path.to_path_buf(), file));
+    }
+
+    pub fn save_scenario_file(&self, path: &Path) -> anyhow::Result<()> {
+        ScenarioFile::from_state(&self.sim.params, &self.sim.scenarios, self.camera.view())?
+            .save(path)?;
+        log::info!("Saved scenario file '{}'", path.display());
+        Ok(())
+    }
+
+    /// Reapply the watched scenario file if it changed
+    fn poll_scenario_file(&mut self) {
+        let Some(watcher) = &mut self.scenario_watcher else {
+            return;
+        };
+        let path = watcher.path().to_path_buf();
+        match watcher.poll() {
+            None => {}
+            Some(Ok((file, regenerate))) => match self.apply_scenario_file(&file, regenerate) {
+                Ok(()) => log::info!("Reloaded scenario file '{}'", path.display()),
+                Err(err) => log::error!("Failed to reload '{}': {err:#}", path.display()),
+            },
+            Some(Err(err)) => log::error!("{err:#}"),
+        }
+    }
+
+    /// Start writing frames with `settings`, replacing the recording in progress
+    pub fn start_recording(&mut self, settings: RecorderSettings) -> anyhow::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(Recorder::start(
+            &self.sim,
+            &self.camera,
+            &self.render_settings,
+            &settings,
+        )?);
+        self.recorder_settings = settings;
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> anyhow::Result<()> {
+        if let Some(recorder) = self.recorder.take() {
+            recorder.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Write the frames that became due after a step of `dt`, stopping on error
+    fn record_step(&mut self, dt: f32) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(err) = recorder.advance(&self.sim, &self.camera, &self.render_settings, dt) {
+            log::error!("Recording stopped: {err:#}");
+            if let Err(err) = self.stop_recording() {
+                log::error!("{err:#}");
+            }
+        }
     }
 
     pub fn handle_egui_event(
@@ -318,13 +585,17 @@ This is common in WSL when GPU/WSLg support is missing or outdated."
         if width > 0 && height > 0 {
             self.config.width = width;
             self.config.height = height;
+            self.camera.resize(width, height);
             // Ensure all operations are done before resizing
-            _ = self.device.poll(wgpu::PollType::Wait);
-            self.surface.configure(&self.device, &self.config);
+            _ = self.sim.device.poll(wgpu::PollType::Wait);
+            self.surface.configure(&self.sim.device, &self.config);
+            self.density.resize(&self.sim.device, width, height);
         }
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        self.poll_scenario_file();
+
         let output = self.surface.get_current_texture()?;
 
         let srgb_view = output.texture.create_view(&wgpu::TextureViewDescriptor {
@@ -333,18 +604,37 @@ This is common in WSL when GPU/WSLg support is missing or outdated."
         });
 
         let mut encoder = self
+            .sim
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
 
+        let camera = self.camera.to_uniform();
+        self.sim.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(std::slice::from_ref(&camera)),
+        );
+
+        // The brush target follows both the pointer and the camera
+        let target = self
+            .brush_cursor
+            .map(|cursor| self.camera.screen_to_world(cursor));
+        self.apply_brush_edit(target);
+        self.sim.set_brush(target, self.brush_inverted);
+
         // Update simulation state
-        self._update(&mut encoder, None);
+        let time = self.sim.clock.time;
+        let stepped = !self.sim.params.paused;
+        if stepped {
+            self.sim.encode_frame(&mut encoder);
+        }
         // Render the scene
         self._render(&mut encoder, &srgb_view);
         // Render the egui UI
-        self._render_egui(&mut encoder, &srgb_view);
+        let stepped = self._render_egui(&mut encoder, &srgb_view) || stepped;
 
         // Submit the commands
-        self.queue.submit(Some(encoder.finish()));
+        self.sim.queue.submit(Some(encoder.finish()));
 
         // Drop the views to release the borrow on the texture
         drop(srgb_view);
@@ -353,64 +643,84 @@ This is common in WSL when GPU/WSLg support is missing or outdated."
         output.present();
 
         // Tick the buffer in use
-        if !self.params.paused {
-            self.buffer_in_use.tick();
-            // Swap out bootstrap flag after first frame
-            if self.params.bootstrap {
-                self.params.bootstrap = false;
-                self.sync_uniform();
-            }
+        if stepped {
+            self.sim.finish_update();
+        }
+        self.sim.poll_clock();
+        self.sim.poll_diagnostics(false);
+        // Adaptive steps reach the clock late, and a reset rewinds it
+        let elapsed = self.sim.clock.time - time;
+        if elapsed > 0.0 {
+            self.record_step(elapsed);
+        }
+
+        if let Some(action) = self.deferred_action.take() {
+            self.apply_deferred_action(action);
         }
 
         Ok(())
     }
 
-    /// Update simulation state via compute shader
-    fn _update(&mut self, encoder: &mut wgpu::CommandEncoder, override_paused: Option<()>) {
-        if self.params.paused && override_paused.is_none() {
-            return;
+    fn apply_deferred_action(&mut self, action: DeferredAction) {
+        let result = match action {
+            DeferredAction::Reset => {
+                self.reset_particles();
+                Ok(())
+            }
+            DeferredAction::SaveSnapshot => {
+                let path = self.snapshot_path.clone();
+                self.save_snapshot(Path::new(&path))
+            }
+            DeferredAction::LoadSnapshot => {
+                let path = self.snapshot_path.clone();
+                self.load_snapshot(Path::new(&path))
+            }
+            DeferredAction::LoadScenarioFile => {
+                let path = self.scenario_file_path.clone();
+                self.load_scenario_file(Path::new(&path))
+            }
+            DeferredAction::ParticleCount(n) => {
+                self.set_particle_count(n);
+                Ok(())
+            }
+        };
+        if let Err(err) = result {
+            log::error!("{err:#}");
         }
-
-        self.params.increment_epoch(); // Increment epoch each update
-
-        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-            label: Some("Compute Pass"),
-            timestamp_writes: None,
-        });
-
-        let id = self.buffer_in_use.id_compute();
-
-        compute_pass.set_pipeline(&self.compute_pipeline);
-        compute_pass.set_bind_group(0, &self.compute_bind_groups[id], &[]);
-
-        let workgroup_count = (self.params.n + constants::shader::WORKGROUP_SIZE - 1)
-            / constants::shader::WORKGROUP_SIZE;
-
-        compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
     }
 
     fn _render(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
-        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Clear Pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: view,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(constants::gpu::BACKGROUND_COLOR),
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
-            ..Default::default()
-        });
-
-        let id = self.buffer_in_use.id_render();
-
-        render_pass.set_pipeline(&self.render_pipeline);
-        render_pass.set_bind_group(0, &self.render_bind_groups[id], &[]);
-        render_pass.draw(0..self.params.n, 0..1);
+        let id = self.sim.buffer_in_use.id_render();
+        match self.render_settings.mode {
+            RenderMode::Blended => renderer::draw(
+                encoder,
+                view,
+                &self.render_pipeline,
+                &self.render_bond_pipeline,
+                &self.render_bind_groups[id],
+                self.sim.params.n,
+                self.sim.drawn_bonds(),
+            ),
+            RenderMode::Density => self.density.draw(
+                &self.sim.queue,
+                encoder,
+                view,
+                &self.render_bind_groups[id],
+                self.sim.params.n,
+                self.sim.drawn_bonds(),
+                &self.render_settings,
+            ),
+        }
     }
 
-    fn _render_egui(&mut self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+    /// Draw the egui UI and apply its actions, returns `true` if a manual step was recorded
+    fn _render_egui(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &wgpu::TextureView,
+    ) -> bool {
+        let mut stepped = false;
+
         // Render the egui frame
         if let Some(egui) = &mut self.egui {
             let screen_descriptor = egui_wgpu::ScreenDescriptor {
@@ -418,14 +728,26 @@ This is common in WSL when GPU/WSLg support is missing or outdated."
                 pixels_per_point: egui.context.pixels_per_point(),
             };
 
-            let mut params = std::mem::take(&mut self.params);
+            let mut params = std::mem::take(&mut self.sim.params);
+            let previous_n = params.n;
             let mut action = ParamsEguiAction::None;
 
             let mut last_frame = self.last_frame;
+            let snapshot_path = &mut self.snapshot_path;
+            let scenario_file_path = &mut self.scenario_file_path;
+            let watch_scenario_file = &mut self.watch_scenario_file;
+            let watched = self.scenario_watcher.as_ref().map(FileWatcher::path);
+            let scenarios = &mut self.sim.scenarios;
+            let diagnostics = &mut self.sim.diagnostics;
+            let clock = &self.sim.clock;
+            let camera = &mut self.camera;
+            let render_settings = &mut self.render_settings;
+            let recorder_settings = &mut self.recorder_settings;
+            let recorder_frames = self.recorder.as_ref().map(Recorder::frames);
 
             egui.draw(
-                &self.device,
-                &self.queue,
+                &self.sim.device,
+                &self.sim.queue,
                 encoder,
                 &self.window,
                 view,
@@ -435,35 +757,127 @@ This is common in WSL when GPU/WSLg support is missing or outdated."
                         .default_width(300.0)
                         .resizable(true)
                         .show(ctx, |ui| {
-                            action = params.render_info(ui, &mut last_frame);
+                            action = params.render_info(ui, &mut last_frame, diagnostics, clock);
+
+                            ui.separator();
+                            let fluid_action = params.fluid.render_ui(ui);
+                            if !matches!(fluid_action, ParamsEguiAction::None) {
+                                action = fluid_action;
+                            }
+
+                            ui.separator();
+                            let scenario_action = scenarios.render_ui(ui);
+                            if !matches!(scenario_action, ParamsEguiAction::None) {
+                                action = scenario_action;
+                            }
+
+                            ui.separator();
+                            let file_action = scenario_file::render_controls(
+                                ui,
+                                scenario_file_path,
+                                watch_scenario_file,
+                                watched,
+                            );
+                            if !matches!(file_action, ParamsEguiAction::None) {
+                                action = file_action;
+                            }
+
+                            ui.separator();
+                            camera.render_ui(ui, &params.world);
+
+                            ui.separator();
+                            render_settings.render_ui(ui);
+
+                            ui.separator();
+                            let brush_action = params.brush.render_ui(ui);
+                            if !matches!(brush_action, ParamsEguiAction::None) {
+                                action = brush_action;
+                            }
+
+                            ui.separator();
+                            let snapshot_action = snapshot::render_controls(ui, snapshot_path);
+                            if !matches!(snapshot_action, ParamsEguiAction::None) {
+                                action = snapshot_action;
+                            }
+
+                            ui.separator();
+                            let recorder_action =
+                                recorder::render_controls(ui, recorder_settings, recorder_frames);
+                            if !matches!(recorder_action, ParamsEguiAction::None) {
+                                action = recorder_action;
+                            }
                         });
                 },
             );
 
             // Put the params back
-            self.params = params;
+            self.sim.params = params;
             self.last_frame = last_frame;
 
             // Handle any actions from the UI
             match action {
                 ParamsEguiAction::None => {}
-                ParamsEguiAction::Reset
-                | ParamsEguiAction::ParameterUpdated(ParticleUpdated::Less)
+                ParamsEguiAction::Reset => {
+                    self.deferred_action = Some(DeferredAction::Reset);
+                }
+                ParamsEguiAction::ParameterUpdated(ParticleUpdated::Less)
                 | ParamsEguiAction::ParameterUpdated(ParticleUpdated::More) => {
-                    self.resize_particles();
+                    // The simulation changes its count from the one it holds
+                    let n = std::mem::replace(&mut self.sim.params.n, previous_n);
+                    self.deferred_action = Some(DeferredAction::ParticleCount(n));
                 }
                 ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same) => {
-                    self.sync_uniform();
+                    self.sim.sync_uniform();
                 }
                 ParamsEguiAction::Step => {
                     debug_assert!(
-                        self.params.paused,
+                        self.sim.params.paused,
                         "Step action should only be possible when paused"
                     );
-                    self._update(encoder, Some(()));
-                    self.buffer_in_use.tick(); // Advance buffer even if paused
+                    // Buffers are advanced after submit, even if paused
+                    self.sim.encode_frame(encoder);
+                    stepped = true;
+                }
+                ParamsEguiAction::SaveSnapshot => {
+                    self.deferred_action = Some(DeferredAction::SaveSnapshot);
+                }
+                ParamsEguiAction::LoadSnapshot => {
+                    self.deferred_action = Some(DeferredAction::LoadSnapshot);
+                }
+                ParamsEguiAction::StartRecording => {
+                    let settings = self.recorder_settings.clone();
+                    if let Err(err) = self.start_recording(settings) {
+                        log::error!("Failed to start recording: {err:#}");
+                    }
+                }
+                ParamsEguiAction::StopRecording => {
+                    if let Err(err) = self.stop_recording() {
+                        log::error!("{err:#}");
+                    }
+                }
+                ParamsEguiAction::LoadScenarioFile => {
+                    self.deferred_action = Some(DeferredAction::LoadScenarioFile);
+                }
+                ParamsEguiAction::SaveScenarioFile => {
+                    let path = self.scenario_file_path.clone();
+                    if let Err(err) = self.save_scenario_file(Path::new(&path)) {
+                        log::error!("{err:#}");
+                    }
+                }
+                ParamsEguiAction::WatchScenarioFile => {
+                    // Watching starts from the file as it is now, without applying it
+                    let path = PathBuf::from(&self.scenario_file_path);
+                    if !self.watch_scenario_file {
+                        self.scenario_watcher = None;
+                    } else if let Err(err) =
+                        ScenarioFile::load(&path).map(|file| self.start_watching(&path, file))
+                    {
+                        log::error!("{err:#}");
+                    }
                 }
             }
         }
+
+        stepped
     }
 }
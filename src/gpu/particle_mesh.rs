@@ -0,0 +1,443 @@
+use crate::{
+    constants,
+    gpu::{
+        compute,
+        sort::RadixSort,
+        validation::{FirstKick, VALIDATION_MASS_SCALES, Validation},
+    },
+    sim::{SimParams, Solver, pm},
+};
+
+/// Byte stride between the FFT stage entries of the stage uniform (dynamic offset alignment)
+const STAGE_STRIDE: u64 = 256;
+
+/// Grid dependent resources, recreated when the grid resolution changes
+struct Grid {
+    size: u32,
+    /// Number of FFT stages per axis (log2 of the size)
+    stages: u32,
+
+    /// Start and end in the sorted particles of the stencils with their lower cell here
+    ranges: wgpu::Buffer,
+    /// Green's function spectrum, uploaded from the CPU
+    green: wgpu::Buffer,
+    /// Key of the uploaded Green's function (softening, world), see `green_key`
+    green_key: [f32; 5],
+
+    /// ID0 := read A, write B
+    /// ID1 := read B, write A
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+/// GPU resources of the particle-mesh solver (`shaders/particle_mesh.wgsl`)
+pub struct ParticleMesh {
+    bind_group_layout: wgpu::BindGroupLayout,
+    grid: Option<Grid>,
+    /// Lower stencil cell of each particle, then sorted
+    keys: wgpu::Buffer,
+    /// Particle indices sorted by lower stencil cell
+    particles: wgpu::Buffer,
+    /// Number of particles `keys` and `particles` can hold
+    capacity: u32,
+
+    sort: RadixSort,
+
+    assign_pipeline: wgpu::ComputePipeline,
+    bounds_pipeline: wgpu::ComputePipeline,
+    load_pipeline: wgpu::ComputePipeline,
+    fft_pipeline: wgpu::ComputePipeline,
+    convolve_pipeline: wgpu::ComputePipeline,
+    gradient_pipeline: wgpu::ComputePipeline,
+    update_pipeline: wgpu::ComputePipeline,
+}
+
+fn green_key(params: &SimParams) -> [f32; 5] {
+    [
+        params.softening,
+        params.world[0].x,
+        params.world[0].y,
+        params.world[1].x,
+        params.world[1].y,
+    ]
+}
+
+/// Offset into the stage uniform of stage `stage` along `axis`, for the forward or inverse pass
+fn stage_offset(stages: u32, stage: u32, axis: u32, inverse: bool) -> u32 {
+    let index = (inverse as u32 * 2 + axis) * stages + stage;
+    (STAGE_STRIDE * index as u64) as u32
+}
+
+pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("particle_mesh_bgl"),
+        entries: &[
+            // cell ranges
+            storage(3, false),
+            // complex field read
+            storage(4, true),
+            // complex field write
+            storage(5, false),
+            // green's function spectrum
+            storage(6, true),
+            // acceleration field
+            storage(7, false),
+            wgpu::BindGroupLayoutEntry {
+                // FFT stage
+                binding: 8,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(16),
+                },
+                count: None,
+            },
+            // lower stencil cells
+            storage(26, false),
+            // particles sorted by lower stencil cell
+            storage(27, false),
+        ],
+    })
+}
+
+fn make_particle_buffers(device: &wgpu::Device, capacity: u32) -> [wgpu::Buffer; 2] {
+    let mk = |label: &str| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: std::mem::size_of::<u32>() as u64 * capacity as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    };
+    [mk("pm_keys"), mk("pm_particles")]
+}
+
+impl Grid {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bgl: &wgpu::BindGroupLayout,
+        size: u32,
+        [keys, particles]: [&wgpu::Buffer; 2],
+    ) -> Self {
+        debug_assert!(size.is_power_of_two());
+        let stages = size.trailing_zeros();
+        let cells = size as u64 * size as u64;
+
+        let mk = |label: &str, size: u64, usage: wgpu::BufferUsages| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+
+        let c_size = std::mem::size_of::<[f32; 2]>() as u64 * cells;
+        let ranges = mk(
+            "pm_ranges",
+            std::mem::size_of::<[u32; 2]>() as u64 * cells,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+        let field_a = mk("pm_field_a", c_size, wgpu::BufferUsages::STORAGE);
+        let field_b = mk("pm_field_b", c_size, wgpu::BufferUsages::STORAGE);
+        let green = mk(
+            "pm_green",
+            c_size,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        );
+        let acc = mk("pm_acc", c_size, wgpu::BufferUsages::STORAGE);
+
+        // One entry per (inverse, axis, stage), in the order of `stage_offset`
+        let stage_buffer = mk(
+            "pm_fft_stages",
+            STAGE_STRIDE * 4 * stages as u64,
+            wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        );
+        for inverse in [false, true] {
+            for axis in 0..2 {
+                for stage in 0..stages {
+                    queue.write_buffer(
+                        &stage_buffer,
+                        stage_offset(stages, stage, axis, inverse) as u64,
+                        bytemuck::cast_slice(&[1u32 << stage, axis, inverse as u32, 0]),
+                    );
+                }
+            }
+        }
+
+        let make_bind_group = |label, src: &wgpu::Buffer, dst: &wgpu::Buffer| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(label),
+                layout: bgl,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: ranges.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 4,
+                        resource: src.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 5,
+                        resource: dst.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 6,
+                        resource: green.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 7,
+                        resource: acc.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        // one 16 byte entry per dynamic offset
+                        binding: 8,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &stage_buffer,
+                            offset: 0,
+                            size: wgpu::BufferSize::new(16),
+                        }),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 26,
+                        resource: keys.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 27,
+                        resource: particles.as_entire_binding(),
+                    },
+                ],
+            })
+        };
+
+        let bind_groups = [
+            make_bind_group("particle_mesh_bg_a_to_b", &field_a, &field_b),
+            make_bind_group("particle_mesh_bg_b_to_a", &field_b, &field_a),
+        ];
+
+        Self {
+            size,
+            stages,
+            ranges,
+            green,
+            green_key: [f32::NAN; 5],
+            bind_groups,
+        }
+    }
+}
+
+impl ParticleMesh {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        compute_bind_group_layout: &wgpu::BindGroupLayout,
+        n: u32,
+    ) -> Self {
+        let capacity = n.max(1);
+        let [keys, particles] = make_particle_buffers(device, capacity);
+        let bind_group_layout = make_bind_group_layout(device);
+        let pipeline_layout =
+            compute::make_pipeline_layout(device, &[compute_bind_group_layout, &bind_group_layout]);
+        let mk = |entry_point| {
+            compute::make_entry_pipeline(device, &pipeline_layout, shader, entry_point)
+        };
+
+        Self {
+            assign_pipeline: mk("pm_assign"),
+            bounds_pipeline: mk("pm_bounds"),
+            load_pipeline: mk("pm_load"),
+            fft_pipeline: mk("pm_fft"),
+            convolve_pipeline: mk("pm_convolve"),
+            gradient_pipeline: mk("pm_gradient"),
+            update_pipeline: mk("update_particle_mesh"),
+
+            bind_group_layout,
+            grid: None,
+            keys,
+            particles,
+            capacity,
+
+            sort: RadixSort::new(device, capacity),
+        }
+    }
+
+    /// Allocate the grid and upload the Green's function if the parameters changed
+    pub fn prepare(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, params: &SimParams) {
+        if params.n > self.capacity {
+            self.capacity = params.n;
+            [self.keys, self.particles] = make_particle_buffers(device, params.n);
+            // The grid binds the particle buffers
+            self.grid = None;
+        }
+        if self
+            .grid
+            .as_ref()
+            .is_none_or(|grid| grid.size != params.pm_grid)
+        {
+            self.grid = Some(Grid::new(
+                device,
+                queue,
+                &self.bind_group_layout,
+                params.pm_grid,
+                [&self.keys, &self.particles],
+            ));
+        }
+
+        let grid = self.grid.as_mut().expect("grid was just created");
+        let key = green_key(params);
+        if grid.green_key != key {
+            let green: Vec<[f32; 2]> = pm::green_spectrum(params, grid.size)
+                .iter()
+                .map(|c| [c[0] as f32, c[1] as f32])
+                .collect();
+            queue.write_buffer(&grid.green, 0, bytemuck::cast_slice(&green));
+            grid.green_key = key;
+        }
+    }
+
+    /// Record the mesh solve and the force/integration pass for `n` particles
+    ///
+    /// [`ParticleMesh::prepare`] must have been called with the current parameters. The
+    /// sort parameters are written through `queue`, see [`RadixSort::encode`].
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        compute_bind_group: &wgpu::BindGroup,
+        n: u32,
+    ) {
+        let Some(grid) = &self.grid else {
+            log::warn!("Particle-mesh grid is not prepared, skipping step");
+            return;
+        };
+
+        let workgroup_size = constants::shader::WORKGROUP_SIZE;
+        let cells = grid.size * grid.size;
+        let cell_groups = cells.div_ceil(workgroup_size);
+
+        // Sort the particles by lower stencil cell
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Particle-Mesh Assign Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_bind_group(0, compute_bind_group, &[]);
+            compute_pass.set_bind_group(1, &grid.bind_groups[0], &[0]);
+            compute_pass.set_pipeline(&self.assign_pipeline);
+            compute_pass.dispatch_workgroups(n.div_ceil(workgroup_size), 1, 1);
+        }
+        self.sort
+            .encode(device, queue, encoder, &self.keys, &self.particles, n);
+        encoder.clear_buffer(&grid.ranges, 0, None);
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Particle-Mesh Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, compute_bind_group, &[]);
+
+        // Index of the field buffer holding the latest data (0 = A, 1 = B)
+        let mut current = 0;
+        let mut dispatch = |pass: &mut wgpu::ComputePass,
+                            pipeline: &wgpu::ComputePipeline,
+                            offset: u32,
+                            groups: u32| {
+            // Read from the current buffer, write the other one
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(1, &grid.bind_groups[current], &[offset]);
+            pass.dispatch_workgroups(groups, 1, 1);
+            current = 1 - current;
+        };
+
+        // Cell ranges (no field access), then the mass into field A
+        dispatch(
+            &mut compute_pass,
+            &self.bounds_pipeline,
+            0,
+            n.div_ceil(workgroup_size),
+        );
+        dispatch(&mut compute_pass, &self.load_pipeline, 0, cell_groups);
+
+        // Forward FFT, convolution, inverse FFT
+        let fft_groups = (cells / 2).div_ceil(workgroup_size);
+        for inverse in [false, true] {
+            for axis in 0..2 {
+                for stage in 0..grid.stages {
+                    let offset = stage_offset(grid.stages, stage, axis, inverse);
+                    dispatch(&mut compute_pass, &self.fft_pipeline, offset, fft_groups);
+                }
+            }
+            if !inverse {
+                dispatch(&mut compute_pass, &self.convolve_pipeline, 0, cell_groups);
+            }
+        }
+
+        // Potential to acceleration field, then interpolate and integrate
+        dispatch(&mut compute_pass, &self.gradient_pipeline, 0, cell_groups);
+        dispatch(
+            &mut compute_pass,
+            &self.update_pipeline,
+            0,
+            n.div_ceil(workgroup_size),
+        );
+    }
+}
+
+/// Largest relative RMS force error accepted against the CPU mesh
+pub const VALIDATION_MESH_TOLERANCE: f32 = 2e-3;
+
+/// Compare the first kick of the GPU mesh with the CPU mesh, with the masses scaled by
+/// each of [`VALIDATION_MASS_SCALES`]
+///
+/// The error against the direct sum is only reported, it depends on the grid resolution.
+pub fn validate(v: &Validation) -> anyhow::Result<()> {
+    let v = Validation {
+        solver: Solver::ParticleMesh,
+        ..*v
+    };
+    for mass_scale in VALIDATION_MASS_SCALES {
+        let kick = FirstKick::run(&v, mass_scale)?;
+        log::info!(
+            "Particle-Mesh error against the direct sum: {:e} (grid {}²)",
+            kick.error(&kick.direct),
+            v.params().pm_grid
+        );
+        let err = kick.error(&kick.reference);
+        log::info!(
+            "Particle-Mesh relative force error with the masses scaled by {mass_scale:e}: {err:e}"
+        );
+        if err.is_nan() || err > VALIDATION_MESH_TOLERANCE {
+            anyhow::bail!(
+                "Particle-Mesh forces deviate from the CPU mesh by {err:e} with the masses \
+scaled by {mass_scale:e}"
+            );
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn forces_match_the_cpu_mesh() -> anyhow::Result<()> {
+        validate(&Validation::quick())
+    }
+}
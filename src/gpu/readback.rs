@@ -0,0 +1,100 @@
+use std::{marker::PhantomData, sync::mpsc};
+
+type MapResult = Result<(), wgpu::BufferAsyncError>;
+
+/// Copy of a small GPU value read without blocking the frame
+///
+/// The blocking counterpart is [`GpuBuffers::read_back`](super::GpuBuffers::read_back).
+pub struct AsyncReadBack<T: bytemuck::Pod> {
+    /// What is read back, for the logs
+    label: &'static str,
+    /// Mappable copy of the value
+    staging: wgpu::Buffer,
+    /// Completion of the mapping of `staging`, while one is in flight
+    pending: Option<mpsc::Receiver<MapResult>>,
+    /// The read back in flight predates a reset and must be dropped
+    stale: bool,
+    _value: PhantomData<T>,
+}
+
+impl<T: bytemuck::Pod> AsyncReadBack<T> {
+    pub fn new(device: &wgpu::Device, label: &'static str) -> Self {
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: std::mem::size_of::<T>() as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            label,
+            staging,
+            pending: None,
+            stale: false,
+            _value: PhantomData,
+        }
+    }
+
+    /// A copy is in flight
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Start copying the start of `source` back to the CPU, unless a copy is still in flight
+    pub fn request(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, source: &wgpu::Buffer) {
+        if self.pending.is_some() {
+            return;
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("readback_encoder"),
+        });
+        encoder.copy_buffer_to_buffer(source, 0, &self.staging, 0, self.staging.size());
+        queue.submit(Some(encoder.finish()));
+
+        let (tx, rx) = mpsc::channel();
+        self.staging
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = tx.send(result);
+            });
+        self.pending = Some(rx);
+    }
+
+    /// The value copied by [`AsyncReadBack::request`], once it has arrived
+    ///
+    /// This never blocks.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<T> {
+        let rx = self.pending.as_ref()?;
+        let _ = device.poll(wgpu::PollType::Poll);
+        let result = match rx.try_recv() {
+            Ok(result) => result,
+            Err(mpsc::TryRecvError::Empty) => return None,
+            Err(mpsc::TryRecvError::Disconnected) => Err(wgpu::BufferAsyncError),
+        };
+        self.pending = None;
+        let stale = std::mem::take(&mut self.stale);
+
+        if let Err(err) = result {
+            log::error!("Failed to read the {} back: {err}", self.label);
+            return None;
+        }
+        let value: T = bytemuck::pod_read_unaligned(&self.staging.slice(..).get_mapped_range());
+        self.staging.unmap();
+
+        (!stale).then_some(value)
+    }
+
+    /// The value copied by [`AsyncReadBack::request`], blocking until the GPU is idle
+    pub fn wait(&mut self, device: &wgpu::Device) -> Option<T> {
+        if self.pending.is_some() {
+            let _ = device.poll(wgpu::PollType::Wait);
+        }
+        self.poll(device)
+    }
+
+    /// Drop the read back in flight, the value was replaced since it was requested
+    pub fn discard(&mut self) {
+        self.stale = self.pending.is_some();
+    }
+}
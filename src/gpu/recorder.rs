@@ -0,0 +1,371 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    constants,
+    gpu::{Camera, DensityTarget, RenderMode, RenderSettings, Simulation, renderer},
+    sim::ParamsEguiAction,
+    utils::video::{Format, FrameSink},
+};
+
+/// Offscreen format of the recorded frames, read back as sRGB bytes
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// What to record and how often, edited in the UI or set on the command line
+#[derive(Debug, Clone)]
+pub struct RecorderSettings {
+    /// PNG directory or Y4M file
+    pub path: String,
+    pub format: Format,
+    pub width: u32,
+    pub height: u32,
+    /// Playback rate written in the Y4M header
+    pub fps: u32,
+    /// Simulated time between two frames
+    pub frame_time: f32,
+}
+
+impl Default for RecorderSettings {
+    fn default() -> Self {
+        Self {
+            path: constants::recorder::DEFAULT_PATH.to_string(),
+            format: Format::default(),
+            width: constants::recorder::WIDTH,
+            height: constants::recorder::HEIGHT,
+            fps: constants::recorder::FPS,
+            frame_time: constants::recorder::FRAME_TIME,
+        }
+    }
+}
+
+impl RecorderSettings {
+    /// Settings for `path`, with the format picked from its extension
+    pub fn for_path(path: &Path) -> Self {
+        Self {
+            path: path.display().to_string(),
+            format: Format::from_path(path),
+            ..Self::default()
+        }
+    }
+}
+
+/// Renders the particles into an offscreen texture and writes the frames to disk
+///
+/// Frames are taken at a fixed simulated-time rate, independent of the display rate:
+/// when a step covers several frame times, the same frame is written several times.
+pub struct Recorder {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    /// Row-padded copy of the texture
+    readback: wgpu::Buffer,
+    padded_bytes_per_row: u32,
+
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+    bond_pipeline: wgpu::RenderPipeline,
+    density: DensityTarget,
+    camera_buffer: wgpu::Buffer,
+
+    sink: FrameSink,
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    frame_time: f64,
+
+    /// Simulated time since the recording started
+    elapsed: f64,
+    /// Frames written so far
+    frames: u64,
+}
+
+impl Recorder {
+    /// Create the output and write the current state as the first frame
+    pub fn start(
+        sim: &Simulation,
+        camera: &Camera,
+        render: &RenderSettings,
+        settings: &RecorderSettings,
+    ) -> anyhow::Result<Self> {
+        let (width, height) = (settings.width.max(1), settings.height.max(1));
+        if settings.frame_time <= 0.0 {
+            anyhow::bail!("Recording frame time must be positive");
+        }
+        let path = PathBuf::from(&settings.path);
+        let sink = FrameSink::create(settings.format, &path, width, height, settings.fps)?;
+
+        let device = &sim.device;
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("recorder_texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let padded_bytes_per_row = (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("recorder_readback"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let shader = renderer::make_shader(device);
+        let bind_group_layout = renderer::make_bind_group_layout(device);
+        let pipeline_layout = renderer::make_pipeline_layout(device, &[&bind_group_layout]);
+        let pipeline = renderer::make_pipeline(device, &pipeline_layout, &shader, FORMAT);
+        let bond_pipeline = renderer::make_bond_pipeline(device, &pipeline_layout, &shader, FORMAT);
+        let density = DensityTarget::new(device, &pipeline_layout, &shader, FORMAT, width, height);
+        let camera_buffer = renderer::make_camera_buffer(device);
+
+        let mut recorder = Self {
+            texture,
+            view,
+            readback,
+            padded_bytes_per_row,
+
+            bind_group_layout,
+            pipeline,
+            bond_pipeline,
+            density,
+            camera_buffer,
+
+            sink,
+            path,
+            width,
+            height,
+            frame_time: settings.frame_time as f64,
+
+            elapsed: 0.0,
+            frames: 0,
+        };
+
+        let frame = recorder.capture(sim, camera, render)?;
+        recorder.sink.write(&frame)?;
+        recorder.frames = 1;
+
+        log::info!(
+            "Recording {}x{} frames every {} simulated seconds to '{}' ({})",
+            width,
+            height,
+            settings.frame_time,
+            recorder.path.display(),
+            settings.format.label()
+        );
+
+        Ok(recorder)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Account for a step of `dt` simulated seconds and write the frames that became due
+    pub fn advance(
+        &mut self,
+        sim: &Simulation,
+        camera: &Camera,
+        render: &RenderSettings,
+        dt: f32,
+    ) -> anyhow::Result<()> {
+        self.elapsed += dt as f64;
+        // Frame k is due at time k * frame_time, frame 0 was written at the start
+        let due = (self.elapsed / self.frame_time).floor() as u64 + 1;
+        if due <= self.frames {
+            return Ok(());
+        }
+
+        let frame = self.capture(sim, camera, render)?;
+        while self.frames < due {
+            self.sink.write(&frame)?;
+            self.frames += 1;
+        }
+        Ok(())
+    }
+
+    /// Flush the output, returns the number of frames written
+    pub fn finish(mut self) -> anyhow::Result<u64> {
+        self.sink.finish()?;
+        log::info!(
+            "Recorded {} frames to '{}'",
+            self.frames,
+            self.path.display()
+        );
+        Ok(self.frames)
+    }
+
+    /// Render the current particles with `camera` and `render` and read the pixels back
+    /// as tight RGBA rows
+    ///
+    /// This blocks until the GPU has finished all submitted work.
+    pub fn capture(
+        &self,
+        sim: &Simulation,
+        camera: &Camera,
+        render: &RenderSettings,
+    ) -> anyhow::Result<Vec<u8>> {
+        let device = &sim.device;
+        let queue = &sim.queue;
+
+        // Same view as the window, with the aspect ratio of the recording
+        let mut camera = camera.clone();
+        camera.resize(self.width, self.height);
+        let uniform = camera.to_uniform();
+        queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(std::slice::from_ref(&uniform)),
+        );
+
+        // Built for every frame, so buffer reallocations never leave them stale
+        let bind_groups = renderer::make_bind_group(
+            device,
+            &self.bind_group_layout,
+            &sim.buffers,
+            &self.camera_buffer,
+        );
+        let id = sim.buffer_in_use.id_render();
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("recorder_encoder"),
+        });
+        match render.mode {
+            RenderMode::Blended => renderer::draw(
+                &mut encoder,
+                &self.view,
+                &self.pipeline,
+                &self.bond_pipeline,
+                &bind_groups[id],
+                sim.params.n,
+                sim.drawn_bonds(),
+            ),
+            RenderMode::Density => self.density.draw(
+                queue,
+                &mut encoder,
+                &self.view,
+                &bind_groups[id],
+                sim.params.n,
+                sim.drawn_bonds(),
+                render,
+            ),
+        }
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::TexelCopyBufferInfo {
+                buffer: &self.readback,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback.slice(..);
+        let (tx, rx) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = tx.send(result);
+        });
+        device.poll(wgpu::PollType::Wait)?;
+        rx.recv()??;
+
+        let row = (self.width * 4) as usize;
+        let mut pixels = Vec::with_capacity(row * self.height as usize);
+        for padded in slice
+            .get_mapped_range()
+            .chunks_exact(self.padded_bytes_per_row as usize)
+        {
+            pixels.extend_from_slice(&padded[..row]);
+        }
+        self.readback.unmap();
+
+        Ok(pixels)
+    }
+}
+
+/// Output settings and start/stop buttons, `frames` is `Some` while recording
+pub fn render_controls(
+    ui: &mut egui::Ui,
+    settings: &mut RecorderSettings,
+    frames: Option<u64>,
+) -> ParamsEguiAction {
+    let mut action = ParamsEguiAction::None;
+    let recording = frames.is_some();
+
+    ui.heading("Recording");
+    ui.add_enabled_ui(!recording, |ui| {
+        ui.horizontal(|ui| {
+            ui.label("Output");
+            ui.text_edit_singleline(&mut settings.path).on_hover_text(
+                "Directory of the PNG frames, or Y4M file, relative to the working directory",
+            );
+        });
+        egui::ComboBox::from_label("Format")
+            .selected_text(settings.format.label())
+            .show_ui(ui, |ui| {
+                for option in Format::ALL {
+                    ui.selectable_value(&mut settings.format, option, option.label());
+                }
+            })
+            .response
+            .on_hover_text("PNG files are lossless, Y4M is a single uncompressed stream that ffmpeg can encode");
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::DragValue::new(&mut settings.width)
+                    .range(constants::recorder::SIZE_RANGE)
+                    .suffix(" px"),
+            );
+            ui.label("x");
+            ui.add(
+                egui::DragValue::new(&mut settings.height)
+                    .range(constants::recorder::SIZE_RANGE)
+                    .suffix(" px"),
+            );
+            ui.label("Resolution");
+        });
+        ui.add(
+            egui::Slider::new(&mut settings.frame_time, constants::recorder::FRAME_TIME_RANGE)
+                .text("Simulated Time per Frame")
+                .logarithmic(true)
+                .suffix(" s"),
+        )
+        .on_hover_text("Frames are taken at this simulated-time rate, whatever the display frame rate");
+        ui.add(
+            egui::Slider::new(&mut settings.fps, constants::recorder::FPS_RANGE)
+                .text("Playback FPS"),
+        )
+        .on_hover_text("Frame rate written in the Y4M header");
+    });
+
+    ui.horizontal(|ui| {
+        if recording {
+            if ui.button("Stop Recording").clicked() {
+                action = ParamsEguiAction::StopRecording;
+            }
+            ui.label(format!("{} frames", frames.unwrap_or_default()));
+        } else if ui
+            .button("Start Recording")
+            .on_hover_text(
+                "Frames are written while the simulation steps, pausing pauses the recording",
+            )
+            .clicked()
+        {
+            action = ParamsEguiAction::StartRecording;
+        }
+    });
+
+    action
+}
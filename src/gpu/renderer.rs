@@ -1,6 +1,9 @@
 use wgpu::PipelineCompilationOptions;
 
-use crate::gpu::buffers::GpuBuffers;
+use crate::{
+    constants,
+    gpu::{buffers::GpuBuffers, camera::CameraUniform},
+};
 
 // Shaders
 
@@ -24,18 +27,96 @@ pub fn make_pipeline_layout(
     })
 }
 
+/// Pipeline drawing one point per particle
 pub fn make_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
     shader: &wgpu::ShaderModule,
     surface_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    make_topology_pipeline(
+        device,
+        layout,
+        shader,
+        surface_format,
+        "vs_main",
+        wgpu::PrimitiveTopology::PointList,
+        wgpu::BlendState::ALPHA_BLENDING,
+    )
+}
+
+/// Pipeline drawing one line per bond, two vertices each
+pub fn make_bond_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    surface_format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+    make_topology_pipeline(
+        device,
+        layout,
+        shader,
+        surface_format,
+        "vs_bond",
+        wgpu::PrimitiveTopology::LineList,
+        wgpu::BlendState::ALPHA_BLENDING,
+    )
+}
+
+/// Adds the colors of the particles (weighted by their alpha) instead of blending them,
+/// so that overlapping particles accumulate into an HDR target
+const ADDITIVE: wgpu::BlendState = wgpu::BlendState {
+    color: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::SrcAlpha,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+    alpha: wgpu::BlendComponent {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::One,
+        operation: wgpu::BlendOperation::Add,
+    },
+};
+
+/// Pipelines drawing the points and the bonds additively, see [`draw`]
+pub fn make_density_pipelines(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    format: wgpu::TextureFormat,
+) -> [wgpu::RenderPipeline; 2] {
+    [
+        ("vs_main", wgpu::PrimitiveTopology::PointList),
+        ("vs_bond", wgpu::PrimitiveTopology::LineList),
+    ]
+    .map(|(entry_point, topology)| {
+        make_topology_pipeline(
+            device,
+            layout,
+            shader,
+            format,
+            entry_point,
+            topology,
+            ADDITIVE,
+        )
+    })
+}
+
+fn make_topology_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    surface_format: wgpu::TextureFormat,
+    vertex_entry_point: &str,
+    topology: wgpu::PrimitiveTopology,
+    blend: wgpu::BlendState,
 ) -> wgpu::RenderPipeline {
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        label: Some("render_pipeline"),
+        label: Some(vertex_entry_point),
         layout: Some(layout),
         vertex: wgpu::VertexState {
             module: shader,
-            entry_point: Some("vs_main"),
+            entry_point: Some(vertex_entry_point),
             buffers: &[],
             compilation_options: PipelineCompilationOptions::default(),
         },
@@ -45,12 +126,12 @@ pub fn make_pipeline(
             compilation_options: PipelineCompilationOptions::default(),
             targets: &[Some(wgpu::ColorTargetState {
                 format: surface_format,
-                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                blend: Some(blend),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
         }),
         primitive: wgpu::PrimitiveState {
-            topology: wgpu::PrimitiveTopology::PointList,
+            topology,
             strip_index_format: None,
             front_face: wgpu::FrontFace::Ccw,
             cull_mode: None,
@@ -65,6 +146,38 @@ pub fn make_pipeline(
     })
 }
 
+/// Clear `view` and draw the `n` particles of `bind_group` over their first `bonds` bonds
+pub fn draw(
+    encoder: &mut wgpu::CommandEncoder,
+    view: &wgpu::TextureView,
+    pipeline: &wgpu::RenderPipeline,
+    bond_pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+    n: u32,
+    bonds: u32,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Clear Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(constants::gpu::BACKGROUND_COLOR),
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        ..Default::default()
+    });
+
+    render_pass.set_bind_group(0, bind_group, &[]);
+    if bonds > 0 {
+        render_pass.set_pipeline(bond_pipeline);
+        render_pass.draw(0..2 * bonds, 0..1);
+    }
+    render_pass.set_pipeline(pipeline);
+    render_pass.draw(0..n, 0..1);
+}
+
 // Buffers
 
 pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
@@ -93,10 +206,74 @@ pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
                 },
                 count: None,
             },
+            // masses
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // camera
+            wgpu::BindGroupLayoutEntry {
+                binding: 3,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // charges
+            wgpu::BindGroupLayoutEntry {
+                binding: 4,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // simulation parameters
+            wgpu::BindGroupLayoutEntry {
+                binding: 5,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // bonds
+            wgpu::BindGroupLayoutEntry {
+                binding: 6,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
         ],
     })
 }
 
+pub fn make_camera_buffer(device: &wgpu::Device) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("camera_uniform"),
+        size: std::mem::size_of::<CameraUniform>() as u64,
+        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
 /// Make bind groups for the two buffer sets (primary and secondary)
 ///
 /// ID0 := primary
@@ -105,6 +282,7 @@ pub fn make_bind_group(
     device: &wgpu::Device,
     bgl: &wgpu::BindGroupLayout,
     buffers: &GpuBuffers,
+    camera: &wgpu::Buffer,
 ) -> [wgpu::BindGroup; 2] {
     [
         device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -121,6 +299,31 @@ pub fn make_bind_group(
                     binding: 1,
                     resource: buffers.colors.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    // masses
+                    binding: 2,
+                    resource: buffers.masses.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // camera
+                    binding: 3,
+                    resource: camera.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // charges
+                    binding: 4,
+                    resource: buffers.charges.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // simulation parameters
+                    binding: 5,
+                    resource: buffers.uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // bonds
+                    binding: 6,
+                    resource: buffers.bonds.as_entire_binding(),
+                },
             ],
         }),
         device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -137,6 +340,31 @@ pub fn make_bind_group(
                     binding: 1,
                     resource: buffers.colors.as_entire_binding(),
                 },
+                wgpu::BindGroupEntry {
+                    // masses
+                    binding: 2,
+                    resource: buffers.masses.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // camera
+                    binding: 3,
+                    resource: camera.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // charges
+                    binding: 4,
+                    resource: buffers.charges.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // simulation parameters
+                    binding: 5,
+                    resource: buffers.uniform.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    // bonds
+                    binding: 6,
+                    resource: buffers.bonds.as_entire_binding(),
+                },
             ],
         }),
     ]
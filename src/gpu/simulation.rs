@@ -0,0 +1,1014 @@
+use glam::Vec2;
+use rand::{SeedableRng, rngs::StdRng};
+
+use crate::{
+    constants,
+    gpu::{
+        BufferInUse,
+        barnes_hut::BarnesHut,
+        buffers::GpuBuffers,
+        collisions::CollisionPass,
+        compute,
+        diagnostics::DiagnosticsPass,
+        edit::EditPass,
+        fluid::FluidPass,
+        grid::CellGrid,
+        particle_mesh::ParticleMesh,
+        request_device, select_adapter,
+        timestep::TimestepPass,
+        validation::{VALIDATION_RUN_STEPS, Validation},
+    },
+    sim::{
+        ParticleData, SimParams, Solver,
+        bonds::{self, Bond},
+        diagnostics::{self, Backend, Diagnostics, Sample},
+        grid::CellList,
+        integrator::Integrator,
+        scenario::{ChargeScheme, Scenarios},
+        scenario_file::ScenarioFile,
+        snapshot::Snapshot,
+        timestep::{StepState, TimestepMode},
+    },
+};
+
+/// GPU side of the simulation: device, particle buffers and the compute pipeline
+///
+/// It does not know about windows or surfaces, so it can be driven either by the
+/// windowed `State` or on its own for headless runs.
+pub struct Simulation {
+    // WGPU core components
+    pub device: wgpu::Device,
+    pub queue: wgpu::Queue,
+
+    /// Compute pipeline
+    compute_bind_group_layout: wgpu::BindGroupLayout,
+    compute_pipeline: wgpu::ComputePipeline,
+    /// Integrator stages that evaluate no force
+    drift_pipeline: wgpu::ComputePipeline,
+    /// Indexed by [`BufferInUse::id_compute`], the bind group of the next step reads the
+    /// current state, so the passes reading it between steps use that one as well
+    compute_bind_groups: [wgpu::BindGroup; 2],
+    /// Charges read by the direct sum
+    charge_bind_group_layout: wgpu::BindGroupLayout,
+    charge_bind_group: wgpu::BindGroup,
+    /// Spring forces, run before the solver
+    bond_bind_group_layout: wgpu::BindGroupLayout,
+    bond_pipeline: wgpu::ComputePipeline,
+    bond_bind_group: wgpu::BindGroup,
+
+    /// Barnes-Hut solver
+    barnes_hut: BarnesHut,
+    /// Particle-mesh solver
+    particle_mesh: ParticleMesh,
+    /// Conserved quantities reduction
+    diagnostics_pass: DiagnosticsPass,
+    /// Choice of the next step length
+    timestep_pass: TimestepPass,
+    /// Removal of particles
+    edit_pass: EditPass,
+    /// Merging of colliding particles
+    collision_pass: CollisionPass,
+    /// Cell lists of the short-range interactions
+    cell_grid: CellGrid,
+    /// SPH fluid forces, on top of the cell lists
+    fluid_pass: FluidPass,
+
+    /// Buffers
+    pub buffers: GpuBuffers,
+    /// Springs between the particles, as uploaded to `buffers`
+    bonds: Vec<Bond>,
+
+    /// Initial condition generators
+    pub scenarios: Scenarios,
+
+    /// Energy and momentum history, sampled every few epochs
+    pub diagnostics: Diagnostics,
+
+    // Simulation state
+    pub params: SimParams,
+    pub buffer_in_use: BufferInUse,
+    /// Simulated time and step length, as last known on the CPU
+    ///
+    /// Fixed steps are accounted for when they finish, adaptive ones when the GPU
+    /// state is read back, see [`Simulation::poll_clock`].
+    pub clock: StepState,
+    /// Steps encoded since the last [`Simulation::finish_update`]
+    pending_steps: u32,
+    /// Whether the velocities on the GPU lag half a step behind the positions
+    staggered: bool,
+}
+
+impl Simulation {
+    pub fn new(device: wgpu::Device, queue: wgpu::Queue, params: SimParams) -> Self {
+        let buffers = GpuBuffers::create(&device, params.n);
+
+        let compute_shader = compute::make_shader(&device);
+        let compute_bind_group_layout = compute::make_bind_group_layout(&device);
+        let charge_bind_group_layout = compute::make_charge_bind_group_layout(&device);
+        let compute_pipeline_layout = compute::make_pipeline_layout(
+            &device,
+            &[&compute_bind_group_layout, &charge_bind_group_layout],
+        );
+        let compute_pipeline =
+            compute::make_pipeline(&device, &compute_pipeline_layout, &compute_shader);
+        let drift_pipeline_layout =
+            compute::make_pipeline_layout(&device, &[&compute_bind_group_layout]);
+        let drift_pipeline =
+            compute::make_entry_pipeline(&device, &drift_pipeline_layout, &compute_shader, "drift");
+        let compute_bind_groups =
+            compute::make_bind_group(&device, &compute_bind_group_layout, &buffers);
+        let charge_bind_group =
+            compute::make_charge_bind_group(&device, &charge_bind_group_layout, &buffers);
+        let bond_bind_group_layout = compute::make_bond_bind_group_layout(&device);
+        let bond_pipeline_layout = compute::make_pipeline_layout(
+            &device,
+            &[&compute_bind_group_layout, &bond_bind_group_layout],
+        );
+        let bond_pipeline = compute::make_entry_pipeline(
+            &device,
+            &bond_pipeline_layout,
+            &compute_shader,
+            "bond_forces",
+        );
+        let bond_bind_group =
+            compute::make_bond_bind_group(&device, &bond_bind_group_layout, &buffers);
+        let barnes_hut = BarnesHut::new(
+            &device,
+            &queue,
+            &compute_shader,
+            &compute_bind_group_layout,
+            params.n,
+        );
+        let particle_mesh = ParticleMesh::new(
+            &device,
+            &compute_shader,
+            &compute_bind_group_layout,
+            params.n,
+        );
+        let diagnostics_pass = DiagnosticsPass::new(
+            &device,
+            &compute_shader,
+            &compute_bind_group_layout,
+            params.n,
+        );
+        let timestep_pass = TimestepPass::new(
+            &device,
+            &compute_shader,
+            &compute_bind_group_layout,
+            params.n,
+        );
+        let edit_pass = EditPass::new(&device, params.n);
+        let cell_grid = CellGrid::new(
+            &device,
+            &compute_shader,
+            &compute_bind_group_layout,
+            params.n,
+        );
+        let collision_pass = CollisionPass::new(
+            &device,
+            &compute_shader,
+            &compute_bind_group_layout,
+            cell_grid.bind_group_layout(),
+            params.n,
+        );
+        let fluid_pass = FluidPass::new(
+            &device,
+            &compute_shader,
+            &compute_bind_group_layout,
+            cell_grid.bind_group_layout(),
+            params.n,
+        );
+
+        let mut _self = Self {
+            device,
+            queue,
+
+            compute_bind_group_layout,
+            compute_pipeline,
+            drift_pipeline,
+            compute_bind_groups,
+            charge_bind_group_layout,
+            charge_bind_group,
+            bond_bind_group_layout,
+            bond_pipeline,
+            bond_bind_group,
+
+            barnes_hut,
+            particle_mesh,
+            diagnostics_pass,
+            timestep_pass,
+            edit_pass,
+            collision_pass,
+            cell_grid,
+            fluid_pass,
+
+            buffers,
+            bonds: Vec::new(),
+
+            scenarios: Scenarios::default(),
+            diagnostics: Diagnostics::default(),
+
+            clock: StepState::start(params.dt),
+            pending_steps: 0,
+            staggered: params.integrator.staggered(),
+            params,
+            buffer_in_use: BufferInUse::Primary,
+        };
+
+        _self.reset_particles();
+        _self.sync_uniform();
+
+        _self
+    }
+
+    /// Create a simulation without any window or surface
+    ///
+    /// Any adapter is accepted, including software ones (lavapipe, llvmpipe), which
+    /// makes this usable on render farms and in CI. `adapter` restricts the choice to
+    /// adapters whose name contains it.
+    pub async fn new_headless(params: SimParams, adapter: Option<&str>) -> anyhow::Result<Self> {
+        let instance_desc = wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        }
+        .with_env();
+        let instance = wgpu::Instance::new(&instance_desc);
+
+        let adapter = select_adapter(&instance, None, adapter).await?;
+        let (device, queue) = request_device(&adapter).await?;
+
+        Ok(Self::new(device, queue, params))
+    }
+
+    /// Grow the buffers to hold `params.n` particles, returns `true` if they were reallocated
+    fn ensure_capacity(&mut self) -> bool {
+        let reallocated = self.params.n > self.buffers.capacity;
+        if reallocated {
+            self.buffers.resize(&self.device, self.params.n);
+            self.rebuild_bind_groups();
+        }
+        reallocated
+    }
+
+    /// Point the compute bind groups at the current buffers, after a reallocation
+    fn rebuild_bind_groups(&mut self) {
+        self.compute_bind_groups =
+            compute::make_bind_group(&self.device, &self.compute_bind_group_layout, &self.buffers);
+        self.charge_bind_group = compute::make_charge_bind_group(
+            &self.device,
+            &self.charge_bind_group_layout,
+            &self.buffers,
+        );
+        self.bond_bind_group = compute::make_bond_bind_group(
+            &self.device,
+            &self.bond_bind_group_layout,
+            &self.buffers,
+        );
+    }
+
+    /// Springs between the particles
+    pub fn bonds(&self) -> &[Bond] {
+        &self.bonds
+    }
+
+    /// Number of bonds to draw, none when they are hidden
+    pub fn drawn_bonds(&self) -> u32 {
+        if self.params.show_bonds {
+            self.bonds.len() as u32
+        } else {
+            0
+        }
+    }
+
+    /// Replace the bonds between the `params.n` particles, returns `true` when the
+    /// buffers had to be reallocated, see [`Simulation::reset_particles`]
+    ///
+    /// Must follow every change of `params.n`, the adjacency lists cover every particle.
+    fn set_bonds(&mut self, bonds: Vec<Bond>) -> bool {
+        if bonds.is_empty() && !self.bonds.is_empty() {
+            self.buffers.clear_bond_forces(&self.device, &self.queue);
+        }
+        let reallocated =
+            self.buffers
+                .write_bonds(&self.device, &self.queue, &bonds, self.params.n);
+        if reallocated {
+            self.rebuild_bind_groups();
+        }
+        self.bonds = bonds;
+        reallocated
+    }
+
+    /// Regenerate the initial conditions for `params.n` particles with the active scenario
+    ///
+    /// Returns `true` when the buffers had to be reallocated, in which case any bind
+    /// group built on top of them (e.g. for rendering) must be recreated.
+    pub fn reset_particles(&mut self) -> bool {
+        // Resize buffers if needed
+        let reallocated = self.ensure_capacity();
+
+        self.params.reset_epoch();
+        self.params.bootstrap = true; // fresh velocities need the half-kick again
+
+        // Compute new initial positions and velocities
+        let mut data = self.scenarios.generate(&self.params);
+        self.buffer_in_use = BufferInUse::Primary; // reset to primary on upload
+
+        // Upload to GPU
+        self.buffers
+            .upload_data(&self.queue, Some(&data), Some(&self.params));
+        let reallocated = self.set_bonds(std::mem::take(&mut data.bonds)) || reallocated;
+        self.set_clock(StepState::start(self.params.dt));
+
+        self.diagnostics.merges = 0;
+        self.restart_diagnostics();
+
+        reallocated
+    }
+
+    /// Replace the step state on both sides, dropping any read back in flight
+    fn set_clock(&mut self, clock: StepState) {
+        self.clock = clock;
+        self.staggered = self.params.integrator.staggered();
+        self.buffers.upload_step_state(&self.queue, &clock);
+        self.timestep_pass.discard();
+    }
+
+    /// Capture the full simulation state, blocking until the GPU is idle
+    pub fn snapshot(&self) -> anyhow::Result<Snapshot> {
+        Ok(Snapshot {
+            params: self.params.clone(),
+            buffer_in_use: self.buffer_in_use,
+            clock: self.read_clock()?,
+            particles: ParticleData {
+                positions: self.read_positions()?,
+                velocities: self.read_velocities()?,
+                colors: self.read_colors()?,
+                masses: self.read_masses()?,
+                charges: self.read_charges()?,
+                bonds: self.bonds.clone(),
+            },
+        })
+    }
+
+    /// Replace the simulation state with `snapshot`
+    ///
+    /// Returns `true` when the buffers had to be reallocated, see
+    /// [`Simulation::reset_particles`].
+    pub fn restore(&mut self, snapshot: Snapshot) -> bool {
+        self.params = snapshot.params;
+        let reallocated = self.ensure_capacity();
+
+        // Both ping-pong buffers receive the state, the parity is kept for the next steps
+        self.buffer_in_use = snapshot.buffer_in_use;
+        let mut data = snapshot.particles;
+        self.buffers
+            .upload_data(&self.queue, Some(&data), Some(&self.params));
+        let reallocated = self.set_bonds(std::mem::take(&mut data.bonds)) || reallocated;
+        self.set_clock(snapshot.clock);
+
+        self.diagnostics.merges = 0;
+        self.restart_diagnostics();
+
+        reallocated
+    }
+
+    /// Apply the parameters, scenario and colors of a scenario file
+    ///
+    /// The particles are only regenerated with `regenerate`, otherwise the new
+    /// parameters apply to the current ones. Returns `true` when the buffers were
+    /// reallocated, see [`Simulation::reset_particles`]. Nothing changes on error.
+    pub fn configure(&mut self, file: &ScenarioFile, regenerate: bool) -> anyhow::Result<bool> {
+        if let Some(table) = &file.scenario {
+            self.scenarios.configure(table)?;
+        }
+        file.sim.apply(&mut self.params);
+        if let Some(colors) = &file.colors {
+            colors.apply(&mut self.params, &mut self.scenarios);
+        }
+        if let Some(charges) = &file.charges {
+            charges.apply(&mut self.scenarios);
+        }
+        if let Some(fluid) = &file.fluid {
+            self.params.fluid = fluid.clone();
+        }
+
+        if regenerate {
+            return Ok(self.reset_particles());
+        }
+        self.sync_uniform();
+        Ok(false)
+    }
+
+    /// Append `data` after the current particles, keeping their state and bonds
+    ///
+    /// Returns `true` when the buffers had to be reallocated, see
+    /// [`Simulation::reset_particles`].
+    pub fn spawn(&mut self, data: &ParticleData) -> bool {
+        let count = data.positions.len() as u32;
+        if count == 0 {
+            return false;
+        }
+        let n = self.params.n;
+        let reallocated = self.buffers.grow(&self.device, &self.queue, n + count, n);
+        if reallocated {
+            self.rebuild_bind_groups();
+        }
+        self.buffers.write_particles(&self.queue, n, data);
+
+        self.params.n = n + count;
+        // The grown buffers start without bonds
+        let mut bonds = self.bonds.clone();
+        bonds.extend(bonds::remap(&data.bonds, |id| Some(n + id)));
+        let reallocated = self.set_bonds(bonds) || reallocated;
+        self.sync_uniform();
+        self.restart_diagnostics();
+
+        reallocated
+    }
+
+    /// Change the number of particles to `n`, keeping the state of the others
+    ///
+    /// Shrinking drops the last particles and their bonds. Growing appends the particles that the active
+    /// scenario generates beyond the current count for `n` particles, see
+    /// [`Simulation::spawn`]. Returns `true` when the buffers had to be reallocated.
+    pub fn set_particle_count(&mut self, n: u32) -> bool {
+        let current = self.params.n;
+        if n <= current {
+            if n < current {
+                self.params.n = n;
+                let bonds = bonds::remap(&self.bonds, |id| (id < n).then_some(id));
+                self.set_bonds(bonds);
+                self.sync_uniform();
+                self.restart_diagnostics();
+            }
+            return false;
+        }
+
+        let params = SimParams {
+            n,
+            ..self.params.clone()
+        };
+        let data = self.scenarios.generate(&params).split_off(current as usize);
+        self.spawn(&data)
+    }
+
+    /// Spawn a burst of the brush at `center` (world units), up to
+    /// `constants::sim::MAX_PARTICLES`, see [`Simulation::spawn`]
+    pub fn spawn_burst(&mut self, center: Vec2) -> bool {
+        let room = constants::sim::MAX_PARTICLES.saturating_sub(self.params.n);
+        let count = self.params.brush.spawn_count.min(room);
+        let [r, g, b] = self
+            .scenarios
+            .solid_color
+            .unwrap_or(constants::scenario::SOLID_COLOR);
+        // Every burst differs, yet a run stays reproducible from its seed
+        let mut rng = StdRng::seed_from_u64(
+            self.params.seed ^ (self.params.epoch as u64) ^ ((self.params.n as u64) << 32),
+        );
+        let mut data = self
+            .params
+            .brush
+            .burst(center, count, [r, g, b, 1.0], &mut rng);
+        self.scenarios
+            .assign_charges(&mut data, self.params.n, &mut rng);
+        self.spawn(&data)
+    }
+
+    /// Remove the particles within the brush radius of `center` (world units), returns
+    /// how many were removed
+    ///
+    /// The other particles keep their state, order and bonds, the bonds of the removed
+    /// ones are dropped. Erasing every particle is
+    /// refused, the simulation always keeps at least one. This blocks until the GPU is
+    /// idle.
+    pub fn erase(&mut self, center: Vec2) -> anyhow::Result<u32> {
+        let n = self.params.n;
+        let buffers = &self.buffers;
+        let (positions, velocities) = buffers.current(self.buffer_in_use);
+        let kept = self.edit_pass.erase(
+            &self.device,
+            &self.queue,
+            buffers,
+            positions,
+            velocities,
+            &self.params,
+            center,
+            self.params.brush.radius,
+        )?;
+        if kept == 0 || kept == n {
+            return Ok(0);
+        }
+
+        self.drop_removed(n, kept)?;
+        self.restart_diagnostics();
+
+        Ok(n - kept)
+    }
+
+    /// Merge the particles closer than `params.merge_radius`, returns how many were absorbed
+    ///
+    /// Only mutual nearest neighbors merge, see [`crate::sim::collisions::merge`]. The bonds of the
+    /// absorbed particles are dropped. The diagnostics history is kept, to show the
+    /// energy lost. This blocks until the GPU is idle.
+    pub fn merge_collisions(&mut self) -> anyhow::Result<u32> {
+        let n = self.params.n;
+        let buffers = &self.buffers;
+        let (positions, velocities) = buffers.current(self.buffer_in_use);
+        self.collision_pass.merge(
+            &self.device,
+            &self.queue,
+            &mut self.cell_grid,
+            &self.compute_bind_groups[self.buffer_in_use.id_compute()],
+            buffers,
+            positions,
+            velocities,
+            &self.params,
+        );
+        let kept = self.edit_pass.remove_absorbed(
+            &self.device,
+            &self.queue,
+            buffers,
+            positions,
+            velocities,
+            &self.params,
+        )?;
+        if kept == n {
+            return Ok(0);
+        }
+
+        self.drop_removed(n, kept)?;
+        self.diagnostics.merges += u64::from(n - kept);
+
+        Ok(n - kept)
+    }
+
+    /// Sort the current particles into cell lists for interactions within `radius`, and
+    /// count the particles within `radius` of each one
+    ///
+    /// See [`crate::sim::grid::build`] and [`crate::sim::grid::neighbor_counts`]. This
+    /// blocks until the GPU is idle.
+    pub fn cell_lists(&mut self, radius: f32) -> anyhow::Result<(CellList, Vec<u32>)> {
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("grid_encoder"),
+            });
+        let compute_bind_group = &self.compute_bind_groups[self.buffer_in_use.id_compute()];
+        self.cell_grid.encode(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            compute_bind_group,
+            &self.params,
+            radius,
+        );
+        self.cell_grid
+            .encode_count(&mut encoder, compute_bind_group, self.params.n);
+        self.queue.submit(Some(encoder.finish()));
+
+        let n = self.params.n;
+        Ok((
+            self.cell_grid.read_cells(&self.device, &self.queue, n)?,
+            self.cell_grid
+                .read_neighbor_counts(&self.device, &self.queue, n)?,
+        ))
+    }
+
+    /// Shrink to the `kept` particles the edit pass compacted out of `n`, with their bonds
+    fn drop_removed(&mut self, n: u32, kept: u32) -> anyhow::Result<()> {
+        self.params.n = kept;
+        let bonds = if self.bonds.is_empty() {
+            Vec::new()
+        } else {
+            let indices = self.edit_pass.new_indices(&self.device, &self.queue, n)?;
+            bonds::remap(&self.bonds, |id| indices[id as usize])
+        };
+        // Bonds only get fewer, the buffers are never reallocated
+        self.set_bonds(bonds);
+        self.sync_uniform();
+        Ok(())
+    }
+
+    /// Start a new diagnostics history, conserved quantities jump when particles change
+    fn restart_diagnostics(&mut self) {
+        // A sample in flight measured the replaced particles
+        let _ = self.diagnostics_pass.poll(&self.device, true);
+        self.diagnostics.clear();
+        self.sample_diagnostics(1);
+    }
+
+    /// Move the brush to `target` (world units), or release it with `None`
+    pub fn set_brush(&mut self, target: Option<Vec2>, inverted: bool) {
+        let brush = &mut self.params.brush;
+        if brush.target != target || brush.inverted != inverted {
+            brush.target = target;
+            brush.inverted = inverted;
+            self.sync_uniform();
+        }
+    }
+
+    pub fn sync_uniform(&mut self) {
+        // Leapfrog needs velocities half a step behind, which the other integrators
+        // do not keep: switching to it takes the bootstrap half-kick again
+        let staggered = self.params.integrator.staggered();
+        if staggered && !self.staggered {
+            self.params.bootstrap = true;
+            self.clock.prev_dt = 0.0;
+            self.buffers.restart_leapfrog(&self.queue);
+        }
+        self.staggered = staggered;
+
+        self.buffers
+            .upload_data(&self.queue, None, Some(&self.params));
+    }
+
+    /// Record one compute step into `encoder`
+    ///
+    /// Several steps can be recorded into the same encoder, each one reads what the
+    /// previous one wrote. The caller must call [`Simulation::finish_update`] once the
+    /// encoder has been submitted so the next steps read the freshly written buffers.
+    pub fn encode_update(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        self.params.increment_epoch(); // Increment epoch each update
+
+        // Every stage of the integrator reads what the previous one wrote
+        let integrator = self.params.integrator;
+        let stages = integrator.stages();
+        let mut id = self.buffer_in_use.id_compute();
+        for stage in 0..stages {
+            if self.pending_steps > 0 || stage > 0 {
+                self.buffer_in_use.tick();
+                id = self.buffer_in_use.id_compute();
+            }
+            if integrator.needs_force(stage) {
+                self.encode_forces(encoder, id);
+            } else {
+                self.encode_drift(encoder, id);
+            }
+            if stage + 1 < stages {
+                self.timestep_pass
+                    .encode_stage(encoder, &self.compute_bind_groups[id]);
+            }
+        }
+        self.pending_steps += 1;
+
+        self.timestep_pass.encode(
+            &self.device,
+            encoder,
+            &self.compute_bind_groups[id],
+            self.params.n,
+            self.params.timestep.is_adaptive(),
+        );
+    }
+
+    /// Record an integrator stage without any solver force
+    fn encode_drift(&self, encoder: &mut wgpu::CommandEncoder, id: usize) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Drift Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.drift_pipeline);
+        compute_pass.set_bind_group(0, &self.compute_bind_groups[id], &[]);
+        compute_pass.dispatch_workgroups(
+            self.params.n.div_ceil(constants::shader::WORKGROUP_SIZE),
+            1,
+            1,
+        );
+    }
+
+    /// Record the bond forces, the fluid forces and the selected solver, which ends with
+    /// one integrator stage
+    ///
+    /// Without self-gravity, the fluid replaces the solver by a drift stage.
+    fn encode_forces(&mut self, encoder: &mut wgpu::CommandEncoder, id: usize) {
+        if !self.bonds.is_empty() {
+            let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Bond Pass"),
+                timestamp_writes: None,
+            });
+            compute_pass.set_pipeline(&self.bond_pipeline);
+            compute_pass.set_bind_group(0, &self.compute_bind_groups[id], &[]);
+            compute_pass.set_bind_group(1, &self.bond_bind_group, &[]);
+            compute_pass.dispatch_workgroups(
+                self.params.n.div_ceil(constants::shader::WORKGROUP_SIZE),
+                1,
+                1,
+            );
+        }
+
+        if self.params.fluid.enabled {
+            self.cell_grid.encode(
+                &self.device,
+                &self.queue,
+                encoder,
+                &self.compute_bind_groups[id],
+                &self.params,
+                self.params.fluid.smoothing,
+            );
+            self.fluid_pass.encode(
+                &self.device,
+                encoder,
+                &self.compute_bind_groups[id],
+                self.cell_grid.bind_group(),
+                self.params.n,
+            );
+        }
+
+        match self.params.solver {
+            _ if !self.params.fluid.runs_solver() => self.encode_drift(encoder, id),
+            Solver::Direct => {
+                let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("Compute Pass"),
+                    timestamp_writes: None,
+                });
+
+                compute_pass.set_pipeline(&self.compute_pipeline);
+                compute_pass.set_bind_group(0, &self.compute_bind_groups[id], &[]);
+                compute_pass.set_bind_group(1, &self.charge_bind_group, &[]);
+
+                let workgroup_count = self.params.n.div_ceil(constants::shader::WORKGROUP_SIZE);
+
+                compute_pass.dispatch_workgroups(workgroup_count, 1, 1);
+            }
+            Solver::BarnesHut => {
+                self.barnes_hut.encode(
+                    &self.device,
+                    &self.queue,
+                    encoder,
+                    &self.compute_bind_groups[id],
+                    self.params.n,
+                );
+            }
+            Solver::ParticleMesh => {
+                self.particle_mesh
+                    .prepare(&self.device, &self.queue, &self.params);
+                self.particle_mesh.encode(
+                    &self.device,
+                    &self.queue,
+                    encoder,
+                    &self.compute_bind_groups[id],
+                    self.params.n,
+                );
+            }
+        }
+    }
+
+    /// Record the `params.substeps` steps of one rendered frame into `encoder`
+    pub fn encode_frame(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        for _ in 0..self.params.substeps {
+            self.encode_update(encoder);
+        }
+    }
+
+    /// Swap the ping-pong buffers after the submitted steps
+    ///
+    /// Colliding particles are merged here, so once per frame rather than once per step.
+    pub fn finish_update(&mut self) {
+        let steps = std::mem::take(&mut self.pending_steps);
+        if steps == 0 {
+            return;
+        }
+        self.buffer_in_use.tick();
+        self.params.bootstrap = false;
+        if !self.params.timestep.is_adaptive() {
+            for _ in 0..steps {
+                self.clock.advance(self.params.dt, self.params.dt);
+            }
+        }
+        if self.params.collisions {
+            self.poll_collisions();
+        }
+        self.sample_diagnostics(steps);
+    }
+
+    /// Merge the colliding particles once a count of the previous frames reports some
+    ///
+    /// Counting never blocks, merging does but only runs when there is something to merge.
+    fn poll_collisions(&mut self) {
+        if let Some(pairs) = self.collision_pass.poll_pairs(&self.device)
+            && pairs > 0
+            && let Err(err) = self.merge_collisions()
+        {
+            log::error!("Failed to merge colliding particles: {err:#}");
+        }
+        self.collision_pass.request_pairs(
+            &self.device,
+            &self.queue,
+            &mut self.cell_grid,
+            &self.compute_bind_groups[self.buffer_in_use.id_compute()],
+            &self.params,
+        );
+    }
+
+    /// Pick up the adaptive step state once it reaches the CPU, never blocks
+    ///
+    /// Meant to be called every frame, the clock then lags by a frame or two.
+    pub fn poll_clock(&mut self) {
+        if !self.params.timestep.is_adaptive() {
+            return;
+        }
+        if let Some(clock) = self.timestep_pass.poll(&self.device) {
+            self.clock = clock;
+        }
+        self.timestep_pass
+            .request_read_back(&self.device, &self.queue, &self.buffers.timestep);
+    }
+
+    /// Read the step state of the GPU, blocking until the GPU is idle
+    pub fn read_clock(&self) -> anyhow::Result<StepState> {
+        let state = GpuBuffers::read_back(&self.device, &self.queue, &self.buffers.timestep, 1)?;
+        Ok(state[0])
+    }
+
+    /// Measure the conserved quantities of the current state with the selected backend
+    ///
+    /// This blocks until the GPU is idle.
+    pub fn measure_diagnostics(&mut self, backend: Backend) -> anyhow::Result<Sample> {
+        Ok(match backend {
+            Backend::Gpu => {
+                let id = self.buffer_in_use.id_compute();
+                self.diagnostics_pass.measure(
+                    &self.device,
+                    &self.queue,
+                    &self.compute_bind_groups[id],
+                    &self.buffers,
+                    &self.params,
+                )?
+            }
+            Backend::Cpu => diagnostics::measure(
+                &self.params,
+                &self.read_positions()?,
+                &self.read_velocities()?,
+                &self.read_masses()?,
+                &self.read_charges()?,
+                &self.bonds,
+            ),
+        })
+    }
+
+    /// Record a diagnostics sample if one was due during the last `steps` epochs
+    ///
+    /// GPU samples are recorded once they reach the CPU, see
+    /// [`Simulation::poll_diagnostics`], and skipped while the previous one is in flight.
+    fn sample_diagnostics(&mut self, steps: u32) {
+        if !self.diagnostics.due(self.params.epoch, steps) {
+            return;
+        }
+        if self.diagnostics.backend == Backend::Gpu {
+            let id = self.buffer_in_use.id_compute();
+            if !self.diagnostics_pass.request(
+                &self.device,
+                &self.queue,
+                &self.compute_bind_groups[id],
+                &self.buffers,
+                &self.params,
+            ) {
+                log::debug!("Skipped the diagnostics of epoch {}", self.params.epoch);
+            }
+            return;
+        }
+        match self.measure_diagnostics(Backend::Cpu) {
+            Ok(sample) => self.diagnostics.record(sample),
+            Err(err) => log::error!("Failed to measure diagnostics: {err:#}"),
+        }
+    }
+
+    /// Record the GPU diagnostics sample once it reaches the CPU
+    ///
+    /// Meant to be called every frame, this never blocks unless `wait` is set.
+    pub fn poll_diagnostics(&mut self, wait: bool) {
+        if let Some(sample) = self.diagnostics_pass.poll(&self.device, wait) {
+            self.diagnostics.record(sample);
+        }
+    }
+
+    /// Advance the simulation by `steps` compute steps, ignoring `params.paused`
+    ///
+    /// `params.substeps` is ignored as well, and the clock and diagnostics are always up
+    /// to date.
+    pub fn step(&mut self, steps: u32) {
+        for _ in 0..steps {
+            let mut encoder = self
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("headless_step_encoder"),
+                });
+            self.encode_update(&mut encoder);
+            // One submit per step so uniform updates land between steps
+            self.queue.submit(Some(encoder.finish()));
+            self.finish_update();
+            self.poll_diagnostics(true);
+        }
+        if self.params.timestep.is_adaptive() {
+            match self.read_clock() {
+                Ok(clock) => self.clock = clock,
+                Err(err) => log::error!("Failed to read the step state back: {err:#}"),
+            }
+        }
+    }
+
+    /// Buffer holding the most recent positions (the one the renderer draws)
+    pub fn current_positions(&self) -> &wgpu::Buffer {
+        self.buffers.current(self.buffer_in_use).0
+    }
+
+    /// Buffer holding the most recent velocities
+    pub fn current_velocities(&self) -> &wgpu::Buffer {
+        self.buffers.current(self.buffer_in_use).1
+    }
+
+    /// Read the current particle positions back to the CPU
+    pub fn read_positions(&self) -> anyhow::Result<Vec<[f32; 2]>> {
+        GpuBuffers::read_back(
+            &self.device,
+            &self.queue,
+            self.current_positions(),
+            self.params.n,
+        )
+    }
+
+    /// Read the particle colors back to the CPU
+    pub fn read_colors(&self) -> anyhow::Result<Vec<[f32; 4]>> {
+        GpuBuffers::read_back(
+            &self.device,
+            &self.queue,
+            &self.buffers.colors,
+            self.params.n,
+        )
+    }
+
+    /// Read the particle masses back to the CPU
+    pub fn read_masses(&self) -> anyhow::Result<Vec<f32>> {
+        GpuBuffers::read_back(
+            &self.device,
+            &self.queue,
+            &self.buffers.masses,
+            self.params.n,
+        )
+    }
+
+    /// Read the particle charges back to the CPU
+    pub fn read_charges(&self) -> anyhow::Result<Vec<f32>> {
+        GpuBuffers::read_back(
+            &self.device,
+            &self.queue,
+            &self.buffers.charges,
+            self.params.n,
+        )
+    }
+
+    /// Read the current particle velocities back to the CPU
+    pub fn read_velocities(&self) -> anyhow::Result<Vec<[f32; 2]>> {
+        GpuBuffers::read_back(
+            &self.device,
+            &self.queue,
+            self.current_velocities(),
+            self.params.n,
+        )
+    }
+}
+
+/// Save the state mid-run, restore it and check that the resumed run is bit-identical
+///
+/// The snapshot goes through the binary encoding first. The run uses an adaptive time
+/// step, whose state must be restored as well, and a multi-stage integrator.
+pub fn validate(v: &Validation) -> anyhow::Result<()> {
+    let mut sim = v.simulation(SimParams {
+        timestep: TimestepMode::Acceleration,
+        integrator: Integrator::Yoshida4,
+        ..v.params()
+    })?;
+    sim.scenarios.charges = ChargeScheme::Random;
+    sim.reset_particles();
+
+    log::info!(
+        "Validating snapshot round trip after {VALIDATION_RUN_STEPS} steps ({})",
+        v.solver.label()
+    );
+
+    sim.step(VALIDATION_RUN_STEPS);
+    let snapshot = Snapshot::decode(&sim.snapshot()?.encode())?;
+
+    sim.step(VALIDATION_RUN_STEPS);
+    let expected = sim.snapshot()?;
+
+    sim.restore(snapshot);
+    sim.step(VALIDATION_RUN_STEPS);
+    if sim.snapshot()? != expected {
+        anyhow::bail!("Run resumed from a snapshot diverged from the original run");
+    }
+
+    log::info!("Resumed run is identical to the original run");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn resumed_runs_are_identical() -> anyhow::Result<()> {
+        validate(&Validation::quick())
+    }
+}
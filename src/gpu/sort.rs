@@ -0,0 +1,442 @@
+use crate::{
+    constants,
+    gpu::{buffers::GpuBuffers, compute, validation::Validation},
+    sim::{SimParams, sort},
+};
+
+/// Uniform of `shaders/radix_sort.wgsl`, one per pass of each sorted count
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct SortUniform {
+    n: u32,
+    shift: u32,
+    tiles: u32,
+    chunks: u32,
+}
+
+/// Bits of the key ordered by each pass, see `RADIX_BITS` in the shader
+const RADIX_BITS: u32 = 8;
+const RADIX: u32 = 1 << RADIX_BITS;
+/// Passes over 32-bit keys, even so the result ends in the sorted buffers
+const PASSES: u32 = u32::BITS / RADIX_BITS;
+/// Distinct pair counts whose pass parameters are kept, see [`RadixSort::encode`]
+const SLOTS: usize = 8;
+/// Buffer pairs whose bind groups are kept
+const CACHED_BIND_GROUPS: usize = 4;
+
+/// Bind groups of the even and odd passes over one pair of caller buffers
+struct SortBindGroups {
+    keys: wgpu::Buffer,
+    values: wgpu::Buffer,
+    groups: [wgpu::BindGroup; 2],
+}
+
+/// GPU radix sort of `u32` key/value pairs (`shaders/radix_sort.wgsl`)
+///
+/// [`RadixSort::encode`] orders the pairs in place without reading anything back, equal
+/// keys keep their order. [`crate::sim::sort::sort_pairs`] is its CPU reference.
+pub struct RadixSort {
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// One uniform per pass and slot, at offsets aligned for binding
+    uniform: wgpu::Buffer,
+    uniform_stride: u64,
+    /// Pair count whose parameters each slot holds, and the next slot to replace
+    slots: [Option<u32>; SLOTS],
+    next_slot: usize,
+    /// Most recently used last
+    bind_groups: Vec<SortBindGroups>,
+    /// Keys and values between two passes
+    keys: wgpu::Buffer,
+    values: wgpu::Buffer,
+    /// Digit counts of each tile, stored digit by digit
+    counts: wgpu::Buffer,
+    /// Counts before each workgroup of `counts`, followed by the total
+    blocks: wgpu::Buffer,
+    /// Number of pairs the scratch buffers can hold
+    capacity: u32,
+
+    count_pipeline: wgpu::ComputePipeline,
+    scan_pipeline: wgpu::ComputePipeline,
+    blocks_pipeline: wgpu::ComputePipeline,
+    scatter_pipeline: wgpu::ComputePipeline,
+}
+
+fn make_shader(device: &wgpu::Device) -> wgpu::ShaderModule {
+    compute::make_standalone_shader(
+        device,
+        "sort_shader",
+        include_str!("../../shaders/radix_sort.wgsl"),
+    )
+}
+
+fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("sort_bgl"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                // pass parameters
+                binding: 0,
+                visibility: wgpu::ShaderStages::COMPUTE,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: true,
+                    min_binding_size: wgpu::BufferSize::new(
+                        std::mem::size_of::<SortUniform>() as u64
+                    ),
+                },
+                count: None,
+            },
+            // keys to sort
+            storage(1, true),
+            // values to sort
+            storage(2, true),
+            // sorted keys
+            storage(3, false),
+            // sorted values
+            storage(4, false),
+            // digit counts
+            storage(5, false),
+            // workgroup offsets and total
+            storage(6, false),
+        ],
+    })
+}
+
+fn workgroups(n: u32) -> u32 {
+    n.div_ceil(constants::shader::WORKGROUP_SIZE).max(1)
+}
+
+fn make_scratch(device: &wgpu::Device, capacity: u32) -> [wgpu::Buffer; 4] {
+    let mk = |label: &str, count: u32| {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: std::mem::size_of::<u32>() as u64 * count as u64,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        })
+    };
+    let counts = RADIX * workgroups(capacity);
+    [
+        mk("sort_keys", capacity),
+        mk("sort_values", capacity),
+        mk("sort_counts", counts),
+        mk("sort_blocks", workgroups(counts) + 1),
+    ]
+}
+
+impl RadixSort {
+    pub fn new(device: &wgpu::Device, n: u32) -> Self {
+        let shader = make_shader(device);
+        let bind_group_layout = make_bind_group_layout(device);
+        let pipeline_layout = compute::make_pipeline_layout(device, &[&bind_group_layout]);
+        let mk = |entry_point| {
+            compute::make_entry_pipeline(device, &pipeline_layout, &shader, entry_point)
+        };
+
+        let capacity = n.max(1);
+        let [keys, values, counts, blocks] = make_scratch(device, capacity);
+        let uniform_stride = (std::mem::size_of::<SortUniform>() as u64)
+            .next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let uniform = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("sort_params"),
+            size: uniform_stride * PASSES as u64 * SLOTS as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            bind_group_layout,
+            uniform,
+            uniform_stride,
+            slots: [None; SLOTS],
+            next_slot: 0,
+            bind_groups: Vec::new(),
+            keys,
+            values,
+            counts,
+            blocks,
+            capacity,
+
+            count_pipeline: mk("sort_count"),
+            scan_pipeline: mk("sort_scan"),
+            blocks_pipeline: mk("sort_blocks"),
+            scatter_pipeline: mk("sort_scatter"),
+        }
+    }
+
+    /// Slot holding the pass parameters of `n` pairs, written on first use
+    fn slot(&mut self, queue: &wgpu::Queue, n: u32) -> usize {
+        if let Some(slot) = self.slots.iter().position(|&count| count == Some(n)) {
+            return slot;
+        }
+        let slot = self.next_slot;
+        self.next_slot = (slot + 1) % SLOTS;
+        self.slots[slot] = Some(n);
+
+        let tiles = workgroups(n);
+        let chunks = workgroups(RADIX * tiles);
+        for pass in 0..PASSES {
+            let uniform = SortUniform {
+                n,
+                shift: pass * RADIX_BITS,
+                tiles,
+                chunks,
+            };
+            queue.write_buffer(
+                &self.uniform,
+                self.uniform_offset(slot, pass),
+                bytemuck::bytes_of(&uniform),
+            );
+        }
+        slot
+    }
+
+    fn uniform_offset(&self, slot: usize, pass: u32) -> u64 {
+        (slot as u64 * PASSES as u64 + pass as u64) * self.uniform_stride
+    }
+
+    /// Move the bind groups over `keys` and `values` last in the cache, creating them on
+    /// first use
+    fn cache_bind_groups(
+        &mut self,
+        device: &wgpu::Device,
+        keys: &wgpu::Buffer,
+        values: &wgpu::Buffer,
+    ) {
+        match self
+            .bind_groups
+            .iter()
+            .position(|cached| cached.keys == *keys && cached.values == *values)
+        {
+            Some(i) => {
+                let cached = self.bind_groups.remove(i);
+                self.bind_groups.push(cached);
+            }
+            None => {
+                if self.bind_groups.len() == CACHED_BIND_GROUPS {
+                    self.bind_groups.remove(0);
+                }
+                // Ping-pong between the caller's buffers and the scratch ones
+                let groups = [
+                    ([keys, values], [&self.keys, &self.values]),
+                    ([&self.keys, &self.values], [keys, values]),
+                ]
+                .map(|(from, to)| {
+                    device.create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("sort_bg"),
+                        layout: &self.bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                    buffer: &self.uniform,
+                                    offset: 0,
+                                    size: wgpu::BufferSize::new(
+                                        std::mem::size_of::<SortUniform>() as u64
+                                    ),
+                                }),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: from[0].as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 2,
+                                resource: from[1].as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 3,
+                                resource: to[0].as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 4,
+                                resource: to[1].as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 5,
+                                resource: self.counts.as_entire_binding(),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 6,
+                                resource: self.blocks.as_entire_binding(),
+                            },
+                        ],
+                    })
+                });
+                self.bind_groups.push(SortBindGroups {
+                    keys: keys.clone(),
+                    values: values.clone(),
+                    groups,
+                });
+            }
+        }
+    }
+
+    /// Record the sort of the first `n` entries of `keys` and `values` by key into
+    /// `encoder`, after the work that fills them
+    ///
+    /// Both buffers need the `STORAGE` usage. The pass parameters of each pair count
+    /// are written once through `queue` and selected per dispatch, so up to `SLOTS`
+    /// different counts can be recorded before one submission.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        keys: &wgpu::Buffer,
+        values: &wgpu::Buffer,
+        n: u32,
+    ) {
+        if n <= 1 {
+            return;
+        }
+        if n > self.capacity {
+            self.capacity = n;
+            [self.keys, self.values, self.counts, self.blocks] = make_scratch(device, n);
+            self.bind_groups.clear();
+        }
+
+        let tiles = workgroups(n);
+        let chunks = workgroups(RADIX * tiles);
+        let slot = self.slot(queue, n);
+        self.cache_bind_groups(device, keys, values);
+        let bind_groups = &self.bind_groups.last().expect("cached above").groups;
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Sort Pass"),
+            timestamp_writes: None,
+        });
+        for pass in 0..PASSES {
+            let offset = self.uniform_offset(slot, pass) as u32;
+            compute_pass.set_bind_group(0, &bind_groups[pass as usize % 2], &[offset]);
+            for (pipeline, count) in [
+                (&self.count_pipeline, tiles),
+                (&self.scan_pipeline, chunks),
+                (&self.blocks_pipeline, 1),
+                (&self.scatter_pipeline, tiles),
+            ] {
+                compute_pass.set_pipeline(pipeline);
+                compute_pass.dispatch_workgroups(count, 1, 1);
+            }
+        }
+    }
+}
+
+/// Random pairs sorted by `--validate`, more than a workgroup of workgroups of digit
+/// counts, and the key bits set so that many keys are equal
+pub const VALIDATION_COUNT: u32 = 200_003;
+pub const VALIDATION_KEY_MASK: u32 = 0xf00f_f0f0;
+
+/// Morton code of the cell holding each position, on a grid of 2^16 cells per side
+/// spanning the world
+///
+/// Positions outside the world fall in the nearest edge cell.
+fn morton_codes(params: &SimParams, positions: &[[f32; 2]]) -> Vec<u32> {
+    let cells = (1u32 << (u32::BITS / 2)) as f32;
+    let size = params.world[1] - params.world[0];
+    positions
+        .iter()
+        .map(|&p| {
+            let cell = ((glam::Vec2::from(p) - params.world[0]) / size * cells)
+                .floor()
+                .clamp(glam::Vec2::ZERO, glam::Vec2::splat(cells - 1.0));
+            sort::morton(cell.as_uvec2())
+        })
+        .collect()
+}
+
+/// Sort the Morton codes of a scenario and `count` random keys on the GPU, like the CPU
+pub fn validate(v: &Validation, count: u32) -> anyhow::Result<()> {
+    let sim = v.simulation(v.params())?;
+    let (device, queue) = (&sim.device, &sim.queue);
+
+    log::info!("Validating the radix sort");
+
+    let morton = morton_codes(&sim.params, &sim.snapshot()?.particles.positions);
+    let mut rng: rand::rngs::StdRng = rand::SeedableRng::seed_from_u64(sim.params.seed);
+    let random: Vec<u32> = (0..count)
+        .map(|_| rand::Rng::random::<u32>(&mut rng) & VALIDATION_KEY_MASK)
+        .collect();
+
+    let upload = |data: &[u32]| {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("validation_sort"),
+            size: std::mem::size_of_val(data) as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&buffer, 0, bytemuck::cast_slice(data));
+        buffer
+    };
+    let cases: Vec<_> = [("Morton codes", morton), ("random keys", random)]
+        .into_iter()
+        .map(|(label, keys)| {
+            let values: Vec<u32> = (0..keys.len() as u32).collect();
+            let buffers = (upload(&keys), upload(&values));
+            (label, keys, values, buffers)
+        })
+        .collect();
+
+    // Both sorts in one submission, each with its own count
+    let mut sorter = RadixSort::new(device, sim.params.n);
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("validation_sort_encoder"),
+    });
+    for (_, keys, _, (key_buffer, value_buffer)) in &cases {
+        let n = keys.len() as u32;
+        sorter.encode(device, queue, &mut encoder, key_buffer, value_buffer, n);
+    }
+    queue.submit(Some(encoder.finish()));
+
+    for (label, keys, values, (key_buffer, value_buffer)) in cases {
+        let n = keys.len() as u32;
+        let sorted_keys = GpuBuffers::read_back::<u32>(device, queue, &key_buffer, n)?;
+        let sorted_values = GpuBuffers::read_back::<u32>(device, queue, &value_buffer, n)?;
+
+        let (expected_keys, expected_values) = sort::sort_pairs(&keys, &values);
+        if let Some(i) = (0..n as usize)
+            .find(|&i| sorted_values[i] != expected_values[i] || sorted_keys[i] != expected_keys[i])
+        {
+            anyhow::bail!(
+                "Sorting {n} {label} deviates from the CPU at entry {i}: key {} of pair {}, \
+expected key {} of pair {}",
+                sorted_keys[i],
+                sorted_values[i],
+                expected_keys[i],
+                expected_values[i]
+            );
+        }
+    }
+
+    log::info!(
+        "Sorted {} Morton codes and {count} random keys like the CPU",
+        sim.params.n
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn radix_sort_matches_the_cpu() -> anyhow::Result<()> {
+        // Still more than a workgroup of workgroups of digit counts
+        validate(&Validation::quick(), 70_001)
+    }
+}
@@ -0,0 +1,318 @@
+use crate::{
+    constants,
+    gpu::{
+        compute,
+        readback::AsyncReadBack,
+        validation::{VALIDATION_RUN_STEPS, Validation, same_bits},
+    },
+    sim::{
+        SimParams,
+        integrator::Integrator,
+        timestep::{self, StepState, TimestepMode},
+    },
+};
+
+/// GPU resources of the time step selection (`shaders/timestep.wgsl`)
+pub struct TimestepPass {
+    bind_group_layout: wgpu::BindGroupLayout,
+    /// (max |a|, max |v|) per workgroup
+    partials: wgpu::Buffer,
+    /// Number of workgroups `partials` can hold
+    capacity: u32,
+
+    bind_group: wgpu::BindGroup,
+
+    partial_pipeline: wgpu::ComputePipeline,
+    total_pipeline: wgpu::ComputePipeline,
+    stage_pipeline: wgpu::ComputePipeline,
+
+    /// Copy of the step state, read without blocking the frame
+    read_back: AsyncReadBack<StepState>,
+}
+
+pub fn make_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("timestep_bgl"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            // workgroup maxima
+            binding: 11,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+fn workgroups(n: u32) -> u32 {
+    n.div_ceil(constants::shader::WORKGROUP_SIZE).max(1)
+}
+
+fn make_partials(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("timestep_partials"),
+        size: std::mem::size_of::<[f32; 2]>() as u64 * capacity as u64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    })
+}
+
+fn make_bind_group(
+    device: &wgpu::Device,
+    bgl: &wgpu::BindGroupLayout,
+    partials: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("timestep_bg"),
+        layout: bgl,
+        entries: &[wgpu::BindGroupEntry {
+            // workgroup maxima
+            binding: 11,
+            resource: partials.as_entire_binding(),
+        }],
+    })
+}
+
+impl TimestepPass {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        compute_bind_group_layout: &wgpu::BindGroupLayout,
+        n: u32,
+    ) -> Self {
+        let capacity = workgroups(n);
+        let partials = make_partials(device, capacity);
+
+        let bind_group_layout = make_bind_group_layout(device);
+        let bind_group = make_bind_group(device, &bind_group_layout, &partials);
+
+        let pipeline_layout =
+            compute::make_pipeline_layout(device, &[compute_bind_group_layout, &bind_group_layout]);
+        let mk = |entry_point| {
+            compute::make_entry_pipeline(device, &pipeline_layout, shader, entry_point)
+        };
+
+        Self {
+            bind_group_layout,
+            partials,
+            capacity,
+
+            bind_group,
+
+            partial_pipeline: mk("ts_partial"),
+            total_pipeline: mk("ts_total"),
+            stage_pipeline: mk("ts_stage"),
+
+            read_back: AsyncReadBack::new(device, "step state"),
+        }
+    }
+
+    /// Pick the length of the next step after the step encoded with `compute_bind_group`
+    ///
+    /// The maxima of the `n` particles are only reduced when `adaptive`.
+    pub fn encode(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        compute_bind_group: &wgpu::BindGroup,
+        n: u32,
+        adaptive: bool,
+    ) {
+        let groups = workgroups(n);
+        if groups > self.capacity {
+            self.capacity = groups;
+            self.partials = make_partials(device, groups);
+            self.bind_group = make_bind_group(device, &self.bind_group_layout, &self.partials);
+        }
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Timestep Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.bind_group, &[]);
+
+        if adaptive {
+            compute_pass.set_pipeline(&self.partial_pipeline);
+            compute_pass.dispatch_workgroups(groups, 1, 1);
+        }
+
+        compute_pass.set_pipeline(&self.total_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// Move on to the next integrator stage of the step encoded with `compute_bind_group`
+    pub fn encode_stage(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        compute_bind_group: &wgpu::BindGroup,
+    ) {
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Integrator Stage Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.stage_pipeline);
+        compute_pass.set_bind_group(0, compute_bind_group, &[]);
+        compute_pass.set_bind_group(1, &self.bind_group, &[]);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    /// Start copying `state` back to the CPU, unless a copy is still in flight
+    pub fn request_read_back(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        state: &wgpu::Buffer,
+    ) {
+        self.read_back.request(device, queue, state);
+    }
+
+    /// The state copied by [`TimestepPass::request_read_back`], once it has arrived
+    ///
+    /// This never blocks.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<StepState> {
+        self.read_back.poll(device)
+    }
+
+    /// Drop the read back in flight, the state was replaced since it was requested
+    pub fn discard(&mut self) {
+        self.read_back.discard();
+    }
+}
+
+/// Substeps per frame and frames run by the substep check
+pub const VALIDATION_SUBSTEPS: u32 = 4;
+pub const VALIDATION_FRAMES: u32 = 3;
+/// Largest relative difference accepted between the GPU and CPU time step choices
+pub const VALIDATION_TOLERANCE: f32 = 1e-4;
+/// Small enough for the adaptive steps of the validation particles not to be clamped
+pub const VALIDATION_ACCURACY: f32 = 0.05;
+
+/// Check the substeps and the time step choice of the GPU
+///
+/// Frames of several substeps recorded into one encoder must match single steps bit
+/// for bit, with the same simulated time, here with a two-stage integrator. Each
+/// adaptive mode must pick the step that the CPU derives from the largest acceleration
+/// and speed of the previous step.
+pub fn validate(v: &Validation) -> anyhow::Result<()> {
+    let params = SimParams {
+        substeps: VALIDATION_SUBSTEPS,
+        integrator: Integrator::VelocityVerlet,
+        ..v.params()
+    };
+
+    log::info!(
+        "Validating {VALIDATION_FRAMES} frames of {VALIDATION_SUBSTEPS} substeps ({})",
+        v.solver.label()
+    );
+
+    let mut frames = v.simulation(params.clone())?;
+    for _ in 0..VALIDATION_FRAMES {
+        let mut encoder = frames
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
+        frames.encode_frame(&mut encoder);
+        frames.queue.submit(Some(encoder.finish()));
+        frames.finish_update();
+    }
+    let mut steps = v.simulation(params)?;
+    steps.step(VALIDATION_FRAMES * VALIDATION_SUBSTEPS);
+
+    let (frames_state, steps_state) = (frames.snapshot()?, steps.snapshot()?);
+    if frames_state.params.epoch != steps_state.params.epoch
+        || !same_bits(&frames_state.particles, &steps_state.particles)
+    {
+        anyhow::bail!("Substeps diverged from single steps");
+    }
+    let expected = frames.params.dt * (VALIDATION_FRAMES * VALIDATION_SUBSTEPS) as f32;
+    for (name, time) in [
+        ("CPU clock", frames.clock.time),
+        ("GPU clock", frames_state.clock.time),
+        ("single step GPU clock", steps_state.clock.time),
+    ] {
+        if (time - expected).abs() > VALIDATION_TOLERANCE * expected {
+            anyhow::bail!("{name} reads {time} s after {expected} s of substeps");
+        }
+    }
+
+    for mode in [TimestepMode::Acceleration, TimestepMode::Velocity] {
+        let mut sim = v.simulation(SimParams {
+            timestep: mode,
+            dt: *constants::sim::DT_RANGE.end(), // room for the adaptive choice
+            accuracy: VALIDATION_ACCURACY,
+            ..v.params()
+        })?;
+        sim.step(VALIDATION_RUN_STEPS);
+
+        let before = sim.read_clock()?;
+        let v_old = sim.read_velocities()?;
+        sim.step(1);
+        let after = sim.read_clock()?;
+        let v_new = sim.read_velocities()?;
+
+        // Same recovery of the accelerations as `ts_partial`
+        let kick = 0.5 * (before.prev_dt + before.dt);
+        let damp_step = sim.params.damping.powf(before.dt);
+        let (max_acceleration, max_speed) =
+            v_old
+                .iter()
+                .zip(&v_new)
+                .fold((0.0f32, 0.0f32), |(max_a, max_v), (old, new)| {
+                    let new = glam::Vec2::from(*new);
+                    let acc = (new / damp_step - glam::Vec2::from(*old)) / kick;
+                    (max_a.max(acc.length()), max_v.max(new.length()))
+                });
+        let expected = timestep::adaptive_dt(
+            mode,
+            sim.params.accuracy,
+            sim.params.softening,
+            sim.params.dt,
+            max_acceleration,
+            max_speed,
+        );
+        log::info!(
+            "{} picked dt = {:e} s (CPU {expected:e} s)",
+            mode.label(),
+            after.dt
+        );
+
+        let expected_clock = StepState {
+            prev_dt: before.dt,
+            dt: expected,
+            time: before.time + before.dt,
+            time_error: after.time_error,
+            ..StepState::default()
+        };
+        let close = |a: f32, b: f32| (a - b).abs() <= VALIDATION_TOLERANCE * b.abs();
+        if !close(after.dt, expected_clock.dt)
+            || after.prev_dt != expected_clock.prev_dt
+            || !close(after.time, expected_clock.time)
+        {
+            anyhow::bail!(
+                "{} step state {after:?} differs from the CPU {expected_clock:?}",
+                mode.label()
+            );
+        }
+        if sim.clock != after {
+            anyhow::bail!("Clock {:?} was not read back after stepping", sim.clock);
+        }
+    }
+
+    log::info!("Substeps and adaptive time steps match the CPU");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs a GPU adapter"]
+    fn time_steps_match_the_cpu_reference() -> anyhow::Result<()> {
+        validate(&Validation::quick())
+    }
+}
@@ -0,0 +1,266 @@
+//! GPU checks against the CPU references, run by `--validate`
+//!
+//! Each check lives next to the GPU module it covers and has a test there, ignored by
+//! default as it needs an adapter: `cargo test -- --ignored` runs them with fewer steps
+//! and smaller bursts than `--validate`.
+
+use glam::Vec2;
+
+use super::{
+    Simulation, barnes_hut, collisions, compute, density, diagnostics, edit, fluid, grid,
+    particle_mesh, simulation, sort, timestep,
+};
+use crate::sim::{ParticleData, SimParams, Solver, cpu::CpuSimulation, scenario::ChargeScheme};
+
+/// Particle count of the checks, small enough for the O(N^2) CPU reference
+pub const VALIDATION_PARTICLES: u32 = 512;
+/// Number of compute steps compared with the CPU reference when `--steps` is not given
+pub const VALIDATION_STEPS: u32 = 50;
+/// Seed of the initial conditions when `--seed` is not given
+pub const VALIDATION_SEED: u64 = 1;
+/// Steps run before and between the edits, merges and snapshots of the checks
+pub const VALIDATION_RUN_STEPS: u32 = 10;
+/// Largest position difference accepted between the GPU kernel and the CPU reference
+pub const VALIDATION_POSITION_TOLERANCE: f32 = 1e-4;
+/// Largest velocity difference accepted between the GPU kernel and the CPU reference
+pub const VALIDATION_VELOCITY_TOLERANCE: f32 = 1e-4;
+/// Scenario with bonds of the kernel, diagnostics and editing checks
+pub const VALIDATION_BONDED_SCENARIO: &str = "cloth";
+/// Particles spawned in one burst by the editing and collision checks, enough for
+/// more than one workgroup of workgroup counts
+pub const VALIDATION_LARGE_BURST: u32 = 80_000;
+/// Particle masses are multiplied by these, and `g` divided, to check that the
+/// approximate solvers neither lose light particles nor overflow with heavy ones
+pub const VALIDATION_MASS_SCALES: [f32; 3] = [1.0, 1e-4, 1e6];
+/// Where the checks spawn their bursts
+pub const VALIDATION_SPAWN_CENTER: Vec2 = Vec2::new(-0.3, 0.2);
+/// Close to the edge of the wrapping world, the areas around it cross the edge
+pub const VALIDATION_EDGE: Vec2 = Vec2::new(0.95, 0.0);
+
+/// Where and how the checks run
+#[derive(Clone, Copy)]
+pub struct Validation<'a> {
+    /// Solver of the simulations of the checks
+    pub solver: Solver,
+    /// Seed of the initial conditions
+    pub seed: u64,
+    /// Name of the adapter, the best one otherwise
+    pub adapter: Option<&'a str>,
+    /// Steps compared with the CPU reference
+    pub steps: u32,
+}
+
+impl Validation<'_> {
+    /// Size of the tests, on the best adapter
+    #[cfg(test)]
+    pub fn quick() -> Self {
+        Validation {
+            solver: Solver::Direct,
+            seed: VALIDATION_SEED,
+            adapter: None,
+            steps: 10,
+        }
+    }
+
+    /// Running parameters of the checks
+    pub fn params(&self) -> SimParams {
+        SimParams {
+            n: VALIDATION_PARTICLES,
+            seed: self.seed,
+            paused: false,
+            solver: self.solver,
+            ..SimParams::default()
+        }
+    }
+
+    pub fn simulation(&self, params: SimParams) -> anyhow::Result<Simulation> {
+        pollster::block_on(Simulation::new_headless(params, self.adapter))
+    }
+
+    /// Step the direct-sum kernel with `params` and `charges` and the CPU reference side
+    /// by side, from the particles of `scenario` if given
+    pub fn compare_steps(
+        &self,
+        params: SimParams,
+        scenario: Option<&str>,
+        charges: ChargeScheme,
+        label: &str,
+    ) -> anyhow::Result<()> {
+        let mut sim = self.simulation(params)?;
+        if let Some(id) = scenario {
+            sim.scenarios.select(id)?;
+        }
+        sim.scenarios.charges = charges;
+        sim.reset_particles();
+        let mut reference = cpu_reference(&sim)?;
+
+        log::info!(
+            "Validating {} steps of {label} with {} particles against the CPU reference",
+            self.steps,
+            sim.params.n
+        );
+
+        for step in 1..=self.steps {
+            sim.step(1);
+            reference.step();
+
+            let (pi, pos_err) = max_abs_diff(&sim.read_positions()?, &reference.positions);
+            let (vi, vel_err) = max_abs_diff(&sim.read_velocities()?, &reference.velocities);
+            log::debug!(
+                "Step {step}: max position error {pos_err:e}, max velocity error {vel_err:e}"
+            );
+
+            if pos_err > VALIDATION_POSITION_TOLERANCE || vel_err > VALIDATION_VELOCITY_TOLERANCE {
+                anyhow::bail!(
+                    "GPU diverged from CPU reference at step {step} ({label}): \
+position error {pos_err:e} (particle {pi}), velocity error {vel_err:e} (particle {vi})"
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Run every check with `solver`, `steps` compared with the CPU reference
+///
+/// The direct-sum solver must match the reference step by step. The approximate solvers
+/// are only checked on the forces of the first step, as trajectories diverge quickly.
+/// Every check starts from the initial conditions of `seed`.
+pub fn validate(
+    steps: u32,
+    solver: Solver,
+    seed: u64,
+    adapter: Option<&str>,
+) -> anyhow::Result<()> {
+    let v = Validation {
+        solver,
+        seed,
+        adapter,
+        steps,
+    };
+    log::info!("Validating with seed {seed}");
+
+    sort::validate(&v, sort::VALIDATION_COUNT)?;
+    grid::validate(&v, grid::VALIDATION_BURST)?;
+    match solver {
+        Solver::Direct => {
+            compute::validate(&v)?;
+            fluid::validate(&v)?;
+        }
+        Solver::BarnesHut => barnes_hut::validate(&v)?,
+        Solver::ParticleMesh => particle_mesh::validate(&v)?,
+    }
+    simulation::validate(&v)?;
+    diagnostics::validate(&v)?;
+    timestep::validate(&v)?;
+    edit::validate(&v, VALIDATION_LARGE_BURST)?;
+    collisions::validate(&v, VALIDATION_LARGE_BURST)?;
+    density::validate(&v)
+}
+
+/// CPU reference starting from the current particles of `sim`
+pub fn cpu_reference(sim: &Simulation) -> anyhow::Result<CpuSimulation> {
+    Ok(CpuSimulation::new(
+        sim.params.clone(),
+        sim.read_positions()?,
+        sim.read_velocities()?,
+        sim.read_masses()?,
+        sim.read_charges()?,
+        sim.bonds().to_vec(),
+    ))
+}
+
+/// Largest coordinate difference between `a` and `b`, and the particle where it is
+pub fn max_abs_diff(a: &[[f32; 2]], b: &[[f32; 2]]) -> (usize, f32) {
+    a.iter()
+        .zip(b)
+        .map(|(a, b)| (Vec2::from(*a) - Vec2::from(*b)).abs().max_element())
+        .enumerate()
+        .fold(
+            (0, 0.0),
+            |best, (i, d)| if d > best.1 { (i, d) } else { best },
+        )
+}
+
+/// Same particles down to the bits of every value
+pub fn same_bits(a: &ParticleData, b: &ParticleData) -> bool {
+    let bytes = |data: &ParticleData| {
+        [
+            bytemuck::cast_slice::<_, u8>(&data.positions).to_vec(),
+            bytemuck::cast_slice(&data.velocities).to_vec(),
+            bytemuck::cast_slice(&data.colors).to_vec(),
+            bytemuck::cast_slice(&data.masses).to_vec(),
+            bytemuck::cast_slice(&data.charges).to_vec(),
+            bytemuck::cast_slice(&data.bonds).to_vec(),
+        ]
+    };
+    bytes(a) == bytes(b)
+}
+
+/// Velocities before and after the first step of an approximate solver on the GPU, of
+/// its CPU reference and of the direct sum
+pub struct FirstKick {
+    pub v0: Vec<[f32; 2]>,
+    pub gpu: Vec<[f32; 2]>,
+    pub reference: Vec<[f32; 2]>,
+    pub direct: Vec<[f32; 2]>,
+}
+
+impl FirstKick {
+    /// Step `v.solver` once with the masses scaled by `mass_scale`, see
+    /// [`VALIDATION_MASS_SCALES`]
+    pub fn run(v: &Validation, mass_scale: f32) -> anyhow::Result<Self> {
+        let mut sim = v.simulation(v.params())?;
+        if mass_scale != 1.0 {
+            // Same accelerations, g times the masses is unchanged
+            let mut snapshot = sim.snapshot()?;
+            for mass in &mut snapshot.particles.masses {
+                *mass *= mass_scale;
+            }
+            snapshot.params.g /= mass_scale;
+            sim.restore(snapshot);
+        }
+
+        let mut reference = cpu_reference(&sim)?;
+        let mut direct = CpuSimulation::new(
+            SimParams {
+                solver: Solver::Direct,
+                ..sim.params.clone()
+            },
+            reference.positions.clone(),
+            reference.velocities.clone(),
+            reference.masses.clone(),
+            reference.charges.clone(),
+            reference.bonds.clone(),
+        );
+        let v0 = reference.velocities.clone();
+
+        sim.step(1);
+        reference.step();
+        direct.step();
+
+        Ok(Self {
+            v0,
+            gpu: sim.read_velocities()?,
+            reference: reference.velocities,
+            direct: direct.velocities,
+        })
+    }
+
+    /// Relative RMS error of the GPU kick, i.e. of the accelerations, against `expected`
+    pub fn error(&self, expected: &[[f32; 2]]) -> f32 {
+        let (err, norm) = self.v0.iter().zip(&self.gpu).zip(expected).fold(
+            (0.0, 0.0),
+            |(err, norm), ((v0, gpu), expected)| {
+                let v0 = Vec2::from(*v0);
+                let expected = Vec2::from(*expected);
+                (
+                    err + (Vec2::from(*gpu) - expected).length_squared(),
+                    norm + (expected - v0).length_squared(),
+                )
+            },
+        );
+        (err / norm.max(f32::MIN_POSITIVE)).sqrt()
+    }
+}
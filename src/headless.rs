@@ -0,0 +1,117 @@
+use std::path::Path;
+
+use crate::{
+    gpu::{Camera, Recorder, RecorderSettings, RenderSettings, Simulation},
+    sim::{SimParams, scenario_file::ScenarioFile, snapshot::Snapshot},
+};
+
+/// Number of compute steps run by `--headless` when `--steps` is not given
+///
+/// `--validate` uses [`crate::gpu::VALIDATION_STEPS`] instead.
+pub const DEFAULT_STEPS: u32 = 1_000;
+
+/// Step the simulation `steps` times without a window and log a summary of the result
+///
+/// The run starts from `setup`, or from the snapshot at `load` if given (the `setup`
+/// parameters are then applied on top of the saved ones), and the final state is written
+/// to `save`. With `record`, frames are written while stepping, with the `setup` camera
+/// and the `render` mode.
+pub fn run(
+    steps: u32,
+    setup: &ScenarioFile,
+    load: Option<&Path>,
+    save: Option<&Path>,
+    record: Option<&RecorderSettings>,
+    adapter: Option<&str>,
+    render: &RenderSettings,
+) -> anyhow::Result<()> {
+    let mut params = SimParams {
+        paused: false,
+        ..SimParams::default()
+    };
+    setup.sim.apply(&mut params);
+    let mut sim = pollster::block_on(Simulation::new_headless(params, adapter))?;
+
+    if setup.customizes_particles() {
+        sim.configure(setup, true)?;
+    }
+
+    if let Some(path) = load {
+        sim.restore(Snapshot::load(path)?);
+        setup.sim.apply(&mut sim.params);
+        sim.sync_uniform();
+        log::info!(
+            "Resuming from snapshot '{}' at epoch {}",
+            path.display(),
+            sim.params.epoch
+        );
+    }
+
+    log::info!(
+        "Running {} headless steps with {} particles ({}, seed {})",
+        steps,
+        sim.params.n,
+        sim.params.solver.label(),
+        sim.params.seed
+    );
+
+    let start = std::time::Instant::now();
+    if let Some(settings) = record {
+        let mut camera = Camera::new(&sim.params.world, settings.width, settings.height);
+        if let Some(view) = &setup.camera {
+            camera.set_view(view);
+        }
+        let mut recorder = Recorder::start(&sim, &camera, render, settings)?;
+        for _ in 0..steps {
+            let time = sim.clock.time;
+            sim.step(1);
+            recorder.advance(&sim, &camera, render, sim.clock.time - time)?;
+        }
+        recorder.finish()?;
+    } else {
+        sim.step(steps);
+    }
+    let positions = sim.read_positions()?;
+    let velocities = sim.read_velocities()?;
+    let elapsed = start.elapsed();
+
+    let n = positions.len().max(1) as f32;
+    let center = positions
+        .iter()
+        .fold(glam::Vec2::ZERO, |acc, p| acc + glam::Vec2::from(*p))
+        / n;
+    let mean_speed = velocities
+        .iter()
+        .map(|v| glam::Vec2::from(*v).length())
+        .sum::<f32>()
+        / n;
+
+    log::info!(
+        "Finished epoch {} ({:.3} s simulated) in {:.2?} ({:.2} steps/s)",
+        sim.params.epoch,
+        sim.clock.time,
+        elapsed,
+        steps as f32 / elapsed.as_secs_f32()
+    );
+    log::info!(
+        "Center of positions: ({:.4}, {:.4}), mean speed: {:.4}",
+        center.x,
+        center.y,
+        mean_speed
+    );
+
+    if sim.params.collisions {
+        log::info!(
+            "{} particles merged, {} left",
+            sim.diagnostics.merges,
+            sim.params.n
+        );
+    }
+
+    if let Some(path) = save {
+        sim.snapshot()?.save(path)?;
+        log::info!("Saved snapshot to '{}'", path.display());
+    }
+
+    Ok(())
+}
@@ -1,21 +1,80 @@
 mod app;
+mod cli;
 mod constants;
 mod gpu;
+mod headless;
 mod sim;
 mod utils;
 
 use winit::event_loop::{ControlFlow, EventLoop};
 
-use crate::app::App;
+use crate::{
+    app::App,
+    cli::{Mode, USAGE},
+};
 
 fn main() {
     utils::logger::init_logger();
 
+    let mode = match cli::parse_args() {
+        Ok(mode) => mode,
+        Err(err) => {
+            log::error!("{err}");
+            log::error!("Run with --help to list the options");
+            std::process::exit(2);
+        }
+    };
+
+    let result = match &mode {
+        Mode::Windowed { .. } => Ok(()),
+        Mode::Help => {
+            print!("{USAGE}");
+            return;
+        }
+        Mode::Headless {
+            steps,
+            setup,
+            load,
+            save,
+            record,
+            adapter,
+            render,
+        } => headless::run(
+            *steps,
+            setup,
+            load.as_deref(),
+            save.as_deref(),
+            record.as_ref(),
+            adapter.as_deref(),
+            render,
+        ),
+        Mode::Validate {
+            steps,
+            solver,
+            seed,
+            adapter,
+        } => gpu::validate(*steps, *solver, *seed, adapter.as_deref()),
+    };
+    if let Err(err) = result {
+        log::error!("Headless run failed: {err:#}");
+        std::process::exit(1);
+    }
+    let Mode::Windowed {
+        setup,
+        watch,
+        load,
+        record,
+        window,
+    } = mode
+    else {
+        return;
+    };
+
     let event_loop = EventLoop::new().expect("Failed to create event loop");
 
     event_loop.set_control_flow(ControlFlow::Poll); // Continuously poll for events
 
-    let mut app = App::default();
+    let mut app = App::new(setup, watch, load, record, window);
 
     if let Err(err) = event_loop.run_app(&mut app) {
         log::error!("Application exited with event loop error: {err}");
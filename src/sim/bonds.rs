@@ -0,0 +1,178 @@
+//! Spring networks between particles
+//!
+//! A [`Bond`] pulls its two particles toward its rest length with a Hooke force, damped
+//! along the bond. The GPU gathers the bonds of every particle from the adjacency lists
+//! built by [`adjacency`] (`shaders/bonds.wgsl`), [`accelerations`] is its CPU reference.
+
+use glam::Vec2;
+
+use super::{SimParams, cpu::wrapped_delta};
+
+/// Spring between particles `i` and `j`, laid out like `Bond` in the shaders
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Bond {
+    pub i: u32,
+    pub j: u32,
+    /// Length without tension
+    pub rest: f32,
+    /// Force per unit of stretch
+    pub stiffness: f32,
+    /// Force per unit of stretching speed
+    pub damping: f32,
+}
+
+impl Bond {
+    /// The particle at the other end of the bond from `id`
+    pub fn other(&self, id: u32) -> u32 {
+        if self.i == id { self.j } else { self.i }
+    }
+}
+
+/// Renumber the particles of `bonds`, dropping the bonds of particles mapped to `None`
+pub fn remap(bonds: &[Bond], new_index: impl Fn(u32) -> Option<u32>) -> Vec<Bond> {
+    bonds
+        .iter()
+        .filter_map(|bond| {
+            Some(Bond {
+                i: new_index(bond.i)?,
+                j: new_index(bond.j)?,
+                ..*bond
+            })
+        })
+        .collect()
+}
+
+/// Bonds of each of the `n` particles, in bond order
+///
+/// Particle `id` has the bonds `refs[offsets[id]..offsets[id + 1]]`, which are indices
+/// into `bonds`.
+pub fn adjacency(bonds: &[Bond], n: u32) -> (Vec<u32>, Vec<u32>) {
+    let mut offsets = vec![0u32; n as usize + 1];
+    for bond in bonds {
+        offsets[bond.i as usize + 1] += 1;
+        offsets[bond.j as usize + 1] += 1;
+    }
+    for id in 0..n as usize {
+        offsets[id + 1] += offsets[id];
+    }
+
+    let mut next = offsets.clone();
+    let mut refs = vec![0u32; 2 * bonds.len()];
+    for (index, bond) in bonds.iter().enumerate() {
+        for end in [bond.i, bond.j] {
+            refs[next[end as usize] as usize] = index as u32;
+            next[end as usize] += 1;
+        }
+    }
+    (offsets, refs)
+}
+
+/// Spring acceleration of every particle, see `bond_forces` in the shader
+pub fn accelerations(
+    params: &SimParams,
+    positions: &[[f32; 2]],
+    velocities: &[[f32; 2]],
+    masses: &[f32],
+    bonds: &[Bond],
+) -> Vec<Vec2> {
+    let world_size = params.world[1] - params.world[0];
+    let (offsets, refs) = adjacency(bonds, positions.len() as u32);
+
+    (0..positions.len())
+        .map(|id| {
+            let p = Vec2::from(positions[id]);
+            let v = Vec2::from(velocities[id]);
+            let mut force = Vec2::ZERO;
+            for &index in &refs[offsets[id] as usize..offsets[id + 1] as usize] {
+                let bond = bonds[index as usize];
+                let other = bond.other(id as u32) as usize;
+
+                let mut delta = Vec2::from(positions[other]) - p;
+                if params.wrap {
+                    delta = wrapped_delta(delta, world_size);
+                }
+                let length = delta.dot(delta).sqrt();
+                if length > 0.0 {
+                    let direction = delta / length;
+                    let stretching = (Vec2::from(velocities[other]) - v).dot(direction);
+                    force += (bond.stiffness * (length - bond.rest) + bond.damping * stretching)
+                        * direction;
+                }
+            }
+            force / masses[id]
+        })
+        .collect()
+}
+
+/// Elastic energy stored in the bonds
+pub fn potential(params: &SimParams, positions: &[[f32; 2]], bonds: &[Bond]) -> f64 {
+    let world_size = params.world[1] - params.world[0];
+    bonds
+        .iter()
+        .map(|bond| {
+            let mut delta =
+                Vec2::from(positions[bond.j as usize]) - Vec2::from(positions[bond.i as usize]);
+            if params.wrap {
+                delta = wrapped_delta(delta, world_size);
+            }
+            let stretch = (delta.length() - bond.rest) as f64;
+            0.5 * bond.stiffness as f64 * stretch * stretch
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bond(i: u32, j: u32) -> Bond {
+        Bond {
+            i,
+            j,
+            rest: 0.1,
+            stiffness: 2.0,
+            damping: 0.0,
+        }
+    }
+
+    #[test]
+    fn adjacency_is_symmetric() {
+        let bonds = [bond(0, 1), bond(1, 2), bond(3, 1), bond(2, 3)];
+        let (offsets, refs) = adjacency(&bonds, 5);
+
+        assert_eq!(offsets, [0, 1, 4, 6, 8, 8]);
+        for (index, bond) in bonds.iter().enumerate() {
+            for end in [bond.i, bond.j] {
+                let own = &refs[offsets[end as usize] as usize..offsets[end as usize + 1] as usize];
+                assert!(own.contains(&(index as u32)), "bond {index} of {end}");
+            }
+        }
+    }
+
+    #[test]
+    fn remap_drops_the_bonds_of_removed_particles() {
+        let bonds = [bond(0, 1), bond(1, 2), bond(2, 3)];
+        let remapped = remap(&bonds, |id| match id {
+            1 => None,
+            id => Some(id - u32::from(id > 1)),
+        });
+        assert_eq!(remapped, [bond(1, 2)]);
+    }
+
+    #[test]
+    fn stretched_bonds_pull_both_ends() {
+        let params = SimParams {
+            wrap: false,
+            ..SimParams::default()
+        };
+        let positions = [[0.0, 0.0], [0.15, 0.0]];
+        let masses = [1.0, 2.0];
+        let acc = accelerations(&params, &positions, &[[0.0; 2]; 2], &masses, &[bond(0, 1)]);
+
+        assert!((acc[0] - Vec2::new(0.1, 0.0)).length() < 1e-6, "{}", acc[0]);
+        assert!((masses[0] * acc[0] + masses[1] * acc[1]).length() < 1e-6);
+        let energy = potential(&params, &positions, &[bond(0, 1)]);
+        assert!((energy - 0.0025).abs() < 1e-8, "{energy}");
+    }
+}
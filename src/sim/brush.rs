@@ -0,0 +1,261 @@
+//! Pointer-driven force field
+//!
+//! While the left mouse button is held over the world, particles within `radius` of
+//! the pointer are pulled towards it (or pushed away), with an acceleration falling
+//! linearly from `strength` at the pointer to zero at the edge. It reaches the compute
+//! shader through `SimUniform::brush` and applies on top of every solver.
+//!
+//! The brush can also edit the particles instead: a click spawns a burst of new
+//! particles within `radius`, and holding the button erases those under the pointer.
+
+use glam::Vec2;
+use rand::{Rng, rngs::StdRng};
+
+use super::{ParamsEguiAction, ParticleData, ParticleUpdated, scenario};
+use crate::constants;
+
+/// What the brush does while the button is held
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BrushMode {
+    #[default]
+    Attract,
+    Repel,
+    /// Add a burst of particles on every click
+    Spawn,
+    /// Remove the particles under the pointer
+    Erase,
+}
+
+impl BrushMode {
+    pub const ALL: [BrushMode; 4] = [
+        BrushMode::Attract,
+        BrushMode::Repel,
+        BrushMode::Spawn,
+        BrushMode::Erase,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            BrushMode::Attract => "Attract",
+            BrushMode::Repel => "Repel",
+            BrushMode::Spawn => "Spawn",
+            BrushMode::Erase => "Erase",
+        }
+    }
+
+    /// Whether the mode acts through a force rather than by editing the particles
+    pub fn is_force(&self) -> bool {
+        matches!(self, BrushMode::Attract | BrushMode::Repel)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Brush {
+    pub mode: BrushMode,
+    /// Acceleration at the pointer, world units / s²
+    pub strength: f32,
+    /// Reach of the force, world units
+    pub radius: f32,
+    /// World position of the pointer while the button is held
+    pub target: Option<Vec2>,
+    /// Apply the opposite of `mode` (Shift held)
+    pub inverted: bool,
+    /// Particles added per click in spawn mode
+    pub spawn_count: u32,
+    /// Velocity standard deviation per axis of spawned particles, world units / s
+    pub spawn_dispersion: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            mode: BrushMode::default(),
+            strength: constants::brush::STRENGTH,
+            radius: constants::brush::RADIUS,
+            target: None,
+            inverted: false,
+            spawn_count: constants::brush::SPAWN_COUNT,
+            spawn_dispersion: constants::brush::SPAWN_DISPERSION,
+        }
+    }
+}
+
+impl Brush {
+    /// Signed strength, positive towards the pointer
+    pub fn acceleration(&self) -> f32 {
+        let attract = (self.mode == BrushMode::Attract) != self.inverted;
+        if attract {
+            self.strength
+        } else {
+            -self.strength
+        }
+    }
+
+    /// (target x, target y, signed strength, radius), all zero while released or editing
+    pub fn to_uniform(&self) -> [f32; 4] {
+        match self.target {
+            Some(target) if self.mode.is_force() => {
+                [target.x, target.y, self.acceleration(), self.radius]
+            }
+            _ => [0.0; 4],
+        }
+    }
+
+    /// At most `count` new particles spread uniformly over the disc of `radius` around
+    /// `center`, with Gaussian velocities of `spawn_dispersion`
+    pub fn burst(
+        &self,
+        center: Vec2,
+        count: u32,
+        color: [f32; 4],
+        rng: &mut StdRng,
+    ) -> ParticleData {
+        let mut data = ParticleData::with_capacity(count);
+        for _ in 0..count {
+            let r = self.radius * rng.random::<f32>().sqrt();
+            let theta = std::f32::consts::TAU * rng.random::<f32>();
+            let position = center + r * Vec2::from_angle(theta);
+            let velocity =
+                Vec2::new(scenario::normal(rng), scenario::normal(rng)) * self.spawn_dispersion;
+            data.push(
+                position,
+                velocity,
+                color,
+                constants::scenario::PARTICLE_MASS,
+            );
+        }
+        data
+    }
+
+    pub fn render_ui(&mut self, ui: &mut egui::Ui) -> ParamsEguiAction {
+        let mut action = ParamsEguiAction::None;
+
+        ui.heading("Brush");
+
+        let mut mode = self.mode;
+        ui.horizontal(|ui| {
+            for option in BrushMode::ALL {
+                ui.selectable_value(&mut mode, option, option.label());
+            }
+        });
+        if mode != self.mode {
+            self.mode = mode;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        let mut strength = self.strength;
+        ui.add_enabled_ui(self.mode.is_force(), |ui| {
+            ui.add(
+                egui::Slider::new(&mut strength, constants::brush::STRENGTH_RANGE)
+                    .text("Strength")
+                    .logarithmic(true)
+                    .step_by(constants::brush::STRENGTH_STEP),
+            )
+            .on_hover_text("Acceleration at the pointer, in world units per second squared. It falls linearly to zero at the radius");
+        });
+        if strength != self.strength {
+            self.strength = strength;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        let mut radius = self.radius;
+        if ui
+            .add(
+                egui::Slider::new(&mut radius, constants::brush::RADIUS_RANGE)
+                    .text("Radius")
+                    .step_by(constants::brush::RADIUS_STEP),
+            )
+            .on_hover_text("Reach of the brush in world units, also the size of spawned bursts and erased areas")
+            .changed()
+        {
+            self.radius = radius;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        let mut spawn_count = self.spawn_count;
+        ui.add_enabled_ui(self.mode == BrushMode::Spawn, |ui| {
+            ui.add(
+                egui::Slider::new(&mut spawn_count, constants::brush::SPAWN_COUNT_RANGE)
+                    .text("Burst Size")
+                    .logarithmic(true),
+            )
+            .on_hover_text("Particles added per click, spread over the brush radius");
+        });
+        if spawn_count != self.spawn_count {
+            self.spawn_count = spawn_count;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        let mut spawn_dispersion = self.spawn_dispersion;
+        ui.add_enabled_ui(self.mode == BrushMode::Spawn, |ui| {
+            ui.add(
+                egui::Slider::new(
+                    &mut spawn_dispersion,
+                    constants::brush::SPAWN_DISPERSION_RANGE,
+                )
+                .text("Velocity Dispersion")
+                .step_by(constants::brush::SPAWN_DISPERSION_STEP),
+            )
+            .on_hover_text("Standard deviation of each velocity component of spawned particles");
+        });
+        if spawn_dispersion != self.spawn_dispersion {
+            self.spawn_dispersion = spawn_dispersion;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        ui.label(match self.mode {
+            BrushMode::Attract | BrushMode::Repel => {
+                "Hold the left mouse button over the particles, Shift reverses the force"
+            }
+            BrushMode::Spawn => "Click to add a burst of particles",
+            BrushMode::Erase => "Hold the left mouse button to erase particles",
+        });
+
+        action
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn force_points_with_the_mode() {
+        let mut brush = Brush {
+            target: Some(Vec2::new(0.1, 0.2)),
+            ..Brush::default()
+        };
+        assert_eq!(brush.acceleration(), brush.strength);
+        brush.inverted = true;
+        assert_eq!(brush.acceleration(), -brush.strength);
+        brush.mode = BrushMode::Repel;
+        assert_eq!(brush.to_uniform(), [0.1, 0.2, brush.strength, brush.radius]);
+
+        // Editing modes and a released button exert no force
+        brush.mode = BrushMode::Erase;
+        assert_eq!(brush.to_uniform(), [0.0; 4]);
+        brush.mode = BrushMode::Attract;
+        brush.target = None;
+        assert_eq!(brush.to_uniform(), [0.0; 4]);
+    }
+
+    #[test]
+    fn bursts_fill_the_disc() {
+        let brush = Brush::default();
+        let center = Vec2::new(-0.3, 0.4);
+        let mut rng = StdRng::seed_from_u64(7);
+        let burst = brush.burst(center, 500, [1.0; 4], &mut rng);
+
+        assert_eq!(burst.positions.len(), 500);
+        assert!(
+            burst
+                .positions
+                .iter()
+                .all(|&p| { (Vec2::from(p) - center).length() <= brush.radius * (1.0 + 1e-6) })
+        );
+        assert!(burst.charges.iter().all(|&q| q == 0.0));
+        assert!(burst.bonds.is_empty());
+    }
+}
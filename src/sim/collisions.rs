@@ -0,0 +1,180 @@
+//! Inelastic collisions, particles closer than the merge radius combine into one
+//!
+//! Every particle pairs with its nearest neighbor within the radius, the lowest index
+//! among equally close ones, and mutual nearest neighbors merge into the lower index.
+//! The merged particle keeps the total mass, momentum and charge, sits at the center of
+//! mass and takes the mass-weighted color. The GPU finds the neighbors through the cell
+//! lists of [`super::grid`] (`shaders/collisions.wgsl`), [`merge`] is its O(N^2) CPU reference.
+
+use glam::Vec2;
+
+use super::{ParticleData, SimParams, bonds};
+
+/// Vector from `p` to `q`, see `merge_delta` in `shaders/merge.wgsl`
+fn delta(params: &SimParams, p: Vec2, q: Vec2) -> Vec2 {
+    let delta = q - p;
+    if params.wrap {
+        let size = params.world[1] - params.world[0];
+        delta - size * (delta / size).round()
+    } else {
+        delta
+    }
+}
+
+/// Nearest neighbor of every particle within the merge radius
+fn nearest(params: &SimParams, positions: &[[f32; 2]]) -> Vec<Option<u32>> {
+    let radius2 = params.merge_radius * params.merge_radius;
+    (0..positions.len())
+        .map(|id| {
+            let p = Vec2::from(positions[id]);
+            let mut best = None;
+            let mut best_dist2 = radius2;
+            for (other, q) in positions.iter().enumerate() {
+                let d = delta(params, p, Vec2::from(*q));
+                let dist2 = d.dot(d);
+                // Scanned by increasing index, the first of equally close ones stays
+                if other != id && dist2 < best_dist2 {
+                    best = Some(other as u32);
+                    best_dist2 = dist2;
+                }
+            }
+            best
+        })
+        .collect()
+}
+
+/// Merge the mutual nearest neighbors of `data`, returns how many particles were absorbed
+///
+/// The particles left keep their order, the bonds of the absorbed ones are dropped.
+pub fn merge(params: &SimParams, data: &mut ParticleData) -> u32 {
+    let links = nearest(params, &data.positions);
+    let mut absorbed = vec![false; links.len()];
+
+    for (id, link) in links.iter().enumerate() {
+        let Some(other) = link.map(|other| other as usize) else {
+            continue;
+        };
+        if other < id || links[other] != Some(id as u32) {
+            continue;
+        }
+
+        let (mi, mj) = (data.masses[id], data.masses[other]);
+        let mass = mi + mj;
+        let weight = mj / mass;
+        let pi = Vec2::from(data.positions[id]);
+        let mut p = pi + weight * delta(params, pi, Vec2::from(data.positions[other]));
+        if params.wrap {
+            let size = params.world[1] - params.world[0];
+            p -= size * ((p - params.world[0]) / size).floor();
+        }
+        let (vi, vj) = (
+            Vec2::from(data.velocities[id]),
+            Vec2::from(data.velocities[other]),
+        );
+        let (ci, cj) = (
+            glam::Vec4::from(data.colors[id]),
+            glam::Vec4::from(data.colors[other]),
+        );
+
+        data.positions[id] = p.to_array();
+        data.velocities[id] = ((mi * vi + mj * vj) / mass).to_array();
+        data.colors[id] = (ci * (1.0 - weight) + cj * weight).to_array(); // WGSL `mix`
+        data.charges[id] += data.charges[other];
+        data.masses[id] = mass;
+        absorbed[other] = true;
+    }
+
+    let mut new_indices = Vec::with_capacity(absorbed.len());
+    let mut kept = 0;
+    for &gone in &absorbed {
+        new_indices.push((!gone).then_some(kept));
+        kept += u32::from(!gone);
+    }
+    retain_kept(&mut data.positions, &absorbed);
+    retain_kept(&mut data.velocities, &absorbed);
+    retain_kept(&mut data.colors, &absorbed);
+    retain_kept(&mut data.masses, &absorbed);
+    retain_kept(&mut data.charges, &absorbed);
+    data.bonds = bonds::remap(&data.bonds, |id| new_indices[id as usize]);
+
+    absorbed.len() as u32 - kept
+}
+
+fn retain_kept<T>(values: &mut Vec<T>, absorbed: &[bool]) {
+    let mut flags = absorbed.iter();
+    values.retain(|_| !flags.next().is_some_and(|&gone| gone));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> SimParams {
+        SimParams {
+            merge_radius: 0.05,
+            wrap: true,
+            ..SimParams::default()
+        }
+    }
+
+    #[test]
+    fn merges_conserve_mass_momentum_and_charge() {
+        let mut data = ParticleData::with_capacity(3);
+        data.push(Vec2::new(0.1, 0.0), Vec2::new(1.0, 0.0), [1.0; 4], 1.0);
+        data.push(Vec2::new(0.5, 0.5), Vec2::ZERO, [1.0; 4], 1.0);
+        data.push(Vec2::new(0.13, 0.0), Vec2::new(0.0, 2.0), [0.0; 4], 3.0);
+        data.charges = vec![1.0, 0.0, -3.0];
+        data.bond(1, 2, 1.0, 0.0);
+
+        assert_eq!(merge(&params(), &mut data), 1);
+        assert_eq!(data.masses, [4.0, 1.0]);
+        assert_eq!(data.charges, [-2.0, 0.0]);
+        let momentum = 4.0 * Vec2::from(data.velocities[0]);
+        assert!(
+            (momentum - Vec2::new(1.0, 6.0)).length() < 1e-6,
+            "{momentum}"
+        );
+        let center = Vec2::from(data.positions[0]);
+        assert!(
+            (center - Vec2::new(0.1225, 0.0)).length() < 1e-6,
+            "{center}"
+        );
+        assert_eq!(data.colors[0], [0.25; 4]);
+        assert!(
+            data.bonds.is_empty(),
+            "the bond of the absorbed particle is kept"
+        );
+    }
+
+    #[test]
+    fn only_mutual_nearest_neighbors_merge() {
+        // The middle particle is closer to the right one, the left one is left alone
+        let mut data = ParticleData::with_capacity(3);
+        for x in [0.0, 0.03, 0.05] {
+            data.push(Vec2::new(x, 0.0), Vec2::ZERO, [1.0; 4], 1.0);
+        }
+        assert_eq!(merge(&params(), &mut data), 1);
+        assert_eq!(data.positions[0], [0.0, 0.0]);
+        assert_eq!(data.masses, [1.0, 2.0]);
+    }
+
+    #[test]
+    fn merges_across_the_wrapping_edge() {
+        let mut data = ParticleData::with_capacity(2);
+        data.push(Vec2::new(0.99, 0.0), Vec2::ZERO, [1.0; 4], 1.0);
+        data.push(Vec2::new(-0.97, 0.0), Vec2::ZERO, [1.0; 4], 1.0);
+
+        assert_eq!(merge(&params(), &mut data), 1);
+        let center = Vec2::from(data.positions[0]);
+        assert!((center - Vec2::new(-0.99, 0.0)).length() < 1e-6, "{center}");
+
+        let mut data = ParticleData::with_capacity(2);
+        data.push(Vec2::new(0.99, 0.0), Vec2::ZERO, [1.0; 4], 1.0);
+        data.push(Vec2::new(-0.97, 0.0), Vec2::ZERO, [1.0; 4], 1.0);
+        let walls = SimParams {
+            wrap: false,
+            ..params()
+        };
+        assert_eq!(merge(&walls, &mut data), 0);
+    }
+}
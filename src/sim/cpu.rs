@@ -0,0 +1,412 @@
+//! CPU reference integrators mirroring `shaders/nbody.wgsl`
+//!
+//! This is intentionally a line-by-line port of the compute kernel (same summation
+//! order, same softening, same wrap handling) so the GPU results can be checked
+//! against it. It is far too slow for interactive use.
+
+use glam::Vec2;
+
+use super::{
+    SimParams, Solver,
+    bonds::{self, Bond},
+    fluid,
+    integrator::{self, Integrator},
+    pm,
+};
+
+/// Particle state integrated on the CPU
+pub struct CpuSimulation {
+    pub params: SimParams,
+    pub positions: Vec<[f32; 2]>,
+    pub velocities: Vec<[f32; 2]>,
+    pub masses: Vec<f32>,
+    pub charges: Vec<f32>,
+    pub bonds: Vec<Bond>,
+}
+
+fn fmod(x: f32, y: f32) -> f32 {
+    // x - y * floor(x / y)  gives a result in [0, y) when y > 0
+    x - y * (x / y).floor()
+}
+
+fn clamp_pos(p: Vec2, world_min: Vec2, world_max: Vec2) -> Vec2 {
+    p.clamp(world_min, world_max)
+}
+
+fn wrap_pos(p: Vec2, world_min: Vec2, world_max: Vec2) -> Vec2 {
+    let mut np = p;
+    let world_size = world_max - world_min;
+
+    if np.x < world_min.x {
+        np.x = world_max.x - fmod(world_min.x - np.x, world_size.x);
+    }
+    if np.y < world_min.y {
+        np.y = world_max.y - fmod(world_min.y - np.y, world_size.y);
+    }
+    if np.x > world_max.x {
+        np.x = world_min.x + fmod(np.x - world_max.x, world_size.x);
+    }
+    if np.y > world_max.y {
+        np.y = world_min.y + fmod(np.y - world_max.y, world_size.y);
+    }
+    np
+}
+
+/// Shortest displacement between two points in a periodic world
+pub fn wrapped_delta(delta: Vec2, world_size: Vec2) -> Vec2 {
+    let mut d = delta;
+    let half_world = 0.5 * world_size;
+
+    if d.x > half_world.x {
+        d.x -= world_size.x;
+    } else if d.x < -half_world.x {
+        d.x += world_size.x;
+    }
+
+    if d.y > half_world.y {
+        d.y -= world_size.y;
+    } else if d.y < -half_world.y {
+        d.y += world_size.y;
+    }
+
+    d
+}
+
+/// Move a particle and keep it inside the world (clamped or wrapped)
+pub fn update_position(p: Vec2, v: Vec2, dt: f32, world: [Vec2; 2], wrap: bool) -> Vec2 {
+    let np = p + v * dt;
+    if wrap {
+        wrap_pos(np, world[0], world[1])
+    } else {
+        clamp_pos(np, world[0], world[1])
+    }
+}
+
+/// Direct-sum gravitational and Coulomb acceleration acting on particle `id`
+pub fn acceleration(
+    params: &SimParams,
+    positions: &[[f32; 2]],
+    masses: &[f32],
+    charges: &[f32],
+    id: usize,
+) -> Vec2 {
+    let world_size = params.world[1] - params.world[0];
+    let g = params.effective_g();
+    let soft2 = params.softening * params.softening;
+    let k = params.effective_coulomb_k();
+    let coulomb = if k != 0.0 {
+        k * charges[id] / masses[id]
+    } else {
+        0.0
+    };
+    let coulomb_soft2 = params.coulomb_softening * params.coulomb_softening;
+    let p = Vec2::from(positions[id]);
+
+    let mut acc = Vec2::ZERO;
+    for (j, ((other, mass), charge)) in positions.iter().zip(masses).zip(charges).enumerate() {
+        if j == id {
+            continue;
+        }
+
+        let mut delta = Vec2::from(*other) - p;
+        if params.wrap {
+            delta = wrapped_delta(delta, world_size);
+        }
+        let dist2 = delta.dot(delta) + soft2; // add softening term to avoid singularity
+        let invd = 1.0 / dist2.sqrt();
+        let invd3 = invd * invd * invd;
+        acc += g * mass * delta * invd3;
+
+        // Like charges repel, opposite charges attract
+        if coulomb != 0.0 {
+            let cinvd = 1.0 / (delta.dot(delta) + coulomb_soft2).sqrt();
+            acc -= coulomb * charge * delta * (cinvd * cinvd * cinvd);
+        }
+    }
+    acc
+}
+
+/// Pointer force field acting on a particle at `p`, see `brush_acceleration` in the shader
+pub fn brush_acceleration(params: &SimParams, p: Vec2) -> Vec2 {
+    let [x, y, strength, radius] = params.brush.to_uniform();
+    if strength == 0.0 {
+        return Vec2::ZERO;
+    }
+
+    let mut delta = Vec2::new(x, y) - p;
+    if params.wrap {
+        delta = wrapped_delta(delta, params.world[1] - params.world[0]);
+    }
+    let dist2 = delta.dot(delta);
+    if dist2 >= radius * radius {
+        return Vec2::ZERO;
+    }
+    let soft2 = params.softening * params.softening;
+    strength * (1.0 - dist2.sqrt() / radius) * delta / (dist2 + soft2).sqrt()
+}
+
+impl CpuSimulation {
+    pub fn new(
+        params: SimParams,
+        positions: Vec<[f32; 2]>,
+        velocities: Vec<[f32; 2]>,
+        masses: Vec<f32>,
+        charges: Vec<f32>,
+        bonds: Vec<Bond>,
+    ) -> Self {
+        debug_assert_eq!(positions.len(), velocities.len());
+        debug_assert_eq!(positions.len(), masses.len());
+        debug_assert_eq!(positions.len(), charges.len());
+        Self {
+            params,
+            positions,
+            velocities,
+            masses,
+            charges,
+            bonds,
+        }
+    }
+
+    /// Direct-sum (or particle-mesh) accelerations at the current positions, brush,
+    /// bonds and fluid included
+    fn accelerations(&self) -> Vec<Vec2> {
+        let accelerations: Vec<Vec2> = match self.params.solver {
+            _ if !self.params.fluid.runs_solver() => vec![Vec2::ZERO; self.positions.len()],
+            Solver::ParticleMesh => pm::accelerations(
+                &self.params,
+                &self.positions,
+                &self.masses,
+                self.params.pm_grid,
+            ),
+            // Barnes-Hut is an approximation of the direct sum, which stays the reference
+            Solver::Direct | Solver::BarnesHut => (0..self.positions.len())
+                .map(|id| {
+                    acceleration(
+                        &self.params,
+                        &self.positions,
+                        &self.masses,
+                        &self.charges,
+                        id,
+                    )
+                })
+                .collect(),
+        };
+        let springs = bonds::accelerations(
+            &self.params,
+            &self.positions,
+            &self.velocities,
+            &self.masses,
+            &self.bonds,
+        );
+        let fluid = if self.params.fluid.enabled {
+            fluid::accelerations(
+                &self.params,
+                &self.positions,
+                &self.velocities,
+                &self.masses,
+            )
+        } else {
+            vec![Vec2::ZERO; self.positions.len()]
+        };
+        accelerations
+            .into_iter()
+            .zip(&self.positions)
+            .zip(springs)
+            .zip(fluid)
+            .map(|(((acc, p), spring), fluid)| {
+                acc + brush_acceleration(&self.params, Vec2::from(*p)) + spring + fluid
+            })
+            .collect()
+    }
+
+    /// Advance the system by one step, exactly like the compute dispatches of its stages
+    pub fn step(&mut self) {
+        let integrator = self.params.integrator;
+        let dt = self.params.dt;
+        let damp_step = self.params.damping.powf(dt);
+        let world = self.params.world;
+        let wrap = self.params.wrap;
+
+        // State at the start of the step and the RK4 sum, like `scratch` in the shader
+        let base: Vec<(Vec2, Vec2)> = self
+            .positions
+            .iter()
+            .zip(&self.velocities)
+            .map(|(p, v)| (Vec2::from(*p), Vec2::from(*v)))
+            .collect();
+        let mut sums = vec![(Vec2::ZERO, Vec2::ZERO); base.len()];
+
+        for stage in 0..integrator.stages() {
+            let accelerations = if integrator.needs_force(stage) {
+                self.accelerations()
+            } else {
+                vec![Vec2::ZERO; base.len()]
+            };
+
+            for (id, acc) in accelerations.into_iter().enumerate() {
+                let p = Vec2::from(self.positions[id]);
+                let v = Vec2::from(self.velocities[id]);
+
+                let (p_new, v_new) = match integrator {
+                    Integrator::Leapfrog => {
+                        // if bootstrap: v0; else: v_{n-1/2}
+                        let kick = if self.params.bootstrap {
+                            0.5 * dt // one-time half-kick: v_{+1/2} from v0
+                        } else {
+                            dt // normal leapfrog kick
+                        };
+                        let v_half = (v + acc * kick) * damp_step;
+                        (update_position(p, v_half, dt, world, wrap), v_half)
+                    }
+                    Integrator::VelocityVerlet => {
+                        let v_half = v + acc * (0.5 * dt);
+                        if stage == 0 {
+                            (update_position(p, v_half, dt, world, wrap), v_half)
+                        } else {
+                            (p, v_half * damp_step)
+                        }
+                    }
+                    Integrator::Yoshida4 => {
+                        let mut v_new = v;
+                        if stage > 0 {
+                            v_new += acc * (integrator::yoshida_kick(stage) * dt);
+                        }
+                        if stage == 3 {
+                            v_new *= damp_step;
+                        }
+                        let drift = integrator::yoshida_drift(stage) * dt;
+                        (update_position(p, v_new, drift, world, wrap), v_new)
+                    }
+                    Integrator::Rk4 => {
+                        let (p0, v0) = base[id];
+                        let weight = if stage == 0 || stage == 3 { 1.0 } else { 2.0 };
+                        let sum = &mut sums[id];
+                        sum.0 += v * weight;
+                        sum.1 += acc * weight;
+                        if stage < 3 {
+                            let h = if stage == 2 { dt } else { 0.5 * dt };
+                            (update_position(p0, v, h, world, wrap), v0 + acc * h)
+                        } else {
+                            let v_new = (v0 + sum.1 * (dt / 6.0)) * damp_step;
+                            (update_position(p0, sum.0, dt / 6.0, world, wrap), v_new)
+                        }
+                    }
+                };
+
+                self.positions[id] = p_new.to_array();
+                self.velocities[id] = v_new.to_array();
+            }
+        }
+
+        self.params.increment_epoch();
+        self.params.bootstrap = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::scenario::Scenarios;
+
+    fn world() -> [Vec2; 2] {
+        [Vec2::splat(-1.0), Vec2::splat(1.0)]
+    }
+
+    #[test]
+    fn wrapped_positions_stay_in_the_world() {
+        let [min, max] = world();
+        for (p, expected) in [
+            (Vec2::new(1.25, 0.0), Vec2::new(-0.75, 0.0)),
+            (Vec2::new(0.0, -1.5), Vec2::new(0.0, 0.5)),
+            (Vec2::new(-3.5, 2.5), Vec2::new(0.5, 0.5)),
+        ] {
+            assert!((wrap_pos(p, min, max) - expected).length() < 1e-6, "{p}");
+        }
+        assert_eq!(
+            clamp_pos(Vec2::new(1.5, -2.0), min, max),
+            Vec2::new(1.0, -1.0)
+        );
+    }
+
+    #[test]
+    fn wrapped_delta_is_the_shortest() {
+        let size = Vec2::splat(2.0);
+        assert_eq!(
+            wrapped_delta(Vec2::new(1.5, -1.5), size),
+            Vec2::new(-0.5, 0.5)
+        );
+        assert_eq!(
+            wrapped_delta(Vec2::new(0.5, -0.5), size),
+            Vec2::new(0.5, -0.5)
+        );
+    }
+
+    #[test]
+    fn pair_accelerations_follow_newton() {
+        let params = SimParams {
+            n: 2,
+            wrap: false,
+            ..SimParams::default()
+        };
+        let positions = [[-0.1, 0.0], [0.2, 0.0]];
+        let masses = [1.0, 3.0];
+        let charges = [0.0; 2];
+
+        let a0 = acceleration(&params, &positions, &masses, &charges, 0);
+        let a1 = acceleration(&params, &positions, &masses, &charges, 1);
+        let soft2 = params.softening * params.softening;
+        let expected = params.effective_g() * 3.0 * 0.3 / (0.09 + soft2).powf(1.5);
+        assert!(
+            (a0.x - expected).abs() < 1e-5 * expected,
+            "{a0} vs {expected}"
+        );
+        assert_eq!(a0.y, 0.0);
+        assert!((masses[0] * a0 + masses[1] * a1).length() < 1e-6 * a0.length());
+    }
+
+    #[test]
+    fn steps_conserve_momentum() {
+        let mut scenarios = Scenarios::default();
+        scenarios.select("plummer").unwrap();
+        let params = SimParams {
+            n: 256,
+            damping: 1.0,
+            seed: 1,
+            ..SimParams::default()
+        };
+        let data = scenarios.generate(&params);
+        let momentum = |sim: &CpuSimulation| {
+            sim.velocities
+                .iter()
+                .zip(&sim.masses)
+                .map(|(v, m)| Vec2::from(*v) * *m)
+                .fold((Vec2::ZERO, 0.0), |(sum, norm), p| {
+                    (sum + p, norm + p.length())
+                })
+        };
+
+        for integrator in Integrator::ALL {
+            let mut sim = CpuSimulation::new(
+                SimParams {
+                    integrator,
+                    ..params.clone()
+                },
+                data.positions.clone(),
+                data.velocities.clone(),
+                data.masses.clone(),
+                data.charges.clone(),
+                data.bonds.clone(),
+            );
+            let (initial, _) = momentum(&sim);
+            for _ in 0..10 {
+                sim.step();
+            }
+            let (last, norm) = momentum(&sim);
+            assert!(
+                (last - initial).length() < 1e-5 * norm,
+                "{}: momentum changed from {initial} to {last}",
+                integrator.label()
+            );
+        }
+    }
+}
@@ -0,0 +1,389 @@
+//! Conserved quantities, to judge the integrator and solver quality
+//!
+//! A [`Sample`] is taken every `interval` epochs, either with the GPU reduction
+//! (`shaders/diagnostics.wgsl`) or with [`measure`] on the CPU, and kept in a bounded
+//! history that is plotted in the UI and logged.
+
+use std::collections::VecDeque;
+
+use glam::{DVec2, Vec2};
+
+use super::{
+    SimParams, Solver,
+    bonds::{self, Bond},
+    cpu::wrapped_delta,
+};
+use crate::constants;
+
+/// Where the diagnostics are computed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Two-pass reduction in a compute shader
+    #[default]
+    Gpu,
+    /// Read the particles back and sum them on the CPU, O(N^2) for the potential
+    Cpu,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 2] = [Backend::Gpu, Backend::Cpu];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Backend::Gpu => "GPU",
+            Backend::Cpu => "CPU",
+        }
+    }
+}
+
+/// Totals over every particle at one epoch
+///
+/// Velocities are the stored leapfrog half-step velocities, so the kinetic energy
+/// is offset by O(dt) from the synchronized one. The potential is the softened direct
+/// pair sum plus the elastic energy of the bonds, see [`measures_potential`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    pub epoch: u128,
+    pub kinetic: f64,
+    /// Not measured with the approximate solvers
+    pub potential: Option<f64>,
+    pub momentum: DVec2,
+    /// About the center of the world
+    pub angular_momentum: f64,
+    pub center_of_mass: DVec2,
+}
+
+impl Sample {
+    pub fn energy(&self) -> Option<f64> {
+        Some(self.kinetic + self.potential?)
+    }
+
+    /// Build a sample from the two `vec4` written by the GPU reduction, `potential` when
+    /// it was measured
+    pub fn from_gpu(epoch: u128, potential: bool, result: &[[f32; 4]]) -> Self {
+        let [kinetic, pairs, angular_momentum, mass] = result[0].map(f64::from);
+        let [px, py, mx, my] = result[1].map(f64::from);
+        Self {
+            epoch,
+            kinetic,
+            potential: potential.then_some(pairs),
+            momentum: DVec2::new(px, py),
+            angular_momentum,
+            center_of_mass: center_of_mass(DVec2::new(mx, my), mass),
+        }
+    }
+}
+
+fn center_of_mass(moment: DVec2, mass: f64) -> DVec2 {
+    if mass > 0.0 {
+        moment / mass
+    } else {
+        DVec2::ZERO
+    }
+}
+
+/// Whether the potential energy is measured, it costs an O(N^2) pair sum so only the
+/// direct-sum solver, which pays as much every step, affords it
+pub fn measures_potential(params: &SimParams) -> bool {
+    params.solver == Solver::Direct
+}
+
+/// CPU reference of the GPU reduction, accumulated in f64
+pub fn measure(
+    params: &SimParams,
+    positions: &[[f32; 2]],
+    velocities: &[[f32; 2]],
+    masses: &[f32],
+    charges: &[f32],
+    bonds: &[Bond],
+) -> Sample {
+    let world_size = params.world[1] - params.world[0];
+    let center = 0.5 * (params.world[0] + params.world[1]);
+    let soft2 = (params.softening * params.softening) as f64;
+    let coulomb_soft2 = (params.coulomb_softening * params.coulomb_softening) as f64;
+    let g = params.effective_g() as f64;
+    let k = params.effective_coulomb_k() as f64;
+    let pairs = measures_potential(params);
+
+    let mut potential = 0.0;
+    let mut sample = Sample {
+        epoch: params.epoch,
+        kinetic: 0.0,
+        potential: None,
+        momentum: DVec2::ZERO,
+        angular_momentum: 0.0,
+        center_of_mass: DVec2::ZERO,
+    };
+    let mut total_mass = 0.0;
+    let mut moment = DVec2::ZERO;
+
+    for (i, ((p, v), m)) in positions.iter().zip(velocities).zip(masses).enumerate() {
+        let (p, v, m) = (Vec2::from(*p), Vec2::from(*v).as_dvec2(), *m as f64);
+        let r = (p - center).as_dvec2();
+
+        sample.kinetic += 0.5 * m * v.length_squared();
+        sample.momentum += m * v;
+        sample.angular_momentum += m * r.perp_dot(v);
+        total_mass += m;
+        moment += m * p.as_dvec2();
+
+        if !pairs {
+            continue;
+        }
+        // Each pair once
+        let q = charges[i] as f64;
+        for ((other, other_mass), other_charge) in positions[i + 1..]
+            .iter()
+            .zip(&masses[i + 1..])
+            .zip(&charges[i + 1..])
+        {
+            let mut delta = Vec2::from(*other) - p;
+            if params.wrap {
+                delta = wrapped_delta(delta, world_size);
+            }
+            let dist2 = delta.as_dvec2().length_squared();
+            potential -= g * m * *other_mass as f64 / (dist2 + soft2).sqrt();
+            if k != 0.0 {
+                potential += k * q * *other_charge as f64 / (dist2 + coulomb_soft2).sqrt();
+            }
+        }
+    }
+    sample.center_of_mass = center_of_mass(moment, total_mass);
+    if pairs {
+        sample.potential = Some(potential + bonds::potential(params, positions, bonds));
+    }
+
+    sample
+}
+
+/// Diagnostics settings and the history of samples
+pub struct Diagnostics {
+    pub enabled: bool,
+    /// Epochs between two samples
+    pub interval: u32,
+    pub backend: Backend,
+    /// Particles absorbed by collisions since the particles were generated
+    pub merges: u64,
+    samples: VecDeque<Sample>,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self {
+            enabled: constants::diagnostics::ENABLED,
+            interval: constants::diagnostics::INTERVAL,
+            backend: Backend::default(),
+            merges: 0,
+            samples: VecDeque::with_capacity(constants::diagnostics::HISTORY),
+        }
+    }
+}
+
+impl Diagnostics {
+    /// Whether a sample is due at one of the last `steps` epochs up to `epoch`
+    pub fn due(&self, epoch: u128, steps: u32) -> bool {
+        self.enabled && epoch % (self.interval.max(1) as u128) < steps as u128
+    }
+
+    /// Forget the history, e.g. when the particles are regenerated
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    pub fn samples(&self) -> &VecDeque<Sample> {
+        &self.samples
+    }
+
+    /// Relative energy change since the oldest sample in the history, when both have
+    /// an energy
+    pub fn energy_drift(&self, sample: &Sample) -> Option<f64> {
+        let energy = sample.energy()?;
+        match self.samples.front()?.energy()? {
+            0.0 => Some(0.0),
+            first => Some((energy - first) / first.abs()),
+        }
+    }
+
+    /// Add a sample to the history and log it
+    pub fn record(&mut self, sample: Sample) {
+        if self.samples.len() == constants::diagnostics::HISTORY {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+
+        log::debug!(
+            "Epoch {}: E = {} (K = {:.6e}, U = {}, drift {}), p = ({:.3e}, {:.3e}), L = {:.6e}, COM = ({:.4}, {:.4})",
+            sample.epoch,
+            format_energy(sample.energy()),
+            sample.kinetic,
+            format_energy(sample.potential),
+            format_drift(self.energy_drift(&sample)),
+            sample.momentum.x,
+            sample.momentum.y,
+            sample.angular_momentum,
+            sample.center_of_mass.x,
+            sample.center_of_mass.y,
+        );
+    }
+
+    /// Settings, latest values and plots of the history
+    pub fn render_ui(&mut self, ui: &mut egui::Ui) {
+        egui::CollapsingHeader::new("Diagnostics")
+            .default_open(false)
+            .show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.enabled, "Enabled")
+                        .on_hover_text("Measure the conserved quantities while the simulation runs. The potential is an O(N^2) pair sum, so it costs about one direct-sum step per sample and is only measured with the direct-sum solver");
+                    ui.add(
+                        egui::DragValue::new(&mut self.interval)
+                            .range(constants::diagnostics::INTERVAL_RANGE)
+                            .suffix(" epochs"),
+                    )
+                    .on_hover_text("Epochs between two samples");
+                    egui::ComboBox::from_id_salt("diagnostics_backend")
+                        .selected_text(self.backend.label())
+                        .show_ui(ui, |ui| {
+                            for option in Backend::ALL {
+                                ui.selectable_value(&mut self.backend, option, option.label());
+                            }
+                        })
+                        .response
+                        .on_hover_text("The CPU fallback reads the particles back and is only practical for small counts");
+                });
+
+                ui.label(format!("Merges: {}", self.merges))
+                    .on_hover_text("Particles absorbed by collisions since the particles were generated or loaded");
+
+                let Some(last) = self.samples.back() else {
+                    ui.label("No sample yet");
+                    return;
+                };
+                ui.label(format!(
+                    "Energy: {} (drift {})",
+                    format_energy(last.energy()),
+                    format_drift(self.energy_drift(last))
+                ));
+                ui.label(format!(
+                    "Kinetic: {:.6e}, Potential: {}",
+                    last.kinetic,
+                    format_energy(last.potential)
+                ));
+                ui.label(format!(
+                    "Momentum: ({:.3e}, {:.3e}), Angular: {:.6e}",
+                    last.momentum.x, last.momentum.y, last.angular_momentum
+                ));
+                ui.label(format!(
+                    "Center of Mass: ({:.4}, {:.4})",
+                    last.center_of_mass.x, last.center_of_mass.y
+                ));
+
+                // Samples without a value, e.g. the potential, are left out
+                let series = |f: fn(&Sample) -> Option<f64>| -> Vec<[f64; 2]> {
+                    self.samples
+                        .iter()
+                        .filter_map(|s| Some([s.epoch as f64, f(s)?]))
+                        .collect()
+                };
+                plot(
+                    ui,
+                    "Energy",
+                    &[
+                        ("Total", egui::Color32::WHITE, series(Sample::energy)),
+                        ("Kinetic", egui::Color32::LIGHT_RED, series(|s| Some(s.kinetic))),
+                        ("Potential", egui::Color32::LIGHT_BLUE, series(|s| s.potential)),
+                    ],
+                );
+                let drift: Vec<[f64; 2]> = self
+                    .samples
+                    .iter()
+                    .filter_map(|s| Some([s.epoch as f64, self.energy_drift(s)?]))
+                    .collect();
+                plot(
+                    ui,
+                    "Energy Drift",
+                    &[("(E - E0) / |E0|", egui::Color32::WHITE, drift)],
+                );
+                plot(
+                    ui,
+                    "Momentum",
+                    &[
+                        ("x", egui::Color32::LIGHT_RED, series(|s| Some(s.momentum.x))),
+                        ("y", egui::Color32::LIGHT_GREEN, series(|s| Some(s.momentum.y))),
+                    ],
+                );
+                plot(
+                    ui,
+                    "Angular Momentum",
+                    &[(
+                        "L",
+                        egui::Color32::LIGHT_YELLOW,
+                        series(|s| Some(s.angular_momentum)),
+                    )],
+                );
+                plot(
+                    ui,
+                    "Center of Mass",
+                    &[
+                        ("x", egui::Color32::LIGHT_RED, series(|s| Some(s.center_of_mass.x))),
+                        ("y", egui::Color32::LIGHT_GREEN, series(|s| Some(s.center_of_mass.y))),
+                    ],
+                );
+            });
+    }
+}
+
+fn format_energy(energy: Option<f64>) -> String {
+    energy.map_or_else(|| "n/a".to_string(), |energy| format!("{energy:.6e}"))
+}
+
+fn format_drift(drift: Option<f64>) -> String {
+    drift.map_or_else(|| "n/a".to_string(), |drift| format!("{drift:+.3e}"))
+}
+
+/// Minimal line plot of `(name, color, points)` series sharing the same axes
+fn plot(ui: &mut egui::Ui, title: &str, series: &[(&str, egui::Color32, Vec<[f64; 2]>)]) {
+    let points = series.iter().flat_map(|(_, _, points)| points);
+    let (mut min, mut max) = (DVec2::splat(f64::INFINITY), DVec2::splat(f64::NEG_INFINITY));
+    for p in points {
+        min = min.min(DVec2::from(*p));
+        max = max.max(DVec2::from(*p));
+    }
+    if !min.is_finite() || !max.is_finite() {
+        return;
+    }
+    // Keep flat series visible in the middle of the plot
+    if max.y - min.y <= f64::EPSILON * max.y.abs() {
+        let pad = 0.5 * max.y.abs().max(1e-12);
+        min.y -= pad;
+        max.y += pad;
+    }
+    if max.x <= min.x {
+        max.x = min.x + 1.0;
+    }
+    let span = max - min;
+
+    ui.horizontal(|ui| {
+        ui.label(egui::RichText::new(title).strong());
+        for (name, color, _) in series {
+            ui.colored_label(*color, *name);
+        }
+        ui.weak(format!("[{:.3e}, {:.3e}]", min.y, max.y));
+    });
+
+    let size = egui::vec2(ui.available_width(), constants::diagnostics::PLOT_HEIGHT);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let rect = response.rect;
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+    let to_screen = |p: &[f64; 2]| {
+        let t = (DVec2::from(*p) - min) / span;
+        egui::pos2(
+            rect.left() + t.x as f32 * rect.width(),
+            rect.bottom() - t.y as f32 * rect.height(),
+        )
+    };
+    for (_, color, points) in series {
+        let line: Vec<egui::Pos2> = points.iter().map(to_screen).collect();
+        painter.add(egui::Shape::line(line, egui::Stroke::new(1.5, *color)));
+    }
+}
@@ -0,0 +1,126 @@
+//! Small radix-2 FFT used on the CPU side of the particle-mesh solver
+//!
+//! It computes the Green's function spectrum uploaded to the GPU and backs the CPU
+//! particle-mesh reference. Complex numbers are stored as `[re, im]`.
+
+pub type Complex = [f64; 2];
+
+fn mul(a: Complex, b: Complex) -> Complex {
+    [a[0] * b[0] - a[1] * b[1], a[0] * b[1] + a[1] * b[0]]
+}
+
+/// In-place unnormalized FFT of a power-of-two length sequence
+///
+/// `inverse` flips the sign of the twiddle factors, the caller divides by the length.
+pub fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "FFT length must be a power of two");
+
+    // Bit reversal permutation
+    let bits = n.trailing_zeros();
+    for i in 0..n {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * std::f64::consts::TAU / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let w = [(angle * k as f64).cos(), (angle * k as f64).sin()];
+                let a = data[start + k];
+                let b = mul(data[start + k + len / 2], w);
+                data[start + k] = [a[0] + b[0], a[1] + b[1]];
+                data[start + k + len / 2] = [a[0] - b[0], a[1] - b[1]];
+            }
+        }
+        len *= 2;
+    }
+}
+
+/// In-place unnormalized FFT of a `size` x `size` row-major grid
+pub fn fft_2d(data: &mut [Complex], size: usize, inverse: bool) {
+    assert_eq!(data.len(), size * size);
+
+    for row in data.chunks_exact_mut(size) {
+        fft(row, inverse);
+    }
+
+    let mut column = vec![[0.0; 2]; size];
+    for x in 0..size {
+        for (y, value) in column.iter_mut().enumerate() {
+            *value = data[y * size + x];
+        }
+        fft(&mut column, inverse);
+        for (y, value) in column.iter().enumerate() {
+            data[y * size + x] = *value;
+        }
+    }
+}
+
+/// Pointwise complex product, used for convolutions in frequency space
+pub fn multiply(a: &mut [Complex], b: &[Complex]) {
+    for (a, b) in a.iter_mut().zip(b) {
+        *a = mul(*a, *b);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: &[Complex], b: &[Complex]) {
+        for (i, (a, b)) in a.iter().zip(b).enumerate() {
+            let err = (a[0] - b[0]).abs().max((a[1] - b[1]).abs());
+            assert!(err < 1e-9, "entry {i}: {a:?} vs {b:?}");
+        }
+    }
+
+    #[test]
+    fn inverse_transform_returns_the_input() {
+        let input: Vec<Complex> = (0..64)
+            .map(|i| [(i as f64 * 0.7).sin(), (i * i % 13) as f64 - 6.0])
+            .collect();
+
+        let mut data = input.clone();
+        fft(&mut data, false);
+        fft(&mut data, true);
+        data.iter_mut()
+            .for_each(|c| *c = [c[0] / 64.0, c[1] / 64.0]);
+        assert_close(&data, &input);
+
+        let mut grid = input.clone();
+        fft_2d(&mut grid, 8, false);
+        fft_2d(&mut grid, 8, true);
+        grid.iter_mut()
+            .for_each(|c| *c = [c[0] / 64.0, c[1] / 64.0]);
+        assert_close(&grid, &input);
+    }
+
+    #[test]
+    fn single_mode_transforms_to_a_single_bin() {
+        let (n, mode) = (32, 5);
+        let mut data: Vec<Complex> = (0..n)
+            .map(|j| {
+                let angle = std::f64::consts::TAU * (mode * j) as f64 / n as f64;
+                [angle.cos(), angle.sin()]
+            })
+            .collect();
+        fft(&mut data, false);
+
+        let mut expected = vec![[0.0; 2]; n];
+        expected[mode] = [n as f64, 0.0];
+        assert_close(&data, &expected);
+    }
+
+    #[test]
+    fn multiply_is_the_complex_product() {
+        let mut a = [[1.0, 2.0], [0.0, 1.0]];
+        multiply(&mut a, &[[3.0, -1.0], [0.0, 1.0]]);
+        assert_eq!(a, [[5.0, 5.0], [-1.0, 0.0]]);
+    }
+}
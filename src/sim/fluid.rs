@@ -0,0 +1,477 @@
+//! Smoothed particle hydrodynamics (SPH)
+//!
+//! Every particle carries a parcel of fluid. Its density is the kernel-weighted sum of
+//! the masses within the smoothing length, the equation of state turns it into a
+//! pressure, and the pressure gradient, an artificial viscosity and a uniform gravity
+//! accelerate the particles. The neighbors come from the cell lists of [`super::grid`].
+//! Without wrapping, the walls mirror the particles near them (ghost particles), so the
+//! fluid rests on the floor. The solver still adds the self-gravity of the fluid when
+//! enabled. The GPU runs `shaders/fluid.wgsl`, [`accelerations`] is its CPU reference.
+
+use glam::{BVec2, Vec2};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ParamsEguiAction, ParticleUpdated, SimParams, check_range,
+    cpu::wrapped_delta,
+    grid::{self, CellList},
+};
+use crate::constants;
+
+/// Smoothing kernel, both vanish at the smoothing length
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kernel {
+    /// Monaghan's cubic B-spline
+    #[default]
+    CubicSpline = 0,
+    /// Wendland C2, smoother and free of the pairing instability
+    WendlandC2 = 1,
+}
+
+impl Kernel {
+    pub const ALL: [Kernel; 2] = [Kernel::CubicSpline, Kernel::WendlandC2];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Kernel::CubicSpline => "Cubic Spline",
+            Kernel::WendlandC2 => "Wendland C2",
+        }
+    }
+
+    /// Kernel value and radial derivative at distance `r`, for smoothing length `h`
+    pub fn eval(&self, r: f32, h: f32) -> (f32, f32) {
+        let q = r / h;
+        if q >= 1.0 {
+            return (0.0, 0.0);
+        }
+        let pi = std::f32::consts::PI;
+        match self {
+            Kernel::WendlandC2 => {
+                let sigma = 7.0 / (pi * h * h);
+                let t = 1.0 - q;
+                (
+                    sigma * (t * t * t * t * (1.0 + 4.0 * q)),
+                    sigma * (-20.0 * q * t * t * t / h),
+                )
+            }
+            Kernel::CubicSpline => {
+                let sigma = 40.0 / (7.0 * pi * h * h);
+                if q <= 0.5 {
+                    (
+                        sigma * (1.0 - 6.0 * q * q + 6.0 * q * q * q),
+                        sigma * ((-12.0 * q + 18.0 * q * q) / h),
+                    )
+                } else {
+                    let t = 1.0 - q;
+                    (sigma * (2.0 * t * t * t), sigma * (-6.0 * t * t / h))
+                }
+            }
+        }
+    }
+}
+
+/// Pressure as a function of the density
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EquationOfState {
+    /// Weakly compressible liquid, no pressure below the rest density
+    #[default]
+    Tait = 0,
+    /// Gas whose pressure grows with the density to the power gamma
+    Polytropic = 1,
+}
+
+impl EquationOfState {
+    pub const ALL: [EquationOfState; 2] = [EquationOfState::Tait, EquationOfState::Polytropic];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            EquationOfState::Tait => "Tait (liquid)",
+            EquationOfState::Polytropic => "Polytropic (gas)",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fluid {
+    pub enabled: bool,
+    pub kernel: Kernel,
+    /// Kernel support radius, world units
+    pub smoothing: f32,
+    /// Density without pressure (Tait) or of the reference pressure (polytropic)
+    pub rest_density: f32,
+    /// Speed of sound at the rest density, sets the stiffness of the fluid
+    pub sound_speed: f32,
+    pub equation_of_state: EquationOfState,
+    /// Exponent of the equation of state
+    pub gamma: f32,
+    /// Artificial viscosity coefficient (alpha)
+    pub viscosity: f32,
+    /// Uniform downward acceleration, world units / s²
+    pub gravity: f32,
+    /// Keep the solver running, so the fluid attracts itself
+    pub self_gravity: bool,
+}
+
+impl Default for Fluid {
+    fn default() -> Self {
+        Self {
+            enabled: constants::fluid::ENABLED,
+            kernel: Kernel::default(),
+            smoothing: constants::fluid::SMOOTHING,
+            rest_density: constants::fluid::REST_DENSITY,
+            sound_speed: constants::fluid::SOUND_SPEED,
+            equation_of_state: EquationOfState::default(),
+            gamma: constants::fluid::GAMMA,
+            viscosity: constants::fluid::VISCOSITY,
+            gravity: constants::fluid::GRAVITY,
+            self_gravity: constants::fluid::SELF_GRAVITY,
+        }
+    }
+}
+
+impl Fluid {
+    /// (smoothing length, rest density, sound speed, gamma) and (kernel, equation of
+    /// state, viscosity, gravity), all zero when the fluid is off
+    pub fn to_uniform(&self) -> [[f32; 4]; 2] {
+        if !self.enabled {
+            return [[0.0; 4]; 2];
+        }
+        [
+            [
+                self.smoothing,
+                self.rest_density,
+                self.sound_speed,
+                self.gamma,
+            ],
+            [
+                self.kernel as u32 as f32,
+                self.equation_of_state as u32 as f32,
+                self.viscosity,
+                self.gravity,
+            ],
+        ]
+    }
+
+    /// Whether the solver runs, it only adds the self-gravity to the fluid
+    pub fn runs_solver(&self) -> bool {
+        !self.enabled || self.self_gravity
+    }
+
+    /// Pressure at `density`, see `fluid_pressure` in the shader
+    pub fn pressure(&self, density: f32) -> f32 {
+        let rest = self.rest_density;
+        let c = self.sound_speed;
+        let stiffness = rest * c * c / self.gamma;
+        let ratio = (density / rest).powf(self.gamma);
+        match self.equation_of_state {
+            EquationOfState::Polytropic => stiffness * ratio,
+            EquationOfState::Tait => (stiffness * (ratio - 1.0)).max(0.0),
+        }
+    }
+
+    /// Check every value against the ranges of the UI sliders
+    pub fn validate(&self) -> anyhow::Result<()> {
+        use constants::fluid;
+
+        for (name, value, range) in [
+            ("Smoothing length", self.smoothing, fluid::SMOOTHING_RANGE),
+            ("Rest density", self.rest_density, fluid::REST_DENSITY_RANGE),
+            ("Sound speed", self.sound_speed, fluid::SOUND_SPEED_RANGE),
+            ("Gamma", self.gamma, fluid::GAMMA_RANGE),
+            ("Viscosity", self.viscosity, fluid::VISCOSITY_RANGE),
+            ("Fluid gravity", self.gravity, fluid::GRAVITY_RANGE),
+        ] {
+            check_range(name, value, &range)?;
+        }
+        Ok(())
+    }
+
+    pub fn render_ui(&mut self, ui: &mut egui::Ui) -> ParamsEguiAction {
+        let mut action = ParamsEguiAction::None;
+        let mut changed = false;
+
+        ui.heading("Fluid");
+
+        changed |= ui
+            .checkbox(&mut self.enabled, "SPH Fluid")
+            .on_hover_text("Treat the particles as parcels of fluid with a density and a pressure. Without self-gravity the solver is skipped. Try the dam-break scenario file")
+            .changed();
+
+        ui.add_enabled_ui(self.enabled, |ui| {
+            let mut kernel = self.kernel;
+            egui::ComboBox::from_label("Kernel")
+                .selected_text(kernel.label())
+                .show_ui(ui, |ui| {
+                    for option in Kernel::ALL {
+                        ui.selectable_value(&mut kernel, option, option.label());
+                    }
+                })
+                .response
+                .on_hover_text("Weight of the neighbors by distance. Wendland C2 is smoother and keeps the particles from clumping in pairs");
+            if kernel != self.kernel {
+                self.kernel = kernel;
+                changed = true;
+            }
+
+            let mut equation_of_state = self.equation_of_state;
+            egui::ComboBox::from_label("Equation of State")
+                .selected_text(equation_of_state.label())
+                .show_ui(ui, |ui| {
+                    for option in EquationOfState::ALL {
+                        ui.selectable_value(&mut equation_of_state, option, option.label());
+                    }
+                })
+                .response
+                .on_hover_text("Tait models a nearly incompressible liquid without pressure below the rest density. Polytropic models a gas, use a gamma of 1 for an isothermal one");
+            if equation_of_state != self.equation_of_state {
+                self.equation_of_state = equation_of_state;
+                changed = true;
+            }
+
+            changed |= ui
+                .add(
+                    egui::Slider::new(&mut self.smoothing, constants::fluid::SMOOTHING_RANGE)
+                        .text("Smoothing Length")
+                        .logarithmic(true)
+                        .step_by(constants::fluid::SMOOTHING_STEP),
+                )
+                .on_hover_text("Reach of the kernel in world units. About three particle spacings gives 20 to 30 neighbors")
+                .changed();
+            changed |= ui
+                .add(
+                    egui::Slider::new(&mut self.rest_density, constants::fluid::REST_DENSITY_RANGE)
+                        .text("Rest Density")
+                        .logarithmic(true),
+                )
+                .on_hover_text("Density of the fluid at rest. The dam break sets the particle masses from it when the particles are reset")
+                .changed();
+            changed |= ui
+                .add(
+                    egui::Slider::new(&mut self.sound_speed, constants::fluid::SOUND_SPEED_RANGE)
+                        .text("Sound Speed")
+                        .logarithmic(true),
+                )
+                .on_hover_text("Stiffness of the fluid. About ten times the flow speed keeps a liquid nearly incompressible, the time step must stay below a third of the smoothing length over it")
+                .changed();
+            changed |= ui
+                .add(
+                    egui::Slider::new(&mut self.gamma, constants::fluid::GAMMA_RANGE)
+                        .text("Gamma")
+                        .step_by(constants::fluid::GAMMA_STEP),
+                )
+                .on_hover_text("Exponent of the equation of state: 7 for water, 5/3 for a monatomic gas")
+                .changed();
+            changed |= ui
+                .add(
+                    egui::Slider::new(&mut self.viscosity, constants::fluid::VISCOSITY_RANGE)
+                        .text("Viscosity")
+                        .step_by(constants::fluid::VISCOSITY_STEP),
+                )
+                .on_hover_text("Artificial viscosity between approaching particles, damps the noise and handles shocks")
+                .changed();
+            changed |= ui
+                .add(
+                    egui::Slider::new(&mut self.gravity, constants::fluid::GRAVITY_RANGE)
+                        .text("Gravity")
+                        .step_by(constants::fluid::GRAVITY_STEP),
+                )
+                .on_hover_text("Uniform downward acceleration of the fluid, in world units per second squared")
+                .changed();
+            changed |= ui
+                .checkbox(&mut self.self_gravity, "Self-Gravity")
+                .on_hover_text("Keep the solver running, so the fluid also attracts itself like the other scenarios")
+                .changed();
+        });
+
+        if changed {
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+        action
+    }
+}
+
+/// Nearest wall along each axis of `p`, and whether it lies within the smoothing
+/// length `h`, see `fluid_walls` in the shader
+fn walls(params: &SimParams, h: f32, p: Vec2) -> (Vec2, BVec2) {
+    if params.wrap {
+        return (Vec2::ZERO, BVec2::FALSE);
+    }
+    let lower = p - params.world[0];
+    let upper = params.world[1] - p;
+    let wall = Vec2::select(lower.cmplt(upper), params.world[0], params.world[1]);
+    (wall, lower.min(upper).cmplt(Vec2::splat(h)))
+}
+
+/// Position and velocity of image `image` of a particle, mirrored across the walls of
+/// its neighbor whose bits are set (1 for x, 2 for y), `None` if a wall is not near
+fn image(walls: (Vec2, BVec2), image: u32, q: Vec2, v: Vec2) -> Option<(Vec2, Vec2)> {
+    let (wall, near) = walls;
+    let mirror = Vec2::new((image & 1) as f32, (image >> 1) as f32);
+    if !mirror
+        .cmple(Vec2::select(near, Vec2::ONE, Vec2::ZERO))
+        .all()
+    {
+        return None;
+    }
+    Some((q + mirror * 2.0 * (wall - q), v * (1.0 - 2.0 * mirror)))
+}
+
+/// Images of every particle, itself and its mirrors across up to two walls
+const IMAGES: u32 = 4;
+
+/// Vector from `p` to `q`, across the edges when the world wraps
+fn delta(params: &SimParams, p: Vec2, q: Vec2) -> Vec2 {
+    if params.wrap {
+        wrapped_delta(q - p, params.world[1] - params.world[0])
+    } else {
+        q - p
+    }
+}
+
+/// Density and pressure of every particle, see `fluid_density` in the shader
+pub fn densities(
+    params: &SimParams,
+    list: &CellList,
+    positions: &[[f32; 2]],
+    masses: &[f32],
+) -> Vec<[f32; 2]> {
+    let fluid = &params.fluid;
+    let h = fluid.smoothing;
+    positions
+        .iter()
+        .map(|&p| {
+            let p = Vec2::from(p);
+            let walls = walls(params, h, p);
+            let mut density = 0.0;
+            for other in list.neighbors(params, h, p) {
+                let q = Vec2::from(positions[other as usize]);
+                for k in 0..IMAGES {
+                    let Some((q, _)) = image(walls, k, q, Vec2::ZERO) else {
+                        continue;
+                    };
+                    let r = delta(params, p, q).length();
+                    density += masses[other as usize] * fluid.kernel.eval(r, h).0;
+                }
+            }
+            [density, fluid.pressure(density)]
+        })
+        .collect()
+}
+
+/// Pressure, viscous and gravity accelerations of every particle, see `fluid_forces`
+/// in the shader
+pub fn accelerations(
+    params: &SimParams,
+    positions: &[[f32; 2]],
+    velocities: &[[f32; 2]],
+    masses: &[f32],
+) -> Vec<Vec2> {
+    let fluid = &params.fluid;
+    let h = fluid.smoothing;
+    let c = fluid.sound_speed;
+    let list = grid::build(params, h, positions);
+    let states = densities(params, &list, positions, masses);
+
+    positions
+        .iter()
+        .enumerate()
+        .map(|(id, &p)| {
+            let p = Vec2::from(p);
+            let v = Vec2::from(velocities[id]);
+            let [density, pressure] = states[id];
+            let walls = walls(params, h, p);
+            let mut acc = Vec2::new(0.0, -fluid.gravity);
+            for other in list.neighbors(params, h, p) {
+                let j = other as usize;
+                let [other_density, other_pressure] = states[j];
+                let pressures = pressure / (density * density)
+                    + other_pressure / (other_density * other_density);
+                for k in 0..IMAGES {
+                    let Some((q, w)) = image(
+                        walls,
+                        k,
+                        Vec2::from(positions[j]),
+                        Vec2::from(velocities[j]),
+                    ) else {
+                        continue;
+                    };
+                    let d = delta(params, p, q);
+                    let r = d.length();
+                    if r == 0.0 || r >= h {
+                        continue;
+                    }
+                    let mut term = pressures;
+                    // Monaghan viscosity, only between approaching particles
+                    let vr = (v - w).dot(-d);
+                    if vr < 0.0 {
+                        let mu = h * vr / (r * r + 0.01 * h * h);
+                        term += -fluid.viscosity * c * mu / (0.5 * (density + other_density));
+                    }
+                    let dw = fluid.kernel.eval(r, h).1;
+                    acc += masses[j] * term * dw / r * d;
+                }
+            }
+            acc
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kernels_integrate_to_one() {
+        let h = 0.1;
+        let steps = 10_000;
+        for kernel in Kernel::ALL {
+            // Over the disc of radius h, in rings of width dr
+            let dr = h / steps as f32;
+            let total: f32 = (0..steps)
+                .map(|i| {
+                    let r = (i as f32 + 0.5) * dr;
+                    kernel.eval(r, h).0 * std::f32::consts::TAU * r * dr
+                })
+                .sum();
+            assert!((total - 1.0).abs() < 1e-3, "{}: {total}", kernel.label());
+        }
+    }
+
+    #[test]
+    fn kernel_derivatives_match_finite_differences() {
+        let (h, dr) = (0.1, 1e-4);
+        for kernel in Kernel::ALL {
+            for r in [0.01, 0.03, 0.05, 0.07, 0.09] {
+                let slope = (kernel.eval(r + dr, h).0 - kernel.eval(r - dr, h).0) / (2.0 * dr);
+                let (_, derivative) = kernel.eval(r, h);
+                assert!(
+                    (derivative - slope).abs() < 1e-2 * derivative.abs(),
+                    "{} at {r}: {derivative} vs {slope}",
+                    kernel.label()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn liquids_only_push_above_the_rest_density() {
+        let fluid = Fluid::default();
+        let rest = fluid.rest_density;
+        assert_eq!(fluid.pressure(rest), 0.0);
+        assert_eq!(fluid.pressure(0.5 * rest), 0.0);
+        assert!(fluid.pressure(1.1 * rest) > 0.0);
+
+        let gas = Fluid {
+            equation_of_state: EquationOfState::Polytropic,
+            ..fluid
+        };
+        let stiffness = rest * gas.sound_speed * gas.sound_speed / gas.gamma;
+        assert!((gas.pressure(rest) - stiffness).abs() < 1e-6 * stiffness);
+        assert!(gas.pressure(0.5 * rest) > 0.0);
+    }
+}
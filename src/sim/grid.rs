@@ -0,0 +1,198 @@
+//! Cell lists, the neighbor lookup of short-range interactions
+//!
+//! The world is split into cells at least the interaction radius wide, numbered by
+//! Morton code, and the particles are sorted by cell. The particles within the radius
+//! of a particle then lie in its cell or the 8 around it. The GPU builds the lists in
+//! `shaders/grid.wgsl`, [`build`] and [`neighbor_counts`] are its CPU references.
+
+use glam::{IVec2, UVec2, Vec2};
+
+use super::{SimParams, cpu, sort};
+use crate::constants;
+
+/// Particles sorted by cell, and where each cell starts and ends among them
+pub struct CellList {
+    /// Particle indices ordered by the Morton code of their cell, then by index
+    pub particles: Vec<u32>,
+    /// `[start, end)` in `particles` of each Morton code, empty for the missing cells
+    pub ranges: Vec<[u32; 2]>,
+}
+
+/// Cells per side of the grid and their size, at least `radius` when the number of
+/// cells allows it
+pub fn dims(params: &SimParams, radius: f32) -> (UVec2, Vec2) {
+    let size = params.world[1] - params.world[0];
+    let cells = (size / radius)
+        .floor()
+        .clamp(
+            Vec2::ONE,
+            Vec2::splat(constants::shader::GRID_MAX_CELLS as f32),
+        )
+        .as_uvec2();
+    (cells, size / cells.as_vec2())
+}
+
+/// Entries of the range table, one past the Morton code of the last cell
+pub fn table_size(cells: UVec2) -> u32 {
+    sort::morton(cells - UVec2::ONE) + 1
+}
+
+/// Cell of `p`, see `grid_cell` in the shader
+fn cell(params: &SimParams, cells: UVec2, cell_size: Vec2, p: Vec2) -> IVec2 {
+    let size = params.world[1] - params.world[0];
+    let mut p = p;
+    if params.wrap {
+        p -= size * ((p - params.world[0]) / size).floor();
+    }
+    ((p - params.world[0]) / cell_size)
+        .floor()
+        .as_ivec2()
+        .clamp(IVec2::ZERO, cells.as_ivec2() - IVec2::ONE)
+}
+
+/// Sort the particles by cell for the interaction `radius`
+pub fn build(params: &SimParams, radius: f32, positions: &[[f32; 2]]) -> CellList {
+    let (cells, cell_size) = dims(params, radius);
+    let codes: Vec<u32> = positions
+        .iter()
+        .map(|&p| sort::morton(cell(params, cells, cell_size, Vec2::from(p)).as_uvec2()))
+        .collect();
+    let indices: Vec<u32> = (0..positions.len() as u32).collect();
+    let (codes, particles) = sort::sort_pairs(&codes, &indices);
+
+    let mut ranges = vec![[0; 2]; table_size(cells) as usize];
+    for (i, &code) in codes.iter().enumerate() {
+        let range = &mut ranges[code as usize];
+        if i == 0 || codes[i - 1] != code {
+            range[0] = i as u32;
+        }
+        range[1] = i as u32 + 1;
+    }
+    CellList { particles, ranges }
+}
+
+impl CellList {
+    /// Particles in the cell of `p` and the cells around it, in the order the shader
+    /// visits them (see `grid_visit` and `grid_range`), for the lists built with `radius`
+    pub fn neighbors<'a>(
+        &'a self,
+        params: &SimParams,
+        radius: f32,
+        p: Vec2,
+    ) -> impl Iterator<Item = u32> + 'a {
+        let (cells, cell_size) = dims(params, radius);
+        let home = cell(params, cells, cell_size, p);
+        let cells = cells.as_ivec2();
+        let wrap = params.wrap;
+        (-1..=1)
+            .flat_map(|dy| (-1..=1).map(move |dx| IVec2::new(dx, dy)))
+            .filter(move |&offset| {
+                // Outside cells only exist when the world wraps, and with fewer than 3
+                // cells per side the offsets that wrap to the same cell are visited once
+                if wrap {
+                    (offset + 1).cmplt(cells).all()
+                } else {
+                    let cell = home + offset;
+                    cell.cmpge(IVec2::ZERO).all() && cell.cmplt(cells).all()
+                }
+            })
+            .flat_map(move |offset| {
+                let cell = ((home + offset + cells) % cells).as_uvec2();
+                let [start, end] = self.ranges[sort::morton(cell) as usize];
+                self.particles[start as usize..end as usize].iter().copied()
+            })
+    }
+}
+
+/// Number of other particles within `radius` of each particle, by brute force
+pub fn neighbor_counts(params: &SimParams, radius: f32, positions: &[[f32; 2]]) -> Vec<u32> {
+    let size = params.world[1] - params.world[0];
+    let radius2 = radius * radius;
+    positions
+        .iter()
+        .enumerate()
+        .map(|(id, &p)| {
+            let p = Vec2::from(p);
+            positions
+                .iter()
+                .enumerate()
+                .filter(|&(other, &q)| {
+                    let mut delta = Vec2::from(q) - p;
+                    if params.wrap {
+                        delta = cpu::wrapped_delta(delta, size);
+                    }
+                    other != id && delta.dot(delta) < radius2
+                })
+                .count() as u32
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn positions(params: &SimParams) -> Vec<[f32; 2]> {
+        let mut rng = StdRng::seed_from_u64(1);
+        let [min, max] = params.world;
+        (0..2_000)
+            .map(|_| {
+                [
+                    rng.random_range(min.x..max.x),
+                    rng.random_range(min.y..max.y),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cells_hold_every_particle_once() {
+        let params = SimParams::default();
+        let positions = positions(&params);
+        let list = build(&params, 0.1, &positions);
+
+        let mut particles = list.particles.clone();
+        particles.sort_unstable();
+        assert!(particles.iter().copied().eq(0..positions.len() as u32));
+        let total: u32 = list.ranges.iter().map(|[start, end]| end - start).sum();
+        assert_eq!(total as usize, positions.len());
+    }
+
+    #[test]
+    fn neighbors_match_brute_force() {
+        for wrap in [false, true] {
+            let params = SimParams {
+                wrap,
+                ..SimParams::default()
+            };
+            let positions = positions(&params);
+            // Down to a single cell per side
+            for radius in [0.05, 0.7, 1.5] {
+                let list = build(&params, radius, &positions);
+                let size = params.world[1] - params.world[0];
+                let counts: Vec<u32> = positions
+                    .iter()
+                    .enumerate()
+                    .map(|(id, &p)| {
+                        let p = Vec2::from(p);
+                        list.neighbors(&params, radius, p)
+                            .filter(|&other| {
+                                let mut delta = Vec2::from(positions[other as usize]) - p;
+                                if wrap {
+                                    delta = cpu::wrapped_delta(delta, size);
+                                }
+                                other as usize != id && delta.dot(delta) < radius * radius
+                            })
+                            .count() as u32
+                    })
+                    .collect();
+                assert!(
+                    counts == neighbor_counts(&params, radius, &positions),
+                    "radius {radius}, wrap {wrap}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Time integration schemes
+//!
+//! Every step of an integrator is a fixed sequence of stages. Each stage is one compute
+//! dispatch that evaluates the forces at the current positions (unless the stage is a
+//! pure drift) and writes the next state, so the stages ping-pong the particle buffers
+//! like whole steps do. `integrate` in `shaders/nbody.wgsl` reads the stage index from
+//! the step state, and [`crate::sim::cpu`] mirrors the same stages.
+
+use serde::{Deserialize, Serialize};
+
+/// 1 / (2 - 2^(1/3)), outer weight of the Yoshida composition
+pub const YOSHIDA_W1: f32 = 1.351_207_2;
+/// -2^(1/3) / (2 - 2^(1/3)), inner (backward) weight of the Yoshida composition
+pub const YOSHIDA_W0: f32 = -1.702_414_4;
+
+/// Scheme advancing positions and velocities by one step
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Integrator {
+    /// Kick-drift leapfrog on half-step velocities, one force evaluation per step
+    #[default]
+    Leapfrog = 0,
+    /// Kick-drift-kick with synchronized velocities, two force evaluations per step
+    VelocityVerlet = 1,
+    /// Classic 4th order Runge-Kutta, not symplectic, four force evaluations per step
+    Rk4 = 2,
+    /// Yoshida 4th order symplectic composition, three force evaluations per step
+    Yoshida4 = 3,
+}
+
+impl Integrator {
+    pub const ALL: [Integrator; 4] = [
+        Integrator::Leapfrog,
+        Integrator::VelocityVerlet,
+        Integrator::Rk4,
+        Integrator::Yoshida4,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Integrator::Leapfrog => "Leapfrog",
+            Integrator::VelocityVerlet => "Velocity Verlet",
+            Integrator::Rk4 => "Runge-Kutta 4",
+            Integrator::Yoshida4 => "Yoshida 4",
+        }
+    }
+
+    /// Compute dispatches per step
+    pub fn stages(&self) -> u32 {
+        match self {
+            Integrator::Leapfrog => 1,
+            Integrator::VelocityVerlet => 2,
+            Integrator::Rk4 | Integrator::Yoshida4 => 4,
+        }
+    }
+
+    /// Whether `stage` evaluates the forces, the first Yoshida stage only drifts
+    pub fn needs_force(&self, stage: u32) -> bool {
+        !(*self == Integrator::Yoshida4 && stage == 0)
+    }
+
+    /// Whether the velocities are stored half a step behind the positions
+    pub fn staggered(&self) -> bool {
+        *self == Integrator::Leapfrog
+    }
+}
+
+/// Drift coefficient of Yoshida `stage`, in units of dt
+pub fn yoshida_drift(stage: u32) -> f32 {
+    if stage == 0 || stage == 3 {
+        0.5 * YOSHIDA_W1
+    } else {
+        0.5 * (YOSHIDA_W0 + YOSHIDA_W1)
+    }
+}
+
+/// Kick coefficient of Yoshida `stage` (1..=3), in units of dt
+pub fn yoshida_kick(stage: u32) -> f32 {
+    if stage == 2 { YOSHIDA_W0 } else { YOSHIDA_W1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::*;
+    use crate::sim::{
+        SimParams, Solver,
+        cpu::{self, CpuSimulation},
+        diagnostics,
+        scenario::Scenarios,
+    };
+
+    /// Particles, steps and step length of the energy drift comparison
+    const PARTICLES: u32 = 128;
+    const STEPS: u32 = 200;
+    const DT: f32 = 0.02;
+    /// Largest energy drift accepted from any integrator over the comparison
+    const DRIFT_TOLERANCE: f64 = 1e-2;
+    /// How much smaller the drift of the 4th order integrators must be than the 2nd order ones
+    const ORDER_GAIN: f64 = 10.0;
+
+    /// Order of accuracy of `integrator`
+    fn order(integrator: Integrator) -> u32 {
+        match integrator {
+            Integrator::Leapfrog | Integrator::VelocityVerlet => 2,
+            Integrator::Rk4 | Integrator::Yoshida4 => 4,
+        }
+    }
+
+    /// Largest relative energy error of `integrator` over the comparison on the CPU reference
+    ///
+    /// A bound Plummer sphere without walls conserves energy exactly, so the error measures
+    /// the integration alone. Leapfrog velocities lag half a step, they are synchronized
+    /// with a half kick before measuring.
+    fn energy_drift(integrator: Integrator) -> f64 {
+        let mut scenarios = Scenarios::default();
+        scenarios.select("plummer").unwrap();
+        let params = SimParams {
+            n: PARTICLES,
+            dt: DT,
+            damping: 1.0,
+            wrap: false,
+            seed: 1,
+            integrator,
+            solver: Solver::Direct, // measures the potential energy
+            ..SimParams::default()
+        };
+        let data = scenarios.generate(&params);
+        let mut sim = CpuSimulation::new(
+            params,
+            data.positions,
+            data.velocities,
+            data.masses,
+            data.charges,
+            data.bonds,
+        );
+
+        let energy = |sim: &CpuSimulation| {
+            let mut velocities = sim.velocities.clone();
+            if integrator.staggered() && !sim.params.bootstrap {
+                for (id, v) in velocities.iter_mut().enumerate() {
+                    let acc = cpu::acceleration(
+                        &sim.params,
+                        &sim.positions,
+                        &sim.masses,
+                        &sim.charges,
+                        id,
+                    );
+                    *v = (Vec2::from(*v) + acc * (0.5 * sim.params.dt)).to_array();
+                }
+            }
+            diagnostics::measure(
+                &sim.params,
+                &sim.positions,
+                &velocities,
+                &sim.masses,
+                &sim.charges,
+                &sim.bonds,
+            )
+            .energy()
+            .expect("the direct sum measures the potential")
+        };
+
+        let initial = energy(&sim);
+        let mut drift = 0.0f64;
+        for _ in 0..STEPS {
+            sim.step();
+            drift = drift.max(((energy(&sim) - initial) / initial).abs());
+        }
+        drift
+    }
+
+    #[test]
+    fn higher_orders_drift_less() {
+        let drifts = Integrator::ALL.map(|integrator| (integrator, energy_drift(integrator)));
+        for (integrator, drift) in drifts {
+            assert!(
+                drift < DRIFT_TOLERANCE,
+                "{} drifted in energy by {drift:e}",
+                integrator.label()
+            );
+        }
+
+        let second_order = drifts
+            .iter()
+            .filter(|(integrator, _)| order(*integrator) == 2)
+            .map(|(_, drift)| *drift)
+            .fold(f64::MAX, f64::min);
+        for (integrator, drift) in drifts
+            .iter()
+            .filter(|(integrator, _)| order(*integrator) == 4)
+        {
+            assert!(
+                drift * ORDER_GAIN < second_order,
+                "{} drifted by {drift:e}, not much better than the 2nd order integrators",
+                integrator.label()
+            );
+        }
+    }
+}
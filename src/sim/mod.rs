@@ -1,81 +1,88 @@
+pub mod bonds;
+pub mod brush;
+pub mod collisions;
+pub mod cpu;
+pub mod diagnostics;
+pub mod fft;
+pub mod fluid;
+pub mod grid;
+pub mod integrator;
 mod params;
+pub mod pm;
+pub mod scenario;
+pub mod scenario_file;
+pub mod snapshot;
+pub mod sort;
+pub mod timestep;
 
 use glam::Vec2;
-use rand::rngs::StdRng;
-use rand::{Rng, SeedableRng};
 
-pub use params::{ParamsEguiAction, ParticleUpdated, SimParams, SimUniform};
+use bonds::Bond;
 
-fn color(r: u8, g: u8, b: u8) -> [f32; 3] {
-    [(r as f32) / 255.0, (g as f32) / 255.0, (b as f32) / 255.0]
-}
-
-pub fn reset_galaxy(n: u32) -> (Vec<[f32; 2]>, Vec<[f32; 2]>, Vec<[f32; 4]>) {
-    let mut rng = StdRng::seed_from_u64(
-        std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap()
-            .as_secs(),
-    );
-    let half = n / 2;
-    let mut pos = Vec::with_capacity(n as usize);
-    let mut vel = Vec::with_capacity(n as usize);
-    let mut col = Vec::with_capacity(n as usize);
-
-    let mut make_disc = |count: u32,
-                         center: Vec2,
-                         rot_dir: f32,
-                         start_color: &[f32; 3],
-                         end_color: &[f32; 3],
-                         pos: &mut Vec<[f32; 2]>,
-                         vel: &mut Vec<[f32; 2]>,
-                         col: &mut Vec<[f32; 4]>| {
-        for _ in 0..count {
-            // rayon ~ uniform in disc
-            let r = (rng.random::<f32>().sqrt()) * 0.45; // compact
-            let theta = rng.random::<f32>() * std::f32::consts::TAU;
-            let p = center + Vec2::new(theta.cos(), theta.sin()) * r;
+pub use params::{
+    Interaction, ParamsEguiAction, ParamsOverrides, ParticleUpdated, SimParams, SimUniform, Solver,
+    check_range,
+};
 
-            // v = tangente * vmag
-            let tangent = Vec2::new(-theta.sin(), theta.cos()) * rot_dir;
-            let vmag = 0.035 / (r + 0.02).sqrt();
-            let v = tangent * vmag * (0.9 + rng.random::<f32>() * 0.1); // add some noise (10%)
+/// Particle data, one entry per particle in each vector, and the bonds between them
+#[derive(Default, PartialEq)]
+pub struct ParticleData {
+    pub positions: Vec<[f32; 2]>,
+    pub velocities: Vec<[f32; 2]>,
+    pub colors: Vec<[f32; 4]>,
+    pub masses: Vec<f32>,
+    pub charges: Vec<f32>,
+    /// Springs between the particles above, by their index in these vectors
+    pub bonds: Vec<Bond>,
+}
 
-            // color (gradient from start_color to end_color regarding the distance to the center)
-            // pow3 for better contrast
-            let red_component =
-                start_color[0] + (end_color[0] - start_color[0]) * (r / 0.45).powi(3);
-            let blue_component =
-                start_color[2] + (end_color[2] - start_color[2]) * (r / 0.45).powi(3);
-            let green_component =
-                start_color[1] + (end_color[1] - start_color[1]) * (r / 0.45).powi(3);
+impl ParticleData {
+    pub fn with_capacity(n: u32) -> Self {
+        Self {
+            positions: Vec::with_capacity(n as usize),
+            velocities: Vec::with_capacity(n as usize),
+            colors: Vec::with_capacity(n as usize),
+            masses: Vec::with_capacity(n as usize),
+            charges: Vec::with_capacity(n as usize),
+            bonds: Vec::new(),
+        }
+    }
 
-            pos.push([p.x, p.y]);
-            vel.push([v.x, v.y]);
-            col.push([red_component, green_component, blue_component, 1.0]); // RGBA
+    /// Split the particles at index `at`, like [`Vec::split_off`]
+    ///
+    /// Bonds between the two halves are dropped.
+    pub fn split_off(&mut self, at: usize) -> Self {
+        let at_index = at as u32;
+        let tail_bonds = bonds::remap(&self.bonds, |id| id.checked_sub(at_index));
+        self.bonds = bonds::remap(&self.bonds, |id| (id < at_index).then_some(id));
+        Self {
+            positions: self.positions.split_off(at),
+            velocities: self.velocities.split_off(at),
+            colors: self.colors.split_off(at),
+            masses: self.masses.split_off(at),
+            charges: self.charges.split_off(at),
+            bonds: tail_bonds,
         }
-    };
+    }
 
-    make_disc(
-        half,
-        Vec2::new(-0.35, 0.0),
-        1.0,
-        &color(255, 128, 0),  // orange core
-        &color(65, 105, 225), // royal blue outskirts
-        &mut pos,
-        &mut vel,
-        &mut col,
-    );
-    make_disc(
-        n - half,
-        Vec2::new(0.35, 0.0),
-        -1.0,
-        &color(0, 165, 225), // light blue core
-        &color(123, 104, 0), // dark goldenrod outskirts
-        &mut pos,
-        &mut vel,
-        &mut col,
-    );
+    /// Append a neutral particle, see [`scenario::Scenarios`] for the charges
+    pub fn push(&mut self, position: Vec2, velocity: Vec2, color: [f32; 4], mass: f32) {
+        self.positions.push(position.to_array());
+        self.velocities.push(velocity.to_array());
+        self.colors.push(color);
+        self.masses.push(mass);
+        self.charges.push(0.0);
+    }
 
-    (pos, vel, col)
+    /// Bond particles `i` and `j` at their current distance
+    pub fn bond(&mut self, i: u32, j: u32, stiffness: f32, damping: f32) {
+        let delta = Vec2::from(self.positions[j as usize]) - Vec2::from(self.positions[i as usize]);
+        self.bonds.push(Bond {
+            i,
+            j,
+            rest: delta.length(),
+            stiffness,
+            damping,
+        });
+    }
 }
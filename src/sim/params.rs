@@ -1,4 +1,15 @@
-use crate::constants;
+use std::{fmt::Display, ops::RangeInclusive};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    brush::Brush,
+    diagnostics::Diagnostics,
+    fluid::Fluid,
+    integrator::Integrator,
+    timestep::{StepState, TimestepMode},
+};
+use crate::{constants, utils::config::Config};
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -6,19 +17,102 @@ use crate::constants;
 pub struct SimUniform {
     /// (dt, g, softening, n as f32)
     pub dt_g_soft_n: [f32; 4],
-    /// (world_x, world_y, damping, wrap as f32, color_by_speed as f32, bootstrap (0 or 1))
-    pub damp_wrap_color_bootstrap: [f32; 4],
+    /// (damping, wrap as f32, color_by_speed as f32, color_by_charge as f32)
+    pub damp_wrap_color: [f32; 4],
     /// Currently used buffer (0 or 1)
     pub world: [f32; 4],
+    /// (solver (0 = direct, 1 = barnes-hut, 2 = particle-mesh), theta, pm grid size, 0)
+    pub solver: [f32; 4],
+    /// (timestep mode (0 = fixed, 1 = acceleration, 2 = velocity), accuracy, smallest dt, integrator)
+    pub timestep: [f32; 4],
+    /// (pointer x, pointer y, signed brush strength, brush radius), zero while released
+    pub brush: [f32; 4],
+    /// (effective g, effective coulomb constant, coulomb softening, 0)
+    pub coulomb: [f32; 4],
+    /// (smoothing length, rest density, sound speed, gamma), zero without the fluid
+    pub fluid: [f32; 4],
+    /// (kernel, equation of state, viscosity, gravity), zero without the fluid
+    pub fluid_model: [f32; 4],
+}
+
+/// Force solver used by the compute pass
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Solver {
+    /// Tiled all-pairs direct sum, exact but O(N^2)
+    #[default]
+    Direct = 0,
+    /// Quadtree approximation, O(N log N)
+    BarnesHut = 1,
+    /// Periodic mesh with FFT Poisson solve, O(N + G^2 log G)
+    ParticleMesh = 2,
+}
+
+impl Solver {
+    pub const ALL: [Solver; 3] = [Solver::Direct, Solver::BarnesHut, Solver::ParticleMesh];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Solver::Direct => "Direct Sum",
+            Solver::BarnesHut => "Barnes-Hut",
+            Solver::ParticleMesh => "Particle-Mesh",
+        }
+    }
+}
+
+/// Pairwise force between the particles
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Interaction {
+    /// Attraction between the masses
+    #[default]
+    Gravity = 0,
+    /// Like charges repel, opposite charges attract
+    Coulomb = 1,
+    /// Both of the above
+    GravityAndCoulomb = 2,
+}
+
+impl Interaction {
+    pub const ALL: [Interaction; 3] = [
+        Interaction::Gravity,
+        Interaction::Coulomb,
+        Interaction::GravityAndCoulomb,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Interaction::Gravity => "Gravity",
+            Interaction::Coulomb => "Coulomb",
+            Interaction::GravityAndCoulomb => "Gravity + Coulomb",
+        }
+    }
 }
 
+#[derive(Clone, PartialEq)]
 pub struct SimParams {
-    /// Time step
+    /// Time step, the largest one with an adaptive time step
     pub dt: f32,
+    /// Compute steps per rendered frame
+    pub substeps: u32,
+    /// How the length of each step is chosen
+    pub timestep: TimestepMode,
+    /// Safety factor of the adaptive time step, smaller is more accurate
+    pub accuracy: f32,
+    /// Scheme advancing the particles by one step
+    pub integrator: Integrator,
     /// Gravitational constant
     pub g: f32,
     /// Softening factor to prevent singularities
     pub softening: f32,
+    /// Pairwise force, only the direct sum supports the Coulomb force
+    pub interaction: Interaction,
+    /// Coulomb constant
+    pub coulomb_k: f32,
+    /// Softening of the Coulomb force
+    pub coulomb_softening: f32,
     /// Number of particles
     pub n: u32,
     /// Size of the simulation world (as a square, from -world to +world)
@@ -27,10 +121,29 @@ pub struct SimParams {
     pub damping: f32,
     /// Whether the world wraps around at the edges
     pub wrap: bool,
+    /// Force solver
+    pub solver: Solver,
+    /// Barnes-Hut opening angle (node size / distance threshold)
+    pub theta: f32,
+    /// Particle-mesh grid resolution (cells per side, power of two)
+    pub pm_grid: u32,
+    /// Seed of the initial conditions, the same seed always gives the same particles
+    pub seed: u64,
     /// Whether the simulation is paused
     pub paused: bool,
     /// Change color based on speed
     pub color_by_speed: bool,
+    /// Color the particles by the sign and size of their charge
+    pub color_by_charge: bool,
+    /// Draw the bonds as lines under the particles
+    pub show_bonds: bool,
+    /// Merge particles that come within `merge_radius` of each other
+    pub collisions: bool,
+    pub merge_radius: f32,
+    /// Pointer force field
+    pub brush: Brush,
+    /// Smoothed particle hydrodynamics
+    pub fluid: Fluid,
     /// Bootstrap the simulation (0 or 1)
     pub bootstrap: bool,
     /// Current epoch (frame) number
@@ -41,20 +154,283 @@ impl Default for SimParams {
     fn default() -> Self {
         Self {
             dt: constants::sim::DT,
+            substeps: constants::sim::SUBSTEPS,
+            timestep: TimestepMode::default(),
+            accuracy: constants::sim::TIMESTEP_ACCURACY,
+            integrator: Integrator::default(),
             g: constants::sim::G,
             damping: constants::sim::DAMPING,
             softening: constants::sim::SOFTENING,
+            interaction: Interaction::default(),
+            coulomb_k: constants::sim::COULOMB_K,
+            coulomb_softening: constants::sim::COULOMB_SOFTENING,
             n: constants::sim::INITIAL_PARTICLES,
             world: constants::sim::WORLD_SIZE,
             wrap: constants::sim::WRAP,
+            solver: Solver::default(),
+            theta: constants::sim::THETA,
+            pm_grid: constants::sim::PM_GRID,
+            seed: Config::get_seed().unwrap_or_else(rand::random),
             paused: constants::sim::PAUSED,
             color_by_speed: constants::sim::COLOR_BY_SPEED,
+            color_by_charge: constants::sim::COLOR_BY_CHARGE,
+            show_bonds: constants::sim::SHOW_BONDS,
+            collisions: constants::sim::COLLISIONS,
+            merge_radius: constants::sim::MERGE_RADIUS,
+            brush: Brush::default(),
+            fluid: Fluid::default(),
             bootstrap: true, // start with bootstrap enabled
             epoch: 0,
         }
     }
 }
 
+/// Fail unless `value` lies within `range`, `name` is used in the error
+pub fn check_range<T: PartialOrd + Display>(
+    name: &str,
+    value: T,
+    range: &RangeInclusive<T>,
+) -> anyhow::Result<T> {
+    if !range.contains(&value) {
+        anyhow::bail!(
+            "{name} must be between {} and {}, got {value}",
+            range.start(),
+            range.end()
+        );
+    }
+    Ok(value)
+}
+
+/// Parameters set on the command line or in a scenario file, applied on top of the
+/// defaults or of a snapshot
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParamsOverrides {
+    pub n: Option<u32>,
+    pub dt: Option<f32>,
+    pub substeps: Option<u32>,
+    pub timestep: Option<TimestepMode>,
+    pub accuracy: Option<f32>,
+    pub integrator: Option<Integrator>,
+    pub g: Option<f32>,
+    pub softening: Option<f32>,
+    pub interaction: Option<Interaction>,
+    pub coulomb_k: Option<f32>,
+    pub coulomb_softening: Option<f32>,
+    pub damping: Option<f32>,
+    pub wrap: Option<bool>,
+    pub solver: Option<Solver>,
+    pub theta: Option<f32>,
+    pub pm_grid: Option<u32>,
+    #[serde(with = "toml_seed")]
+    pub seed: Option<u64>,
+    pub paused: Option<bool>,
+    /// Scenario files set it in their `[colors]` section
+    #[serde(skip)]
+    pub color_by_speed: Option<bool>,
+    /// Scenario files set it in their `[colors]` section
+    #[serde(skip)]
+    pub color_by_charge: Option<bool>,
+    pub show_bonds: Option<bool>,
+    pub collisions: Option<bool>,
+    pub merge_radius: Option<f32>,
+}
+
+/// TOML integers are signed 64-bit, larger seeds are written as strings
+mod toml_seed {
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+
+    pub fn serialize<S: Serializer>(seed: &Option<u64>, serializer: S) -> Result<S::Ok, S::Error> {
+        match seed.map(i64::try_from) {
+            None => serializer.serialize_none(),
+            Some(Ok(seed)) => serializer.serialize_some(&seed),
+            Some(Err(_)) => serializer.serialize_some(&seed.unwrap_or_default().to_string()),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<u64>, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Seed {
+            Integer(u64),
+            Text(String),
+        }
+
+        Option::<Seed>::deserialize(deserializer)?
+            .map(|seed| match seed {
+                Seed::Integer(seed) => Ok(seed),
+                Seed::Text(text) => text
+                    .parse()
+                    .map_err(|err| D::Error::custom(format!("invalid seed '{text}': {err}"))),
+            })
+            .transpose()
+    }
+}
+
+impl From<&SimParams> for ParamsOverrides {
+    fn from(params: &SimParams) -> Self {
+        Self {
+            n: Some(params.n),
+            dt: Some(params.dt),
+            substeps: Some(params.substeps),
+            timestep: Some(params.timestep),
+            accuracy: Some(params.accuracy),
+            integrator: Some(params.integrator),
+            g: Some(params.g),
+            softening: Some(params.softening),
+            interaction: Some(params.interaction),
+            coulomb_k: Some(params.coulomb_k),
+            coulomb_softening: Some(params.coulomb_softening),
+            damping: Some(params.damping),
+            wrap: Some(params.wrap),
+            solver: Some(params.solver),
+            theta: Some(params.theta),
+            pm_grid: Some(params.pm_grid),
+            seed: Some(params.seed),
+            paused: Some(params.paused),
+            color_by_speed: Some(params.color_by_speed),
+            color_by_charge: Some(params.color_by_charge),
+            show_bonds: Some(params.show_bonds),
+            collisions: Some(params.collisions),
+            merge_radius: Some(params.merge_radius),
+        }
+    }
+}
+
+impl ParamsOverrides {
+    pub fn apply(&self, params: &mut SimParams) {
+        let Self {
+            n,
+            dt,
+            substeps,
+            timestep,
+            accuracy,
+            integrator,
+            g,
+            softening,
+            interaction,
+            coulomb_k,
+            coulomb_softening,
+            damping,
+            wrap,
+            solver,
+            theta,
+            pm_grid,
+            seed,
+            paused,
+            color_by_speed,
+            color_by_charge,
+            show_bonds,
+            collisions,
+            merge_radius,
+        } = *self;
+
+        params.n = n.unwrap_or(params.n);
+        params.dt = dt.unwrap_or(params.dt);
+        params.substeps = substeps.unwrap_or(params.substeps);
+        params.timestep = timestep.unwrap_or(params.timestep);
+        params.accuracy = accuracy.unwrap_or(params.accuracy);
+        params.integrator = integrator.unwrap_or(params.integrator);
+        params.g = g.unwrap_or(params.g);
+        params.softening = softening.unwrap_or(params.softening);
+        params.interaction = interaction.unwrap_or(params.interaction);
+        params.coulomb_k = coulomb_k.unwrap_or(params.coulomb_k);
+        params.coulomb_softening = coulomb_softening.unwrap_or(params.coulomb_softening);
+        params.damping = damping.unwrap_or(params.damping);
+        params.wrap = wrap.unwrap_or(params.wrap);
+        params.solver = solver.unwrap_or(params.solver);
+        params.theta = theta.unwrap_or(params.theta);
+        params.pm_grid = pm_grid.unwrap_or(params.pm_grid);
+        params.seed = seed.unwrap_or(params.seed);
+        params.paused = paused.unwrap_or(params.paused);
+        params.color_by_speed = color_by_speed.unwrap_or(params.color_by_speed);
+        params.color_by_charge = color_by_charge.unwrap_or(params.color_by_charge);
+        params.show_bonds = show_bonds.unwrap_or(params.show_bonds);
+        params.collisions = collisions.unwrap_or(params.collisions);
+        params.merge_radius = merge_radius.unwrap_or(params.merge_radius);
+    }
+
+    /// Replace the values that `other` sets
+    pub fn extend(&mut self, other: &ParamsOverrides) {
+        self.n = other.n.or(self.n);
+        self.dt = other.dt.or(self.dt);
+        self.substeps = other.substeps.or(self.substeps);
+        self.timestep = other.timestep.or(self.timestep);
+        self.accuracy = other.accuracy.or(self.accuracy);
+        self.integrator = other.integrator.or(self.integrator);
+        self.g = other.g.or(self.g);
+        self.softening = other.softening.or(self.softening);
+        self.interaction = other.interaction.or(self.interaction);
+        self.coulomb_k = other.coulomb_k.or(self.coulomb_k);
+        self.coulomb_softening = other.coulomb_softening.or(self.coulomb_softening);
+        self.damping = other.damping.or(self.damping);
+        self.wrap = other.wrap.or(self.wrap);
+        self.solver = other.solver.or(self.solver);
+        self.theta = other.theta.or(self.theta);
+        self.pm_grid = other.pm_grid.or(self.pm_grid);
+        self.seed = other.seed.or(self.seed);
+        self.paused = other.paused.or(self.paused);
+        self.color_by_speed = other.color_by_speed.or(self.color_by_speed);
+        self.color_by_charge = other.color_by_charge.or(self.color_by_charge);
+        self.show_bonds = other.show_bonds.or(self.show_bonds);
+        self.collisions = other.collisions.or(self.collisions);
+        self.merge_radius = other.merge_radius.or(self.merge_radius);
+    }
+
+    /// Check every value against the ranges of the UI sliders
+    pub fn validate(&self) -> anyhow::Result<()> {
+        use constants::sim;
+
+        if let Some(n) = self.n {
+            check_range("n", n, &sim::INITIAL_PARTICLES_RANGE)?;
+        }
+        if let Some(substeps) = self.substeps {
+            check_range("substeps", substeps, &sim::SUBSTEPS_RANGE)?;
+        }
+        for (name, value, range) in [
+            ("dt", self.dt, sim::DT_RANGE),
+            ("accuracy", self.accuracy, sim::TIMESTEP_ACCURACY_RANGE),
+            ("g", self.g, sim::G_RANGE),
+            ("softening", self.softening, sim::SOFTENING_RANGE),
+            ("coulomb_k", self.coulomb_k, sim::COULOMB_K_RANGE),
+            (
+                "coulomb_softening",
+                self.coulomb_softening,
+                sim::SOFTENING_RANGE,
+            ),
+            ("damping", self.damping, sim::DAMPING_RANGE),
+            ("theta", self.theta, sim::THETA_RANGE),
+            ("merge_radius", self.merge_radius, sim::MERGE_RADIUS_RANGE),
+        ] {
+            if let Some(value) = value {
+                check_range(name, value, &range)?;
+            }
+        }
+        if let Some(cells) = self.pm_grid
+            && !sim::PM_GRID_OPTIONS.contains(&cells)
+        {
+            anyhow::bail!(
+                "pm_grid must be one of {:?}, got {cells}",
+                sim::PM_GRID_OPTIONS
+            );
+        }
+        // The approximate solvers only compute gravity, see `SimParams::effective_g`
+        if let (Some(interaction), Some(solver)) = (self.interaction, self.solver)
+            && interaction != Interaction::Gravity
+            && solver != Solver::Direct
+        {
+            anyhow::bail!(
+                "The {} interaction requires the direct-sum solver, {} only computes gravity",
+                interaction.label(),
+                solver.label()
+            );
+        }
+        Ok(())
+    }
+}
+
 pub enum ParticleUpdated {
     Less,
     More,
@@ -70,6 +446,20 @@ pub enum ParamsEguiAction {
     ParameterUpdated(ParticleUpdated),
     /// Step the simulation (if paused)
     Step,
+    /// Write the current state to the snapshot path
+    SaveSnapshot,
+    /// Replace the current state with the snapshot at the snapshot path
+    LoadSnapshot,
+    /// Start writing frames with the recorder settings
+    StartRecording,
+    /// Stop writing frames and flush the output
+    StopRecording,
+    /// Apply the scenario file at the scenario file path
+    LoadScenarioFile,
+    /// Write the current state to the scenario file path
+    SaveScenarioFile,
+    /// The watch toggle of the scenario file changed
+    WatchScenarioFile,
 }
 
 /// Compute average frame time and FPS over the last N frames and update the last_frames array
@@ -98,13 +488,14 @@ fn compute_time_info(last_frame: &mut std::time::Instant) -> (f32, f32) {
 
 impl SimParams {
     pub fn to_uniform(&self) -> SimUniform {
+        let [fluid, fluid_model] = self.fluid.to_uniform();
         SimUniform {
             dt_g_soft_n: [self.dt, self.g, self.softening, self.n as f32],
-            damp_wrap_color_bootstrap: [
+            damp_wrap_color: [
                 self.damping,
                 if self.wrap { 1.0 } else { 0.0 },
                 if self.color_by_speed { 1.0 } else { 0.0 },
-                if self.bootstrap { 1.0 } else { 0.0 },
+                if self.color_by_charge { 1.0 } else { 0.0 },
             ],
             world: [
                 self.world[0].x,
@@ -112,6 +503,46 @@ impl SimParams {
                 self.world[1].x,
                 self.world[1].y,
             ],
+            solver: [
+                self.solver as u32 as f32,
+                self.theta,
+                self.pm_grid as f32,
+                0.0,
+            ],
+            timestep: [
+                self.timestep as u32 as f32,
+                self.accuracy,
+                *constants::sim::DT_RANGE.start(),
+                self.integrator as u32 as f32,
+            ],
+            brush: self.brush.to_uniform(),
+            coulomb: [
+                self.effective_g(),
+                self.effective_coulomb_k(),
+                self.coulomb_softening,
+                0.0,
+            ],
+            fluid,
+            fluid_model,
+        }
+    }
+
+    /// Gravitational constant of the force loop, zero without gravity
+    pub fn effective_g(&self) -> f32 {
+        match self.interaction {
+            _ if !self.fluid.runs_solver() => 0.0,
+            Interaction::Coulomb if self.solver == Solver::Direct => 0.0,
+            _ => self.g,
+        }
+    }
+
+    /// Coulomb constant of the force loop, zero unless the direct sum computes it
+    pub fn effective_coulomb_k(&self) -> f32 {
+        match self.interaction {
+            Interaction::Gravity => 0.0,
+            _ if !self.fluid.runs_solver() => 0.0,
+            _ if self.solver != Solver::Direct => 0.0,
+            _ => self.coulomb_k,
         }
     }
 
@@ -127,6 +558,8 @@ impl SimParams {
         &mut self,
         ui: &mut egui::Ui,
         last_frame: &mut std::time::Instant,
+        diagnostics: &mut Diagnostics,
+        clock: &StepState,
     ) -> ParamsEguiAction {
         let mut action = ParamsEguiAction::None;
 
@@ -136,6 +569,11 @@ impl SimParams {
         ui.label(format!("Frame Time: {:.2} ms", frame_time));
         ui.label(format!("FPS: {:.2}", frame_per_sec));
         ui.label(format!("Epoch: {}", self.epoch));
+        ui.label(format!("Simulated Time: {:.3} s", clock.time));
+        ui.label(format!("Time Step: {:.5} s", clock.dt))
+            .on_hover_text("Length of the next compute step. Adaptive steps are read back from the GPU and may lag by a frame");
+        ui.label(format!("Seed: {}", self.seed));
+        diagnostics.render_ui(ui);
 
         ui.separator();
         ui.heading("Simulation Parameters");
@@ -149,13 +587,76 @@ impl SimParams {
                     .step_by(constants::sim::DT_STEP)
                     .suffix(" s"),
             )
-            .on_hover_text("Simulation time advanced per compute step. Lower values are more stable but slower in simulated time")
+            .on_hover_text("Simulation time advanced per compute step, the largest step with an adaptive time step. Lower values are more stable but slower in simulated time")
             .changed()
         {
             self.dt = dt;
             action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
         }
 
+        // Compute steps per frame
+        let mut substeps = self.substeps;
+        if ui
+            .add(
+                egui::Slider::new(&mut substeps, constants::sim::SUBSTEPS_RANGE)
+                    .text("Substeps / Frame"),
+            )
+            .on_hover_text("Compute steps run for every rendered frame. More substeps advance simulated time faster at the same dt, at the cost of frame rate")
+            .changed()
+        {
+            self.substeps = substeps;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        // Fixed or adaptive time step
+        let mut timestep = self.timestep;
+        egui::ComboBox::from_label("Time Stepping")
+            .selected_text(timestep.label())
+            .show_ui(ui, |ui| {
+                for option in TimestepMode::ALL {
+                    ui.selectable_value(&mut timestep, option, option.label());
+                }
+            })
+            .response
+            .on_hover_text("Adaptive modes shrink the step on the GPU when the largest acceleration or speed grows, between the smallest dt and the time step above");
+        if timestep != self.timestep {
+            self.timestep = timestep;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        // Adaptive time step safety factor
+        let mut accuracy = self.accuracy;
+        if ui
+            .add_enabled(
+                self.timestep.is_adaptive(),
+                egui::Slider::new(&mut accuracy, constants::sim::TIMESTEP_ACCURACY_RANGE)
+                    .text("Step Accuracy")
+                    .logarithmic(true)
+                    .step_by(constants::sim::TIMESTEP_ACCURACY_STEP),
+            )
+            .on_hover_text("Safety factor of the adaptive time step, in units of the softening length. Lower is more accurate but slower in simulated time")
+            .changed()
+        {
+            self.accuracy = accuracy;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        // Integration scheme
+        let mut integrator = self.integrator;
+        egui::ComboBox::from_label("Integrator")
+            .selected_text(integrator.label())
+            .show_ui(ui, |ui| {
+                for option in Integrator::ALL {
+                    ui.selectable_value(&mut integrator, option, option.label());
+                }
+            })
+            .response
+            .on_hover_text("Leapfrog and Velocity Verlet are 2nd order and symplectic. Yoshida 4 is 4th order and symplectic, Runge-Kutta 4 is 4th order but slowly drifts in energy. Higher orders evaluate the forces several times per step");
+        if integrator != self.integrator {
+            self.integrator = integrator;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
         // Gravitational constant
         let mut g = self.g;
         if ui
@@ -181,7 +682,9 @@ impl SimParams {
                     .logarithmic(true)
                     .step_by(constants::sim::SOFTENING_STEP),
             )
-            .on_hover_text("Minimum interaction scale used to avoid singular forces and reduce core collapse")
+            .on_hover_text(
+                "Minimum interaction scale used to avoid singular forces and reduce core collapse",
+            )
             .changed()
         {
             self.softening = softening;
@@ -196,13 +699,148 @@ impl SimParams {
                     .text("Velocity Retention / s")
                     .step_by(constants::sim::DAMPING_STEP),
             )
-            .on_hover_text("Fraction of velocity preserved over one simulated second. Use 1.0 for no drag")
+            .on_hover_text(
+                "Fraction of velocity preserved over one simulated second. Use 1.0 for no drag",
+            )
             .changed()
         {
             self.damping = damping;
             action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
         }
 
+        // Force solver
+        let mut solver = self.solver;
+        egui::ComboBox::from_label("Solver")
+            .selected_text(solver.label())
+            .show_ui(ui, |ui| {
+                for option in Solver::ALL {
+                    ui.selectable_value(&mut solver, option, option.label());
+                }
+            })
+            .response
+            .on_hover_text("Direct sum is exact but O(N^2). Barnes-Hut groups distant particles in a quadtree and scales to larger counts. Particle-Mesh solves gravity on a periodic grid with FFTs, best for very large counts with wrapping enabled");
+        if solver != self.solver {
+            self.solver = solver;
+            // Like `ParamsOverrides::validate`, the approximate solvers only know gravity
+            if solver != Solver::Direct {
+                self.interaction = Interaction::Gravity;
+            }
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        // Barnes-Hut opening angle
+        let mut theta = self.theta;
+        if ui
+            .add_enabled(
+                self.solver == Solver::BarnesHut,
+                egui::Slider::new(&mut theta, constants::sim::THETA_RANGE)
+                    .text("Opening Angle (theta)")
+                    .step_by(constants::sim::THETA_STEP),
+            )
+            .on_hover_text("Barnes-Hut accuracy. Nodes smaller than theta times their distance are treated as a single mass. Lower is more accurate but slower")
+            .changed()
+        {
+            self.theta = theta;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        // Particle-mesh grid resolution
+        let mut pm_grid = self.pm_grid;
+        ui.add_enabled_ui(self.solver == Solver::ParticleMesh, |ui| {
+            egui::ComboBox::from_label("Mesh Resolution")
+                .selected_text(format!("{pm_grid}²"))
+                .show_ui(ui, |ui| {
+                    for &option in constants::sim::PM_GRID_OPTIONS {
+                        ui.selectable_value(&mut pm_grid, option, format!("{option}²"));
+                    }
+                })
+                .response
+                .on_hover_text("Particle-Mesh grid cells per side. Finer grids resolve smaller structures but cost more per step. Forces are always periodic, even without wrapping");
+        });
+        if pm_grid != self.pm_grid {
+            self.pm_grid = pm_grid;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        // Pairwise force, the approximate solvers only know gravity
+        let direct = self.solver == Solver::Direct;
+        let mut interaction = self.interaction;
+        ui.add_enabled_ui(direct, |ui| {
+            egui::ComboBox::from_label("Interaction")
+                .selected_text(interaction.label())
+                .show_ui(ui, |ui| {
+                    for option in Interaction::ALL {
+                        ui.selectable_value(&mut interaction, option, option.label());
+                    }
+                })
+                .response
+                .on_hover_text("Force between the particles. Coulomb uses the particle charges: like charges repel, opposite charges attract. Only the direct-sum solver computes it, picking another solver switches back to gravity");
+        });
+        if interaction != self.interaction {
+            self.interaction = interaction;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        // Coulomb constant and softening
+        let coulomb = direct && self.interaction != Interaction::Gravity;
+        let mut coulomb_k = self.coulomb_k;
+        if ui
+            .add_enabled(
+                coulomb,
+                egui::Slider::new(&mut coulomb_k, constants::sim::COULOMB_K_RANGE)
+                    .text("Coulomb Constant (k)")
+                    .logarithmic(true)
+                    .step_by(constants::sim::COULOMB_K_STEP),
+            )
+            .on_hover_text("Strength of the electrostatic force between two unit charges")
+            .changed()
+        {
+            self.coulomb_k = coulomb_k;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+        let mut coulomb_softening = self.coulomb_softening;
+        if ui
+            .add_enabled(
+                coulomb,
+                egui::Slider::new(&mut coulomb_softening, constants::sim::SOFTENING_RANGE)
+                    .text("Coulomb Softening")
+                    .logarithmic(true)
+                    .step_by(constants::sim::SOFTENING_STEP),
+            )
+            .on_hover_text("Minimum interaction scale of the electrostatic force, keeps opposite charges from collapsing onto each other")
+            .changed()
+        {
+            self.coulomb_softening = coulomb_softening;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        // Inelastic collisions
+        let mut collisions = self.collisions;
+        let mut merge_radius = self.merge_radius;
+        ui.horizontal(|ui| {
+            if ui
+                .checkbox(&mut collisions, "Merge on Contact")
+                .on_hover_text("Particles closer than the merge radius combine into one, keeping their mass, momentum and charge. Mutual nearest neighbors merge once per frame")
+                .changed()
+            {
+                self.collisions = collisions;
+                action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+            }
+            if ui
+                .add_enabled(
+                    collisions,
+                    egui::Slider::new(&mut merge_radius, constants::sim::MERGE_RADIUS_RANGE)
+                        .text("Merge Radius")
+                        .logarithmic(true)
+                        .step_by(constants::sim::MERGE_RADIUS_STEP),
+                )
+                .changed()
+            {
+                self.merge_radius = merge_radius;
+                action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+            }
+        });
+
         // Number of particles
         let mut n = self.n;
         if ui
@@ -211,7 +849,7 @@ impl SimParams {
                     .text("Number of Particles")
                     .step_by(constants::sim::INITIAL_PARTICLES_STEP),
             )
-            .on_hover_text("Total particle count. Larger values quickly increase cost because the kernel is still O(N^2)")
+            .on_hover_text("Total particle count. Lowering it drops the last particles, raising it adds particles from the active scenario, the others keep their state. Larger values quickly increase cost, especially with the O(N^2) direct-sum solver")
             .changed()
         {
             if n < self.n {
@@ -224,6 +862,42 @@ impl SimParams {
             self.n = n;
         }
 
+        // Seed of the initial conditions, edited as text as a `DragValue` goes through f64
+        // and rounds the seeds above 2^53
+        let mut seed = self.seed;
+        ui.horizontal(|ui| {
+            let id = ui.make_persistent_id("seed");
+            let mut text = ui
+                .data(|data| data.get_temp::<String>(id))
+                .unwrap_or_else(|| seed.to_string());
+            let response = ui
+                .add(egui::TextEdit::singleline(&mut text).desired_width(constants::egui::SEED_WIDTH))
+                .on_hover_text("Seed of the initial conditions. The same seed always generates the same particles, changing it resets them");
+            if response.has_focus() {
+                ui.data_mut(|data| data.insert_temp(id, text));
+            } else {
+                ui.data_mut(|data| data.remove::<String>(id));
+                // Applied when the field is left, text that is not a seed is dropped
+                if response.lost_focus()
+                    && let Ok(value) = text.trim().parse::<u64>()
+                    && value != seed
+                {
+                    seed = value;
+                    action = ParamsEguiAction::Reset;
+                }
+            }
+            if ui
+                .button("Randomize")
+                .on_hover_text("Pick a random seed and reset the particles")
+                .clicked()
+            {
+                seed = rand::random();
+                action = ParamsEguiAction::Reset;
+            }
+            ui.label("Seed");
+        });
+        self.seed = seed;
+
         // World wrap
         let mut wrap = self.wrap;
         if ui
@@ -248,14 +922,40 @@ impl SimParams {
             action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
         }
 
+        // Color by charge
+        let mut color_by_charge = self.color_by_charge;
+        if ui
+            .checkbox(&mut color_by_charge, "Color by Charge")
+            .on_hover_text(
+                "Draw positive charges in red, negative ones in blue and neutral particles in gray",
+            )
+            .changed()
+        {
+            self.color_by_charge = color_by_charge;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
+        // Bond lines
+        let mut show_bonds = self.show_bonds;
+        if ui
+            .checkbox(&mut show_bonds, "Show Bonds")
+            .on_hover_text("Draw the springs of the cloth and rope scenarios as lines")
+            .changed()
+        {
+            self.show_bonds = show_bonds;
+            action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
+        }
+
         ui.separator();
 
         ui.horizontal(|ui| {
             // Reset parameters button
             if ui.button("Reset Parameters").clicked() {
-                let mut _def = SimParams::default();
-                _def.n = self.n; // keep current n
-                *self = _def;
+                *self = SimParams {
+                    n: self.n,       // keep current n
+                    seed: self.seed, // and the seed of the current particles
+                    ..SimParams::default()
+                };
                 action = ParamsEguiAction::ParameterUpdated(ParticleUpdated::Same);
             }
 
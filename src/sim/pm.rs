@@ -0,0 +1,151 @@
+//! Particle-mesh solver helpers and CPU reference
+//!
+//! The potential is the periodic convolution of the mass grid with the softened
+//! (Plummer) Green's function `-1 / sqrt(r^2 + softening^2)`, so the mesh forces
+//! follow the same law as the direct-sum kernel. The GPU path in
+//! `shaders/particle_mesh.wgsl` mirrors [`accelerations`] step by step.
+
+use glam::Vec2;
+
+use super::{
+    SimParams,
+    fft::{self, Complex},
+};
+
+/// Size of one grid cell in world units
+pub fn cell_size(params: &SimParams, grid: u32) -> Vec2 {
+    (params.world[1] - params.world[0]) / grid as f32
+}
+
+/// Spectrum of the unit-strength Green's function sampled on the periodic grid
+///
+/// Multiplying a mass spectrum by this (and by `g`) gives the potential spectrum.
+pub fn green_spectrum(params: &SimParams, grid: u32) -> Vec<Complex> {
+    let size = grid as usize;
+    let h = cell_size(params, grid).as_dvec2();
+    let soft2 = (params.softening as f64).powi(2);
+
+    // Minimum image offset of cell `i` from the origin
+    let offset = |i: usize| -> f64 {
+        if i <= size / 2 {
+            i as f64
+        } else {
+            i as f64 - size as f64
+        }
+    };
+
+    let mut green = Vec::with_capacity(size * size);
+    for y in 0..size {
+        for x in 0..size {
+            let dx = offset(x) * h.x;
+            let dy = offset(y) * h.y;
+            green.push([-1.0 / (dx * dx + dy * dy + soft2).sqrt(), 0.0]);
+        }
+    }
+
+    fft::fft_2d(&mut green, size, false);
+    green
+}
+
+/// Cloud-in-cell stencil: the 4 surrounding cells and their weights
+fn cic(p: Vec2, params: &SimParams, grid: u32) -> [(usize, f32); 4] {
+    let size = grid as i64;
+    let u = (p - params.world[0]) / cell_size(params, grid) - 0.5;
+    let base = u.floor();
+    let f = u - base;
+
+    let wrap = |i: i64| i.rem_euclid(size) as usize;
+    let (x0, y0) = (wrap(base.x as i64), wrap(base.y as i64));
+    let (x1, y1) = ((x0 + 1) % grid as usize, (y0 + 1) % grid as usize);
+    let row = grid as usize;
+
+    [
+        (y0 * row + x0, (1.0 - f.x) * (1.0 - f.y)),
+        (y0 * row + x1, f.x * (1.0 - f.y)),
+        (y1 * row + x0, (1.0 - f.x) * f.y),
+        (y1 * row + x1, f.x * f.y),
+    ]
+}
+
+/// Particle-mesh accelerations of all particles, CPU reference
+pub fn accelerations(
+    params: &SimParams,
+    positions: &[[f32; 2]],
+    masses: &[f32],
+    grid: u32,
+) -> Vec<Vec2> {
+    let size = grid as usize;
+
+    // Mass deposition
+    let mut field: Vec<Complex> = vec![[0.0; 2]; size * size];
+    for (p, m) in positions.iter().zip(masses) {
+        for (idx, w) in cic(Vec2::from(*p), params, grid) {
+            field[idx][0] += (m * w) as f64;
+        }
+    }
+
+    // Potential = g * (mass (*) green), normalized inverse transform
+    fft::fft_2d(&mut field, size, false);
+    fft::multiply(&mut field, &green_spectrum(params, grid));
+    fft::fft_2d(&mut field, size, true);
+    let scale = params.g as f64 / (size * size) as f64;
+    let potential: Vec<f64> = field.iter().map(|c| c[0] * scale).collect();
+
+    // Acceleration field by central differences
+    let h = cell_size(params, grid).as_dvec2();
+    let mut acc_grid = vec![Vec2::ZERO; size * size];
+    for y in 0..size {
+        for x in 0..size {
+            let xp = (x + 1) % size;
+            let xm = (x + size - 1) % size;
+            let yp = (y + 1) % size;
+            let ym = (y + size - 1) % size;
+            let ax = -(potential[y * size + xp] - potential[y * size + xm]) / (2.0 * h.x);
+            let ay = -(potential[yp * size + x] - potential[ym * size + x]) / (2.0 * h.y);
+            acc_grid[y * size + x] = Vec2::new(ax as f32, ay as f32);
+        }
+    }
+
+    // Interpolation back to the particles
+    positions
+        .iter()
+        .map(|p| {
+            cic(Vec2::from(*p), params, grid)
+                .iter()
+                .fold(Vec2::ZERO, |acc, &(idx, w)| acc + acc_grid[idx] * w)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::cpu;
+
+    #[test]
+    fn two_body_forces_approximate_the_direct_sum() {
+        let params = SimParams {
+            n: 2,
+            wrap: true,
+            ..SimParams::default()
+        };
+        let positions = [[-0.13, 0.05], [0.21, -0.08]];
+        let masses = [1.0, 3.0];
+
+        let mesh = accelerations(&params, &positions, &masses, 256);
+        for (id, mesh) in mesh.into_iter().enumerate() {
+            let direct = cpu::acceleration(&params, &positions, &masses, &[0.0; 2], id);
+            let err = (mesh - direct).length() / direct.length();
+            assert!(err < 1e-2, "particle {id}: {mesh} vs {direct}");
+        }
+    }
+
+    #[test]
+    fn cloud_in_cell_weights_sum_to_one() {
+        let params = SimParams::default();
+        for p in [Vec2::new(0.3, -0.7), Vec2::new(-1.0, 0.999), Vec2::ZERO] {
+            let total: f32 = cic(p, &params, 64).iter().map(|&(_, w)| w).sum();
+            assert!((total - 1.0).abs() < 1e-6, "{p}: {total}");
+        }
+    }
+}
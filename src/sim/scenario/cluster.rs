@@ -0,0 +1,164 @@
+use glam::Vec2;
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use super::{InitialCondition, circular_speed, color, gradient, normal, polar, world_frame};
+use crate::{
+    constants,
+    sim::{ParticleData, SimParams},
+};
+
+/// Plummer sphere seen from above
+///
+/// Radii follow the projected Plummer profile, whose enclosed mass fraction is
+/// `R² / (R² + a²)`. Velocities are isotropic with a dispersion set by the circular
+/// speed of the enclosed mass, so `virial = 1` starts close to equilibrium.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Plummer {
+    pub scale: f32,
+    pub virial: f32,
+}
+
+impl Default for Plummer {
+    fn default() -> Self {
+        Self {
+            scale: constants::scenario::PLUMMER_SCALE,
+            virial: constants::scenario::VIRIAL,
+        }
+    }
+}
+
+impl InitialCondition for Plummer {
+    fn id(&self) -> &'static str {
+        "plummer"
+    }
+
+    fn label(&self) -> &'static str {
+        "Plummer Sphere"
+    }
+
+    fn generate(&self, params: &SimParams, rng: &mut StdRng) -> ParticleData {
+        let (center, half) = world_frame(params);
+        let a = self.scale * half;
+        let r_max = constants::scenario::PLUMMER_TRUNCATION * half;
+        let total_mass = params.n as f32 * constants::scenario::PARTICLE_MASS;
+
+        // Invert the enclosed mass fraction, truncated at r_max
+        let max_fraction = r_max * r_max / (r_max * r_max + a * a);
+
+        let core = color(255, 240, 200); // warm white core
+        let halo = color(200, 60, 40); // red halo
+
+        let mut data = ParticleData::with_capacity(params.n);
+        for _ in 0..params.n {
+            let fraction = rng.random::<f32>() * max_fraction;
+            let r = a * (fraction / (1.0 - fraction)).sqrt();
+            let (dir, _) = polar(rng.random::<f32>() * std::f32::consts::TAU);
+
+            let enclosed = fraction / max_fraction * total_mass;
+            let sigma = circular_speed(params, enclosed, r) * (0.5 * self.virial).sqrt();
+            let v = Vec2::new(normal(rng), normal(rng)) * sigma;
+
+            data.push(
+                center + dir * r,
+                v,
+                gradient(&core, &halo, (r / r_max).sqrt()),
+                constants::scenario::PARTICLE_MASS,
+            );
+        }
+
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.scale, constants::scenario::PLUMMER_SCALE_RANGE)
+                    .text("Scale Radius"),
+            )
+            .on_hover_text("Radius of the dense core, half of the mass lies within it")
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.virial, constants::scenario::VIRIAL_RANGE)
+                    .text("Virial Ratio"),
+            )
+            .on_hover_text("Random velocities relative to equilibrium. Below 1 the cluster collapses, above 1 it expands")
+            .changed();
+        changed
+    }
+}
+
+/// Uniform disc released at rest (or with a solid-body spin)
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColdCollapse {
+    pub radius: f32,
+    pub spin: f32,
+}
+
+impl Default for ColdCollapse {
+    fn default() -> Self {
+        Self {
+            radius: constants::scenario::COLLAPSE_RADIUS,
+            spin: constants::scenario::SPIN,
+        }
+    }
+}
+
+impl InitialCondition for ColdCollapse {
+    fn id(&self) -> &'static str {
+        "cold-collapse"
+    }
+
+    fn label(&self) -> &'static str {
+        "Cold Collapse"
+    }
+
+    fn generate(&self, params: &SimParams, rng: &mut StdRng) -> ParticleData {
+        let (center, half) = world_frame(params);
+        let radius = self.radius * half;
+        let total_mass = params.n as f32 * constants::scenario::PARTICLE_MASS;
+
+        let inner = color(180, 220, 255); // icy blue
+        let outer = color(120, 80, 200); // violet
+
+        let mut data = ParticleData::with_capacity(params.n);
+        for _ in 0..params.n {
+            let t = rng.random::<f32>().sqrt();
+            let r = t * radius;
+            let (dir, tangent) = polar(rng.random::<f32>() * std::f32::consts::TAU);
+
+            // Solid-body rotation as a fraction of the speed that would balance gravity at the rim
+            let v_rim = circular_speed(params, total_mass, radius);
+            let v = tangent * self.spin * v_rim * t;
+
+            data.push(
+                center + dir * r,
+                v,
+                gradient(&inner, &outer, t),
+                constants::scenario::PARTICLE_MASS,
+            );
+        }
+
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.radius, constants::scenario::RADIUS_RANGE)
+                    .text("Radius"),
+            )
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut self.spin, constants::scenario::SPIN_RANGE).text("Spin"))
+            .on_hover_text("Solid-body rotation, as a fraction of the circular speed at the rim")
+            .changed();
+        changed
+    }
+}
@@ -0,0 +1,84 @@
+use glam::Vec2;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use super::{InitialCondition, color, gradient};
+use crate::{
+    constants,
+    sim::{ParticleData, SimParams},
+};
+
+/// Column of fluid at rest in the lower left corner, released at once
+///
+/// The particles sit on a square lattice, their masses give the rest density of the
+/// fluid at the lattice spacing. Meant for the SPH fluid without wrapping, see
+/// `scenarios/dam-break.toml`.
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DamBreak {
+    /// Fractions of the world width and height
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for DamBreak {
+    fn default() -> Self {
+        Self {
+            width: constants::scenario::DAM_WIDTH,
+            height: constants::scenario::DAM_HEIGHT,
+        }
+    }
+}
+
+impl InitialCondition for DamBreak {
+    fn id(&self) -> &'static str {
+        "dam-break"
+    }
+
+    fn label(&self) -> &'static str {
+        "Dam Break"
+    }
+
+    fn generate(&self, params: &SimParams, _rng: &mut StdRng) -> ParticleData {
+        let size = params.world[1] - params.world[0];
+        let extent = size * Vec2::new(self.width, self.height);
+
+        // The last row is left incomplete when n does not fill the lattice
+        let n = params.n;
+        let cols = ((n as f32 * extent.x / extent.y).sqrt().round() as u32).clamp(1, n.max(1));
+        let rows = n.div_ceil(cols).max(1);
+        let spacing = extent.x / cols as f32;
+        let mass = params.fluid.rest_density * spacing * spacing;
+
+        let deep = color(20, 60, 160); // dark blue
+        let surface = color(120, 200, 255); // light blue
+
+        let mut data = ParticleData::with_capacity(n);
+        for i in 0..n {
+            let (col, row) = (i % cols, i / cols);
+            data.push(
+                params.world[0] + (Vec2::new(col as f32, row as f32) + 0.5) * spacing,
+                Vec2::ZERO,
+                gradient(&deep, &surface, row as f32 / rows as f32),
+                mass,
+            );
+        }
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(egui::Slider::new(&mut self.width, constants::scenario::DAM_RANGE).text("Width"))
+            .on_hover_text("Width of the column, as a fraction of the world")
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.height, constants::scenario::DAM_RANGE)
+                    .text("Height"),
+            )
+            .on_hover_text("Height of the column, as a fraction of the world. The lattice spacing follows from the particle count")
+            .changed();
+        changed
+    }
+}
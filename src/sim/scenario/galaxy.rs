@@ -0,0 +1,231 @@
+use glam::Vec2;
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use super::{InitialCondition, circular_speed, color, gradient, polar, world_frame};
+use crate::{
+    constants,
+    sim::{ParticleData, SimParams},
+};
+
+/// Rotating disc of `count` particles around a heavy central body
+struct Disc {
+    center: Vec2,
+    radius: f32,
+    /// 1 for counter-clockwise, -1 for clockwise
+    rot_dir: f32,
+    core: [f32; 3],
+    rim: [f32; 3],
+}
+
+impl Disc {
+    /// `angle` picks the polar angle of a particle from its distance to the center
+    fn fill(
+        &self,
+        params: &SimParams,
+        rng: &mut StdRng,
+        count: u32,
+        data: &mut ParticleData,
+        mut angle: impl FnMut(&mut StdRng, f32) -> f32,
+    ) {
+        if count == 0 {
+            return;
+        }
+
+        // Heavy central body, drawn in the core color
+        let central_mass = (constants::sim::CENTRAL_MASS_FRACTION * count as f32)
+            .max(constants::sim::MASS_RANGE.end);
+        data.push(
+            self.center,
+            Vec2::ZERO,
+            gradient(&self.core, &self.rim, 0.0),
+            central_mass,
+        );
+
+        for _ in 1..count {
+            // rayon ~ uniform in disc
+            let r = rng.random::<f32>().sqrt() * self.radius;
+            let (dir, tangent) = polar(angle(rng, r));
+            let p = self.center + dir * r;
+
+            // v = tangente * vmag, with the circular speed around the central body added
+            let v_disc = 0.035 / (r + 0.02).sqrt();
+            let v_central = circular_speed(params, central_mass, r);
+            let vmag = (v_disc * v_disc + v_central * v_central).sqrt();
+            let v = tangent * self.rot_dir * vmag * (0.9 + rng.random::<f32>() * 0.1); // add some noise (10%)
+
+            // color gradient from the core to the rim, pow3 for better contrast
+            let c = gradient(&self.core, &self.rim, (r / self.radius).powi(3));
+            let mass = rng.random_range(constants::sim::MASS_RANGE);
+
+            data.push(p, v, c, mass);
+        }
+    }
+}
+
+/// Two counter-rotating discs side by side, the historical default
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GalaxyCollision {
+    pub separation: f32,
+    pub radius: f32,
+    pub counter_rotating: bool,
+}
+
+impl Default for GalaxyCollision {
+    fn default() -> Self {
+        Self {
+            separation: constants::scenario::COLLISION_SEPARATION,
+            radius: constants::scenario::COLLISION_RADIUS,
+            counter_rotating: true,
+        }
+    }
+}
+
+impl InitialCondition for GalaxyCollision {
+    fn id(&self) -> &'static str {
+        "galaxy-collision"
+    }
+
+    fn label(&self) -> &'static str {
+        "Galaxy Collision"
+    }
+
+    fn generate(&self, params: &SimParams, rng: &mut StdRng) -> ParticleData {
+        let (center, half) = world_frame(params);
+        let offset = Vec2::new(0.5 * self.separation * half, 0.0);
+        let uniform = |rng: &mut StdRng, _r| rng.random::<f32>() * std::f32::consts::TAU;
+
+        let n = params.n;
+        let mut data = ParticleData::with_capacity(n);
+
+        Disc {
+            center: center - offset,
+            radius: self.radius * half,
+            rot_dir: 1.0,
+            core: color(255, 128, 0), // orange core
+            rim: color(65, 105, 225), // royal blue outskirts
+        }
+        .fill(params, rng, n / 2, &mut data, uniform);
+        Disc {
+            center: center + offset,
+            radius: self.radius * half,
+            rot_dir: if self.counter_rotating { -1.0 } else { 1.0 },
+            core: color(0, 165, 225), // light blue core
+            rim: color(123, 104, 0),  // dark goldenrod outskirts
+        }
+        .fill(params, rng, n - n / 2, &mut data, uniform);
+
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(
+                egui::Slider::new(
+                    &mut self.separation,
+                    constants::scenario::COLLISION_SEPARATION_RANGE,
+                )
+                .text("Separation"),
+            )
+            .on_hover_text("Distance between the two galaxy centers")
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.radius, constants::scenario::RADIUS_RANGE)
+                    .text("Disc Radius"),
+            )
+            .changed();
+        changed |= ui
+            .checkbox(&mut self.counter_rotating, "Counter-rotating")
+            .on_hover_text("Whether the second galaxy spins in the opposite direction")
+            .changed();
+        changed
+    }
+}
+
+/// Single disc galaxy with trailing spiral arms
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SpiralGalaxy {
+    pub arms: u32,
+    pub radius: f32,
+    pub winding: f32,
+    pub spread: f32,
+}
+
+impl Default for SpiralGalaxy {
+    fn default() -> Self {
+        Self {
+            arms: constants::scenario::SPIRAL_ARMS,
+            radius: constants::scenario::SPIRAL_RADIUS,
+            winding: constants::scenario::SPIRAL_WINDING,
+            spread: constants::scenario::SPIRAL_SPREAD,
+        }
+    }
+}
+
+impl InitialCondition for SpiralGalaxy {
+    fn id(&self) -> &'static str {
+        "spiral"
+    }
+
+    fn label(&self) -> &'static str {
+        "Spiral Galaxy"
+    }
+
+    fn generate(&self, params: &SimParams, rng: &mut StdRng) -> ParticleData {
+        let (center, half) = world_frame(params);
+        let arms = self.arms.max(1);
+
+        let mut data = ParticleData::with_capacity(params.n);
+        Disc {
+            center,
+            radius: self.radius * half,
+            rot_dir: 1.0,
+            core: color(255, 223, 150), // pale yellow bulge
+            rim: color(90, 140, 255),   // blue arms
+        }
+        .fill(params, rng, params.n, &mut data, |rng, r| {
+            // Arms trail the counter-clockwise rotation, so the angle decreases outwards
+            let arm = rng.random_range(0..arms) as f32;
+            arm * std::f32::consts::TAU / arms as f32 - self.winding * r
+                + self.spread * super::normal(rng)
+        });
+
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.arms, constants::scenario::SPIRAL_ARMS_RANGE)
+                    .text("Arms"),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.radius, constants::scenario::RADIUS_RANGE)
+                    .text("Disc Radius"),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.winding, constants::scenario::SPIRAL_WINDING_RANGE)
+                    .text("Winding"),
+            )
+            .on_hover_text("How tightly the arms wrap around the center")
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.spread, constants::scenario::SPIRAL_SPREAD_RANGE)
+                    .text("Arm Spread"),
+            )
+            .on_hover_text("Angular scatter of the particles around their arm")
+            .changed();
+        changed
+    }
+}
@@ -0,0 +1,266 @@
+use glam::Vec2;
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use super::{InitialCondition, circular_speed, color, gradient, normal, polar, world_frame};
+use crate::{
+    constants,
+    sim::{ParticleData, SimParams},
+};
+
+/// Particles spread uniformly over a square, with optional random velocities
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UniformBox {
+    pub half_size: f32,
+    pub dispersion: f32,
+}
+
+impl Default for UniformBox {
+    fn default() -> Self {
+        Self {
+            half_size: constants::scenario::BOX_HALF_SIZE,
+            dispersion: constants::scenario::BOX_DISPERSION,
+        }
+    }
+}
+
+impl InitialCondition for UniformBox {
+    fn id(&self) -> &'static str {
+        "uniform-box"
+    }
+
+    fn label(&self) -> &'static str {
+        "Uniform Box"
+    }
+
+    fn generate(&self, params: &SimParams, rng: &mut StdRng) -> ParticleData {
+        let (center, half) = world_frame(params);
+        let extent = self.half_size * half;
+
+        let left = color(70, 200, 160); // teal
+        let right = color(240, 200, 80); // amber
+
+        let mut data = ParticleData::with_capacity(params.n);
+        for _ in 0..params.n {
+            let t = Vec2::new(rng.random::<f32>(), rng.random::<f32>());
+            let p = center + (2.0 * t - Vec2::ONE) * extent;
+            let v = Vec2::new(normal(rng), normal(rng)) * self.dispersion;
+
+            data.push(
+                p,
+                v,
+                gradient(&left, &right, t.x),
+                constants::scenario::PARTICLE_MASS,
+            );
+        }
+
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.half_size, constants::scenario::RADIUS_RANGE)
+                    .text("Half Size"),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(
+                    &mut self.dispersion,
+                    constants::scenario::BOX_DISPERSION_RANGE,
+                )
+                .text("Velocity Dispersion"),
+            )
+            .on_hover_text("Standard deviation of each random velocity component")
+            .changed();
+        changed
+    }
+}
+
+/// Regular square grid at rest, optionally perturbed
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Lattice {
+    pub extent: f32,
+    pub jitter: f32,
+}
+
+impl Default for Lattice {
+    fn default() -> Self {
+        Self {
+            extent: constants::scenario::LATTICE_EXTENT,
+            jitter: constants::scenario::LATTICE_JITTER,
+        }
+    }
+}
+
+impl InitialCondition for Lattice {
+    fn id(&self) -> &'static str {
+        "lattice"
+    }
+
+    fn label(&self) -> &'static str {
+        "Lattice"
+    }
+
+    fn generate(&self, params: &SimParams, rng: &mut StdRng) -> ParticleData {
+        let (center, half) = world_frame(params);
+        let extent = self.extent * half;
+
+        let n = params.n;
+        let cols = (n as f32).sqrt().ceil().max(1.0) as u32;
+        let rows = n.div_ceil(cols).max(1);
+        let spacing = 2.0 * extent / cols.max(rows) as f32;
+        let origin = center - 0.5 * spacing * Vec2::new((cols - 1) as f32, (rows - 1) as f32);
+
+        let bottom = color(255, 90, 120); // pink
+        let top = color(80, 160, 255); // sky blue
+
+        let mut data = ParticleData::with_capacity(n);
+        for i in 0..n {
+            let (col, row) = (i % cols, i / cols);
+            let jitter = (Vec2::new(rng.random::<f32>(), rng.random::<f32>()) - 0.5)
+                * (2.0 * self.jitter * spacing);
+            let p = origin + Vec2::new(col as f32, row as f32) * spacing + jitter;
+
+            data.push(
+                p,
+                Vec2::ZERO,
+                gradient(&bottom, &top, row as f32 / rows as f32),
+                constants::scenario::PARTICLE_MASS,
+            );
+        }
+
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.extent, constants::scenario::RADIUS_RANGE)
+                    .text("Extent"),
+            )
+            .on_hover_text("Half size of the grid")
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.jitter, constants::scenario::LATTICE_JITTER_RANGE)
+                    .text("Jitter"),
+            )
+            .on_hover_text("Random offset of each site, as a fraction of the grid spacing")
+            .changed();
+        changed
+    }
+}
+
+/// Thin ring orbiting an optional central body
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Ring {
+    pub radius: f32,
+    pub width: f32,
+    pub central_mass: f32,
+    pub spin: f32,
+}
+
+impl Default for Ring {
+    fn default() -> Self {
+        Self {
+            radius: constants::scenario::RING_RADIUS,
+            width: constants::scenario::RING_WIDTH,
+            central_mass: constants::scenario::RING_CENTRAL_MASS,
+            spin: constants::scenario::RING_SPIN,
+        }
+    }
+}
+
+impl InitialCondition for Ring {
+    fn id(&self) -> &'static str {
+        "ring"
+    }
+
+    fn label(&self) -> &'static str {
+        "Ring"
+    }
+
+    fn generate(&self, params: &SimParams, rng: &mut StdRng) -> ParticleData {
+        let (center, half) = world_frame(params);
+        let radius = self.radius * half;
+        let width = self.width * half;
+
+        let n = params.n;
+        let mut data = ParticleData::with_capacity(n);
+
+        let mut ring_count = n;
+        let mut central_mass = 0.0;
+        if self.central_mass > 0.0 && n > 1 {
+            ring_count -= 1;
+            central_mass =
+                self.central_mass * ring_count as f32 * constants::scenario::PARTICLE_MASS;
+            data.push(center, Vec2::ZERO, [1.0, 1.0, 1.0, 1.0], central_mass);
+        }
+
+        // The ring orbits the central body plus its own mass, seen as a point at the center
+        let ring_mass = ring_count as f32 * constants::scenario::PARTICLE_MASS;
+
+        let inner = color(255, 150, 50); // orange
+        let outer = color(255, 60, 160); // magenta
+
+        for _ in 0..ring_count {
+            let offset = (rng.random::<f32>() - 0.5) * width;
+            let r = radius + offset;
+            let (dir, tangent) = polar(rng.random::<f32>() * std::f32::consts::TAU);
+            let v = tangent * self.spin * circular_speed(params, central_mass + ring_mass, r);
+
+            let t = if width > 0.0 {
+                offset / width + 0.5
+            } else {
+                0.5
+            };
+            data.push(
+                center + dir * r,
+                v,
+                gradient(&inner, &outer, t),
+                constants::scenario::PARTICLE_MASS,
+            );
+        }
+
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.radius, constants::scenario::RADIUS_RANGE)
+                    .text("Radius"),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.width, constants::scenario::RING_WIDTH_RANGE)
+                    .text("Width"),
+            )
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(
+                    &mut self.central_mass,
+                    constants::scenario::RING_CENTRAL_MASS_RANGE,
+                )
+                .text("Central Mass"),
+            )
+            .on_hover_text("Mass of the central body relative to the whole ring, 0 removes it")
+            .changed();
+        changed |= ui
+            .add(egui::Slider::new(&mut self.spin, constants::scenario::SPIN_RANGE).text("Spin"))
+            .on_hover_text("Orbital speed as a fraction of the circular speed")
+            .changed();
+        changed
+    }
+}
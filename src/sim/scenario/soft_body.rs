@@ -0,0 +1,217 @@
+use glam::Vec2;
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+
+use super::{InitialCondition, color, gradient, world_frame};
+use crate::{
+    constants,
+    sim::{ParticleData, SimParams},
+};
+
+/// Stiffness and damping sliders shared by the bonded scenarios
+fn bond_ui(ui: &mut egui::Ui, stiffness: &mut f32, damping: &mut f32) -> bool {
+    let mut changed = false;
+    changed |= ui
+        .add(
+            egui::Slider::new(stiffness, constants::scenario::BOND_STIFFNESS_RANGE)
+                .text("Stiffness")
+                .logarithmic(true),
+        )
+        .on_hover_text("Spring force per unit of stretch. Stiffer bonds oscillate faster and need a smaller time step")
+        .changed();
+    changed |= ui
+        .add(egui::Slider::new(damping, constants::scenario::BOND_DAMPING_RANGE).text("Damping"))
+        .on_hover_text("Spring force per unit of stretching speed, calms the oscillations")
+        .changed();
+    changed
+}
+
+/// Square sheet of particles held together by springs to their grid neighbors
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Cloth {
+    pub size: f32,
+    pub stiffness: f32,
+    pub damping: f32,
+    /// Bond the diagonal neighbors as well, so the sheet resists shearing
+    pub shear: bool,
+}
+
+impl Default for Cloth {
+    fn default() -> Self {
+        Self {
+            size: constants::scenario::CLOTH_SIZE,
+            stiffness: constants::scenario::BOND_STIFFNESS,
+            damping: constants::scenario::BOND_DAMPING,
+            shear: constants::scenario::CLOTH_SHEAR,
+        }
+    }
+}
+
+impl InitialCondition for Cloth {
+    fn id(&self) -> &'static str {
+        "cloth"
+    }
+
+    fn label(&self) -> &'static str {
+        "Cloth"
+    }
+
+    fn generate(&self, params: &SimParams, _rng: &mut StdRng) -> ParticleData {
+        let (center, half) = world_frame(params);
+        let extent = self.size * half;
+
+        // The last row is left incomplete when n is not a square
+        let n = params.n;
+        let cols = (n as f32).sqrt().ceil().max(2.0) as u32;
+        let rows = n.div_ceil(cols).max(1);
+        let spacing = 2.0 * extent / (cols - 1) as f32;
+        let origin = center - 0.5 * spacing * Vec2::new((cols - 1) as f32, (rows - 1) as f32);
+
+        let bottom = color(120, 90, 255); // violet
+        let top = color(90, 230, 200); // mint
+
+        let mut data = ParticleData::with_capacity(n);
+        for i in 0..n {
+            let (col, row) = (i % cols, i / cols);
+            data.push(
+                origin + Vec2::new(col as f32, row as f32) * spacing,
+                Vec2::ZERO,
+                gradient(&bottom, &top, row as f32 / rows as f32),
+                constants::scenario::PARTICLE_MASS,
+            );
+        }
+
+        for i in 0..n {
+            let (col, row) = (i % cols, i / cols);
+            let right = col + 1 < cols;
+            let mut neighbors = Vec::with_capacity(4);
+            if right {
+                neighbors.push(i + 1);
+            }
+            neighbors.push(i + cols);
+            if self.shear {
+                if right {
+                    neighbors.push(i + cols + 1);
+                }
+                if col > 0 && row + 1 < rows {
+                    neighbors.push(i + cols - 1);
+                }
+            }
+            for j in neighbors.into_iter().filter(|&j| j < n) {
+                data.bond(i, j, self.stiffness, self.damping);
+            }
+        }
+
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.size, constants::scenario::RADIUS_RANGE)
+                    .text("Half Size"),
+            )
+            .changed();
+        changed |= bond_ui(ui, &mut self.stiffness, &mut self.damping);
+        changed |= ui
+            .checkbox(&mut self.shear, "Shear Bonds")
+            .on_hover_text("Also bond the diagonal neighbors, so the sheet keeps its shape")
+            .changed();
+        changed
+    }
+}
+
+/// Parallel chains of particles, launched with a transverse wave
+#[derive(Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rope {
+    pub ropes: u32,
+    pub length: f32,
+    pub stiffness: f32,
+    pub damping: f32,
+    pub wave: f32,
+}
+
+impl Default for Rope {
+    fn default() -> Self {
+        Self {
+            ropes: constants::scenario::ROPES,
+            length: constants::scenario::ROPE_LENGTH,
+            stiffness: constants::scenario::BOND_STIFFNESS,
+            damping: constants::scenario::BOND_DAMPING,
+            wave: constants::scenario::ROPE_WAVE,
+        }
+    }
+}
+
+impl InitialCondition for Rope {
+    fn id(&self) -> &'static str {
+        "rope"
+    }
+
+    fn label(&self) -> &'static str {
+        "Ropes"
+    }
+
+    fn generate(&self, params: &SimParams, _rng: &mut StdRng) -> ParticleData {
+        let (center, half) = world_frame(params);
+        let extent = self.length * half;
+
+        // The first ropes take one more particle when n does not divide evenly
+        let n = params.n;
+        let ropes = self.ropes.clamp(1, n);
+        let gap = 2.0 * extent / ropes as f32;
+
+        let start = color(255, 120, 60); // coral
+        let end = color(250, 230, 120); // pale yellow
+
+        let mut data = ParticleData::with_capacity(n);
+        for rope in 0..ropes {
+            let count = n / ropes + u32::from(rope < n % ropes);
+            let first = data.positions.len() as u32;
+            let y = center.y - extent + (rope as f32 + 0.5) * gap;
+            let spacing = 2.0 * extent / count.saturating_sub(1).max(1) as f32;
+
+            for i in 0..count {
+                // Fundamental mode, the ends stay still
+                let t = i as f32 / count.saturating_sub(1).max(1) as f32;
+                let v = Vec2::new(0.0, self.wave * (std::f32::consts::PI * t).sin());
+                data.push(
+                    Vec2::new(center.x - extent + i as f32 * spacing, y),
+                    v,
+                    gradient(&start, &end, rope as f32 / ropes as f32),
+                    constants::scenario::PARTICLE_MASS,
+                );
+            }
+            for i in first + 1..first + count {
+                data.bond(i - 1, i, self.stiffness, self.damping);
+            }
+        }
+
+        data
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui) -> bool {
+        let mut changed = false;
+        changed |= ui
+            .add(egui::Slider::new(&mut self.ropes, constants::scenario::ROPES_RANGE).text("Ropes"))
+            .changed();
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.length, constants::scenario::RADIUS_RANGE)
+                    .text("Half Length"),
+            )
+            .changed();
+        changed |= bond_ui(ui, &mut self.stiffness, &mut self.damping);
+        changed |= ui
+            .add(
+                egui::Slider::new(&mut self.wave, constants::scenario::ROPE_WAVE_RANGE)
+                    .text("Wave"),
+            )
+            .on_hover_text("Initial transverse speed at the middle of each rope")
+            .changed();
+        changed
+    }
+}
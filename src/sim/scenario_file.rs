@@ -0,0 +1,501 @@
+//! Scenario files: parameters, initial conditions, colors and view in one TOML file
+//!
+//! ```toml
+//! [sim]
+//! n = 50000
+//! solver = "barnes-hut"
+//!
+//! [scenario]
+//! id = "plummer"
+//! scale = 0.2
+//!
+//! [colors]
+//! scheme = "solid"
+//! solid = [1.0, 0.85, 0.6]
+//!
+//! [charges]
+//! scheme = "alternating"
+//! magnitude = 1.0
+//!
+//! [fluid]
+//! enabled = true
+//! smoothing = 0.025
+//!
+//! [camera]
+//! center = [0.0, 0.0]
+//! zoom = 1.5
+//! ```
+//!
+//! Every section and key is optional, absent parameters keep their current value and
+//! absent generator parameters their default. [`FileWatcher`] polls the modification
+//! time of a file so that edits are applied while the simulation runs.
+
+use std::{
+    path::{Path, PathBuf},
+    time::{Instant, SystemTime},
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    ParamsEguiAction, ParamsOverrides, SimParams, check_range,
+    fluid::Fluid,
+    scenario::{ChargeScheme, Scenarios},
+};
+use crate::constants;
+
+/// How the particles are colored
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ColorScheme {
+    /// Colors chosen by the generator
+    #[default]
+    Scenario,
+    /// Colored by speed on the GPU
+    Speed,
+    /// Every particle in the `solid` color
+    Solid,
+    /// Colored by the sign and size of the charges when drawn
+    Charge,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Colors {
+    pub scheme: ColorScheme,
+    /// Linear RGB in [0, 1], used by [`ColorScheme::Solid`]
+    pub solid: [f32; 3],
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            scheme: ColorScheme::default(),
+            solid: constants::scenario::SOLID_COLOR,
+        }
+    }
+}
+
+impl Colors {
+    pub fn from_state(params: &SimParams, scenarios: &Scenarios) -> Self {
+        // The charge colors are drawn over any other
+        if params.color_by_charge {
+            return Self {
+                scheme: ColorScheme::Charge,
+                ..Self::default()
+            };
+        }
+        match (params.color_by_speed, scenarios.solid_color) {
+            (true, _) => Self {
+                scheme: ColorScheme::Speed,
+                ..Self::default()
+            },
+            (false, Some(solid)) => Self {
+                scheme: ColorScheme::Solid,
+                solid,
+            },
+            (false, None) => Self::default(),
+        }
+    }
+
+    pub fn apply(&self, params: &mut SimParams, scenarios: &mut Scenarios) {
+        params.color_by_speed = self.scheme == ColorScheme::Speed;
+        params.color_by_charge = self.scheme == ColorScheme::Charge;
+        scenarios.solid_color = (self.scheme == ColorScheme::Solid).then_some(self.solid);
+    }
+}
+
+/// Charges handed out to the generated particles, see [`ChargeScheme`]
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Charges {
+    pub scheme: ChargeScheme,
+    pub magnitude: f32,
+}
+
+impl Default for Charges {
+    fn default() -> Self {
+        Self {
+            scheme: ChargeScheme::default(),
+            magnitude: constants::scenario::CHARGE,
+        }
+    }
+}
+
+impl Charges {
+    pub fn from_state(scenarios: &Scenarios) -> Self {
+        Self {
+            scheme: scenarios.charges,
+            magnitude: scenarios.charge,
+        }
+    }
+
+    pub fn apply(&self, scenarios: &mut Scenarios) {
+        scenarios.charges = self.scheme;
+        scenarios.charge = self.magnitude;
+    }
+}
+
+/// Camera position, see `gpu::Camera`
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraView {
+    /// World position at the center of the window
+    pub center: [f32; 2],
+    /// Magnification relative to the whole world
+    pub zoom: f32,
+}
+
+impl Default for CameraView {
+    fn default() -> Self {
+        Self {
+            center: [0.0; 2],
+            zoom: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScenarioFile {
+    pub sim: ParamsOverrides,
+    /// Generator `id` and its parameters
+    pub scenario: Option<toml::Table>,
+    pub colors: Option<Colors>,
+    pub charges: Option<Charges>,
+    /// Smoothed particle hydrodynamics, see [`Fluid`]
+    pub fluid: Option<Fluid>,
+    pub camera: Option<CameraView>,
+}
+
+impl ScenarioFile {
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let file: Self = toml::from_str(text)?;
+        file.validate()?;
+        Ok(file)
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read scenario file '{}'", path.display()))?;
+        Self::parse(&text).with_context(|| format!("Invalid scenario file '{}'", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let text = toml::to_string(self)?;
+        std::fs::write(path, text)
+            .with_context(|| format!("Failed to write scenario file '{}'", path.display()))
+    }
+
+    /// Describe the current state, every section filled in
+    pub fn from_state(
+        params: &SimParams,
+        scenarios: &Scenarios,
+        camera: CameraView,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            sim: ParamsOverrides {
+                // stored in `colors`
+                color_by_speed: None,
+                color_by_charge: None,
+                ..ParamsOverrides::from(params)
+            },
+            scenario: Some(scenarios.to_table()?),
+            colors: Some(Colors::from_state(params, scenarios)),
+            charges: Some(Charges::from_state(scenarios)),
+            fluid: Some(params.fluid.clone()),
+            camera: Some(camera),
+        })
+    }
+
+    /// Check the values against the ranges of the UI, generator parameters are
+    /// checked when they are applied
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.sim.validate()?;
+        if let Some(colors) = &self.colors
+            && let Some(channel) = colors.solid.iter().find(|c| !(0.0..=1.0).contains(*c))
+        {
+            anyhow::bail!("Solid color channels must be between 0 and 1, got {channel}");
+        }
+        if let Some(charges) = &self.charges {
+            check_range(
+                "Charge magnitude",
+                charges.magnitude,
+                &constants::scenario::CHARGE_RANGE,
+            )?;
+        }
+        if let Some(fluid) = &self.fluid {
+            fluid.validate()?;
+        }
+        if let Some(camera) = &self.camera {
+            check_range("Camera zoom", camera.zoom, &constants::camera::ZOOM_RANGE)?;
+        }
+        Ok(())
+    }
+
+    /// Put the command-line values on top of the file
+    pub fn override_with(&mut self, overrides: &ParamsOverrides, scenario: Option<&str>) {
+        self.sim.extend(overrides);
+        if let Some(id) = scenario {
+            let mut table = toml::Table::new();
+            table.insert("id".to_string(), id.into());
+            self.scenario = Some(table);
+        }
+    }
+
+    /// Whether going from `previous` to this file needs new particles
+    pub fn regenerates(&self, previous: &ScenarioFile) -> bool {
+        self.sim.n != previous.sim.n
+            || self.sim.seed != previous.sim.seed
+            || self.scenario != previous.scenario
+            || self.colors != previous.colors
+            || self.charges != previous.charges
+            // The dam break sets the particle masses from the rest density
+            || self.fluid.as_ref().map(|fluid| fluid.rest_density)
+                != previous.fluid.as_ref().map(|fluid| fluid.rest_density)
+    }
+
+    /// Whether the particles generated from the parameters alone must be regenerated
+    pub fn customizes_particles(&self) -> bool {
+        self.scenario.is_some() || self.colors.is_some() || self.charges.is_some()
+    }
+}
+
+/// Reloads a scenario file whenever its modification time changes
+pub struct FileWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_poll: Instant,
+    /// Last successfully loaded content
+    current: ScenarioFile,
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl FileWatcher {
+    /// Watch `path`, whose content `current` was just applied
+    pub fn new(path: PathBuf, current: ScenarioFile) -> Self {
+        Self {
+            modified: modified(&path),
+            path,
+            last_poll: Instant::now(),
+            current,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The new content if the file changed since the last poll, with whether the
+    /// particles must be regenerated
+    ///
+    /// The file is checked at most every `constants::scenario_file::POLL_INTERVAL`.
+    /// A file that fails to load is reported once, and retried at the next change.
+    pub fn poll(&mut self) -> Option<anyhow::Result<(ScenarioFile, bool)>> {
+        if self.last_poll.elapsed() < constants::scenario_file::POLL_INTERVAL {
+            return None;
+        }
+        self.last_poll = Instant::now();
+
+        // Editors may briefly remove the file while saving, wait for it to come back
+        let modified = modified(&self.path)?;
+        if self.modified == Some(modified) {
+            return None;
+        }
+        self.modified = Some(modified);
+
+        Some(ScenarioFile::load(&self.path).map(|file| {
+            let regenerate = file.regenerates(&self.current);
+            self.current = file.clone();
+            (file, regenerate)
+        }))
+    }
+}
+
+/// `.toml` files of the scenario directory, sorted by name
+fn list_files() -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(constants::scenario_file::DIRECTORY) else {
+        return Vec::new();
+    };
+    let mut files: Vec<String> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .map(|path| path.display().to_string())
+        .collect();
+    files.sort();
+    files
+}
+
+/// File picker, load/save buttons and the watch toggle
+///
+/// `watched` is the file being watched for changes, if any.
+pub fn render_controls(
+    ui: &mut egui::Ui,
+    path: &mut String,
+    watch: &mut bool,
+    watched: Option<&Path>,
+) -> ParamsEguiAction {
+    let mut action = ParamsEguiAction::None;
+
+    ui.heading("Scenario File");
+    ui.horizontal(|ui| {
+        ui.label("File");
+        ui.text_edit_singleline(path)
+            .on_hover_text("TOML scenario file, relative to the working directory");
+        egui::ComboBox::from_id_salt("scenario_file_picker")
+            .selected_text("Browse")
+            .show_ui(ui, |ui| {
+                let files = list_files();
+                if files.is_empty() {
+                    ui.weak(format!(
+                        "No .toml file in '{}'",
+                        constants::scenario_file::DIRECTORY
+                    ));
+                }
+                for file in files {
+                    if ui.selectable_label(*path == file, &file).clicked() {
+                        *path = file;
+                        action = ParamsEguiAction::LoadScenarioFile;
+                    }
+                }
+            });
+    });
+
+    ui.horizontal(|ui| {
+        if ui
+            .button("Load")
+            .on_hover_text("Apply the parameters, scenario, colors and view of the file")
+            .clicked()
+        {
+            action = ParamsEguiAction::LoadScenarioFile;
+        }
+        if ui
+            .button("Save")
+            .on_hover_text("Write the current parameters, scenario, colors and view to the file")
+            .clicked()
+        {
+            action = ParamsEguiAction::SaveScenarioFile;
+        }
+        if ui
+            .checkbox(watch, "Watch")
+            .on_hover_text("Reapply the loaded file whenever it is saved")
+            .changed()
+        {
+            action = ParamsEguiAction::WatchScenarioFile;
+        }
+    });
+    if let Some(watched) = watched {
+        ui.weak(format!("Watching '{}'", watched.display()));
+    }
+
+    action
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::{integrator::Integrator, timestep::TimestepMode};
+
+    #[test]
+    fn scenarios_round_trip_through_toml() {
+        let params = SimParams {
+            n: *constants::sim::INITIAL_PARTICLES_RANGE.start(),
+            seed: u64::MAX, // does not fit a TOML integer
+            substeps: 4,
+            timestep: TimestepMode::Velocity,
+            integrator: Integrator::Rk4,
+            ..SimParams::default()
+        };
+        let camera = CameraView {
+            center: [0.25, -0.5],
+            zoom: 2.0,
+        };
+
+        let mut scenarios = Scenarios::default();
+        scenarios.solid_color = Some([0.5, 0.25, 1.0]);
+        scenarios.charges = ChargeScheme::Random;
+        scenarios.charge = 2.5;
+        for id in scenarios.ids() {
+            scenarios.select(id).unwrap();
+            let file = ScenarioFile::from_state(&params, &scenarios, camera).unwrap();
+            let text = toml::to_string(&file).unwrap();
+            let parsed = ScenarioFile::parse(&text)
+                .unwrap_or_else(|err| panic!("Scenario '{id}' does not parse back: {err:#}"));
+            assert_eq!(
+                parsed, file,
+                "Scenario '{id}' changed through TOML:\n{text}"
+            );
+
+            let mut restored = Scenarios::default();
+            restored
+                .configure(parsed.scenario.as_ref().expect("scenario section"))
+                .unwrap();
+            if let Some(colors) = &parsed.colors {
+                colors.apply(&mut params.clone(), &mut restored);
+            }
+            if let Some(charges) = &parsed.charges {
+                charges.apply(&mut restored);
+            }
+            assert!(
+                scenarios.generate(&params) == restored.generate(&params),
+                "Scenario '{id}' generates other particles once loaded from TOML"
+            );
+        }
+    }
+
+    #[test]
+    fn example_files_load() {
+        let directory =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join(constants::scenario_file::DIRECTORY);
+        let mut loaded = 0;
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|ext| ext == "toml") {
+                let file = ScenarioFile::load(&path).unwrap();
+                if let Some(table) = &file.scenario {
+                    Scenarios::default()
+                        .configure(table)
+                        .unwrap_or_else(|err| panic!("'{}': {err:#}", path.display()));
+                }
+                loaded += 1;
+            }
+        }
+        assert!(loaded > 0);
+    }
+
+    #[test]
+    fn invalid_files_are_rejected() {
+        for invalid in [
+            "[sim]\ndt = 1.0",
+            "[sim]\nparticles = 1000",
+            "[sim]\nsubsteps = 0",
+            "[sim]\ntimestep = \"variable\"",
+            "[sim]\nintegrator = \"euler\"",
+            "[camera]\nzoom = 0.0",
+            "[colors]\nscheme = \"rainbow\"",
+            "[charges]\nscheme = \"positive\"",
+            "[charges]\nmagnitude = -1.0",
+            "[sim]\ninteraction = \"magnetic\"",
+            "[sim]\nmerge_radius = 0.0",
+            "[fluid]\nsmoothing = 0.0",
+            "[fluid]\nkernel = \"gaussian\"",
+        ] {
+            assert!(
+                ScenarioFile::parse(invalid).is_err(),
+                "Invalid scenario file was accepted:\n{invalid}"
+            );
+        }
+        for invalid in ["radius = 0.5", "id = \"plummer\"\nradius = 0.5"] {
+            assert!(
+                Scenarios::default()
+                    .configure(&toml::from_str(invalid).unwrap())
+                    .is_err(),
+                "Invalid scenario section was accepted:\n{invalid}"
+            );
+        }
+    }
+}
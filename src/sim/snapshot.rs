@@ -0,0 +1,542 @@
+//! Versioned binary snapshots of the full simulation state
+//!
+//! Layout (all values little-endian):
+//!
+//! | field         | encoding                                           |
+//! |---------------|----------------------------------------------------|
+//! | magic         | `MAGIC` (8 bytes)                                  |
+//! | version       | u32                                                |
+//! | params        | see [`write_params`]                               |
+//! | epoch         | u128                                               |
+//! | buffer in use | u32 (0 = primary, 1 = secondary)                   |
+//! | step state    | 4 x f32, see [`StepState`]                         |
+//! | positions     | n x 2 x f32                                        |
+//! | velocities    | n x 2 x f32                                        |
+//! | colors        | n x 4 x f32                                        |
+//! | masses        | n x f32                                            |
+//! | charges       | n x f32                                            |
+//! | bond count    | u32                                                |
+//! | bonds         | count x (i, j as u32, rest, stiffness, damping)    |
+
+use std::path::Path;
+
+use anyhow::Context;
+use glam::Vec2;
+
+use super::{
+    Interaction, ParamsEguiAction, ParamsOverrides, ParticleData, SimParams, Solver,
+    bonds::Bond,
+    brush::Brush,
+    check_range,
+    fluid::{EquationOfState, Fluid, Kernel},
+    integrator::Integrator,
+    timestep::{StepState, TimestepMode},
+};
+use crate::{constants, gpu::BufferInUse};
+
+const MAGIC: [u8; 8] = *b"PPSNAP\0\0";
+/// Format version, bumped on every layout change
+const VERSION: u32 = 1;
+
+/// Everything needed to resume a simulation exactly where it was saved
+#[derive(PartialEq)]
+pub struct Snapshot {
+    /// Parameters, including the epoch
+    pub params: SimParams,
+    /// Ping-pong state, so the resumed run uses the same buffer parity
+    pub buffer_in_use: BufferInUse,
+    /// Simulated time and the length of the steps around the saved state
+    pub clock: StepState,
+    /// Latest particle state (`params.n` entries)
+    pub particles: ParticleData,
+}
+
+/// Sequential little-endian reader over the snapshot bytes
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        if self.bytes.len() < N {
+            anyhow::bail!("Snapshot is truncated");
+        }
+        let (head, tail) = self.bytes.split_at(N);
+        self.bytes = tail;
+        Ok(head.try_into().expect("split at N"))
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    fn bool(&mut self) -> anyhow::Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            other => anyhow::bail!("Invalid boolean {other} in snapshot"),
+        }
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    fn u128(&mut self) -> anyhow::Result<u128> {
+        Ok(u128::from_le_bytes(self.take()?))
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    fn f32s<const N: usize>(&mut self, count: u32) -> anyhow::Result<Vec<[f32; N]>> {
+        (0..count)
+            .map(|_| {
+                let mut value = [0.0; N];
+                for v in &mut value {
+                    *v = self.f32()?;
+                }
+                Ok(value)
+            })
+            .collect()
+    }
+
+    fn scalars(&mut self, count: u32) -> anyhow::Result<Vec<f32>> {
+        Ok(self.f32s::<1>(count)?.into_iter().map(|[x]| x).collect())
+    }
+
+    /// Bonds between `n` particles, rejecting unknown particles
+    fn bonds(&mut self, n: u32) -> anyhow::Result<Vec<Bond>> {
+        let count = self.u32()?;
+        (0..count)
+            .map(|_| {
+                let bond = Bond {
+                    i: self.u32()?,
+                    j: self.u32()?,
+                    rest: self.f32()?,
+                    stiffness: self.f32()?,
+                    damping: self.f32()?,
+                };
+                if bond.i >= n || bond.j >= n {
+                    anyhow::bail!(
+                        "Bond between particles {} and {} out of {n} in snapshot",
+                        bond.i,
+                        bond.j
+                    );
+                }
+                Ok(bond)
+            })
+            .collect()
+    }
+}
+
+fn write_f32s<const N: usize>(out: &mut Vec<u8>, values: &[[f32; N]]) {
+    for value in values {
+        for v in value {
+            out.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+}
+
+fn solver_from_id(id: u32) -> anyhow::Result<Solver> {
+    Solver::ALL
+        .into_iter()
+        .find(|solver| *solver as u32 == id)
+        .ok_or_else(|| anyhow::anyhow!("Unknown solver id {id} in snapshot"))
+}
+
+fn integrator_from_id(id: u32) -> anyhow::Result<Integrator> {
+    Integrator::ALL
+        .into_iter()
+        .find(|integrator| *integrator as u32 == id)
+        .ok_or_else(|| anyhow::anyhow!("Unknown integrator {id} in snapshot"))
+}
+
+fn interaction_from_id(id: u32) -> anyhow::Result<Interaction> {
+    Interaction::ALL
+        .into_iter()
+        .find(|interaction| *interaction as u32 == id)
+        .ok_or_else(|| anyhow::anyhow!("Unknown interaction {id} in snapshot"))
+}
+
+fn kernel_from_id(id: u32) -> anyhow::Result<Kernel> {
+    Kernel::ALL
+        .into_iter()
+        .find(|kernel| *kernel as u32 == id)
+        .ok_or_else(|| anyhow::anyhow!("Unknown fluid kernel {id} in snapshot"))
+}
+
+fn equation_of_state_from_id(id: u32) -> anyhow::Result<EquationOfState> {
+    EquationOfState::ALL
+        .into_iter()
+        .find(|equation| *equation as u32 == id)
+        .ok_or_else(|| anyhow::anyhow!("Unknown equation of state {id} in snapshot"))
+}
+
+fn timestep_from_id(id: u32) -> anyhow::Result<TimestepMode> {
+    TimestepMode::ALL
+        .into_iter()
+        .find(|mode| *mode as u32 == id)
+        .ok_or_else(|| anyhow::anyhow!("Unknown time stepping mode {id} in snapshot"))
+}
+
+/// Parameters, the epoch is stored separately
+fn write_params(out: &mut Vec<u8>, params: &SimParams) {
+    out.extend_from_slice(&params.dt.to_le_bytes());
+    out.extend_from_slice(&params.g.to_le_bytes());
+    out.extend_from_slice(&params.softening.to_le_bytes());
+    out.extend_from_slice(&params.n.to_le_bytes());
+    write_f32s(
+        out,
+        &[params.world[0].to_array(), params.world[1].to_array()],
+    );
+    out.extend_from_slice(&params.damping.to_le_bytes());
+    out.push(params.wrap as u8);
+    out.extend_from_slice(&(params.solver as u32).to_le_bytes());
+    out.extend_from_slice(&params.theta.to_le_bytes());
+    out.extend_from_slice(&params.pm_grid.to_le_bytes());
+    out.push(params.paused as u8);
+    out.push(params.color_by_speed as u8);
+    out.push(params.bootstrap as u8);
+    out.extend_from_slice(&params.seed.to_le_bytes());
+    out.extend_from_slice(&params.substeps.to_le_bytes());
+    out.extend_from_slice(&(params.timestep as u32).to_le_bytes());
+    out.extend_from_slice(&params.accuracy.to_le_bytes());
+    out.extend_from_slice(&(params.integrator as u32).to_le_bytes());
+    out.extend_from_slice(&(params.interaction as u32).to_le_bytes());
+    out.extend_from_slice(&params.coulomb_k.to_le_bytes());
+    out.extend_from_slice(&params.coulomb_softening.to_le_bytes());
+    out.push(params.color_by_charge as u8);
+    out.push(params.show_bonds as u8);
+    out.push(params.collisions as u8);
+    out.extend_from_slice(&params.merge_radius.to_le_bytes());
+    let fluid = &params.fluid;
+    out.push(fluid.enabled as u8);
+    out.extend_from_slice(&(fluid.kernel as u32).to_le_bytes());
+    out.extend_from_slice(&fluid.smoothing.to_le_bytes());
+    out.extend_from_slice(&fluid.rest_density.to_le_bytes());
+    out.extend_from_slice(&fluid.sound_speed.to_le_bytes());
+    out.extend_from_slice(&(fluid.equation_of_state as u32).to_le_bytes());
+    out.extend_from_slice(&fluid.gamma.to_le_bytes());
+    out.extend_from_slice(&fluid.viscosity.to_le_bytes());
+    out.extend_from_slice(&fluid.gravity.to_le_bytes());
+    out.push(fluid.self_gravity as u8);
+}
+
+fn read_params(reader: &mut Reader) -> anyhow::Result<SimParams> {
+    let dt = reader.f32()?;
+    let g = reader.f32()?;
+    let softening = reader.f32()?;
+    let n = reader.u32()?;
+    let world = reader.f32s::<2>(2)?;
+    let damping = reader.f32()?;
+    let wrap = reader.bool()?;
+    let solver = solver_from_id(reader.u32()?)?;
+    let theta = reader.f32()?;
+    let pm_grid = reader.u32()?;
+    let paused = reader.bool()?;
+    let color_by_speed = reader.bool()?;
+    let bootstrap = reader.bool()?;
+    let seed = reader.u64()?;
+    let substeps = reader.u32()?;
+    let timestep = timestep_from_id(reader.u32()?)?;
+    let accuracy = reader.f32()?;
+    let integrator = integrator_from_id(reader.u32()?)?;
+    let interaction = interaction_from_id(reader.u32()?)?;
+    let coulomb_k = reader.f32()?;
+    let coulomb_softening = reader.f32()?;
+    let color_by_charge = reader.bool()?;
+    let show_bonds = reader.bool()?;
+    let collisions = reader.bool()?;
+    let merge_radius = reader.f32()?;
+    let fluid = Fluid {
+        enabled: reader.bool()?,
+        kernel: kernel_from_id(reader.u32()?)?,
+        smoothing: reader.f32()?,
+        rest_density: reader.f32()?,
+        sound_speed: reader.f32()?,
+        equation_of_state: equation_of_state_from_id(reader.u32()?)?,
+        gamma: reader.f32()?,
+        viscosity: reader.f32()?,
+        gravity: reader.f32()?,
+        self_gravity: reader.bool()?,
+    };
+    fluid
+        .validate()
+        .context("Invalid fluid parameters in snapshot")?;
+
+    let params = SimParams {
+        dt,
+        substeps,
+        timestep,
+        accuracy,
+        integrator,
+        g,
+        softening,
+        interaction,
+        coulomb_k,
+        coulomb_softening,
+        n,
+        world: [Vec2::from(world[0]), Vec2::from(world[1])],
+        damping,
+        wrap,
+        solver,
+        theta,
+        pm_grid,
+        seed,
+        paused,
+        color_by_speed,
+        color_by_charge,
+        show_bonds,
+        collisions,
+        merge_radius,
+        brush: Brush::default(),
+        fluid,
+        bootstrap,
+        epoch: 0,
+    };
+
+    // Same checks as the command line and the scenario files, except that spawning and
+    // erasing take the particle count out of the initial range
+    check_range("n", n, &(1..=constants::sim::MAX_PARTICLES))
+        .context("Invalid particle count in snapshot")?;
+    ParamsOverrides {
+        n: None,
+        ..ParamsOverrides::from(&params)
+    }
+    .validate()
+    .context("Invalid parameters in snapshot")?;
+    let [min, max] = params.world;
+    if !(min.is_finite() && max.is_finite() && min.cmplt(max).all()) {
+        anyhow::bail!("Invalid world bounds {min} to {max} in snapshot");
+    }
+
+    Ok(params)
+}
+
+impl Snapshot {
+    /// Serialize into the versioned binary format
+    pub fn encode(&self) -> Vec<u8> {
+        let n = self.params.n as usize;
+        let mut out = Vec::with_capacity(128 + n * std::mem::size_of::<f32>() * 10);
+
+        out.extend_from_slice(&MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        write_params(&mut out, &self.params);
+        out.extend_from_slice(&self.params.epoch.to_le_bytes());
+        out.extend_from_slice(&(self.buffer_in_use as u32).to_le_bytes());
+        let clock = self.clock;
+        write_f32s(
+            &mut out,
+            &[[clock.prev_dt, clock.dt, clock.time, clock.time_error]],
+        );
+
+        write_f32s(&mut out, &self.particles.positions);
+        write_f32s(&mut out, &self.particles.velocities);
+        write_f32s(&mut out, &self.particles.colors);
+        for value in self.particles.masses.iter().chain(&self.particles.charges) {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&(self.particles.bonds.len() as u32).to_le_bytes());
+        for bond in &self.particles.bonds {
+            out.extend_from_slice(&bond.i.to_le_bytes());
+            out.extend_from_slice(&bond.j.to_le_bytes());
+            for value in [bond.rest, bond.stiffness, bond.damping] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+
+        out
+    }
+
+    /// Parse a snapshot of the current version, rejecting inconsistent data and
+    /// parameters outside the ranges of the UI
+    pub fn decode(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader { bytes };
+
+        if reader.take::<8>()? != MAGIC {
+            anyhow::bail!("Not a particle playground snapshot");
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            anyhow::bail!("Unsupported snapshot version {version} (expected {VERSION})");
+        }
+
+        let mut params = read_params(&mut reader)?;
+        params.epoch = reader.u128()?;
+        let buffer_in_use = match reader.u32()? {
+            0 => BufferInUse::Primary,
+            1 => BufferInUse::Secondary,
+            other => anyhow::bail!("Invalid buffer in use {other} in snapshot"),
+        };
+        let [prev_dt, dt, time, time_error] = reader.f32s::<4>(1)?[0];
+        let clock = StepState {
+            prev_dt,
+            dt,
+            time,
+            time_error,
+            ..StepState::default()
+        };
+
+        let n = params.n;
+        let particles = ParticleData {
+            positions: reader.f32s(n)?,
+            velocities: reader.f32s(n)?,
+            colors: reader.f32s(n)?,
+            masses: reader.scalars(n)?,
+            charges: reader.scalars(n)?,
+            bonds: reader.bonds(n)?,
+        };
+
+        if !reader.bytes.is_empty() {
+            anyhow::bail!(
+                "Snapshot has {} unexpected trailing bytes",
+                reader.bytes.len()
+            );
+        }
+
+        Ok(Self {
+            params,
+            buffer_in_use,
+            clock,
+            particles,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, self.encode())
+            .with_context(|| format!("Failed to write snapshot '{}'", path.display()))
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("Failed to read snapshot '{}'", path.display()))?;
+        Self::decode(&bytes).with_context(|| format!("Invalid snapshot '{}'", path.display()))
+    }
+}
+
+/// Snapshot path field with save and load buttons
+pub fn render_controls(ui: &mut egui::Ui, path: &mut String) -> ParamsEguiAction {
+    let mut action = ParamsEguiAction::None;
+
+    ui.heading("Snapshot");
+    ui.horizontal(|ui| {
+        ui.label("File");
+        ui.text_edit_singleline(path)
+            .on_hover_text("Snapshot file, relative to the working directory");
+    });
+
+    ui.horizontal(|ui| {
+        if ui
+            .button("Save Snapshot")
+            .on_hover_text("Write parameters, epoch and every particle to the file")
+            .clicked()
+        {
+            action = ParamsEguiAction::SaveSnapshot;
+        }
+        if ui
+            .button("Load Snapshot")
+            .on_hover_text("Resume exactly from the state stored in the file")
+            .clicked()
+        {
+            action = ParamsEguiAction::LoadSnapshot;
+        }
+    });
+
+    action
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::scenario::{ChargeScheme, Scenarios};
+
+    /// A bonded state with random charges, saved mid-run with an adaptive time step
+    fn snapshot() -> Snapshot {
+        let params = SimParams {
+            n: 512,
+            seed: 1,
+            epoch: 123,
+            timestep: TimestepMode::Acceleration,
+            integrator: Integrator::Yoshida4,
+            ..SimParams::default()
+        };
+        let mut scenarios = Scenarios::default();
+        scenarios.select("cloth").unwrap();
+        scenarios.charges = ChargeScheme::Random;
+        let particles = scenarios.generate(&params);
+        assert!(!particles.bonds.is_empty());
+
+        Snapshot {
+            params,
+            buffer_in_use: BufferInUse::Secondary,
+            clock: StepState {
+                prev_dt: 0.004,
+                dt: 0.005,
+                time: 1.5,
+                time_error: 1e-9,
+                ..StepState::default()
+            },
+            particles,
+        }
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let snapshot = snapshot();
+        assert!(Snapshot::decode(&snapshot.encode()).unwrap() == snapshot);
+    }
+
+    #[test]
+    fn file_round_trips() {
+        let snapshot = snapshot();
+        let path = std::env::temp_dir().join(format!(
+            "particle_playground_test_{}.ppsnap",
+            std::process::id()
+        ));
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path);
+        let _ = std::fs::remove_file(&path);
+        assert!(loaded.unwrap() == snapshot);
+    }
+
+    #[test]
+    fn corrupted_snapshots_are_rejected() {
+        let bytes = snapshot().encode();
+        assert!(Snapshot::decode(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(Snapshot::decode(&trailing).is_err());
+
+        let mut magic = bytes.clone();
+        magic[0] ^= 0xff;
+        assert!(Snapshot::decode(&magic).is_err());
+
+        let mut version = bytes;
+        version[8] = version[8].wrapping_add(1);
+        assert!(Snapshot::decode(&version).is_err());
+    }
+
+    #[test]
+    fn out_of_range_parameters_are_rejected() {
+        let corruptions: [fn(&mut SimParams); 6] = [
+            |params| params.pm_grid = 1 << 31,
+            |params| params.substeps = 0,
+            |params| params.dt = f32::NAN,
+            |params| params.theta = -1.0,
+            |params| params.n = constants::sim::MAX_PARTICLES + 1,
+            |params| params.world[1] = params.world[0],
+        ];
+        for corrupt in corruptions {
+            let mut snapshot = snapshot();
+            corrupt(&mut snapshot.params);
+            assert!(Snapshot::decode(&snapshot.encode()).is_err());
+        }
+    }
+}
@@ -0,0 +1,52 @@
+//! Spatial ordering of the particles along a Morton (Z-order) curve
+//!
+//! Particles close in space get close Morton codes, so sorting them by code groups
+//! neighbors in memory. The GPU sorts the codes with `RadixSort` (`shaders/radix_sort.wgsl`),
+//! [`sort_pairs`] is its CPU reference.
+
+use glam::UVec2;
+
+/// Spread the low 16 bits of `x` over the even bits
+fn spread(x: u32) -> u32 {
+    let mut x = x & 0xffff;
+    x = (x | (x << 8)) & 0x00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333;
+    (x | (x << 1)) & 0x5555_5555
+}
+
+/// Interleave the bits of a cell of the Morton grid, x on the even bits
+pub fn morton(cell: UVec2) -> u32 {
+    spread(cell.x) | (spread(cell.y) << 1)
+}
+
+/// Pairs of `keys` and `values` ordered by key, equal keys keep their order
+pub fn sort_pairs(keys: &[u32], values: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    let mut pairs: Vec<(u32, u32)> = keys.iter().copied().zip(values.iter().copied()).collect();
+    pairs.sort_by_key(|&(key, _)| key);
+    pairs.into_iter().unzip()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn morton_interleaves_the_cell_bits() {
+        assert_eq!(morton(UVec2::new(0, 0)), 0);
+        assert_eq!(morton(UVec2::new(1, 0)), 0b01);
+        assert_eq!(morton(UVec2::new(0, 1)), 0b10);
+        assert_eq!(morton(UVec2::new(3, 5)), 0b10_01_11);
+        assert_eq!(morton(UVec2::splat(0xffff)), u32::MAX);
+    }
+
+    #[test]
+    fn sort_pairs_is_stable() {
+        let keys = [3, 1, 3, 0, 1];
+        let values = [0, 1, 2, 3, 4];
+        assert_eq!(
+            sort_pairs(&keys, &values),
+            (vec![0, 1, 1, 3, 3], vec![3, 1, 4, 0, 2])
+        );
+    }
+}
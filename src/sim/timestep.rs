@@ -0,0 +1,162 @@
+//! Fixed and adaptive time steps
+//!
+//! The length of every step is decided on the GPU (`shaders/timestep.wgsl`) and kept
+//! in a [`StepState`] buffer next to the particles, so adaptive steps never wait for
+//! the CPU. The CPU only reads it back to display the chosen dt and to time recordings.
+
+use serde::{Deserialize, Serialize};
+
+use crate::constants;
+
+/// How the length of the next step is chosen
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TimestepMode {
+    /// Always `dt`
+    #[default]
+    Fixed = 0,
+    /// `accuracy * sqrt(softening / max |a|)`, resolves close encounters
+    Acceleration = 1,
+    /// `accuracy * softening / max |v|`, no particle moves further than a softening length
+    Velocity = 2,
+}
+
+impl TimestepMode {
+    pub const ALL: [TimestepMode; 3] = [
+        TimestepMode::Fixed,
+        TimestepMode::Acceleration,
+        TimestepMode::Velocity,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            TimestepMode::Fixed => "Fixed",
+            TimestepMode::Acceleration => "Adaptive (acceleration)",
+            TimestepMode::Velocity => "Adaptive (velocity)",
+        }
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        *self != TimestepMode::Fixed
+    }
+}
+
+/// Step bookkeeping shared by every particle, mirrors `Step` in `shaders/nbody.wgsl`
+#[repr(C)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct StepState {
+    /// Length of the previous step, 0 before the first step (half-kick bootstrap)
+    pub prev_dt: f32,
+    /// Length of the next step when adaptive
+    pub dt: f32,
+    /// Simulated time since the particles were generated
+    pub time: f32,
+    /// Kahan compensation of `time`
+    pub time_error: f32,
+    /// Integrator stage within the current step, 0 between steps
+    pub stage: u32,
+}
+
+impl StepState {
+    /// State before the first step of fresh particles
+    pub fn start(dt: f32) -> Self {
+        Self {
+            dt,
+            ..Self::default()
+        }
+    }
+
+    /// Account for a step of `dt` followed by one of `next_dt`, like `ts_total`
+    pub fn advance(&mut self, dt: f32, next_dt: f32) {
+        let y = dt - self.time_error;
+        let t = self.time + y;
+        self.time_error = (t - self.time) - y;
+        self.time = t;
+        self.prev_dt = dt;
+        self.dt = next_dt;
+    }
+}
+
+/// Length of the next adaptive step from the largest acceleration and speed, see `ts_total`
+pub fn adaptive_dt(
+    mode: TimestepMode,
+    accuracy: f32,
+    softening: f32,
+    dt_max: f32,
+    max_acceleration: f32,
+    max_speed: f32,
+) -> f32 {
+    // Without any motion the largest step is taken
+    let dt = match mode {
+        TimestepMode::Acceleration if max_acceleration > 0.0 => {
+            accuracy * (softening / max_acceleration).sqrt()
+        }
+        TimestepMode::Velocity if max_speed > 0.0 => accuracy * softening / max_speed,
+        _ => dt_max,
+    };
+    let dt_min = *constants::sim::DT_RANGE.start();
+    if dt.is_nan() {
+        dt_min
+    } else {
+        dt.clamp(dt_min, dt_max.max(dt_min))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adaptive_steps_follow_their_formula() {
+        let (accuracy, softening, dt_max) = (0.1, 0.02, 0.03);
+        let dt = adaptive_dt(
+            TimestepMode::Acceleration,
+            accuracy,
+            softening,
+            dt_max,
+            8.0,
+            100.0,
+        );
+        assert!((dt - 0.1 * (0.02f32 / 8.0).sqrt()).abs() < 1e-7, "{dt}");
+        let dt = adaptive_dt(
+            TimestepMode::Velocity,
+            accuracy,
+            softening,
+            dt_max,
+            100.0,
+            0.5,
+        );
+        assert!((dt - 0.1 * 0.02 / 0.5).abs() < 1e-7, "{dt}");
+
+        // At rest the largest step is taken, and the steps stay within the range
+        let dt_min = *constants::sim::DT_RANGE.start();
+        for (mode, acceleration, speed, expected) in [
+            (TimestepMode::Acceleration, 0.0, 0.0, dt_max),
+            (TimestepMode::Velocity, 0.0, 0.0, dt_max),
+            (TimestepMode::Acceleration, 1e-9, 0.0, dt_max),
+            (TimestepMode::Velocity, 0.0, 1e9, dt_min),
+            (TimestepMode::Acceleration, f32::INFINITY, 0.0, dt_min),
+            (TimestepMode::Fixed, 1e9, 1e9, dt_max),
+        ] {
+            let dt = adaptive_dt(mode, accuracy, softening, dt_max, acceleration, speed);
+            assert_eq!(
+                dt,
+                expected,
+                "{} at {acceleration} and {speed}",
+                mode.label()
+            );
+        }
+    }
+
+    #[test]
+    fn compensated_time_adds_up_small_steps() {
+        let dt = 1e-3;
+        let mut state = StepState::start(dt);
+        for _ in 0..100_000 {
+            state.advance(dt, dt);
+        }
+        assert!((state.time - 100.0).abs() < 1e-5, "{}", state.time);
+        assert_eq!((state.prev_dt, state.dt), (dt, dt));
+    }
+}
@@ -1,5 +1,6 @@
-use std::{collections::BTreeMap, sync::OnceLock};
+use std::{collections::BTreeMap, io::Write, sync::OnceLock};
 
+use colored::Colorize;
 use log::LevelFilter;
 
 use super::env;
@@ -11,6 +12,9 @@ pub struct Config {
     log_level: Vec<(String, LevelFilter)>,
     default_log_level: LevelFilter,
     max_log_level: LevelFilter,
+
+    // Simulation configuration
+    seed: Option<u64>,
 }
 
 static CONFIG: OnceLock<Config> = OnceLock::new();
@@ -58,26 +62,45 @@ impl Config {
             }
         }
 
-        (
-            map.into_iter().map(|(k, v)| (k, v)).collect(),
-            max_log_level,
-            default_log_level,
-        )
+        (map.into_iter().collect(), max_log_level, default_log_level)
+    }
+
+    fn parse_seed() -> Option<u64> {
+        let value = std::env::var("SEED").ok()?;
+        match value.trim().parse() {
+            Ok(seed) => Some(seed),
+            Err(err) => {
+                // The logger is configured from here, so it cannot be used yet
+                let mut stdout = std::io::stdout().lock();
+                let _ = writeln!(
+                    stdout,
+                    "{} {}:{} - Ignoring invalid SEED '{}' ({})",
+                    "[WARN]".yellow(),
+                    file!(),
+                    line!(),
+                    value,
+                    err
+                );
+                None
+            }
+        }
     }
 
     fn init() -> Self {
         env::load_env(); // Ensure env is loaded once
         let (log_level, max_log_level, default_log_level) = Self::parse_log_level();
+        let seed = Self::parse_seed();
 
         Config {
             log_level,
             max_log_level,
             default_log_level,
+            seed,
         }
     }
 
     pub fn get() -> &'static Self {
-        CONFIG.get_or_init(|| Self::init())
+        CONFIG.get_or_init(Self::init)
     }
 
     /// Find the target's that start with `target` and return the most specific
@@ -96,4 +119,9 @@ impl Config {
     pub fn get_max_log_level() -> LevelFilter {
         Self::get().max_log_level
     }
+
+    /// Seed of the initial conditions from the `SEED` variable, if set
+    pub fn get_seed() -> Option<u64> {
+        Self::get().seed
+    }
 }
@@ -0,0 +1,148 @@
+//! Frame writers used by the recorder
+//!
+//! Frames are tightly packed 8-bit sRGB RGBA rows, top row first.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+/// Output format of a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// Numbered PNG files in a directory
+    #[default]
+    PngSequence,
+    /// Single uncompressed YUV4MPEG2 stream (4:4:4), readable by ffmpeg and most players
+    Y4m,
+}
+
+impl Format {
+    pub const ALL: [Format; 2] = [Format::PngSequence, Format::Y4m];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Format::PngSequence => "PNG Sequence",
+            Format::Y4m => "Y4M Video",
+        }
+    }
+
+    /// Y4M for paths ending in `.y4m`, a PNG sequence directory otherwise
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("y4m") => Format::Y4m,
+            _ => Format::PngSequence,
+        }
+    }
+}
+
+/// Destination of the recorded frames
+pub enum FrameSink {
+    PngSequence {
+        dir: PathBuf,
+        width: u32,
+        height: u32,
+        next_index: u32,
+    },
+    Y4m {
+        writer: BufWriter<File>,
+        width: u32,
+        height: u32,
+    },
+}
+
+impl FrameSink {
+    /// Create the output directory or file, `fps` is the playback rate of the Y4M stream
+    pub fn create(
+        format: Format,
+        path: &Path,
+        width: u32,
+        height: u32,
+        fps: u32,
+    ) -> anyhow::Result<Self> {
+        Ok(match format {
+            Format::PngSequence => {
+                std::fs::create_dir_all(path).with_context(|| {
+                    format!("Failed to create frame directory '{}'", path.display())
+                })?;
+                FrameSink::PngSequence {
+                    dir: path.to_path_buf(),
+                    width,
+                    height,
+                    next_index: 0,
+                }
+            }
+            Format::Y4m => {
+                let file = File::create(path)
+                    .with_context(|| format!("Failed to create video '{}'", path.display()))?;
+                let mut writer = BufWriter::new(file);
+                // Limited range BT.601, the default assumed by ffmpeg for Y4M input
+                writeln!(
+                    writer,
+                    "YUV4MPEG2 W{width} H{height} F{fps}:1 Ip A1:1 C444 XCOLORRANGE=LIMITED"
+                )?;
+                FrameSink::Y4m {
+                    writer,
+                    width,
+                    height,
+                }
+            }
+        })
+    }
+
+    /// Append one frame of `width * height` RGBA pixels
+    pub fn write(&mut self, rgba: &[u8]) -> anyhow::Result<()> {
+        match self {
+            FrameSink::PngSequence {
+                dir,
+                width,
+                height,
+                next_index,
+            } => {
+                debug_assert_eq!(rgba.len(), (*width * *height * 4) as usize);
+                let path = dir.join(format!("frame_{next_index:06}.png"));
+                let file = File::create(&path)
+                    .with_context(|| format!("Failed to create frame '{}'", path.display()))?;
+                let mut encoder = png::Encoder::new(BufWriter::new(file), *width, *height);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+                let mut writer = encoder.write_header()?;
+                writer.write_image_data(rgba)?;
+                writer.finish()?;
+                *next_index += 1;
+            }
+            FrameSink::Y4m {
+                writer,
+                width,
+                height,
+            } => {
+                debug_assert_eq!(rgba.len(), (*width * *height * 4) as usize);
+                let pixels = (*width * *height) as usize;
+                let mut planes = vec![0u8; 3 * pixels];
+                let (y, uv) = planes.split_at_mut(pixels);
+                let (u, v) = uv.split_at_mut(pixels);
+                for (i, px) in rgba.chunks_exact(4).enumerate() {
+                    let [r, g, b] = [px[0], px[1], px[2]].map(|c| c as f32);
+                    y[i] = (16.0 + 0.257 * r + 0.504 * g + 0.098 * b).round() as u8;
+                    u[i] = (128.0 - 0.148 * r - 0.291 * g + 0.439 * b).round() as u8;
+                    v[i] = (128.0 + 0.439 * r - 0.368 * g - 0.071 * b).round() as u8;
+                }
+                writer.write_all(b"FRAME\n")?;
+                writer.write_all(&planes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flush buffered data, the sink must not be used afterwards
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        if let FrameSink::Y4m { writer, .. } = self {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -18,13 +18,13 @@ fn extract_source_code(dir: &Path, ext: &str, out_file: &mut dyn Write) -> std::
         let path = entry.path();
         if path.is_dir() {
             extract_source_code(&path, ext, out_file)?;
-        } else if let Some(read_ext) = path.extension() {
-            if read_ext == ext {
-                let content = fs::read_to_string(&path)?;
-                writeln!(out_file, "// File: {}\n", path.display())?;
-                writeln!(out_file, "{}", content)?;
-                writeln!(out_file, "\n")?;
-            }
+        } else if let Some(read_ext) = path.extension()
+            && read_ext == ext
+        {
+            let content = fs::read_to_string(&path)?;
+            writeln!(out_file, "// File: {}\n", path.display())?;
+            writeln!(out_file, "{}", content)?;
+            writeln!(out_file, "\n")?;
         }
     }
 
@@ -42,7 +42,7 @@ fn main() {
         eprintln!("Error extracting source code: {}", e);
     }
     // Extract wgsl files
-    if let Err(e) = extract_source_code(&shader_dir, "wgsl", &mut out_file) {
+    if let Err(e) = extract_source_code(shader_dir, "wgsl", &mut out_file) {
         eprintln!("Error extracting shader code: {}", e);
     }
     println!("Source code extraction completed successfully.");